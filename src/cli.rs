@@ -1,11 +1,16 @@
 use clap::Parser;
 
-#[derive(Parser)]
+#[derive(Parser, Default)]
 pub struct Cli {
     #[clap(short, long)]
     pub port: Option<u16>,
     #[clap(long)]
     pub replicaof: Option<String>,
     #[clap(long)]
-    pub rdb_path: Option<String>,
+    pub rdb_path: Option<String>, // RDB文件名
+    #[clap(long)]
+    pub dir: Option<String>, // 工作目录
+    /// 检查RDB文件的结构和校验和，不启动服务器
+    #[clap(long, value_name = "FILE")]
+    pub check_rdb: Option<String>,
 }
@@ -1,11 +1,11 @@
 use super::CmdExecutor;
 use crate::{
-    conf::{OFFSET, CONFIG},
-    db::Db,
+    conf::{CONFIG, OFFSET},
+    db::{self, Db, DbInner},
     frame::Frame,
     util,
 };
-use anyhow::{anyhow, Error, Result};
+use anyhow::{anyhow, bail, Error, Result};
 use bytes::Bytes;
 use tracing::debug;
 
@@ -19,6 +19,10 @@ impl CmdExecutor for Command {
         debug!("executing command 'COMMAND'");
         Ok(Some(Frame::Array(vec![])))
     }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
 }
 
 // *1\r\n$4\r\nping\r\n
@@ -31,6 +35,10 @@ impl CmdExecutor for Ping {
         debug!("executing command 'PING'");
         Ok(Some(Frame::Simple("PONG".to_string())))
     }
+
+    fn execute_locked(&self, _db: &mut DbInner) -> Result<Frame> {
+        Ok(Frame::Simple("PONG".to_string()))
+    }
 }
 
 // *2\r\n$4\r\necho\r\n$3\r\nhey\r\n
@@ -45,6 +53,10 @@ impl CmdExecutor for Echo {
         debug!("executing command 'ECHO'");
         Ok(Some(Frame::Bulk(self.msg.clone())))
     }
+
+    fn execute_locked(&self, _db: &mut DbInner) -> Result<Frame> {
+        Ok(Frame::Bulk(self.msg.clone()))
+    }
 }
 
 // 该命令用于获取Redis服务器的各种信息和统计数值
@@ -99,6 +111,12 @@ impl TryFrom<Bytes> for Section {
         let value = value.to_ascii_lowercase();
         match value.as_slice() {
             b"replication" => Ok(Section::Replication),
+            b"memory" => Ok(Section::Memory),
+            b"persistence" => Ok(Section::Persistence),
+            b"stats" => Ok(Section::Stats),
+            b"default" => Ok(Section::Default),
+            b"all" => Ok(Section::All),
+            b"everything" => Ok(Section::Everything),
             // TODO:
             _ => Err(anyhow!("Incomplete")),
         }
@@ -118,48 +136,223 @@ impl TryFrom<Vec<Bytes>> for Section {
 
 #[async_trait::async_trait]
 impl CmdExecutor for Info {
-    async fn execute(&self, _db: &Db) -> Result<Option<Frame>> {
+    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
         debug!("executing command 'INFO'");
 
-        match self.sections {
+        let sections = match &self.sections {
+            Section::Array(sections) => sections.iter().collect(),
+            section => vec![section],
+        };
+        let res = sections
+            .into_iter()
+            .flat_map(|section| section.render(db))
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        Ok(Some(Frame::Bulk(res.into())))
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
+}
+
+impl Section {
+    // 返回各个子节的内容，每个子节以"# <名称>"开头
+    fn render(&self, db: &Db) -> Vec<String> {
+        match self {
+            Section::Array(sections) => sections.iter().flat_map(|s| s.render(db)).collect(),
+            Section::All | Section::Default | Section::Everything => [
+                Section::Memory,
+                Section::Persistence,
+                Section::Stats,
+                Section::Replication,
+            ]
+            .iter()
+            .flat_map(|s| s.render(db))
+            .collect(),
+            Section::Memory => {
+                vec![format!(
+                    "# Memory\r\nused_memory:{}\r\nmaxmemory:{}\r\nmaxmemory_policy:{}\r\nlazyfree_pending_objects:{}\r\n",
+                    db.used_memory(),
+                    CONFIG.memory.maxmemory,
+                    CONFIG.memory.maxmemory_policy.name(),
+                    db::lazyfree_pending_objects()
+                )]
+            }
+            Section::Persistence => {
+                let status = util::rdb_status();
+                let aof = util::aof_status();
+                let rewrite = util::aof_rewrite_status();
+                vec![format!(
+                    "# Persistence\r\nloading:{}\r\nrdb_changes_since_last_save:{}\r\nrdb_bgsave_in_progress:{}\r\nrdb_last_save_time:{}\r\nrdb_last_bgsave_status:{}\r\naof_enabled:{}\r\naof_rewrite_in_progress:{}\r\naof_rewrite_scheduled:{}\r\naof_last_rewrite_time_sec:{}\r\naof_last_bgrewrite_status:{}\r\naof_last_write_status:{}\r\naof_delayed_fsync:{}\r\n",
+                    util::is_loading() as u8,
+                    status.changes_since_last_save,
+                    status.bgsave_in_progress as u8,
+                    status.last_save_time,
+                    if status.last_bgsave_ok { "ok" } else { "err" },
+                    CONFIG.aof.enable as u8,
+                    rewrite.in_progress as u8,
+                    rewrite.scheduled as u8,
+                    rewrite.last_rewrite_time_sec,
+                    if rewrite.last_rewrite_ok { "ok" } else { "err" },
+                    if aof.last_write_ok { "ok" } else { "err" },
+                    aof.delayed_fsync
+                )]
+            }
+            Section::Stats => {
+                let stats = db::expire_stats();
+                vec![format!(
+                    "# Stats\r\nexpired_keys:{}\r\nexpired_stale_perc:{:.2}\r\nexpired_time_cap_reached_count:{}\r\nevicted_keys:{}\r\n",
+                    stats.expired_keys,
+                    stats.expired_stale_perc,
+                    stats.expired_time_cap_reached_count,
+                    db::evicted_keys()
+                )]
+            }
             Section::Replication => {
-                let res = if CONFIG.replication.replicaof.is_none() {
-                    format!(
-                        "role:master\r\nmaster_replid:{}\r\nmaster_repl_offset:{}\r\n",
-                        CONFIG.replication.replid,
-                        OFFSET.load(std::sync::atomic::Ordering::SeqCst)
-                    )
+                let role = if CONFIG.replication.replicaof.is_none() {
+                    "master"
                 } else {
-                    format!(
-                        "role:slave\r\nmaster_replid:{}\r\nmaster_repl_offset:{}\r\n",
-                        CONFIG.replication.replid,
-                        OFFSET.load(std::sync::atomic::Ordering::SeqCst)
-                    )
+                    "slave"
                 };
-                Ok(Some(Frame::Bulk(res.into())))
+                vec![format!(
+                    "# Replication\r\nrole:{}\r\nmaster_replid:{}\r\nmaster_repl_offset:{}\r\n",
+                    role,
+                    CONFIG.replication.replid,
+                    OFFSET.load(std::sync::atomic::Ordering::SeqCst)
+                )]
             }
             // TODO:
-            _ => Err(anyhow!("Incomplete")),
+            _ => vec![],
         }
     }
 }
 
-// 该命令用于在后台异步保存当前数据库的数据到磁盘
-pub struct BgSave;
+// 该命令用于在后台异步保存当前数据库的数据到磁盘。指定SCHEDULE时，如果已经有后台保存或AOF重写
+// 正在进行，则在其完成后再进行一次后台保存
+pub struct BgSave {
+    pub schedule: bool,
+}
 
 #[async_trait::async_trait]
 impl CmdExecutor for BgSave {
     async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
-        let db = db.inner.read().await.clone();
-        std::thread::spawn(|| match util::rdb_save(db) {
-            Ok(_) => tracing::info!("RDB file generated successfully!!!"),
-            Err(e) => tracing::error!("Failed to save RDB file: {:?}", e),
-        });
+        let rewriting = util::aof_rewrite_status().in_progress;
+        if self.schedule && (rewriting || util::rdb_status().bgsave_in_progress) {
+            util::rdb_schedule_bgsave();
+            return Ok(Some(Frame::Simple(
+                "Background saving scheduled".to_string(),
+            )));
+        }
+        if rewriting {
+            bail!("ERR An AOF log rewriting in progress: can't BGSAVE right now. Use BGSAVE SCHEDULE in order to schedule a BGSAVE whenever possible.");
+        }
 
-        Ok(Some(Frame::Simple(
-            "Background saving scheduled".to_string(),
+        util::rdb_bgsave(db).await?;
+        Ok(Some(Frame::Simple("Background saving started".to_string())))
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
+}
+
+// 该命令用于同步保存当前数据库的数据到磁盘，保存完成后才返回
+pub struct Save;
+
+#[async_trait::async_trait]
+impl CmdExecutor for Save {
+    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'SAVE'");
+        util::rdb_save_foreground(db).await?;
+        Ok(Some(Frame::Simple("OK".to_string())))
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
+}
+
+// 该命令用于获取最后一次成功保存RDB文件的unix时间戳
+pub struct LastSave;
+
+#[async_trait::async_trait]
+impl CmdExecutor for LastSave {
+    async fn execute(&self, _db: &Db) -> Result<Option<Frame>> {
+        Ok(Some(Frame::Integer(
+            util::rdb_status().last_save_time as i64,
         )))
     }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
+}
+
+// 该命令用于关闭服务器。save为None时，如果配置了自动保存则在关闭前保存RDB文件；
+// 保存失败时不会关闭服务器，除非指定了FORCE
+pub struct Shutdown {
+    pub save: Option<bool>,
+    pub force: bool,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Shutdown {
+    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'SHUTDOWN'");
+        if let Err(e) = prepare_for_shutdown(db, self.save).await {
+            if !self.force {
+                return Err(e);
+            }
+        }
+        std::process::exit(0);
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
 }
 
-// pub struct BgRewriteAof;
+/// 关闭服务器前的准备工作：需要时保存RDB文件。返回Err时不应关闭服务器
+pub async fn prepare_for_shutdown(db: &Db, save: Option<bool>) -> Result<()> {
+    tracing::info!("User requested shutdown...");
+    // 与Redis一致，AOF写入失败时仍然继续关闭
+    if let Err(e) = util::aof_flush().await {
+        tracing::error!("Error flushing the AOF file on shutdown: {e}");
+    }
+    if save.unwrap_or_else(util::auto_save_enabled) && util::rdb_save_on_shutdown(db).await.is_err()
+    {
+        tracing::error!("Error trying to save the DB, can't exit.");
+        bail!("ERR Errors trying to SHUTDOWN. Check logs.");
+    }
+    tracing::info!("rutinose is now ready to exit, bye bye...");
+    Ok(())
+}
+
+// 该命令用于在后台重写AOF文件。有后台保存正在进行时，推迟到保存完成之后再重写
+pub struct BgRewriteAof;
+
+#[async_trait::async_trait]
+impl CmdExecutor for BgRewriteAof {
+    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'BGREWRITEAOF'");
+        if util::aof_rewrite_status().in_progress {
+            bail!("ERR Background append only file rewriting already in progress");
+        }
+        if util::rdb_status().bgsave_in_progress {
+            util::aof_schedule_rewrite();
+            return Ok(Some(Frame::Simple(
+                "Background append only file rewriting scheduled".to_string(),
+            )));
+        }
+
+        util::aof_bgrewrite(db)?;
+        Ok(Some(Frame::Simple(
+            "Background append only file rewriting started".to_string(),
+        )))
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
+}
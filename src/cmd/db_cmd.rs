@@ -0,0 +1,176 @@
+use super::CmdExecutor;
+use crate::{
+    db::{free_async, incr_dirty, Db, DbInner, Shard},
+    frame::Frame,
+    util::{notify_keyspace_event, NOTIFY_GENERIC},
+};
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use tracing::debug;
+
+// 切换当前连接所选择的数据库
+// *2\r\n$6\r\nselect\r\n$1\r\n1\r\n
+pub struct Select {
+    pub index: usize,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Select {
+    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'SELECT'");
+        db.select(self.index)?;
+        Ok(Some(Frame::Simple("OK".to_string())))
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
+}
+
+// 交换两个数据库的数据，选择了这两个数据库的连接会立即看到交换后的数据
+// *3\r\n$6\r\nswapdb\r\n$1\r\n0\r\n$1\r\n1\r\n
+pub struct SwapDb {
+    pub index1: usize,
+    pub index2: usize,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for SwapDb {
+    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'SWAPDB'");
+        if self.index1 == self.index2 {
+            db.check_index(self.index1)?;
+        } else {
+            let (mut db1, mut db2) = db.lock_pair(self.index1, self.index2, None).await?;
+            db1.swap(&mut db2);
+        }
+        Ok(Some(Frame::Simple("OK".to_string())))
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
+}
+
+// 将键移动到另一个数据库。当键在当前数据库中不存在，或者在目标数据库中已存在时，不做任何操作
+// *3\r\n$4\r\nmove\r\n$3\r\nkey\r\n$1\r\n1\r\n
+pub struct Move {
+    pub key: Bytes,
+    pub index: usize,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Move {
+    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'MOVE'");
+        if self.index == db.index() {
+            bail!("ERR source and destination objects are the same");
+        }
+        let (mut src, mut dst) = db
+            .lock_pair(db.index(), self.index, Some(&[&self.key]))
+            .await?;
+        if !src.exists(&self.key) || dst.exists(&self.key) {
+            return Ok(Some(Frame::Integer(0)));
+        }
+
+        let obj = src.remove(&self.key).expect("key should exist");
+        dst.insert(self.key.clone(), obj);
+        incr_dirty(1);
+        notify_keyspace_event(NOTIFY_GENERIC, "move_from", &self.key, src.id());
+        notify_keyspace_event(NOTIFY_GENERIC, "move_to", &self.key, dst.id());
+        Ok(Some(Frame::Integer(1)))
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
+}
+
+// 返回当前数据库中键的数量
+// *1\r\n$6\r\ndbsize\r\n
+pub struct DbSize;
+
+#[async_trait::async_trait]
+impl CmdExecutor for DbSize {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        Ok(Frame::Integer(db.len() as i64))
+    }
+}
+
+// 清空当前数据库。ASYNC时在后台线程中释放被删除的键值对
+// *2\r\n$7\r\nflushdb\r\n$5\r\nasync\r\n
+pub struct FlushDb {
+    pub lazy: bool,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for FlushDb {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'FLUSHDB'");
+        free(db.flush(), self.lazy);
+        Ok(Frame::Simple("OK".to_string()))
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+}
+
+// 清空所有数据库。ASYNC时在后台线程中释放被删除的键值对
+// *1\r\n$8\r\nflushall\r\n
+pub struct FlushAll {
+    pub lazy: bool,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for FlushAll {
+    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'FLUSHALL'");
+        for mut inner in db.write_all().await {
+            free(inner.flush(), self.lazy);
+        }
+        Ok(Some(Frame::Simple("OK".to_string())))
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
+}
+
+// 释放被清空的数据。数据量很大时释放可能很耗时，lazy为true时交给后台线程释放
+fn free(shards: Vec<Shard>, lazy: bool) {
+    if lazy {
+        let objects = shards.iter().map(|shard| shard.len()).sum();
+        free_async(shards, objects);
+    } else {
+        drop(shards);
+    }
+}
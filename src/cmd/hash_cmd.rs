@@ -0,0 +1,176 @@
+use super::CmdExecutor;
+use crate::{
+    db::{DbInner, Hash},
+    frame::Frame,
+    util::{notify_keyspace_event, NOTIFY_HASH},
+};
+use anyhow::Result;
+use bytes::Bytes;
+use tracing::debug;
+
+// 设置哈希表中字段的值，键不存在时创建哈希表，返回新增字段的数量
+// *4\r\n$4\r\nhset\r\n$3\r\nkey\r\n$5\r\nfield\r\n$5\r\nvalue\r\n
+// return: :1\r\n
+pub struct HSet {
+    pub key: Bytes,
+    pub pairs: Vec<(Bytes, Bytes)>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for HSet {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'HSET'");
+        let added = db
+            .write::<Hash, _>(&self.key, true, |hash| {
+                let added = self
+                    .pairs
+                    .iter()
+                    .filter(|(field, value)| hash.insert(field.clone(), value.clone()))
+                    .count();
+                // 覆盖已有字段的值也是修改
+                (added, true)
+            })?
+            .unwrap_or_default();
+        notify_keyspace_event(NOTIFY_HASH, "hset", &self.key, db.id());
+        Ok(Frame::Integer(added as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+// 返回哈希表中字段的值，键或字段不存在时返回nil
+// *3\r\n$4\r\nhget\r\n$3\r\nkey\r\n$5\r\nfield\r\n
+pub struct HGet {
+    pub key: Bytes,
+    pub field: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for HGet {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'HGET'");
+        let value = db
+            .read::<Hash>(&self.key)?
+            .and_then(|hash| hash.get(&self.field));
+        Ok(value.map_or(Frame::Null, Frame::Bulk))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+// 删除哈希表中的字段，返回被删除字段的数量。哈希表为空时键会被删除
+// *3\r\n$4\r\nhdel\r\n$3\r\nkey\r\n$5\r\nfield\r\n
+pub struct HDel {
+    pub key: Bytes,
+    pub fields: Vec<Bytes>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for HDel {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'HDEL'");
+        let deleted = db
+            .write::<Hash, _>(&self.key, false, |hash| {
+                let deleted = self
+                    .fields
+                    .iter()
+                    .filter(|field| hash.remove(field))
+                    .count();
+                (deleted, deleted > 0)
+            })?
+            .unwrap_or_default();
+        if deleted > 0 {
+            notify_keyspace_event(NOTIFY_HASH, "hdel", &self.key, db.id());
+        }
+        Ok(Frame::Integer(deleted as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+}
+
+// 返回哈希表中字段的数量
+// *2\r\n$4\r\nhlen\r\n$3\r\nkey\r\n
+pub struct HLen {
+    pub key: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for HLen {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'HLEN'");
+        let len = db.read::<Hash>(&self.key)?.map_or(0, |hash| hash.len());
+        Ok(Frame::Integer(len as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+// 返回哈希表中所有的字段与值
+// *2\r\n$7\r\nhgetall\r\n$3\r\nkey\r\n
+pub struct HGetAll {
+    pub key: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for HGetAll {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'HGETALL'");
+        let pairs = db
+            .read::<Hash>(&self.key)?
+            .map(|hash| {
+                hash.iter()
+                    .flat_map(|(field, value)| {
+                        [Frame::Bulk(field.clone()), Frame::Bulk(value.clone())]
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Frame::Array(pairs))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+// 返回哈希表中是否存在字段
+// *3\r\n$7\r\nhexists\r\n$3\r\nkey\r\n$5\r\nfield\r\n
+pub struct HExists {
+    pub key: Bytes,
+    pub field: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for HExists {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'HEXISTS'");
+        let exists = db
+            .read::<Hash>(&self.key)?
+            .is_some_and(|hash| hash.contains(&self.field));
+        Ok(Frame::Integer(exists as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
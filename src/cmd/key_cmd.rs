@@ -0,0 +1,161 @@
+use super::CmdExecutor;
+use crate::{conf::CONFIG, db::DbInner, frame::Frame};
+use anyhow::Result;
+use bytes::Bytes;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+// 返回键保存的值的类型，键不存在时返回none
+// *2\r\n$4\r\ntype\r\n$3\r\nkey\r\n
+// return: +string\r\n
+pub struct Type {
+    pub key: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Type {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'TYPE'");
+        let name = if db.exists(&self.key) {
+            db.peek(&self.key)
+                .map_or("none", |obj| obj.value.obj_type().name())
+        } else {
+            "none"
+        };
+        Ok(Frame::Simple(name.to_string()))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+// 返回存在的键的数量，同一个键出现多次时会被计算多次
+// *3\r\n$6\r\nexists\r\n$4\r\nkey1\r\n$4\r\nkey2\r\n
+// return: :2\r\n
+pub struct Exists {
+    pub keys: Vec<Bytes>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Exists {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'EXISTS'");
+        let count = self.keys.iter().filter(|key| db.exists(key)).count();
+        Ok(Frame::Integer(count as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(self.keys.iter().collect())
+    }
+}
+
+// EXPIRE, PEXPIRE, EXPIREAT, PEXPIREAT。过期时间在解析命令时被转换为绝对时间(毫秒)，
+// 并以PEXPIREAT的形式传播，使从节点和AOF重放时得到相同的过期时间
+// *3\r\n$6\r\nexpire\r\n$3\r\nkey\r\n$2\r\n10\r\n
+// return: :1\r\n
+pub struct Expire {
+    pub key: Bytes,
+    pub expire_at_ms: i64,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Expire {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'EXPIRE'");
+        // 过期时间已经过去，直接删除键
+        if self.expire_at_ms <= unix_time_ms() {
+            let lazy = CONFIG.lazyfree.lazyfree_lazy_expire;
+            return Ok(Frame::Integer(db.del(&self.key, lazy) as i64));
+        }
+        let expire_at = UNIX_EPOCH + Duration::from_millis(self.expire_at_ms as u64);
+        Ok(Frame::Integer(
+            db.set_expire(&self.key, Some(expire_at)) as i64
+        ))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+
+    fn propagation(&self, _frame: Frame) -> Vec<Frame> {
+        vec![Frame::Array(vec![
+            Frame::Bulk("PEXPIREAT".into()),
+            Frame::Bulk(self.key.clone()),
+            Frame::Bulk(self.expire_at_ms.to_string().into()),
+        ])]
+    }
+}
+
+// 返回键的剩余存活时间。键不存在时返回-2，键永不过期时返回-1
+// *2\r\n$3\r\nttl\r\n$3\r\nkey\r\n
+// return: :10\r\n
+pub struct Ttl {
+    pub key: Bytes,
+    // PTTL以毫秒为单位返回
+    pub millis: bool,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Ttl {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'TTL'");
+        let ttl = match db.ttl(&self.key) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(ttl)) if self.millis => ttl.as_millis() as i64,
+            // 与Redis一致，秒数四舍五入
+            Some(Some(ttl)) => ((ttl.as_millis() + 500) / 1000) as i64,
+        };
+        Ok(Frame::Integer(ttl))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+// 移除键的过期时间，返回是否移除成功
+// *2\r\n$7\r\npersist\r\n$3\r\nkey\r\n
+// return: :1\r\n
+pub struct Persist {
+    pub key: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Persist {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'PERSIST'");
+        let persisted =
+            matches!(db.ttl(&self.key), Some(Some(_))) && db.set_expire(&self.key, None);
+        Ok(Frame::Integer(persisted as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+}
+
+/// 当前的Unix时间(毫秒)
+pub fn unix_time_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
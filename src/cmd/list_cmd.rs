@@ -0,0 +1,180 @@
+use super::CmdExecutor;
+use crate::{
+    db::{DbInner, List},
+    frame::Frame,
+    util::{notify_keyspace_event, NOTIFY_LIST},
+};
+use anyhow::Result;
+use bytes::Bytes;
+use tracing::debug;
+
+// 列表的头部(左端)或尾部(右端)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+impl ListEnd {
+    fn name(&self) -> &'static str {
+        match self {
+            ListEnd::Left => "l",
+            ListEnd::Right => "r",
+        }
+    }
+}
+
+// LPUSH, RPUSH。依次将元素插入列表的一端，键不存在时创建列表，返回插入后列表的长度
+// *3\r\n$5\r\nlpush\r\n$3\r\nkey\r\n$1\r\na\r\n
+// return: :1\r\n
+pub struct Push {
+    pub end: ListEnd,
+    pub key: Bytes,
+    pub values: Vec<Bytes>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Push {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command '{}PUSH'", self.end.name());
+        let len = db
+            .write::<List, _>(&self.key, true, |list| {
+                for value in &self.values {
+                    match self.end {
+                        ListEnd::Left => list.push_front(value.clone()),
+                        ListEnd::Right => list.push_back(value.clone()),
+                    }
+                }
+                (list.len(), true)
+            })?
+            .unwrap_or_default();
+        let event = format!("{}push", self.end.name());
+        notify_keyspace_event(NOTIFY_LIST, &event, &self.key, db.id());
+        Ok(Frame::Integer(len as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+// LPOP, RPOP。从列表的一端弹出元素。没有count参数时返回一个元素，否则返回最多count个元素的数组
+// *2\r\n$4\r\nlpop\r\n$3\r\nkey\r\n
+// return: $1\r\na\r\n
+pub struct Pop {
+    pub end: ListEnd,
+    pub key: Bytes,
+    pub count: Option<usize>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Pop {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command '{}POP'", self.end.name());
+        let count = self.count.unwrap_or(1);
+        let popped = db.write::<List, _>(&self.key, false, |list| {
+            let popped = (0..count)
+                .map_while(|_| match self.end {
+                    ListEnd::Left => list.pop_front(),
+                    ListEnd::Right => list.pop_back(),
+                })
+                .collect::<Vec<_>>();
+            let modified = !popped.is_empty();
+            (popped, modified)
+        })?;
+
+        let Some(popped) = popped else {
+            return Ok(Frame::Null);
+        };
+        if !popped.is_empty() {
+            let event = format!("{}pop", self.end.name());
+            notify_keyspace_event(NOTIFY_LIST, &event, &self.key, db.id());
+        }
+        let frame = match self.count {
+            Some(_) => Frame::Array(popped.into_iter().map(Frame::Bulk).collect()),
+            None => popped.into_iter().next().map_or(Frame::Null, Frame::Bulk),
+        };
+        Ok(frame)
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+}
+
+// 返回列表的长度，键不存在时返回0
+// *2\r\n$4\r\nllen\r\n$3\r\nkey\r\n
+pub struct LLen {
+    pub key: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for LLen {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'LLEN'");
+        let len = db.read::<List>(&self.key)?.map_or(0, |list| list.len());
+        Ok(Frame::Integer(len as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+// 返回列表中[start, stop]之间的元素，负数下标从列表尾部开始计算
+// *4\r\n$6\r\nlrange\r\n$3\r\nkey\r\n$1\r\n0\r\n$2\r\n-1\r\n
+pub struct LRange {
+    pub key: Bytes,
+    pub start: i64,
+    pub stop: i64,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for LRange {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'LRANGE'");
+        let values = db
+            .read::<List>(&self.key)?
+            .map(|list| list.range(self.start, self.stop))
+            .unwrap_or_default();
+        Ok(Frame::Array(values.into_iter().map(Frame::Bulk).collect()))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+// 返回列表中下标对应的元素，下标超出范围时返回nil
+// *3\r\n$6\r\nlindex\r\n$3\r\nkey\r\n$1\r\n0\r\n
+pub struct LIndex {
+    pub key: Bytes,
+    pub index: i64,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for LIndex {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'LINDEX'");
+        let value = db
+            .read::<List>(&self.key)?
+            .and_then(|list| list.get(self.index));
+        Ok(value.map_or(Frame::Null, Frame::Bulk))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
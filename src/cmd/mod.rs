@@ -1,19 +1,93 @@
 mod command;
+mod db_cmd;
+mod hash_cmd;
+mod key_cmd;
+mod list_cmd;
+mod object_cmd;
+mod pubsub_cmd;
 mod replicate;
+mod scan_cmd;
+mod script_cmd;
+mod set_cmd;
 mod string_cmd;
+mod transaction;
+mod zset_cmd;
 
-use crate::{db::Db, frame::Frame};
+use crate::{
+    conf::CONFIG,
+    db::{self, Db, DbInner},
+    frame::Frame,
+    util,
+};
+use bytes::Bytes;
 use tokio::sync::broadcast::Sender;
 
 pub use command::*;
+pub use db_cmd::*;
+pub use hash_cmd::*;
+pub use key_cmd::*;
+pub use list_cmd::*;
+pub use object_cmd::*;
+pub use pubsub_cmd::*;
 pub use replicate::*;
+pub use scan_cmd::*;
+pub use script_cmd::*;
+pub use set_cmd::*;
 pub use string_cmd::*;
-
-// TODO: 实现SAVE, BGSAVE, BGREWRITEAOF
+pub use transaction::*;
+pub use zset_cmd::*;
 
 #[async_trait::async_trait]
 pub trait CmdExecutor: Send + Sync {
-    async fn execute(&self, db: &Db) -> anyhow::Result<Option<Frame>>;
+    /// 默认情况下，锁住命令访问的键所在的分片后调用execute_locked。写命令持有写锁，
+    /// 其它命令只持有读锁，读锁下发现的已过期的键在释放读锁之后删除
+    async fn execute(&self, db: &Db) -> anyhow::Result<Option<Frame>> {
+        if self.is_write() {
+            let mut inner = db.lock(self.keys()).await;
+            return self.execute_locked(&mut inner).map(Some);
+        }
+        let mut inner = db.read(self.keys()).await;
+        let res = self.execute_locked(&mut inner);
+        db.expire_found(inner).await;
+        res.map(Some)
+    }
+
+    /// 命令访问的键。执行命令时只会锁住这些键所在的分片，返回None时锁住整个数据库
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        None
+    }
+
+    /// 在调用者已经持有的数据库锁下执行命令，不是写命令时可能只持有读锁。EXEC会在一把写锁下
+    /// 依次执行事务中的命令，因此可以在事务中执行的命令都需要实现该方法
+    fn execute_locked(&self, _db: &mut DbInner) -> anyhow::Result<Frame> {
+        anyhow::bail!("ERR Command not allowed inside a transaction")
+    }
+
+    /// 命令能否在事务中执行。没有实现execute_locked的命令需要返回false，在MULTI之后入队时就会被拒绝
+    fn allow_in_transaction(&self) -> bool {
+        true
+    }
+
+    /// 写命令会被传播给从节点和AOF
+    fn is_write(&self) -> bool {
+        false
+    }
+
+    /// 内存超出maxmemory且无法淘汰足够的键时，会使内存增长的命令将被拒绝。默认与is_write一致，
+    /// 只会减少内存占用的写命令(如DEL)需要返回false
+    fn deny_oom(&self) -> bool {
+        self.is_write()
+    }
+
+    /// 返回命令执行之后需要传播给从节点和AOF的命令。默认情况下写命令会被原样传播，
+    /// 脚本则传播它执行过的写命令
+    fn propagation(&self, frame: Frame) -> Vec<Frame> {
+        if self.is_write() {
+            vec![frame]
+        } else {
+            vec![]
+        }
+    }
 
     /// 默认情况下，replicate_execute与execute行为一致，但不会返回结果给客户端
     async fn replicate_execute(&self, db: &Db) -> anyhow::Result<Option<Frame>> {
@@ -32,3 +106,42 @@ pub trait CmdExecutor: Send + Sync {
         Ok(())
     }
 }
+
+/// 开启了stop_writes_on_bgsave_error时，如果最后一次后台保存失败，则拒绝执行写命令，
+/// 避免继续写入无法持久化的数据。保存成功后恢复
+pub fn deny_write_on_bgsave_error(is_write: bool) -> anyhow::Result<()> {
+    if is_write
+        && CONFIG.rdb.stop_writes_on_bgsave_error
+        && util::auto_save_enabled()
+        && !util::rdb_status().last_bgsave_ok
+    {
+        anyhow::bail!(
+            "MISCONF Errors writing to the RDB file, check the logs for details. \
+             Commands that may modify the data set are disabled, because this instance is \
+             configured to report errors during writes if RDB snapshotting fails \
+             (stop-writes-on-bgsave-error option)."
+        );
+    }
+    Ok(())
+}
+
+/// 执行命令之前，如果内存超出maxmemory则淘汰键，被淘汰的键作为DEL传播给从节点和AOF。
+/// 无法释放足够的内存时，deny_oom的命令会收到OOM错误，其它命令照常执行
+pub async fn evict_before_execute(
+    db: &Db,
+    write_cmd_sender: &Sender<Frame>,
+    deny_oom: bool,
+) -> anyhow::Result<()> {
+    let res = db::perform_evictions(db, |index, key| {
+        propagate(
+            write_cmd_sender,
+            index,
+            vec![Frame::from(vec!["DEL".into(), key.clone()])],
+        )
+    })
+    .await;
+    match res {
+        Err(e) if deny_oom => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,376 @@
+use super::CmdExecutor;
+use crate::{
+    conf::{MaxmemoryPolicy, CONFIG},
+    db::{self, entry_mem_usage, Db, DbInner, ObjValue, RedisObject},
+    frame::Frame,
+};
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use std::mem::size_of;
+use tracing::debug;
+
+// 与Redis一致，0到9999的整数是共享对象，其引用计数为i32::MAX
+const OBJ_SHARED_INTEGERS: i64 = 10000;
+const OBJ_SHARED_REFCOUNT: i64 = i32::MAX as i64;
+
+const OBJECT_HELP: &[&str] = &[
+    "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "ENCODING <key>",
+    "    Return the kind of internal representation used in order to store the value",
+    "    associated with a <key>.",
+    "FREQ <key>",
+    "    Return the access frequency index of the <key>. The returned integer is",
+    "    proportional to the logarithm of the recent access frequency of the key.",
+    "IDLETIME <key>",
+    "    Return the idle time of the <key>, that is the approximated number of",
+    "    seconds elapsed since the last access to the key.",
+    "REFCOUNT <key>",
+    "    Return the number of references of the value associated with the specified",
+    "    <key>.",
+    "HELP",
+    "    Print this help.",
+];
+
+const MEMORY_HELP: &[&str] = &[
+    "MEMORY <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "DOCTOR",
+    "    Return memory problems reports.",
+    "STATS",
+    "    Return information about the memory usage of the server.",
+    "USAGE <key> [SAMPLES <count>]",
+    "    Return memory in bytes used by <key> and its value. The memory of values",
+    "    is accounted incrementally, so <count> is accepted but never needed.",
+    "HELP",
+    "    Print this help.",
+];
+
+// 查看键的内部信息，不会更新键的访问信息
+// *3\r\n$6\r\nobject\r\n$8\r\nencoding\r\n$3\r\nkey\r\n
+// return: $6\r\nembstr\r\n
+pub enum Object {
+    // 值的编码方式
+    Encoding(Bytes),
+    // 值的引用计数
+    RefCount(Bytes),
+    // 键多久没有被访问(秒)，只在非LFU淘汰策略下可用
+    IdleTime(Bytes),
+    // 键的LFU计数器，只在LFU淘汰策略下可用
+    Freq(Bytes),
+    Help,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Object {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'OBJECT'");
+        let key = match self {
+            Object::Help => return Ok(help_reply(OBJECT_HELP)),
+            Object::Encoding(key)
+            | Object::RefCount(key)
+            | Object::IdleTime(key)
+            | Object::Freq(key) => key,
+        };
+        if !db.exists(key) {
+            return Ok(Frame::Null);
+        }
+        let obj = db.peek(key).expect("key should exist");
+
+        let lfu = matches!(
+            CONFIG.memory.maxmemory_policy,
+            MaxmemoryPolicy::AllkeysLfu | MaxmemoryPolicy::VolatileLfu
+        );
+        let frame = match self {
+            Object::Encoding(_) => Frame::Bulk(obj.value.encoding().into()),
+            Object::RefCount(_) => match obj.value {
+                ObjValue::Int(i) if (0..OBJ_SHARED_INTEGERS).contains(&i) => {
+                    Frame::Integer(OBJ_SHARED_REFCOUNT)
+                }
+                _ => Frame::Integer(1),
+            },
+            Object::IdleTime(_) => {
+                if lfu {
+                    bail!("ERR An LFU maxmemory policy is selected, idle time not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.");
+                }
+                Frame::Integer(obj.idle_time().as_secs() as i64)
+            }
+            Object::Freq(_) => {
+                if !lfu {
+                    bail!("ERR An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.");
+                }
+                Frame::Integer(obj.lfu_decayed_counter() as i64)
+            }
+            Object::Help => unreachable!("help is handled above"),
+        };
+        Ok(frame)
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        match self {
+            Object::Encoding(key)
+            | Object::RefCount(key)
+            | Object::IdleTime(key)
+            | Object::Freq(key) => Some(vec![key]),
+            // 不需要锁住任何分片
+            Object::Help => Some(vec![]),
+        }
+    }
+}
+
+// 内存使用情况
+// *3\r\n$6\r\nmemory\r\n$5\r\nusage\r\n$3\r\nkey\r\n
+// return: :56\r\n
+pub enum Memory {
+    // 键值对估算的内存占用(字节)。每个值的内存占用都是增量维护的，因此不需要抽样，
+    // SAMPLES参数只是为了与Redis兼容
+    Usage(Bytes),
+    // 所有数据库的内存使用统计
+    Stats,
+    // 根据内存使用统计给出的建议
+    Doctor,
+    Help,
+}
+
+// 一个数据库的内存使用统计
+struct DbStats {
+    id: usize,
+    keys: usize,
+    expires: usize,
+    bytes: usize,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Memory {
+    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+        let frame = match self {
+            Memory::Usage(_) => {
+                let mut inner = db.read(self.keys()).await;
+                let res = self.execute_locked(&mut inner);
+                db.expire_found(inner).await;
+                res?
+            }
+            Memory::Stats => {
+                debug!("executing command 'MEMORY STATS'");
+                memory_stats(db).await
+            }
+            Memory::Doctor => {
+                debug!("executing command 'MEMORY DOCTOR'");
+                Frame::Bulk(memory_doctor(db).await.into())
+            }
+            Memory::Help => help_reply(MEMORY_HELP),
+        };
+        Ok(Some(frame))
+    }
+
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        let Memory::Usage(key) = self else {
+            bail!("ERR Command not allowed inside a transaction");
+        };
+        debug!("executing command 'MEMORY USAGE'");
+        if !db.exists(key) {
+            return Ok(Frame::Null);
+        }
+        let obj = db.peek(key).expect("key should exist");
+        Ok(Frame::Integer(entry_mem_usage(key, obj) as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        match self {
+            Memory::Usage(key) => Some(vec![key]),
+            _ => None,
+        }
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        matches!(self, Memory::Usage(_))
+    }
+}
+
+async fn db_stats(db: &Db) -> Vec<DbStats> {
+    db.read_all()
+        .await
+        .iter()
+        .map(|inner| DbStats {
+            id: inner.id(),
+            keys: inner.len(),
+            expires: inner.expires_len(),
+            bytes: inner.used_memory(),
+        })
+        .collect()
+}
+
+async fn memory_stats(db: &Db) -> Frame {
+    let dbs = db_stats(db).await;
+    let total = db.used_memory();
+    let keys: usize = dbs.iter().map(|stats| stats.keys).sum();
+    // 每个键值对的固定开销：键本身以及对象头
+    let overhead = keys * (size_of::<Bytes>() + size_of::<RedisObject>());
+    let dataset = total.saturating_sub(overhead);
+
+    let mut frames = vec![
+        Frame::Bulk("total.allocated".into()),
+        Frame::Integer(total as i64),
+        Frame::Bulk("overhead.total".into()),
+        Frame::Integer(overhead as i64),
+        Frame::Bulk("keys.count".into()),
+        Frame::Integer(keys as i64),
+        Frame::Bulk("keys.bytes-per-key".into()),
+        Frame::Integer(total.checked_div(keys).unwrap_or_default() as i64),
+        Frame::Bulk("dataset.bytes".into()),
+        Frame::Integer(dataset as i64),
+        Frame::Bulk("dataset.percentage".into()),
+        Frame::Bulk(format!("{:.2}", percentage(dataset, total)).into()),
+    ];
+    for stats in dbs.iter().filter(|stats| stats.keys > 0) {
+        frames.push(Frame::Bulk(format!("db.{}", stats.id).into()));
+        frames.push(Frame::Array(vec![
+            Frame::Bulk("keys".into()),
+            Frame::Integer(stats.keys as i64),
+            Frame::Bulk("expires".into()),
+            Frame::Integer(stats.expires as i64),
+            Frame::Bulk("bytes".into()),
+            Frame::Integer(stats.bytes as i64),
+        ]));
+    }
+    Frame::Array(frames)
+}
+
+async fn memory_doctor(db: &Db) -> String {
+    let dbs = db_stats(db).await;
+    let total = db.used_memory();
+    let keys: usize = dbs.iter().map(|stats| stats.keys).sum();
+    if keys == 0 {
+        return "Hi Sam, this instance is empty or is using very little memory, my issues detector can't be used in these conditions. Please, leave for your mission on Earth and fill it with some data. The new Sam and I will be back to our programming as soon as I finished rebooting.".to_string();
+    }
+
+    let mut issues = Vec::new();
+    let maxmemory = CONFIG.memory.maxmemory as usize;
+    let policy = CONFIG.memory.maxmemory_policy;
+    if maxmemory > 0 && percentage(total, maxmemory) > 90.0 {
+        if policy == MaxmemoryPolicy::NoEviction {
+            issues.push(format!(
+                "Used memory is {:.2}% of maxmemory and the maxmemory policy is 'noeviction': write commands will soon be rejected with OOM errors. Consider raising maxmemory or selecting an eviction policy.",
+                percentage(total, maxmemory)
+            ));
+        } else if policy.is_volatile() {
+            let volatile: usize = dbs.iter().map(|stats| stats.expires).sum();
+            if percentage(volatile, keys) < 10.0 {
+                issues.push(format!(
+                    "Used memory is close to maxmemory but only {} of {} keys have an expire set, while the maxmemory policy '{}' can only evict keys with an expire. Consider using an allkeys policy.",
+                    volatile,
+                    keys,
+                    policy.name()
+                ));
+            }
+        }
+    }
+    let evicted = db::evicted_keys();
+    if evicted > 0 {
+        issues.push(format!(
+            "{} keys were evicted because used memory exceeded maxmemory. If this is not expected, consider raising maxmemory.",
+            evicted
+        ));
+    }
+    let stale_perc = db::expire_stats().expired_stale_perc;
+    if stale_perc > 25.0 {
+        issues.push(format!(
+            "About {:.2}% of the keys with an expire are already logically expired but still use memory. Consider raising active_expire_effort or hz.",
+            stale_perc
+        ));
+    }
+    let bytes_per_key = total / keys;
+    if let Some(stats) = dbs
+        .iter()
+        .find(|stats| stats.keys > 0 && stats.bytes / stats.keys > bytes_per_key * 10)
+    {
+        issues.push(format!(
+            "Keys in db {} use {} bytes on average, more than 10 times the average of all keys. Big keys can be found with MEMORY USAGE.",
+            stats.id,
+            stats.bytes / stats.keys
+        ));
+    }
+
+    if issues.is_empty() {
+        return "Hi Sam, I can't find any memory issue in your instance. I can only account for what occurs on this base.".to_string();
+    }
+    let mut report = "Sam, I detected a few issues in this Redis instance memory implants:\n\n".to_string();
+    for issue in issues {
+        report.push_str(&format!(" * {}\n\n", issue));
+    }
+    report.push_str("I'm here to keep you safe, Sam. I want to help you.\n");
+    report
+}
+
+fn percentage(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 * 100.0 / total as f64
+}
+
+fn help_reply(lines: &[&str]) -> Frame {
+    Frame::Array(
+        lines
+            .iter()
+            .map(|line| Frame::Simple(line.to_string()))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod object_cmd_test {
+    use super::*;
+    use crate::db::List;
+
+    #[tokio::test]
+    async fn test_object_and_memory_usage() {
+        let db = Db::with_shards(1, 4);
+        let encoding = |key: &'static str| Object::Encoding(key.into());
+        let usage = |key: &'static str| Memory::Usage(key.into());
+        {
+            let mut inner = db.lock(None).await;
+            inner.set_string("int".into(), "100".into(), None, false);
+            inner.set_string("embstr".into(), "hello".into(), None, false);
+            inner.set_string("raw".into(), "v".repeat(100).into(), None, false);
+            inner
+                .write::<List, _>(&"list".into(), true, |list| {
+                    (list.push_back("a".into()), true)
+                })
+                .unwrap();
+        }
+
+        let exec = |cmd: Box<dyn CmdExecutor>| {
+            let db = db.clone();
+            async move { cmd.execute(&db).await.unwrap().unwrap() }
+        };
+        assert_eq!(exec(Box::new(encoding("int"))).await, Frame::Bulk("int".into()));
+        assert_eq!(exec(Box::new(encoding("embstr"))).await, Frame::Bulk("embstr".into()));
+        assert_eq!(exec(Box::new(encoding("raw"))).await, Frame::Bulk("raw".into()));
+        assert_eq!(exec(Box::new(encoding("nope"))).await, Frame::Null);
+        assert_eq!(
+            exec(Box::new(Object::RefCount("int".into()))).await,
+            Frame::Integer(OBJ_SHARED_REFCOUNT)
+        );
+        assert_eq!(
+            exec(Box::new(Object::RefCount("raw".into()))).await,
+            Frame::Integer(1)
+        );
+
+        let Frame::Integer(small) = exec(Box::new(usage("embstr"))).await else {
+            panic!("should be integer");
+        };
+        let Frame::Integer(big) = exec(Box::new(usage("raw"))).await else {
+            panic!("should be integer");
+        };
+        assert_eq!(big - small, 95 - 3);
+        assert_eq!(exec(Box::new(usage("nope"))).await, Frame::Null);
+
+        // 所有键值对的内存占用之和等于数据库的内存占用
+        let mut sum = 0;
+        for key in ["int", "embstr", "raw", "list"] {
+            if let Frame::Integer(n) = exec(Box::new(usage(key))).await {
+                sum += n as usize;
+            }
+        }
+        assert_eq!(sum, db.used_memory());
+    }
+}
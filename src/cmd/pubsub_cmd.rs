@@ -0,0 +1,438 @@
+use super::CmdExecutor;
+use crate::{
+    conf::PUBSUB,
+    db::{Db, DbInner},
+    frame::Frame,
+    stream::FrameHandler,
+    util::{self, Subscriber},
+};
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use std::collections::HashSet;
+use tokio::{io::AsyncWriteExt, net::TcpStream, select, sync::broadcast::Sender};
+use tracing::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionKind {
+    // SUBSCRIBE/UNSUBSCRIBE
+    Channel,
+    // PSUBSCRIBE/PUNSUBSCRIBE
+    Pattern,
+    // SSUBSCRIBE/SUNSUBSCRIBE，频道会被哈希到key slot上
+    ShardChannel,
+}
+
+impl SubscriptionKind {
+    fn subscribe_name(self) -> &'static str {
+        match self {
+            SubscriptionKind::Channel => "subscribe",
+            SubscriptionKind::Pattern => "psubscribe",
+            SubscriptionKind::ShardChannel => "ssubscribe",
+        }
+    }
+
+    fn unsubscribe_name(self) -> &'static str {
+        match self {
+            SubscriptionKind::Channel => "unsubscribe",
+            SubscriptionKind::Pattern => "punsubscribe",
+            SubscriptionKind::ShardChannel => "sunsubscribe",
+        }
+    }
+}
+
+// 订阅频道（或模式，或分片频道），客户端进入订阅模式，直到取消订阅所有频道
+// *2\r\n$10\r\nssubscribe\r\n$4\r\nnews\r\n
+// return: *3\r\n$10\r\nssubscribe\r\n$4\r\nnews\r\n:1\r\n
+pub struct Subscribe {
+    pub kind: SubscriptionKind,
+    pub names: Vec<Bytes>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Subscribe {
+    async fn execute(&self, _db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command '{}'", self.kind.subscribe_name());
+        Ok(None)
+    }
+
+    async fn hook(
+        &self,
+        stream: &mut TcpStream,
+        _replacate_msg_sender: &Sender<Frame>,
+        _write_cmd_sender: &Sender<Frame>,
+        _db: &Db,
+        _frame: Frame,
+    ) -> Result<()> {
+        let mut subscription = Subscription {
+            subscriber: PUBSUB.new_subscriber(),
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashSet::new(),
+        };
+
+        let res = match subscription.subscribe(stream, self.kind, &self.names).await {
+            Ok(_) => subscription.serve(stream).await,
+            Err(e) => Err(e),
+        };
+        // 不管连接是正常退出订阅模式还是出错，都要取消该连接的所有订阅
+        subscription.unsubscribe_all();
+
+        res
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
+}
+
+// 在订阅模式之外取消订阅，只需回复客户端当前没有订阅任何频道
+// *2\r\n$12\r\nsunsubscribe\r\n$4\r\nnews\r\n
+// return: *3\r\n$12\r\nsunsubscribe\r\n$4\r\nnews\r\n:0\r\n
+pub struct Unsubscribe {
+    pub kind: SubscriptionKind,
+    pub names: Vec<Bytes>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Unsubscribe {
+    async fn execute(&self, _db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command '{}'", self.kind.unsubscribe_name());
+        Ok(None)
+    }
+
+    async fn hook(
+        &self,
+        stream: &mut TcpStream,
+        _replacate_msg_sender: &Sender<Frame>,
+        _write_cmd_sender: &Sender<Frame>,
+        _db: &Db,
+        _frame: Frame,
+    ) -> Result<()> {
+        if self.names.is_empty() {
+            stream
+                .write_frame(unsubscribe_reply(self.kind, None, 0))
+                .await?;
+        }
+        for name in &self.names {
+            stream
+                .write_frame(unsubscribe_reply(self.kind, Some(name.clone()), 0))
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
+}
+
+// 向频道发布消息，返回接收到消息的订阅者数量
+// *3\r\n$7\r\npublish\r\n$4\r\nnews\r\n$5\r\nhello\r\n
+// return: :1\r\n
+pub struct Publish {
+    pub channel: Bytes,
+    pub message: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Publish {
+    async fn execute(&self, _db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'PUBLISH'");
+        let receivers = PUBSUB.publish(self.channel.clone(), self.message.clone());
+        Ok(Some(Frame::Integer(receivers as i64)))
+    }
+
+    fn execute_locked(&self, _db: &mut DbInner) -> Result<Frame> {
+        let receivers = PUBSUB.publish(self.channel.clone(), self.message.clone());
+        Ok(Frame::Integer(receivers as i64))
+    }
+}
+
+// 向分片频道发布消息，返回接收到消息的订阅者数量
+// *3\r\n$8\r\nspublish\r\n$4\r\nnews\r\n$5\r\nhello\r\n
+// return: :1\r\n
+pub struct SPublish {
+    pub channel: Bytes,
+    pub message: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for SPublish {
+    async fn execute(&self, _db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'SPUBLISH'");
+        let receivers = PUBSUB.spublish(self.channel.clone(), self.message.clone());
+        Ok(Some(Frame::Integer(receivers as i64)))
+    }
+
+    fn execute_locked(&self, _db: &mut DbInner) -> Result<Frame> {
+        let receivers = PUBSUB.spublish(self.channel.clone(), self.message.clone());
+        Ok(Frame::Integer(receivers as i64))
+    }
+
+    // 该节点拥有所有的slot。如果该节点是主节点，则通过复制流将消息传播给从节点，
+    // 从节点再将消息发布给自己的订阅者。消息不会被写入AOF
+    fn propagation(&self, frame: Frame) -> Vec<Frame> {
+        vec![frame]
+    }
+}
+
+// PUBSUB的子命令，用于查看发布订阅系统的状态
+// *2\r\n$6\r\npubsub\r\n$13\r\nshardchannels\r\n
+// *3\r\n$6\r\npubsub\r\n$13\r\nshardnumsub\r\n$4\r\nnews\r\n
+pub enum PubSubCmd {
+    // 返回所有活跃的频道，可以指定一个glob模式进行过滤
+    Channels(Option<Bytes>),
+    // 返回指定频道的订阅者数量（不包括模式订阅者）
+    NumSub(Vec<Bytes>),
+    // 返回被订阅的模式的数量
+    NumPat,
+    // 返回所有活跃的分片频道，可以指定一个glob模式进行过滤
+    ShardChannels(Option<Bytes>),
+    // 返回指定分片频道的订阅者数量
+    ShardNumSub(Vec<Bytes>),
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for PubSubCmd {
+    async fn execute(&self, _db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'PUBSUB'");
+        let numsub = |channels: &[Bytes], f: fn(&Bytes) -> usize| {
+            Frame::Array(
+                channels
+                    .iter()
+                    .flat_map(|channel| {
+                        [
+                            Frame::Bulk(channel.clone()),
+                            Frame::Integer(f(channel) as i64),
+                        ]
+                    })
+                    .collect(),
+            )
+        };
+        let res = match self {
+            PubSubCmd::Channels(pattern) => Frame::from(PUBSUB.channels(pattern.as_ref())),
+            PubSubCmd::NumSub(channels) => numsub(channels, |c| PUBSUB.numsub(c)),
+            PubSubCmd::NumPat => Frame::Integer(PUBSUB.numpat() as i64),
+            PubSubCmd::ShardChannels(pattern) => {
+                Frame::from(PUBSUB.shard_channels(pattern.as_ref()))
+            }
+            PubSubCmd::ShardNumSub(channels) => numsub(channels, |c| PUBSUB.shard_numsub(c)),
+        };
+        Ok(Some(res))
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
+}
+
+/// 一个处于订阅模式的连接的订阅状态
+struct Subscription {
+    subscriber: Subscriber,
+    channels: HashSet<Bytes>,
+    patterns: HashSet<Bytes>,
+    shard_channels: HashSet<Bytes>,
+}
+
+impl Subscription {
+    /// 订阅模式下，连接只接受(P|S)SUBSCRIBE, (P|S)UNSUBSCRIBE, PING, QUIT, RESET命令，
+    /// 同时将订阅频道的消息转发给客户端。当连接取消订阅所有频道后，退出订阅模式
+    async fn serve(&mut self, stream: &mut TcpStream) -> Result<()> {
+        while !self.is_empty() {
+            let mut prefix = [0u8; 1];
+            select! {
+                // peek不会消费数据，因此即使被另一个分支取消也不会丢失客户端的命令
+                n = stream.peek(&mut prefix) => {
+                    if n? == 0 {
+                        return Ok(());
+                    }
+                    let frame = match stream.read_frame().await? {
+                        Some(frame) => frame,
+                        None => return Ok(()),
+                    };
+                    let bulks: Vec<Bytes> = frame.try_into()?;
+                    let cmd_name = util::bytes_to_string(bulks[0].clone())?.to_lowercase();
+                    let args = &bulks[1..];
+                    match cmd_name.as_str() {
+                        "subscribe" | "psubscribe" | "ssubscribe" => {
+                            let kind = match cmd_name.as_str() {
+                                "subscribe" => SubscriptionKind::Channel,
+                                "psubscribe" => SubscriptionKind::Pattern,
+                                _ => SubscriptionKind::ShardChannel,
+                            };
+                            if let Err(e) = check_subscribe_args(kind, args) {
+                                stream.write_frame(Frame::Error(e.to_string())).await?;
+                                continue;
+                            }
+                            self.subscribe(stream, kind, args).await?;
+                        }
+                        "unsubscribe" => {
+                            self.unsubscribe(stream, SubscriptionKind::Channel, args).await?
+                        }
+                        "punsubscribe" => {
+                            self.unsubscribe(stream, SubscriptionKind::Pattern, args).await?
+                        }
+                        "sunsubscribe" => {
+                            self.unsubscribe(stream, SubscriptionKind::ShardChannel, args).await?
+                        }
+                        "ping" => {
+                            let msg = args.first().cloned().unwrap_or_default();
+                            stream
+                                .write_frame(Frame::from(vec!["pong".into(), msg]))
+                                .await?;
+                        }
+                        "reset" => {
+                            self.unsubscribe_all();
+                            stream.write_frame(Frame::Simple("RESET".to_string())).await?;
+                        }
+                        "quit" => {
+                            self.unsubscribe_all();
+                            stream.write_frame(Frame::Simple("OK".to_string())).await?;
+                            stream.shutdown().await?;
+                        }
+                        _ => {
+                            stream
+                                .write_frame(Frame::Error(format!(
+                                    "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                                    cmd_name
+                                )))
+                                .await?;
+                        }
+                    }
+                }
+                Some(msg) = self.subscriber.receiver.recv() => {
+                    stream.write_frame(msg).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.channels.is_empty() && self.patterns.is_empty() && self.shard_channels.is_empty()
+    }
+
+    fn names_mut(&mut self, kind: SubscriptionKind) -> &mut HashSet<Bytes> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    /// 回复给客户端的订阅数量。普通频道与模式共享同一个计数，分片频道单独计数
+    fn count(&self, kind: SubscriptionKind) -> i64 {
+        match kind {
+            SubscriptionKind::Channel | SubscriptionKind::Pattern => {
+                (self.channels.len() + self.patterns.len()) as i64
+            }
+            SubscriptionKind::ShardChannel => self.shard_channels.len() as i64,
+        }
+    }
+
+    async fn subscribe(
+        &mut self,
+        stream: &mut TcpStream,
+        kind: SubscriptionKind,
+        names: &[Bytes],
+    ) -> Result<()> {
+        for name in names {
+            if self.names_mut(kind).insert(name.clone()) {
+                match kind {
+                    SubscriptionKind::Channel => PUBSUB.subscribe(name.clone(), &self.subscriber),
+                    SubscriptionKind::Pattern => PUBSUB.psubscribe(name.clone(), &self.subscriber),
+                    SubscriptionKind::ShardChannel => {
+                        PUBSUB.ssubscribe(name.clone(), &self.subscriber)
+                    }
+                }
+            }
+            stream
+                .write_frame(Frame::Array(vec![
+                    Frame::Bulk(kind.subscribe_name().into()),
+                    Frame::Bulk(name.clone()),
+                    Frame::Integer(self.count(kind)),
+                ]))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// 取消订阅指定的频道，如果没有指定频道，则取消订阅该类型的所有频道
+    async fn unsubscribe(
+        &mut self,
+        stream: &mut TcpStream,
+        kind: SubscriptionKind,
+        names: &[Bytes],
+    ) -> Result<()> {
+        let names = if names.is_empty() {
+            self.names_mut(kind).iter().cloned().collect()
+        } else {
+            names.to_vec()
+        };
+
+        if names.is_empty() {
+            stream
+                .write_frame(unsubscribe_reply(kind, None, self.count(kind)))
+                .await?;
+        }
+        for name in names {
+            if self.names_mut(kind).remove(&name) {
+                unsubscribe_from_pubsub(kind, &name, self.subscriber.id);
+            }
+            let count = self.count(kind);
+            stream
+                .write_frame(unsubscribe_reply(kind, Some(name), count))
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn unsubscribe_all(&mut self) {
+        for kind in [
+            SubscriptionKind::Channel,
+            SubscriptionKind::Pattern,
+            SubscriptionKind::ShardChannel,
+        ] {
+            let id = self.subscriber.id;
+            for name in self.names_mut(kind).drain() {
+                unsubscribe_from_pubsub(kind, &name, id);
+            }
+        }
+    }
+}
+
+fn unsubscribe_from_pubsub(kind: SubscriptionKind, name: &Bytes, subscriber_id: u64) {
+    match kind {
+        SubscriptionKind::Channel => PUBSUB.unsubscribe(name, subscriber_id),
+        SubscriptionKind::Pattern => PUBSUB.punsubscribe(name, subscriber_id),
+        SubscriptionKind::ShardChannel => PUBSUB.sunsubscribe(name, subscriber_id),
+    }
+}
+
+fn unsubscribe_reply(kind: SubscriptionKind, name: Option<Bytes>, count: i64) -> Frame {
+    Frame::Array(vec![
+        Frame::Bulk(kind.unsubscribe_name().into()),
+        name.map_or(Frame::Null, Frame::Bulk),
+        Frame::Integer(count),
+    ])
+}
+
+/// 订阅命令至少需要一个频道，并且一条命令中的所有分片频道必须属于同一个slot
+pub fn check_subscribe_args(kind: SubscriptionKind, names: &[Bytes]) -> Result<()> {
+    if names.is_empty() {
+        bail!(
+            "ERR wrong number of arguments for '{}' command",
+            kind.subscribe_name()
+        );
+    }
+    if kind == SubscriptionKind::ShardChannel {
+        let mut slots = names.iter().map(|channel| util::key_hash_slot(channel));
+        if let Some(first) = slots.next() {
+            if slots.any(|slot| slot != first) {
+                bail!("CROSSSLOT Keys in request don't hash to the same slot");
+            }
+        }
+    }
+    Ok(())
+}
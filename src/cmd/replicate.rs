@@ -6,34 +6,55 @@ use crate::{
     db::Db,
     frame::Frame,
     stream::FrameHandler,
-    util::{self, bytes_to_string, bytes_to_u64},
+    util::{self, bytes_to_u64},
 };
 use anyhow::Result;
-use bytes::BufMut;
+use rand::Rng;
 use std::{sync::atomic::Ordering, time::Duration};
-use tokio::{io::AsyncWriteExt, sync::broadcast::Sender};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::broadcast::{error::RecvError, Sender},
+};
 
+/// 客户端(包括从服务器)通过该命令进行认证。没有ACL，因此用户名只能是default
 pub struct Auth {
     pub username: Option<String>,
     pub password: String,
 }
 
+impl Auth {
+    fn check(&self) -> Frame {
+        let Some(passwd) = &CONFIG.security.requirepass else {
+            return Frame::Error("ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?".to_string());
+        };
+        let default_user = matches!(self.username.as_deref(), None | Some("default"));
+        if !default_user || self.password != *passwd {
+            return Frame::Error(
+                "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+            );
+        }
+        Frame::Simple("OK".to_string())
+    }
+}
+
 #[async_trait::async_trait]
 impl CmdExecutor for Auth {
     async fn execute(&self, _db: &Db) -> Result<Option<Frame>> {
-        Ok(None)
+        Ok(Some(self.check()))
     }
 
     async fn replicate_execute(&self, _db: &Db) -> anyhow::Result<Option<Frame>> {
-        if let Some(passwd) = &CONFIG.security.requirepass {
-            if self.password != *passwd {
-                return Ok(Some(Frame::Error("ERR invalid password".to_string())));
-            }
-        }
-        Ok(Some(Frame::Simple("OK".to_string())))
+        Ok(Some(self.check()))
+    }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
     }
 }
 
+// 全量复制时标记RDB数据结束的分隔符的长度
+const RDB_EOF_MARK_LEN: usize = 40;
+
 #[derive(Default)]
 pub enum Replconf {
     #[default]
@@ -62,6 +83,10 @@ impl CmdExecutor for Replconf {
     async fn replicate_execute(&self, db: &Db) -> anyhow::Result<Option<Frame>> {
         self.execute(db).await
     }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
 }
 
 ///  master收到该命令后开始同步数据，例如：
@@ -75,7 +100,7 @@ pub struct Psync {
 
 #[async_trait::async_trait]
 impl CmdExecutor for Psync {
-    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+    async fn execute(&self, _db: &Db) -> Result<Option<Frame>> {
         Ok(None)
     }
 
@@ -87,8 +112,6 @@ impl CmdExecutor for Psync {
         db: &Db,
         _frame: Frame,
     ) -> anyhow::Result<()> {
-        // TODO: Auth验证
-
         let old_offset = OFFSET.load(Ordering::SeqCst);
         stream
             .write_frame(Frame::Simple(format!(
@@ -97,31 +120,35 @@ impl CmdExecutor for Psync {
             )))
             .await?;
 
+        // 暂不支持增量复制，replid不为None时也进行全量复制
         if let Some(replid) = &self.replid {
-            // 如果replid不为None，则进行增量复制
+            tracing::info!(
+                "Partial resynchronization not accepted ({replid}:{}), starting full resync",
+                self.repli_offset
+            );
+        }
+        // 将快照编码为RDB格式，直接流式地发送给replicate，不写入磁盘也不需要将整个RDB读入内存。
+        // 事先不知道RDB的长度，因此与Redis的无盘复制一致，使用随机的分隔符标记RDB的结束：
+        // $EOF:<40字节的分隔符>\r\n<RDB数据><分隔符>
+        let mark: String = rand::thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(RDB_EOF_MARK_LEN)
+            .map(char::from)
+            .collect();
+        let snapshot = db.snapshot().await;
+        stream
+            .write_all(format!("$EOF:{mark}\r\n").as_bytes())
+            .await?;
+        util::rdb_encode_async(snapshot, stream).await?;
+        stream.write_all(mark.as_bytes()).await?;
+        stream.flush().await?;
 
-            // 如果ack_offset - offset >
+        // 当生成rdb时，可能有新的命令写入，所以需要把新的命令发送给master
+        let len_should_send = OFFSET.load(Ordering::SeqCst) - old_offset;
+        if let Some(cmds_shoud_send) = REPLI_BACKLOG.get_from_end(len_should_send as usize).await {
+            stream.write_all(&cmds_shoud_send).await?;
         } else {
-            // 如果replid为None，则进行全量复制
-
-            // 保存rdb并发送给replicate
-            util::rdb_save(db.inner.read().await.clone())?;
-            let rdb = tokio::fs::read("dump.rdb").await?;
-            let mut buf = format!("${}\r\n", rdb.len()).into_bytes();
-            buf.extend(rdb);
-            // $<length_of_file>\r\n<contents_of_file>
-            stream.write_all(&buf).await?;
-            stream.flush().await?;
-
-            // 当生成rdb时，可能有新的命令写入，所以需要把新的命令发送给master
-            let len_should_send = OFFSET.load(Ordering::SeqCst) - old_offset;
-            if let Some(cmds_shoud_send) =
-                REPLI_BACKLOG.get_from_end(len_should_send as usize).await
-            {
-                stream.write_all(&cmds_shoud_send).await?;
-            } else {
-                tracing::error!("Failed to get cmds from end of backlog");
-            }
+            tracing::error!("Failed to get cmds from end of backlog");
         }
 
         // let empty_rdb = [
@@ -136,6 +163,8 @@ impl CmdExecutor for Psync {
         // let buf = [b"$88\r\n", empty_rdb.as_ref()].concat();
 
         let mut propagate_rx = write_cmd_sender.subscribe();
+        // 从节点从0号数据库开始执行命令，因此需要在下一条写命令之前重新传播SELECT
+        super::reset_propagated_db();
         // 对每个握手后的replication都进行持久化连接。
         loop {
             // TODO: 命令缓冲区
@@ -173,6 +202,10 @@ impl CmdExecutor for Psync {
             }
         }
     }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
 }
 
 // master接收到该命令后，向所有的replication发送"REPLCONF GETACK *"
@@ -197,6 +230,9 @@ impl CmdExecutor for Wait {
         _db: &Db,
         _frame: Frame,
     ) -> anyhow::Result<()> {
+        // 接收Psync消息的接收者。先订阅再发送，避免错过从服务器的回复
+        let mut recv_from_psync = replacate_msg_sender.subscribe();
+
         // 向Psync任务发送"REPLCONF GETACK *"，即向所有的replication连接发送"REPLCONF GETACK *"
         write_cmd_sender.send(Frame::from(vec![
             "REPLCONF".into(),
@@ -204,8 +240,6 @@ impl CmdExecutor for Wait {
             "*".into(),
         ]))?;
 
-        // 接收Psync消息的接收者
-        let mut recv_from_psync = replacate_msg_sender.subscribe();
         let mut ack_replicas = 0; // 同步的replication数量
         let mut max_ack_offset = OFFSET.load(Ordering::SeqCst); // 最大的ack offset
 
@@ -218,11 +252,16 @@ impl CmdExecutor for Wait {
             self.timeout
         };
         println!("waitting...");
-        tokio::time::sleep(timeout).await; // 等待指定时间
+        let deadline = tokio::time::Instant::now() + timeout;
 
-        // 从Psync任务接收"REPLCONF ACK <offset>"，即从所有的replication连接接收"REPLCONF ACK <offset>"
-        // try_recv()方法是非阻塞的，如果没有消息则立即返回Err
-        while let Ok(Frame::Array(frames)) = recv_from_psync.try_recv() {
+        // 从Psync任务接收"REPLCONF ACK <offset>"，即从所有的replication连接接收"REPLCONF ACK <offset>"。
+        // numreplicas个从服务器已同步或者超时后返回
+        while ack_replicas < self.numreplicas {
+            let frames = match tokio::time::timeout_at(deadline, recv_from_psync.recv()).await {
+                Ok(Ok(Frame::Array(frames))) => frames,
+                Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) | Err(_) => break,
+            };
             println!("recv frame: {:?}", frames);
             // 检验返回的是不是"REPLCONF ACK <offset>"
             if Some(Frame::Bulk("REPLCONF".into())) != frames.first().cloned() {
@@ -244,9 +283,13 @@ impl CmdExecutor for Wait {
 
         // 返回同步的replication数量
         stream
-            .write_frame(Frame::Integer(ack_replicas as u64))
+            .write_frame(Frame::Integer(ack_replicas as i64))
             .await?;
 
         Ok(())
     }
+
+    fn allow_in_transaction(&self) -> bool {
+        false
+    }
 }
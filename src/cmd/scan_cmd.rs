@@ -0,0 +1,190 @@
+use super::CmdExecutor;
+use crate::{
+    db::{format_score, Db, DbInner, Hash, Set, ZSet},
+    frame::Frame,
+    util::{self, glob_match},
+};
+use anyhow::Result;
+use bytes::Bytes;
+use tracing::debug;
+
+// 返回当前数据库中所有与模式匹配的键
+// *2\r\n$4\r\nkeys\r\n$1\r\n*\r\n
+pub struct Keys {
+    pub pattern: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Keys {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'KEYS'");
+        let matched: Vec<Bytes> = db
+            .keys()
+            .filter(|key| glob_match(&self.pattern, key, false))
+            .cloned()
+            .collect();
+        // 已过期的键会被删除，且不会被返回
+        let keys = matched
+            .into_iter()
+            .filter(|key| db.exists(key))
+            .map(Frame::Bulk)
+            .collect();
+        Ok(Frame::Array(keys))
+    }
+}
+
+// SCAN, HSCAN, SSCAN, ZSCAN共有的参数
+pub struct ScanArgs {
+    pub cursor: u64,
+    // 只返回与模式匹配的元素。匹配发生在取出元素之后，因此一次扫描可能返回少于count个元素
+    pub pattern: Option<Bytes>,
+    pub count: usize,
+}
+
+impl ScanArgs {
+    fn matches(&self, item: &[u8]) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| glob_match(pattern, item, false))
+    }
+}
+
+// 增量迭代当前数据库中的键。扫描期间一直存在的键至少会被返回一次
+// *2\r\n$4\r\nscan\r\n$1\r\n0\r\n
+// return: *2\r\n$<len>\r\n<next_cursor>\r\n*<n>\r\n<keys>...
+pub struct Scan {
+    pub args: ScanArgs,
+    // 只返回该类型的键
+    pub type_name: Option<String>,
+}
+
+impl Scan {
+    // 扫描游标所在的分片，返回下一次扫描的游标与扫描过的键的数量，符合条件的键被加入matched
+    fn scan_shard(&self, db: &mut DbInner, cursor: u64, matched: &mut Vec<Frame>) -> (u64, usize) {
+        let (next, keys) = db.scan(cursor, self.args.count);
+        let scanned = keys.len();
+        // 已过期的键会被删除，且不会被返回
+        for key in keys {
+            if db.exists(&key)
+                && self.args.matches(&key)
+                && self.type_name.as_ref().is_none_or(|type_name| {
+                    db.peek(&key)
+                        .is_some_and(|obj| obj.value.obj_type().name() == type_name.as_str())
+                })
+            {
+                matched.push(Frame::Bulk(key));
+            }
+        }
+        (next, scanned)
+    }
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Scan {
+    // 每次只锁住游标所在的分片，扫描够count个键或者扫描结束为止
+    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'SCAN'");
+        let (mut cursor, mut scanned, mut matched) = (self.args.cursor, 0, Vec::new());
+        loop {
+            let (shard, _) = util::split_shard_cursor(cursor);
+            if shard >= db.num_shards() {
+                return Ok(Some(scan_reply(0, matched)));
+            }
+            let mut inner = db.read_shard(db.index(), shard).await?;
+            let (next, n) = self.scan_shard(&mut inner, cursor, &mut matched);
+            db.expire_found(inner).await;
+            (cursor, scanned) = (next, scanned + n);
+            if cursor == 0 || scanned >= self.args.count {
+                return Ok(Some(scan_reply(cursor, matched)));
+            }
+        }
+    }
+
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'SCAN'");
+        let (mut cursor, mut scanned, mut matched) = (self.args.cursor, 0, Vec::new());
+        loop {
+            let (next, n) = self.scan_shard(db, cursor, &mut matched);
+            (cursor, scanned) = (next, scanned + n);
+            if cursor == 0 || scanned >= self.args.count {
+                return Ok(scan_reply(cursor, matched));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanKind {
+    Hash,
+    Set,
+    ZSet,
+}
+
+// 增量迭代集合类型的键中的元素。HSCAN返回字段与值，SSCAN返回成员，ZSCAN返回成员与分数
+// *3\r\n$5\r\nhscan\r\n$3\r\nkey\r\n$1\r\n0\r\n
+pub struct CollectionScan {
+    pub kind: ScanKind,
+    pub key: Bytes,
+    pub args: ScanArgs,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for CollectionScan {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command '{:?}SCAN'", self.kind);
+        let (cursor, count) = (self.args.cursor, self.args.count);
+        // 键不存在时视为空集合
+        let (next, elements): (u64, Vec<Bytes>) = match self.kind {
+            ScanKind::Hash => match db.read::<Hash>(&self.key)? {
+                Some(hash) => {
+                    let (next, pairs) = hash.scan(cursor, count);
+                    let elements = pairs
+                        .into_iter()
+                        .filter(|(field, _)| self.args.matches(field))
+                        .flat_map(|(field, value)| [field, value])
+                        .collect();
+                    (next, elements)
+                }
+                None => (0, vec![]),
+            },
+            ScanKind::Set => match db.read::<Set>(&self.key)? {
+                Some(set) => {
+                    let (next, members) = set.scan(cursor, count);
+                    let elements = members
+                        .into_iter()
+                        .filter(|m| self.args.matches(m))
+                        .collect();
+                    (next, elements)
+                }
+                None => (0, vec![]),
+            },
+            ScanKind::ZSet => match db.read::<ZSet>(&self.key)? {
+                Some(zset) => {
+                    let (next, members) = zset.scan(cursor, count);
+                    let elements = members
+                        .into_iter()
+                        .filter(|(m, _)| self.args.matches(m))
+                        .flat_map(|(m, score)| [m, format_score(score).into()])
+                        .collect();
+                    (next, elements)
+                }
+                None => (0, vec![]),
+            },
+        };
+        Ok(scan_reply(
+            next,
+            elements.into_iter().map(Frame::Bulk).collect(),
+        ))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+fn scan_reply(next: u64, elements: Vec<Frame>) -> Frame {
+    Frame::Array(vec![
+        Frame::Bulk(next.to_string().into()),
+        Frame::Array(elements),
+    ])
+}
@@ -0,0 +1,232 @@
+use super::CmdExecutor;
+use crate::{
+    conf::SCRIPTING,
+    db::{Db, DbInner},
+    frame::Frame,
+    util::{self, RestorePolicy},
+};
+use anyhow::Result;
+use bytes::Bytes;
+use std::sync::Mutex;
+use tracing::debug;
+
+// 执行Lua脚本。EVAL_RO/EVALSHA_RO执行的脚本不能执行写命令
+// *4\r\n$4\r\neval\r\n$22\r\nreturn redis.call('get', KEYS[1])\r\n$1\r\n1\r\n$3\r\nkey\r\n
+// *3\r\n$7\r\nevalsha\r\n$40\r\n<sha1>\r\n$1\r\n0\r\n
+pub struct Eval {
+    pub sha: String,
+    // EVALSHA时为None
+    pub body: Option<Bytes>,
+    pub keys: Vec<Bytes>,
+    pub args: Vec<Bytes>,
+    pub read_only: bool,
+    // 脚本执行过的写命令，执行之后代替脚本本身被传播
+    pub effects: Mutex<Vec<Frame>>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Eval {
+    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+        let mut inner = db.lock(None).await;
+        // 脚本可能长时间运行，将当前线程的其它任务交给别的线程，
+        // 使其它客户端能够收到BUSY错误或者执行SCRIPT KILL
+        tokio::task::block_in_place(|| self.execute_locked(&mut inner)).map(Some)
+    }
+
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'EVAL'");
+        let output = SCRIPTING.eval(
+            db,
+            &self.sha,
+            self.body.as_ref(),
+            &self.keys,
+            &self.args,
+            self.read_only,
+        )?;
+        *self.effects.lock().expect("Failed to lock effects") = output.effects;
+        Ok(output.reply)
+    }
+
+    fn is_write(&self) -> bool {
+        !self.read_only
+    }
+
+    fn propagation(&self, _frame: Frame) -> Vec<Frame> {
+        std::mem::take(&mut *self.effects.lock().expect("Failed to lock effects"))
+    }
+}
+
+// 管理脚本缓存
+// *3\r\n$6\r\nscript\r\n$4\r\nload\r\n$8\r\nreturn 1\r\n
+// *3\r\n$6\r\nscript\r\n$6\r\nexists\r\n$40\r\n<sha1>\r\n
+pub enum Script {
+    // 缓存脚本但不执行，返回脚本的sha1
+    Load(Bytes),
+    // 返回各个脚本是否已被缓存
+    Exists(Vec<Bytes>),
+    // 清空脚本缓存
+    Flush,
+    // 中止正在执行的、未执行过写命令的脚本
+    Kill,
+}
+
+impl Script {
+    // SCRIPT命令不访问数据库，SCRIPT KILL更是需要在脚本持有数据库的写锁时执行
+    fn run(&self) -> Result<Frame> {
+        let res = match self {
+            Script::Load(body) => Frame::Bulk(SCRIPTING.load(body)?.into()),
+            Script::Exists(shas) => Frame::Array(
+                shas.iter()
+                    .map(|sha| {
+                        let exists = std::str::from_utf8(sha).is_ok_and(|s| SCRIPTING.exists(s));
+                        Frame::Integer(exists as i64)
+                    })
+                    .collect(),
+            ),
+            Script::Flush => {
+                SCRIPTING.flush()?;
+                Frame::Simple("OK".to_string())
+            }
+            Script::Kill => {
+                SCRIPTING.kill()?;
+                Frame::Simple("OK".to_string())
+            }
+        };
+        Ok(res)
+    }
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Script {
+    async fn execute(&self, _db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'SCRIPT'");
+        self.run().map(Some)
+    }
+
+    fn execute_locked(&self, _db: &mut DbInner) -> Result<Frame> {
+        self.run()
+    }
+}
+
+// 调用函数库中的函数。FCALL_RO只能调用声明了no-writes的函数
+// *4\r\n$5\r\nfcall\r\n$6\r\nmyfunc\r\n$1\r\n1\r\n$3\r\nkey\r\n
+pub struct FCall {
+    pub name: String,
+    pub keys: Vec<Bytes>,
+    pub args: Vec<Bytes>,
+    pub read_only: bool,
+    // 函数执行过的写命令，执行之后代替FCALL本身被传播
+    pub effects: Mutex<Vec<Frame>>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for FCall {
+    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+        let mut inner = db.lock(None).await;
+        // 与EVAL相同，函数可能长时间运行
+        tokio::task::block_in_place(|| self.execute_locked(&mut inner)).map(Some)
+    }
+
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'FCALL'");
+        let output = SCRIPTING.fcall(db, &self.name, &self.keys, &self.args, self.read_only)?;
+        *self.effects.lock().expect("Failed to lock effects") = output.effects;
+        Ok(output.reply)
+    }
+
+    fn is_write(&self) -> bool {
+        !self.read_only
+    }
+
+    fn propagation(&self, _frame: Frame) -> Vec<Frame> {
+        std::mem::take(&mut *self.effects.lock().expect("Failed to lock effects"))
+    }
+}
+
+// 管理函数库
+// *3\r\n$8\r\nfunction\r\n$4\r\nload\r\n$...\r\n#!lua name=mylib\n...\r\n
+// *2\r\n$8\r\nfunction\r\n$4\r\nlist\r\n
+pub enum FunctionCmd {
+    // 载入函数库，返回库名
+    Load {
+        code: Bytes,
+        replace: bool,
+    },
+    // 返回函数库的信息，可以通过glob模式过滤库名
+    List {
+        pattern: Option<Bytes>,
+        with_code: bool,
+    },
+    // 删除函数库
+    Delete(String),
+    // 删除所有函数库
+    Flush,
+    // 序列化所有函数库
+    Dump,
+    // 从FUNCTION DUMP的结果中恢复函数库
+    Restore {
+        payload: Bytes,
+        policy: RestorePolicy,
+    },
+    // 中止正在执行的、未执行过写命令的函数
+    Kill,
+}
+
+impl FunctionCmd {
+    // 与SCRIPT命令相同，FUNCTION命令不访问数据库
+    fn run(&self) -> Result<Frame> {
+        let ok = || Frame::Simple("OK".to_string());
+        let res = match self {
+            FunctionCmd::Load { code, replace } => {
+                Frame::Bulk(SCRIPTING.function_load(code, *replace)?.into())
+            }
+            FunctionCmd::List { pattern, with_code } => {
+                SCRIPTING.function_list(pattern.as_ref(), *with_code)
+            }
+            FunctionCmd::Delete(library) => {
+                SCRIPTING.function_delete(library)?;
+                ok()
+            }
+            FunctionCmd::Flush => {
+                SCRIPTING.function_flush()?;
+                ok()
+            }
+            FunctionCmd::Dump => {
+                Frame::Bulk(util::encode_functions_payload(&SCRIPTING.function_codes()).into())
+            }
+            FunctionCmd::Restore { payload, policy } => {
+                let codes = util::decode_functions_payload(payload)?;
+                SCRIPTING.function_restore(codes, *policy)?;
+                ok()
+            }
+            FunctionCmd::Kill => {
+                SCRIPTING.kill()?;
+                ok()
+            }
+        };
+        Ok(res)
+    }
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for FunctionCmd {
+    async fn execute(&self, _db: &Db) -> Result<Option<Frame>> {
+        debug!("executing command 'FUNCTION'");
+        self.run().map(Some)
+    }
+
+    fn execute_locked(&self, _db: &mut DbInner) -> Result<Frame> {
+        self.run()
+    }
+
+    // 修改函数库的命令需要传播给从节点和AOF
+    fn is_write(&self) -> bool {
+        matches!(
+            self,
+            FunctionCmd::Load { .. }
+                | FunctionCmd::Delete(_)
+                | FunctionCmd::Flush
+                | FunctionCmd::Restore { .. }
+        )
+    }
+}
@@ -0,0 +1,149 @@
+use super::CmdExecutor;
+use crate::{
+    db::{DbInner, Set},
+    frame::Frame,
+    util::{notify_keyspace_event, NOTIFY_SET},
+};
+use anyhow::Result;
+use bytes::Bytes;
+use tracing::debug;
+
+// 向集合中添加成员，键不存在时创建集合，返回新增成员的数量
+// *3\r\n$4\r\nsadd\r\n$3\r\nkey\r\n$1\r\na\r\n
+// return: :1\r\n
+pub struct SAdd {
+    pub key: Bytes,
+    pub members: Vec<Bytes>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for SAdd {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'SADD'");
+        let added = db
+            .write::<Set, _>(&self.key, true, |set| {
+                let added = self
+                    .members
+                    .iter()
+                    .filter(|member| set.insert((*member).clone()))
+                    .count();
+                (added, added > 0)
+            })?
+            .unwrap_or_default();
+        if added > 0 {
+            notify_keyspace_event(NOTIFY_SET, "sadd", &self.key, db.id());
+        }
+        Ok(Frame::Integer(added as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+// 从集合中移除成员，返回被移除成员的数量。集合为空时键会被删除
+// *3\r\n$4\r\nsrem\r\n$3\r\nkey\r\n$1\r\na\r\n
+pub struct SRem {
+    pub key: Bytes,
+    pub members: Vec<Bytes>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for SRem {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'SREM'");
+        let removed = db
+            .write::<Set, _>(&self.key, false, |set| {
+                let removed = self
+                    .members
+                    .iter()
+                    .filter(|member| set.remove(member))
+                    .count();
+                (removed, removed > 0)
+            })?
+            .unwrap_or_default();
+        if removed > 0 {
+            notify_keyspace_event(NOTIFY_SET, "srem", &self.key, db.id());
+        }
+        Ok(Frame::Integer(removed as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+}
+
+// 返回集合中所有的成员
+// *2\r\n$8\r\nsmembers\r\n$3\r\nkey\r\n
+pub struct SMembers {
+    pub key: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for SMembers {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'SMEMBERS'");
+        let members = db
+            .read::<Set>(&self.key)?
+            .map(|set| set.iter().map(Frame::Bulk).collect())
+            .unwrap_or_default();
+        Ok(Frame::Array(members))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+// 返回成员是否在集合中
+// *3\r\n$9\r\nsismember\r\n$3\r\nkey\r\n$1\r\na\r\n
+pub struct SIsMember {
+    pub key: Bytes,
+    pub member: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for SIsMember {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'SISMEMBER'");
+        let is_member = db
+            .read::<Set>(&self.key)?
+            .is_some_and(|set| set.contains(&self.member));
+        Ok(Frame::Integer(is_member as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+// 返回集合中成员的数量
+// *2\r\n$5\r\nscard\r\n$3\r\nkey\r\n
+pub struct SCard {
+    pub key: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for SCard {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'SCARD'");
+        let len = db.read::<Set>(&self.key)?.map_or(0, |set| set.len());
+        Ok(Frame::Integer(len as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
@@ -1,14 +1,8 @@
 use super::CmdExecutor;
-use crate::{
-    conf::{CONFIG, REPLI_BACKLOG},
-    db::Db,
-    frame::Frame,
-};
+use crate::{conf::CONFIG, db::DbInner, frame::Frame};
 use anyhow::Result;
 use bytes::Bytes;
-use crossbeam::queue::ArrayQueue;
 use std::time::Duration;
-use tokio::sync::broadcast::Sender;
 use tracing::debug;
 
 // https://redis.io/commands/get/
@@ -21,9 +15,9 @@ pub struct Get {
 
 #[async_trait::async_trait]
 impl CmdExecutor for Get {
-    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
         debug!("executing command 'GET'");
-        let frame = match db.inner.write().await.string_kvs.get(self.key.clone()) {
+        let frame = match db.get_string(&self.key)? {
             Some(value) => Frame::Bulk(value),
             // 键不存在，或已过期
             None => {
@@ -31,7 +25,11 @@ impl CmdExecutor for Get {
                 Frame::Null
             }
         };
-        Ok(Some(frame))
+        Ok(frame)
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
     }
 }
 
@@ -44,28 +42,80 @@ pub struct Set {
 
 #[async_trait::async_trait]
 impl CmdExecutor for Set {
-    async fn execute(&self, db: &Db) -> Result<Option<Frame>> {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
         debug!("executing command 'SET'");
-        db.inner
-            .write()
-            .await
-            .string_kvs
-            .set(self.key.clone(), self.value.clone(), self.expire);
-        Ok(Some(Frame::Simple("OK".to_string())))
+        db.set_string(
+            self.key.clone(),
+            self.value.clone(),
+            self.expire,
+            self.keep_ttl,
+        );
+        Ok(Frame::Simple("OK".to_string()))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+// https://redis.io/commands/del/
+// *2\r\n$3\r\ndel\r\n$3\r\nkey\r\n
+// return: :1\r\n
+pub struct Del {
+    pub keys: Vec<Bytes>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Del {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'DEL'");
+        let lazy = CONFIG.lazyfree.lazyfree_lazy_user_del;
+        let deleted = self.keys.iter().filter(|key| db.del(key, lazy)).count();
+        Ok(Frame::Integer(deleted as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(self.keys.iter().collect())
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+}
+
+// https://redis.io/commands/unlink/
+// 与DEL相同，但释放代价较大的值时交给后台线程释放
+// *2\r\n$6\r\nunlink\r\n$3\r\nkey\r\n
+// return: :1\r\n
+pub struct Unlink {
+    pub keys: Vec<Bytes>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for Unlink {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'UNLINK'");
+        let deleted = self.keys.iter().filter(|key| db.del(key, true)).count();
+        Ok(Frame::Integer(deleted as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(self.keys.iter().collect())
+    }
+
+    fn is_write(&self) -> bool {
+        true
     }
 
-    async fn hook(
-        &self,
-        _stream: &mut tokio::net::TcpStream,
-        _replacate_msg_sender: &Sender<Frame>,
-        write_cmd_sender: &Sender<Frame>,
-        _db: &Db,
-        frame: Frame,
-    ) -> anyhow::Result<()> {
-        // 如果该节点是主节点，则向其它节点广播
-        if CONFIG.replication.replicaof.is_none() {
-            write_cmd_sender.send(frame)?;
-        }
-        Ok(())
+    fn deny_oom(&self) -> bool {
+        false
     }
 }
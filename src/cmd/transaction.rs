@@ -0,0 +1,271 @@
+use super::CmdExecutor;
+use crate::{conf::CONFIG, db::Db, frame::Frame, util};
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use std::sync::Mutex;
+use tokio::sync::broadcast::Sender;
+use tracing::debug;
+
+/// 连接的事务状态。MULTI之后的命令不会立即执行，而是进入队列，直到EXEC时在一把写锁下
+/// 依次执行；WATCH的键如果在EXEC之前被修改，则EXEC不执行任何命令
+#[derive(Default)]
+pub struct Transaction {
+    // 入队的命令及其原始Frame。None代表连接不处于事务中
+    queue: Option<Vec<(Box<dyn CmdExecutor>, Frame)>>,
+    // 命令入队时出错，EXEC将放弃执行事务
+    aborted: bool,
+    // 被WATCH的键所属的数据库、键，以及WATCH时键的版本号(None代表键不存在)
+    watched: Vec<(usize, Bytes, Option<u64>)>,
+}
+
+impl Transaction {
+    /// 处理MULTI, EXEC, DISCARD, WATCH, UNWATCH命令，以及处于事务中时需要入队的命令。
+    /// 返回None代表该命令与事务无关，应当正常执行
+    pub async fn process(
+        &mut self,
+        frame: &Frame,
+        db: &Db,
+        write_cmd_sender: &Sender<Frame>,
+    ) -> Result<Option<Frame>> {
+        let (name, argc) = match frame {
+            Frame::Array(args) => match args.first() {
+                Some(Frame::Bulk(name)) => (name.to_ascii_lowercase(), args.len()),
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+
+        let res = match name.as_slice() {
+            b"multi" => {
+                check_argc("multi", argc == 1)?;
+                if self.queue.is_some() {
+                    bail!("ERR MULTI calls can not be nested");
+                }
+                self.queue = Some(Vec::new());
+                Frame::Simple("OK".to_string())
+            }
+            b"exec" => {
+                check_argc("exec", argc == 1)?;
+                self.exec(db, write_cmd_sender).await?
+            }
+            b"discard" => {
+                check_argc("discard", argc == 1)?;
+                if self.queue.is_none() {
+                    bail!("ERR DISCARD without MULTI");
+                }
+                self.reset();
+                Frame::Simple("OK".to_string())
+            }
+            b"watch" => {
+                check_argc("watch", argc >= 2)?;
+                if self.queue.is_some() {
+                    bail!("ERR WATCH inside MULTI is not allowed");
+                }
+                let bulks: Vec<Bytes> = frame.clone().try_into()?;
+                let keys = &bulks[1..];
+                let inner = db.read(Some(keys.iter().collect())).await;
+                for key in keys.iter().cloned() {
+                    let version = inner.version(&key);
+                    self.watched.push((db.index(), key, version));
+                }
+                Frame::Simple("OK".to_string())
+            }
+            b"unwatch" => {
+                check_argc("unwatch", argc == 1)?;
+                self.watched.clear();
+                Frame::Simple("OK".to_string())
+            }
+            _ => {
+                let Some(queue) = &mut self.queue else {
+                    return Ok(None);
+                };
+                match frame.clone().parse_cmd() {
+                    // 不能在事务中执行的命令在入队时就被拒绝，EXEC将放弃执行事务
+                    Ok(cmd) if !cmd.allow_in_transaction() => {
+                        self.aborted = true;
+                        bail!("ERR Command not allowed inside a transaction");
+                    }
+                    Ok(cmd) => {
+                        queue.push((cmd, frame.clone()));
+                        Frame::Simple("QUEUED".to_string())
+                    }
+                    Err(e) => {
+                        self.aborted = true;
+                        return Err(e);
+                    }
+                }
+            }
+        };
+        Ok(Some(res))
+    }
+
+    async fn exec(&mut self, db: &Db, write_cmd_sender: &Sender<Frame>) -> Result<Frame> {
+        debug!("executing command 'EXEC'");
+        let aborted = self.aborted;
+        let watched = std::mem::take(&mut self.watched);
+        let Some(queue) = self.queue.take() else {
+            bail!("ERR EXEC without MULTI");
+        };
+        self.reset();
+        if aborted {
+            bail!("EXECABORT Transaction discarded because of previous errors.");
+        }
+        super::deny_write_on_bgsave_error(queue.iter().any(|(cmd, _)| cmd.is_write()))?;
+        let deny_oom = queue.iter().any(|(cmd, _)| cmd.deny_oom());
+        super::evict_before_execute(db, write_cmd_sender, deny_oom).await?;
+
+        // 按编号顺序锁住当前数据库以及被WATCH的键所属的数据库
+        let mut ids: Vec<usize> = watched.iter().map(|(id, _, _)| *id).collect();
+        ids.push(db.index());
+        ids.sort_unstable();
+        ids.dedup();
+        let mut guards = db.lock_dbs(&ids, None).await?;
+        // 被WATCH的键被修改、删除或过期，放弃执行事务
+        if watched.iter().any(|(id, key, version)| {
+            guards[ids.binary_search(id).expect("db should be locked")].version(key) != *version
+        }) {
+            return Ok(Frame::Null);
+        }
+        let inner = &mut guards[ids.binary_search(&db.index()).expect("db should be locked")];
+
+        let mut replies = Vec::with_capacity(queue.len());
+        let mut write_cmds = Vec::new();
+        for (cmd, frame) in queue {
+            // 命令执行出错不会影响事务中的其它命令
+            let res = cmd
+                .execute_locked(inner)
+                .unwrap_or_else(|e| Frame::Error(e.to_string()));
+            write_cmds.extend(cmd.propagation(frame));
+            replies.push(res);
+        }
+
+        // 在释放写锁之前传播，保证传播的顺序与执行的顺序一致
+        propagate(write_cmd_sender, db.index(), write_cmds)?;
+
+        Ok(Frame::Array(replies))
+    }
+
+    /// 是否处于MULTI之后、EXEC或DISCARD之前
+    pub fn in_multi(&self) -> bool {
+        self.queue.is_some()
+    }
+
+    // 退出事务，并取消所有WATCH
+    fn reset(&mut self) {
+        self.queue = None;
+        self.aborted = false;
+        self.watched.clear();
+    }
+}
+
+// 最近一次传播的写命令所属的数据库。None代表下一条写命令之前需要发送SELECT
+static PROPAGATED_DB: Mutex<Option<usize>> = Mutex::new(None);
+
+/// 如果该节点是主节点，则将写命令传播给从节点和AOF。多条写命令会被包裹在MULTI/EXEC中，
+/// 保证从节点和AOF载入时的原子性。当写命令所属的数据库与上一次不同时，先传播SELECT
+pub fn propagate(
+    write_cmd_sender: &Sender<Frame>,
+    dbid: usize,
+    mut frames: Vec<Frame>,
+) -> Result<()> {
+    if frames.is_empty() || CONFIG.replication.replicaof.is_some() {
+        return Ok(());
+    }
+    if frames.len() > 1 {
+        frames.insert(0, Frame::from(vec!["MULTI".into()]));
+        frames.push(Frame::from(vec!["EXEC".into()]));
+    }
+    // 持有锁直到发送完毕，保证SELECT与其后的写命令之间不会插入其它数据库的写命令
+    let mut propagated_db = PROPAGATED_DB.lock().expect("Failed to lock propagated db");
+    if *propagated_db != Some(dbid) {
+        let select = Frame::from(vec!["SELECT".into(), dbid.to_string().into()]);
+        util::aof_append(&select);
+        write_cmd_sender.send(select)?;
+        *propagated_db = Some(dbid);
+    }
+    for frame in frames {
+        util::aof_append(&frame);
+        write_cmd_sender.send(frame)?;
+    }
+    Ok(())
+}
+
+/// 有新的从节点开始全量复制或开始重写AOF时调用，使下一条写命令之前重新传播SELECT
+pub fn reset_propagated_db() {
+    *PROPAGATED_DB.lock().expect("Failed to lock propagated db") = None;
+}
+
+fn check_argc(cmd: &str, ok: bool) -> Result<()> {
+    if !ok {
+        bail!("ERR wrong number of arguments for '{}' command", cmd);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod transaction_test {
+    use super::*;
+
+    fn cmd(args: &[&str]) -> Frame {
+        Frame::Array(
+            args.iter()
+                .map(|arg| Frame::Bulk(Bytes::from(arg.to_string())))
+                .collect(),
+        )
+    }
+
+    // WATCH key之后由另一个连接执行other，返回EXEC的结果
+    async fn watch_then_exec(db: &Db, other: &[&str]) -> Frame {
+        let (sender, _receiver) = tokio::sync::broadcast::channel(16);
+        let mut txn = Transaction::default();
+        for args in [&["WATCH", "key"][..], &["MULTI"], &["SCARD", "key"]] {
+            txn.process(&cmd(args), db, &sender).await.unwrap();
+        }
+        let other = cmd(other).parse_cmd().unwrap();
+        other.execute(db).await.unwrap();
+        txn.process(&cmd(&["EXEC"]), db, &sender)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_watch() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let db = Db::with_databases(1);
+        runtime.block_on(async {
+            let sadd = cmd(&["SADD", "key", "a", "b"]).parse_cmd().unwrap();
+            sadd.execute(&db).await.unwrap();
+
+            // 没有修改键的命令不会使WATCH失效
+            let res = watch_then_exec(&db, &["SREM", "key", "missing"]).await;
+            assert_eq!(res, Frame::Array(vec![Frame::Integer(2)]));
+            let res = watch_then_exec(&db, &["SADD", "key", "a"]).await;
+            assert_eq!(res, Frame::Array(vec![Frame::Integer(2)]));
+
+            // 键被修改后EXEC不执行任何命令
+            let res = watch_then_exec(&db, &["SREM", "key", "a"]).await;
+            assert_eq!(res, Frame::Null);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_reject_on_queue() {
+        let db = Db::with_databases(1);
+        let (sender, _receiver) = tokio::sync::broadcast::channel(16);
+        let mut txn = Transaction::default();
+        txn.process(&cmd(&["MULTI"]), &db, &sender).await.unwrap();
+        let res = txn.process(&cmd(&["SADD", "key", "a"]), &db, &sender).await;
+        assert_eq!(res.unwrap(), Some(Frame::Simple("QUEUED".to_string())));
+
+        // 不能在事务中执行的命令在入队时就返回错误，EXEC不执行任何命令
+        let res = txn
+            .process(&cmd(&["SUBSCRIBE", "news"]), &db, &sender)
+            .await;
+        assert!(res.is_err());
+        let res = txn.process(&cmd(&["EXEC"]), &db, &sender).await;
+        assert!(res.unwrap_err().to_string().starts_with("EXECABORT"));
+        let scard = cmd(&["SCARD", "key"]).parse_cmd().unwrap();
+        assert_eq!(scard.execute(&db).await.unwrap(), Some(Frame::Integer(0)));
+    }
+}
@@ -0,0 +1,184 @@
+use super::CmdExecutor;
+use crate::{
+    db::{format_score, DbInner, ZSet},
+    frame::Frame,
+    util::{notify_keyspace_event, NOTIFY_ZSET},
+};
+use anyhow::Result;
+use bytes::Bytes;
+use tracing::debug;
+
+// 向有序集合中添加成员或更新成员的分数，键不存在时创建有序集合，返回新增成员的数量
+// *4\r\n$4\r\nzadd\r\n$3\r\nkey\r\n$1\r\n1\r\n$1\r\na\r\n
+// return: :1\r\n
+pub struct ZAdd {
+    pub key: Bytes,
+    pub pairs: Vec<(f64, Bytes)>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for ZAdd {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'ZADD'");
+        let (added, changed) = db
+            .write::<ZSet, _>(&self.key, true, |zset| {
+                let (mut added, mut changed) = (0, false);
+                for (score, member) in &self.pairs {
+                    // 成员已存在且分数不变时不算修改
+                    changed |= zset.score(member) != Some(*score);
+                    if zset.insert(member.clone(), *score) {
+                        added += 1;
+                    }
+                }
+                ((added, changed), changed)
+            })?
+            .unwrap_or_default();
+        if changed {
+            notify_keyspace_event(NOTIFY_ZSET, "zadd", &self.key, db.id());
+        }
+        Ok(Frame::Integer(added as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+// 从有序集合中移除成员，返回被移除成员的数量。有序集合为空时键会被删除
+// *3\r\n$4\r\nzrem\r\n$3\r\nkey\r\n$1\r\na\r\n
+pub struct ZRem {
+    pub key: Bytes,
+    pub members: Vec<Bytes>,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for ZRem {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'ZREM'");
+        let removed = db
+            .write::<ZSet, _>(&self.key, false, |zset| {
+                let removed = self
+                    .members
+                    .iter()
+                    .filter(|member| zset.remove(member))
+                    .count();
+                (removed, removed > 0)
+            })?
+            .unwrap_or_default();
+        if removed > 0 {
+            notify_keyspace_event(NOTIFY_ZSET, "zrem", &self.key, db.id());
+        }
+        Ok(Frame::Integer(removed as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+}
+
+// 返回成员的分数，键或成员不存在时返回nil
+// *3\r\n$6\r\nzscore\r\n$3\r\nkey\r\n$1\r\na\r\n
+pub struct ZScore {
+    pub key: Bytes,
+    pub member: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for ZScore {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'ZSCORE'");
+        let score = db
+            .read::<ZSet>(&self.key)?
+            .and_then(|zset| zset.score(&self.member));
+        Ok(score.map_or(Frame::Null, |score| Frame::Bulk(format_score(score).into())))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+// 返回有序集合中成员的数量
+// *2\r\n$5\r\nzcard\r\n$3\r\nkey\r\n
+pub struct ZCard {
+    pub key: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for ZCard {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'ZCARD'");
+        let len = db.read::<ZSet>(&self.key)?.map_or(0, |zset| zset.len());
+        Ok(Frame::Integer(len as i64))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+// 按分数从小到大返回排名在[start, stop]之间的成员，WITHSCORES时成员后跟随其分数
+// *4\r\n$6\r\nzrange\r\n$3\r\nkey\r\n$1\r\n0\r\n$2\r\n-1\r\n
+pub struct ZRange {
+    pub key: Bytes,
+    pub start: i64,
+    pub stop: i64,
+    pub with_scores: bool,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for ZRange {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'ZRANGE'");
+        let members = db
+            .read::<ZSet>(&self.key)?
+            .map(|zset| zset.range(self.start, self.stop))
+            .unwrap_or_default();
+        let mut frames = Vec::with_capacity(members.len());
+        for (member, score) in members {
+            frames.push(Frame::Bulk(member));
+            if self.with_scores {
+                frames.push(Frame::Bulk(format_score(score).into()));
+            }
+        }
+        Ok(Frame::Array(frames))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
+
+// 返回成员按分数从小到大排序的排名(从0开始)，键或成员不存在时返回nil
+// *3\r\n$5\r\nzrank\r\n$3\r\nkey\r\n$1\r\na\r\n
+pub struct ZRank {
+    pub key: Bytes,
+    pub member: Bytes,
+}
+
+#[async_trait::async_trait]
+impl CmdExecutor for ZRank {
+    fn execute_locked(&self, db: &mut DbInner) -> Result<Frame> {
+        debug!("executing command 'ZRANK'");
+        let rank = db
+            .read::<ZSet>(&self.key)?
+            .and_then(|zset| zset.rank(&self.member));
+        Ok(rank.map_or(Frame::Null, |rank| Frame::Integer(rank as i64)))
+    }
+
+    fn keys(&self) -> Option<Vec<&Bytes>> {
+        Some(vec![&self.key])
+    }
+}
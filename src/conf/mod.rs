@@ -12,20 +12,14 @@ use crate::{
     replicaof::enable_replicaof,
     util,
 };
-use bytes::Bytes;
 use clap::Parser;
-use crossbeam::{atomic::AtomicCell, queue::ArrayQueue, sync::ShardedLock};
+use crossbeam::sync::ShardedLock;
 use rand::Rng;
-use serde_with::serde_as;
 use std::{
-    collections::VecDeque,
-    fmt::Display,
+    path::{Path, PathBuf},
     sync::{atomic::AtomicU64, Arc},
 };
-use tokio::{
-    select,
-    sync::{broadcast::Sender, Mutex, RwLockWriteGuard},
-};
+use tokio::sync::broadcast::Sender;
 
 pub static CONFIG: once_cell::sync::Lazy<Arc<Conf>> =
     once_cell::sync::Lazy::new(|| Arc::new(Conf::new()));
@@ -38,11 +32,19 @@ pub static OFFSET: AtomicU64 = AtomicU64::new(0);
 pub static REPLI_BACKLOG: once_cell::sync::Lazy<util::RepliBackLog> =
     once_cell::sync::Lazy::new(|| util::RepliBackLog::new(1024));
 
+/// 发布订阅中心，记录所有频道及其订阅者
+pub static PUBSUB: once_cell::sync::Lazy<util::PubSub> =
+    once_cell::sync::Lazy::new(util::PubSub::new);
+
+/// Lua脚本引擎，缓存所有通过EVAL或SCRIPT LOAD载入的脚本
+pub static SCRIPTING: once_cell::sync::Lazy<util::Scripting> =
+    once_cell::sync::Lazy::new(util::Scripting::new);
+
 #[derive(Debug, serde::Deserialize)]
 pub struct Conf {
     #[serde(rename = "server")]
     pub server: ServerConf,
-    #[serde(rename = "security")]
+    #[serde(rename = "security", default)]
     pub security: SecurityConf,
     #[serde(rename = "replication")]
     pub replication: ReplicationConf,
@@ -50,17 +52,31 @@ pub struct Conf {
     pub rdb: RDBConf,
     #[serde(rename = "aof")]
     pub aof: AOFConf,
+    #[serde(rename = "memory")]
+    pub memory: MemoryConf,
+    #[serde(rename = "lazyfree")]
+    pub lazyfree: LazyfreeConf,
+    #[serde(rename = "advanced")]
+    pub advanced: AdvancedConf,
 }
 
 #[derive(Debug, serde::Deserialize)]
 pub struct ServerConf {
     pub port: u16,
-    pub expire_check_interval_secs: u64, // 检查过期键的周期
+    // 开启的键空间通知的类型，配置为K/E/g/$/l/s/h/z/x/e/t/m/n/A的组合
+    #[serde(default, deserialize_with = "serialize::deserialize_keyspace_events")]
+    pub notify_keyspace_events: u32,
+    pub busy_reply_threshold_ms: u64, // 脚本执行超过该时间后，其它客户端的命令会收到BUSY错误
+    pub databases: usize,             // 逻辑数据库的数量，客户端通过SELECT选择数据库
+    pub shards: usize,                // 每个逻辑数据库的分片数量，每个分片有独立的锁
+    pub hz: u64,                      // 每秒执行多少次主动过期周期，范围为1到500
+    pub active_expire_effort: u64, // 主动过期的力度，范围为1到10。越大则过期键被删除得越及时，但占用的CPU越多
+    pub dir: String,               // 工作目录，RDB文件保存在该目录中
 }
 
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Default, serde::Deserialize)]
 pub struct SecurityConf {
-    pub requirepass: Option<String>, // 访问密码
+    pub requirepass: Option<String>, // 访问密码，设置该值之后，客户端需要先通过AUTH认证
 }
 
 #[derive(Debug)]
@@ -74,9 +90,13 @@ pub struct ReplicationConf {
 #[derive(Debug, serde::Deserialize)]
 pub struct RDBConf {
     pub enable: bool,          // 是否启用RDB持久化
-    pub file_path: String,     // RDB文件路径
-    pub version: u32,          // RDB版本号
+    pub dbfilename: String,    // RDB文件名，文件保存在server.dir中
     pub enable_checksum: bool, // 是否启用RDB校验和
+    pub rdbcompression: bool,  // 保存时是否使用LZF压缩较长的字符串
+    // 自动保存的规则(seconds, changes)：距离上次保存至少经过了seconds秒，且至少有changes次修改时进行后台保存
+    #[serde(deserialize_with = "serialize::deserialize_save_params")]
+    pub save: Vec<(u64, u64)>,
+    pub stop_writes_on_bgsave_error: bool, // 开启自动保存且最后一次后台保存失败时，是否拒绝写命令
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -85,9 +105,88 @@ pub struct AOFConf {
     pub file_path: String,
     #[serde(rename = "append_fsync")]
     pub append_fsync: AppendFSync,
+    // AOF文件相对于上一次重写之后的大小增长了该百分比时自动重写，0代表不自动重写
+    pub auto_aof_rewrite_percentage: u64,
+    // AOF文件小于该大小(字节)时不自动重写
+    #[serde(deserialize_with = "serialize::deserialize_memory")]
+    pub auto_aof_rewrite_min_size: u64,
 }
 
 #[derive(Debug, serde::Deserialize)]
+pub struct MemoryConf {
+    // 键值对估算的内存占用的上限(字节)，超过后按照maxmemory_policy淘汰键。0代表不限制
+    #[serde(deserialize_with = "serialize::deserialize_memory")]
+    pub maxmemory: u64,
+    pub maxmemory_policy: MaxmemoryPolicy,
+    pub maxmemory_samples: usize, // 每次淘汰时从每个数据库中抽样的键的数量
+}
+
+/// 删除释放代价较大的值时，是否交给后台线程释放
+#[derive(Debug, serde::Deserialize)]
+pub struct LazyfreeConf {
+    pub lazyfree_lazy_eviction: bool,   // 因内存不足淘汰的键
+    pub lazyfree_lazy_expire: bool,     // 过期的键
+    pub lazyfree_lazy_server_del: bool, // 命令的副作用删除的值，如SET覆盖原来的值
+    pub lazyfree_lazy_user_del: bool,   // DEL删除的键。UNLINK总是在后台释放
+}
+
+/// 集合的编码。元素较少且较短的集合使用紧凑的listpack编码，超出限制后转换为完整的编码
+#[derive(Debug, serde::Deserialize)]
+pub struct AdvancedConf {
+    // 列表的listpack最多保存的元素数量。为负数时限制listpack的字节数：-1到-5分别为4kb到64kb
+    pub list_max_listpack_size: i64,
+    // quicklist两端各有多少个节点不压缩，其余的内部节点使用LZF压缩。0代表不压缩
+    pub list_compress_depth: usize,
+    pub hash_max_listpack_entries: usize, // 哈希表的listpack最多保存的字段数量
+    pub hash_max_listpack_value: usize,   // 哈希表的listpack中字段和值的最大字节数
+    pub zset_max_listpack_entries: usize, // 有序集合的listpack最多保存的成员数量
+    pub zset_max_listpack_value: usize,   // 有序集合的listpack中成员的最大字节数
+}
+
+/// 内存超出maxmemory时淘汰键的策略。volatile策略只淘汰设置了过期时间的键
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MaxmemoryPolicy {
+    // 不淘汰键，拒绝会使内存增长的写命令
+    #[serde(rename = "noeviction")]
+    NoEviction,
+    AllkeysLru,
+    VolatileLru,
+    AllkeysLfu,
+    VolatileLfu,
+    AllkeysRandom,
+    VolatileRandom,
+    // 淘汰剩余存活时间最短的键
+    VolatileTtl,
+}
+
+impl MaxmemoryPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaxmemoryPolicy::NoEviction => "noeviction",
+            MaxmemoryPolicy::AllkeysLru => "allkeys-lru",
+            MaxmemoryPolicy::VolatileLru => "volatile-lru",
+            MaxmemoryPolicy::AllkeysLfu => "allkeys-lfu",
+            MaxmemoryPolicy::VolatileLfu => "volatile-lfu",
+            MaxmemoryPolicy::AllkeysRandom => "allkeys-random",
+            MaxmemoryPolicy::VolatileRandom => "volatile-random",
+            MaxmemoryPolicy::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    /// 是否只淘汰设置了过期时间的键
+    pub fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            MaxmemoryPolicy::VolatileLru
+                | MaxmemoryPolicy::VolatileLfu
+                | MaxmemoryPolicy::VolatileRandom
+                | MaxmemoryPolicy::VolatileTtl
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
 pub enum AppendFSync {
     EverySec,
     Always,
@@ -109,14 +208,21 @@ impl Conf {
 
         // TODO: 改进加载的方式，不然每次修改cli都需要修改这里
         // 3. 从命令行中加载配置
-        let cli = Cli::parse();
+        // 测试时命令行参数属于测试框架，不应被解析
+        let cli = if cfg!(test) {
+            Cli::default()
+        } else {
+            Cli::parse()
+        };
         let config_builder = config_builder
             .set_override_option("replication.replicaof", cli.replicaof)
             .expect("Failed to set replicaof")
             .set_override_option("server.port", cli.port)
             .expect("Failed to set port")
-            .set_override_option("rdb.file_path", cli.rdb_path)
-            .expect("Failed to set rdb file path");
+            .set_override_option("rdb.dbfilename", cli.rdb_path)
+            .expect("Failed to set rdb file name")
+            .set_override_option("server.dir", cli.dir)
+            .expect("Failed to set dir");
 
         // 4. 运行时配置
         let replid: String = rand::thread_rng()
@@ -134,6 +240,11 @@ impl Conf {
             .expect("Failed to deserialize config")
     }
 
+    /// RDB文件的路径：dir/dbfilename
+    pub fn rdb_path(&self) -> PathBuf {
+        Path::new(&self.server.dir).join(&self.rdb.dbfilename)
+    }
+
     pub fn may_enable_replicaof(
         &self,
         db: Db,
@@ -157,108 +268,50 @@ impl Conf {
         }
     }
 
-    pub fn may_enable_rdb(&self, db: &mut RwLockWriteGuard<DbInner>) {
+    pub fn may_enable_rdb(&self, dbs: &mut [DbInner]) {
         // AOF持久化优先级高于RDB持久化，当AOF持久化开启时，不加载RDB文件
         if !self.rdb.enable || self.aof.enable {
             return;
         }
 
-        match util::rdb_load(db) {
+        // 与Redis一致，RDB文件损坏时退出。以空的数据库继续运行的话，之后的保存会覆盖可能可以修复的RDB文件
+        util::set_loading(true);
+        let res = util::rdb_load(dbs);
+        util::set_loading(false);
+        match res {
             Ok(_) => {
                 tracing::info!("RDB file loaded successfully!!!");
             }
             Err(e) => {
-                tracing::error!("Failed to load RDB file: {:?}", e);
+                tracing::error!("Failed to load RDB file: {e}. Exiting now.");
+                std::process::exit(1);
             }
         }
     }
 
-    pub async fn may_enable_aof(
-        &self,
-        write_cmd_sender: Sender<Frame>,
-        finished_notify: Arc<tokio::sync::Notify>,
-    ) {
+    pub async fn may_enable_aof(&self, db: &Db) {
         if !self.aof.enable {
             return;
         }
 
-        let aof = util::Aof::new(write_cmd_sender.subscribe()).await.unwrap();
-        // 载入AOF文件
-        std::thread::spawn(move || {
-            if let Err(e) = aof.load() {
+        // 在接受客户端连接之前载入AOF文件。AOF文件损坏时退出，避免之后追加的命令使文件无法修复
+        util::set_loading(true);
+        let res = util::aof_load(db).await;
+        util::set_loading(false);
+        match res {
+            Ok(_) => tracing::info!("AOF file loaded successfully!!!"),
+            Err(e) => {
                 tracing::error!("Failed to load AOF file: {:?}", e);
-            } else {
-                tracing::info!("AOF file loaded successfully!!!");
+                std::process::exit(1);
             }
-        });
+        }
+        // 载入之后追加的第一条写命令之前需要先写入SELECT
+        crate::cmd::reset_propagated_db();
 
-        let mut aof = util::Aof::new(write_cmd_sender.subscribe()).await.unwrap();
-        match self.aof.append_fsync {
-            AppendFSync::Always => {
-                // receive命令, append入buffer，
-                tokio::spawn(async move {
-                    loop {
-                        select! {
-                            // 每次事件循环（即当一条连接结束时），将buffer中的内容写入AOF文件并同步
-                            _ = finished_notify.notified() => {
-                                if let Err(e) = aof.write_in().await {
-                                    tracing::error!("Failed to write AOF file: {:?}", e);
-                                }
-                                if let Err(e) = aof.fsync().await {
-                                    tracing::error!("Failed to fsync AOF file: {:?}", e);
-                                }
-                            }
-                            cmd = aof.write_cmd_receiver.recv() => {
-                                if let Ok(cmd) = cmd {
-                                    aof.append(&format!("{}\n", cmd));
-                                }
-                            }
-                        }
-                    }
-                });
-            }
-            AppendFSync::EverySec => {
-                tokio::spawn(async move {
-                    loop {
-                        select! {
-                            // 每次事件循环，将buffer中的内容写入AOF文件
-                            _ = finished_notify.notified() => {
-                                if let Err(e) = aof.write_in().await {
-                                    tracing::error!("Failed to write AOF file: {:?}", e);
-                                }
-                            }
-                            // 每过一秒，同步AOF文件
-                            _ = tokio::time::sleep(tokio::time::Duration::from_secs(1)) => {
-                                if let Err(e) = aof.fsync().await {
-                                    tracing::error!("Failed to fsync AOF file: {:?}", e);
-                                }
-                            }
-                            cmd = aof.write_cmd_receiver.recv() => {
-                                if let Ok(cmd) = cmd {
-                                    aof.append(&format!("{}\n", cmd));
-                                }
-                            }
-                        }
-                    }
-                });
-            }
-            AppendFSync::No => {
-                tokio::spawn(async move {
-                    select! {
-                        // 每次事件循环，将buffer中的内容写入AOF文件
-                        _ = finished_notify.notified() => {
-                            if let Err(e) = aof.write_in().await {
-                                tracing::error!("Failed to write AOF file: {:?}", e);
-                            }
-                        }
-                        cmd = aof.write_cmd_receiver.recv() => {
-                            if let Ok(cmd) = cmd {
-                                aof.append(&format!("{}\n", cmd));
-                            }
-                        }
-                    }
-                });
-            }
+        // 载入完成后才开始追加写命令
+        if let Err(e) = util::aof_start() {
+            tracing::error!("Failed to open AOF file: {:?}", e);
+            std::process::exit(1);
         }
     }
 }
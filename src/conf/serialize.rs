@@ -1,6 +1,6 @@
 use super::ReplicationConf;
+use crate::util::keyspace_events_from_str;
 
-use crossbeam::atomic::AtomicCell;
 use crossbeam::sync::ShardedLock;
 use serde::ser::SerializeStruct;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -14,12 +14,7 @@ impl Serialize for ReplicationConf {
     {
         let mut state = serializer.serialize_struct("ReplicationConf", 4)?;
         let replicaof = if let Some(replicaof) = &self.replicaof {
-            Some(
-                replicaof
-                    .read()
-                    .map_err(|e| serde::ser::Error::custom(e))?
-                    .clone(),
-            )
+            Some(replicaof.read().map_err(serde::ser::Error::custom)?.clone())
         } else {
             None
         };
@@ -32,72 +27,163 @@ impl Serialize for ReplicationConf {
 }
 
 // 实现Deserialize
+const FIELDS: &[&str] = &["replicaof", "replid", "max_replicate", "masterauth"];
+
 struct ReplicationConfVisitor;
 
+impl<'de> serde::de::Visitor<'de> for ReplicationConfVisitor {
+    type Value = ReplicationConf;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("struct ReplicationConf")
+    }
+
+    fn visit_map<V>(self, mut map: V) -> Result<ReplicationConf, V::Error>
+    where
+        V: serde::de::MapAccess<'de>,
+    {
+        let mut replicaof = None;
+        let mut replid = None;
+        let mut max_replicate = None;
+        let mut masterauth = None;
+        // 配置源中的键是拥有所有权的字符串，不能以&str的形式借用
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "replicaof" => {
+                    if replicaof.is_some() {
+                        return Err(serde::de::Error::duplicate_field("replicaof"));
+                    }
+                    replicaof = map.next_value::<Option<String>>()?.map(ShardedLock::new);
+                }
+                "replid" => {
+                    if replid.is_some() {
+                        return Err(serde::de::Error::duplicate_field("replid"));
+                    }
+                    replid = Some(map.next_value()?);
+                }
+                "max_replicate" => {
+                    if max_replicate.is_some() {
+                        return Err(serde::de::Error::duplicate_field("max_replicate"));
+                    }
+                    max_replicate = Some(map.next_value()?);
+                }
+                "masterauth" => {
+                    if masterauth.is_some() {
+                        return Err(serde::de::Error::duplicate_field("masterauth"));
+                    }
+                    masterauth = Some(map.next_value()?);
+                }
+                _ => {
+                    return Err(serde::de::Error::unknown_field(&key, FIELDS));
+                }
+            }
+        }
+        Ok(ReplicationConf {
+            replicaof,
+            replid: replid.ok_or_else(|| serde::de::Error::missing_field("replid"))?,
+            max_replicate: max_replicate
+                .ok_or_else(|| serde::de::Error::missing_field("max_replicate"))?,
+            masterauth,
+        })
+    }
+}
+
 impl<'de> Deserialize<'de> for ReplicationConf {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        const FIELDS: &'static [&'static str] =
-            &["replicaof", "replid", "max_replicate", "masterauth"];
+        deserializer.deserialize_struct("ReplicationConf", FIELDS, ReplicationConfVisitor)
+    }
+}
 
-        impl<'de> serde::de::Visitor<'de> for ReplicationConfVisitor {
-            type Value = ReplicationConf;
+/// 将配置中的字符串（如"KEA"）解析为键空间通知的标志位
+pub fn deserialize_keyspace_events<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let classes = String::deserialize(deserializer)?;
+    keyspace_events_from_str(&classes).map_err(serde::de::Error::custom)
+}
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("struct ReplicationConf")
-            }
+/// 自动保存的规则，如"3600 1 300 100"表示3600秒内至少有1次修改，或者300秒内至少有100次修改时保存。
+/// 为空则关闭自动保存
+pub fn deserialize_save_params<'de, D>(deserializer: D) -> Result<Vec<(u64, u64)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    parse_save_params(&text)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid save parameters: {}", text)))
+}
 
-            fn visit_map<V>(self, mut map: V) -> Result<ReplicationConf, V::Error>
-            where
-                V: serde::de::MapAccess<'de>,
-            {
-                let mut replicaof = None;
-                let mut replid = None;
-                let mut max_replicate = None;
-                let mut masterauth = None;
-                while let Some(key) = map.next_key()? {
-                    match key {
-                        "replicaof" => {
-                            if replicaof.is_some() {
-                                return Err(serde::de::Error::duplicate_field("replicaof"));
-                            }
-                            replicaof = map.next_value::<Option<String>>()?.map(ShardedLock::new);
-                        }
-                        "replid" => {
-                            if replid.is_some() {
-                                return Err(serde::de::Error::duplicate_field("replid"));
-                            }
-                            replid = Some(map.next_value()?);
-                        }
-                        "max_replicate" => {
-                            if max_replicate.is_some() {
-                                return Err(serde::de::Error::duplicate_field("max_replicate"));
-                            }
-                            max_replicate = Some(map.next_value()?);
-                        }
-                        "masterauth" => {
-                            if masterauth.is_some() {
-                                return Err(serde::de::Error::duplicate_field("masterauth"));
-                            }
-                            masterauth = Some(map.next_value()?);
-                        }
-                        _ => {
-                            return Err(serde::de::Error::unknown_field(key, FIELDS));
-                        }
-                    }
-                }
-                Ok(ReplicationConf {
-                    replicaof,
-                    replid: replid.ok_or_else(|| serde::de::Error::missing_field("replid"))?,
-                    max_replicate: max_replicate
-                        .ok_or_else(|| serde::de::Error::missing_field("max_replicate"))?,
-                    masterauth,
-                })
-            }
-        }
+fn parse_save_params(text: &str) -> Option<Vec<(u64, u64)>> {
+    let nums = text
+        .split_whitespace()
+        .map(|n| n.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if !nums.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(nums.chunks(2).map(|pair| (pair[0], pair[1])).collect())
+}
 
-        deserializer.deserialize_struct("ReplicationConf", FIELDS, ReplicationConfVisitor)
+/// 内存大小可以是字节数，也可以是带单位的字符串，如"100mb"。
+/// 与Redis一致，k/m/g以1000为倍数，kb/mb/gb以1024为倍数
+pub fn deserialize_memory<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Memory {
+        Bytes(u64),
+        Text(String),
     }
+
+    match Memory::deserialize(deserializer)? {
+        Memory::Bytes(bytes) => Ok(bytes),
+        Memory::Text(text) => parse_memory(&text)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid memory size: {}", text))),
+    }
+}
+
+fn parse_memory(text: &str) -> Option<u64> {
+    let text = text.trim().to_ascii_lowercase();
+    let digits = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (num, unit) = text.split_at(digits);
+    let unit = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    num.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+#[test]
+fn test_parse_save_params() {
+    assert_eq!(parse_save_params(""), Some(vec![]));
+    assert_eq!(
+        parse_save_params("3600 1 300 100 60 10000"),
+        Some(vec![(3600, 1), (300, 100), (60, 10000)])
+    );
+    assert_eq!(parse_save_params("3600"), None);
+    assert_eq!(parse_save_params("3600 -1"), None);
+}
+
+#[test]
+fn test_parse_memory() {
+    assert_eq!(parse_memory("0"), Some(0));
+    assert_eq!(parse_memory("100"), Some(100));
+    assert_eq!(parse_memory("1k"), Some(1000));
+    assert_eq!(parse_memory("1KB"), Some(1024));
+    assert_eq!(parse_memory("100mb"), Some(100 * 1024 * 1024));
+    assert_eq!(parse_memory("2g"), Some(2_000_000_000));
+    assert_eq!(parse_memory("mb"), None);
+    assert_eq!(parse_memory("10tb"), None);
 }
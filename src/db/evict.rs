@@ -0,0 +1,318 @@
+//! 内存淘汰。与Redis一致，使用近似的LRU/LFU：每次从各个数据库中随机抽样少量的键，
+//! 按照淘汰策略计算得分后放入淘汰池，淘汰池保留历次抽样中得分最高的键，
+//! 每次淘汰池中得分最高的键。随机策略则依次从各个数据库中随机淘汰一个键
+
+use super::{free_value, Db, DbInner, RedisObject, Shard};
+use crate::{
+    conf::{MaxmemoryPolicy, CONFIG},
+    util::{notify_keyspace_event, NOTIFY_EVICTED},
+};
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use rand::Rng;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::UNIX_EPOCH,
+};
+
+pub const OOM: &str = "OOM command not allowed when used memory > 'maxmemory'.";
+
+// 淘汰池的大小
+const EVPOOL_SIZE: usize = 16;
+
+static EVICTED_KEYS: AtomicU64 = AtomicU64::new(0);
+
+/// 淘汰池，按得分从小到大保存历次抽样中最适合被淘汰的键
+#[derive(Debug, Default)]
+pub struct EvictionPool {
+    entries: Vec<(u64, usize, Bytes)>, // (得分, 数据库编号, 键)
+    next_db: usize,                    // 随机策略下一次从哪个数据库中淘汰键
+}
+
+impl EvictionPool {
+    fn insert(&mut self, score: u64, index: usize, key: Bytes) {
+        if self.entries.len() == EVPOOL_SIZE && score <= self.entries[0].0 {
+            return;
+        }
+        if self
+            .entries
+            .iter()
+            .any(|(_, i, k)| *i == index && *k == key)
+        {
+            return;
+        }
+        if self.entries.len() == EVPOOL_SIZE {
+            self.entries.remove(0);
+        }
+        let pos = self.entries.partition_point(|(s, _, _)| *s < score);
+        self.entries.insert(pos, (score, index, key));
+    }
+
+    // 取出得分最高的键
+    fn pop(&mut self) -> Option<(usize, Bytes)> {
+        self.entries.pop().map(|(_, index, key)| (index, key))
+    }
+}
+
+/// 因内存不足被淘汰的键的数量
+pub fn evicted_keys() -> u64 {
+    EVICTED_KEYS.load(Ordering::Relaxed)
+}
+
+/// 按照配置的maxmemory和maxmemory_policy淘汰键，直到内存占用不超过maxmemory。
+/// 每淘汰一个键，都会在持有该键所在分片的锁时调用on_evict，用于传播DEL。
+/// 无法释放足够的内存时返回OOM错误。从节点不淘汰键
+pub async fn perform_evictions(
+    db: &Db,
+    on_evict: impl FnMut(usize, &Bytes) -> Result<()>,
+) -> Result<()> {
+    let conf = &CONFIG.memory;
+    // 从节点不主动淘汰键，而是执行主节点传播的DEL，保证与主节点一致
+    if conf.maxmemory == 0 || CONFIG.replication.replicaof.is_some() {
+        return Ok(());
+    }
+    evict(
+        db,
+        conf.maxmemory as usize,
+        conf.maxmemory_policy,
+        conf.maxmemory_samples.max(1),
+        on_evict,
+    )
+    .await
+}
+
+/// 淘汰键直到内存占用不超过maxmemory
+pub async fn evict(
+    db: &Db,
+    maxmemory: usize,
+    policy: MaxmemoryPolicy,
+    samples: usize,
+    mut on_evict: impl FnMut(usize, &Bytes) -> Result<()>,
+) -> Result<()> {
+    while db.used_memory() > maxmemory {
+        if policy == MaxmemoryPolicy::NoEviction {
+            bail!(OOM);
+        }
+        let victim = match policy {
+            MaxmemoryPolicy::AllkeysRandom | MaxmemoryPolicy::VolatileRandom => {
+                random_victim(db, policy).await
+            }
+            _ => pool_victim(db, policy, samples).await,
+        };
+        let Some((index, key)) = victim else {
+            bail!(OOM);
+        };
+
+        // 候选键在抽样之后可能已被删除或修改，加锁后需要重新检查
+        let mut inner = db
+            .lock_dbs(&[index], Some(&[&key]))
+            .await?
+            .pop()
+            .expect("one db is locked");
+        if inner.evict(&key, policy.is_volatile()) {
+            EVICTED_KEYS.fetch_add(1, Ordering::Relaxed);
+            on_evict(index, &key)?;
+        }
+    }
+    Ok(())
+}
+
+// 从每个数据库中抽样，放入淘汰池，然后取出淘汰池中得分最高的键
+async fn pool_victim(db: &Db, policy: MaxmemoryPolicy, samples: usize) -> Option<(usize, Bytes)> {
+    for index in 0..db.len() {
+        let candidates = sample_db(db, index, policy, samples).await;
+        let mut pool = db.pool.lock().expect("Failed to lock eviction pool");
+        for (score, key) in candidates {
+            pool.insert(score, index, key);
+        }
+    }
+    db.pool.lock().expect("Failed to lock eviction pool").pop()
+}
+
+// 从下一个数据库开始，找到第一个有候选键的数据库，从中随机选择一个键
+async fn random_victim(db: &Db, policy: MaxmemoryPolicy) -> Option<(usize, Bytes)> {
+    let start = {
+        let mut pool = db.pool.lock().expect("Failed to lock eviction pool");
+        pool.next_db = (pool.next_db + 1) % db.len();
+        pool.next_db
+    };
+    for i in 0..db.len() {
+        let index = (start + i) % db.len();
+        if let Some((_, key)) = sample_db(db, index, policy, 1).await.pop() {
+            return Some((index, key));
+        }
+    }
+    None
+}
+
+// 从数据库的一个随机分片开始，找到第一个有候选键的分片，从中随机抽样samples次，
+// 返回抽样到的键及其得分
+async fn sample_db(
+    db: &Db,
+    index: usize,
+    policy: MaxmemoryPolicy,
+    samples: usize,
+) -> Vec<(u64, Bytes)> {
+    let start = rand::thread_rng().gen_range(0..db.num_shards());
+    for i in 0..db.num_shards() {
+        let inner = db
+            .read_shard(index, (start + i) % db.num_shards())
+            .await
+            .expect("index is in range");
+        let candidates = sample_shard(&inner.shards[0].1, policy, samples);
+        if !candidates.is_empty() {
+            return candidates;
+        }
+    }
+    vec![]
+}
+
+fn sample_shard(shard: &Shard, policy: MaxmemoryPolicy, samples: usize) -> Vec<(u64, Bytes)> {
+    let mut rng = rand::thread_rng();
+    (0..samples)
+        .filter_map(|_| {
+            if policy.is_volatile() {
+                let key = shard.expires.sample(&mut rng)?;
+                Some((key, shard.get(key)?))
+            } else {
+                shard.sample(&mut rng)
+            }
+        })
+        .map(|(key, obj)| (score(policy, obj), key.clone()))
+        .collect()
+}
+
+// 得分越高越应该被淘汰
+fn score(policy: MaxmemoryPolicy, obj: &RedisObject) -> u64 {
+    match policy {
+        MaxmemoryPolicy::AllkeysLru | MaxmemoryPolicy::VolatileLru => {
+            obj.idle_time().as_millis() as u64
+        }
+        MaxmemoryPolicy::AllkeysLfu | MaxmemoryPolicy::VolatileLfu => {
+            (u8::MAX - obj.lfu_decayed_counter()) as u64
+        }
+        MaxmemoryPolicy::VolatileTtl => {
+            let expire_at = obj
+                .expire_at
+                .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+                .map_or(u64::MAX, |at| at.as_millis() as u64);
+            u64::MAX - expire_at
+        }
+        MaxmemoryPolicy::NoEviction
+        | MaxmemoryPolicy::AllkeysRandom
+        | MaxmemoryPolicy::VolatileRandom => 0,
+    }
+}
+
+impl DbInner<'_> {
+    /// 淘汰键并发布evicted事件。volatile为true时只淘汰设置了过期时间的键。返回键是否被淘汰
+    pub fn evict(&mut self, key: &Bytes, volatile: bool) -> bool {
+        let shard = self.shard_mut(key);
+        if !shard
+            .get(key)
+            .is_some_and(|obj| !volatile || obj.expire_at.is_some())
+        {
+            return false;
+        }
+        if let Some(obj) = shard.remove(key) {
+            free_value(obj.value, CONFIG.lazyfree.lazyfree_lazy_eviction);
+        }
+        notify_keyspace_event(NOTIFY_EVICTED, "evicted", key, self.id);
+        true
+    }
+}
+
+#[cfg(test)]
+mod evict_test {
+    use super::*;
+    use std::time::Duration;
+
+    async fn fill(db: &Db, volatile: usize, persistent: usize) {
+        let mut inner = db.lock(None).await;
+        for i in 0..volatile {
+            let ttl = Some(Duration::from_secs(100 + i as u64));
+            inner.set_string(format!("volatile{}", i).into(), "v".into(), ttl, false);
+        }
+        for i in 0..persistent {
+            inner.set_string(format!("persistent{}", i).into(), "v".into(), None, false);
+        }
+    }
+
+    #[test]
+    fn test_eviction_pool() {
+        let mut pool = EvictionPool::default();
+        for score in 0..EVPOOL_SIZE as u64 * 2 {
+            pool.insert(score, 0, Bytes::from(format!("key{}", score)));
+        }
+        pool.insert(40, 0, "key40".into());
+        pool.insert(40, 0, "key40".into());
+        pool.insert(1, 0, "key1".into());
+        assert_eq!(pool.entries.len(), EVPOOL_SIZE);
+        assert_eq!(pool.pop(), Some((0, "key40".into())));
+        assert_eq!(pool.pop(), Some((0, "key31".into())));
+    }
+
+    #[tokio::test]
+    async fn test_memory_accounting() {
+        let db = Db::with_shards(2, 4);
+        assert_eq!(db.used_memory(), 0);
+        fill(&db, 10, 10).await;
+        let used = db.used_memory();
+        assert!(used > 0);
+
+        // 值变大或变小时，内存占用随之变化
+        let mut inner = db.lock(None).await;
+        let key = Bytes::from("persistent0");
+        inner.set_string(key.clone(), "v".repeat(1000).into(), None, false);
+        assert_eq!(db.used_memory(), used + 999);
+        inner.set_string(key.clone(), "v".into(), None, false);
+        assert_eq!(db.used_memory(), used);
+
+        // 集合被清空后键被删除，内存占用回到原来的值
+        let list = Bytes::from("list");
+        inner
+            .write::<crate::db::List, _>(&list, true, |list| (list.push_back("a".into()), true))
+            .unwrap();
+        assert!(db.used_memory() > used);
+        inner
+            .write::<crate::db::List, _>(&list, false, |list| (list.pop_back(), true))
+            .unwrap();
+        assert_eq!(db.used_memory(), used);
+
+        drop(inner);
+        for mut inner in db.write_all().await {
+            inner.flush();
+        }
+        assert_eq!(db.used_memory(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_evict() {
+        let db = Db::with_shards(1, 4);
+        fill(&db, 50, 50).await;
+        let used = db.used_memory();
+
+        // noeviction时拒绝
+        let res = evict(&db, used / 2, MaxmemoryPolicy::NoEviction, 5, |_, _| Ok(())).await;
+        assert_eq!(res.unwrap_err().to_string(), OOM);
+
+        // volatile策略只淘汰设置了过期时间的键，没有可淘汰的键时返回OOM
+        let mut evicted = Vec::new();
+        let res = evict(&db, used / 4, MaxmemoryPolicy::VolatileTtl, 5, |_, key| {
+            evicted.push(key.clone());
+            Ok(())
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(evicted.len(), 50);
+        assert!(evicted.iter().all(|key| key.starts_with(b"volatile")));
+        assert_eq!(db.lock(None).await.len(), 50);
+
+        // allkeys策略淘汰任意键，直到内存占用不超过上限
+        for policy in [MaxmemoryPolicy::AllkeysLru, MaxmemoryPolicy::AllkeysRandom] {
+            let limit = db.used_memory() / 2;
+            evict(&db, limit, policy, 5, |_, _| Ok(())).await.unwrap();
+            assert!(db.used_memory() <= limit);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! 主动过期。与Redis一致，每秒执行hz次过期周期，每次从设置了过期时间的键中随机抽样，
+//! 删除其中已过期的键。如果抽样中已过期的键的比例较高，则继续抽样，直到比例足够低
+//! 或者用完本次周期的时间预算
+
+use super::{Db, DbInner};
+use crate::conf::CONFIG;
+use bytes::Bytes;
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+// 每轮抽样的键的数量
+const ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP: usize = 20;
+// 每个周期最多占用的CPU时间的百分比
+const ACTIVE_EXPIRE_CYCLE_SLOW_TIME_PERC: u64 = 25;
+// 抽样中已过期的键的比例不超过该百分比时，停止抽样。effort每增加1，该比例降低1%
+const ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE: usize = 25;
+// 每抽样这么多轮检查一次是否用完了时间预算
+const ACTIVE_EXPIRE_CYCLE_CHECK_TIME_LOOPS: usize = 16;
+
+static EXPIRED_KEYS: AtomicU64 = AtomicU64::new(0);
+static EXPIRED_STALE_PERC: AtomicU64 = AtomicU64::new(0); // f64的位表示
+static EXPIRED_TIME_CAP_REACHED_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// 设置了过期时间的键。键同时保存在数组和哈希表中，支持O(1)的插入、删除以及随机抽样
+#[derive(Debug, Clone, Default)]
+pub struct Expires {
+    keys: Vec<Bytes>,
+    positions: HashMap<Bytes, usize>,
+}
+
+impl Expires {
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn insert(&mut self, key: Bytes) {
+        if self.positions.contains_key(&key) {
+            return;
+        }
+        self.positions.insert(key.clone(), self.keys.len());
+        self.keys.push(key);
+    }
+
+    pub fn remove(&mut self, key: &[u8]) {
+        let Some(pos) = self.positions.remove(key) else {
+            return;
+        };
+        self.keys.swap_remove(pos);
+        if let Some(moved) = self.keys.get(pos) {
+            self.positions.insert(moved.clone(), pos);
+        }
+    }
+
+    /// 随机返回一个键
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<&Bytes> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        self.keys.get(rng.gen_range(0..self.keys.len()))
+    }
+}
+
+/// INFO中与过期相关的统计数据
+pub struct ExpireStats {
+    pub expired_keys: u64,
+    pub expired_stale_perc: f64,
+    pub expired_time_cap_reached_count: u64,
+}
+
+pub fn expire_stats() -> ExpireStats {
+    ExpireStats {
+        expired_keys: EXPIRED_KEYS.load(Ordering::Relaxed),
+        expired_stale_perc: f64::from_bits(EXPIRED_STALE_PERC.load(Ordering::Relaxed)) * 100.0,
+        expired_time_cap_reached_count: EXPIRED_TIME_CAP_REACHED_COUNT.load(Ordering::Relaxed),
+    }
+}
+
+/// 记录一个因过期被删除的键，包括访问时被删除的键
+pub(super) fn incr_expired_keys() {
+    EXPIRED_KEYS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 开启一个异步任务，每秒执行hz次主动过期周期
+pub fn spawn_active_expire(db: &Db) {
+    let db = db.clone();
+    let hz = CONFIG.server.hz.clamp(1, 500);
+    let effort = CONFIG.server.active_expire_effort.clamp(1, 10) - 1;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_micros(1_000_000 / hz));
+        let mut cursor = 0;
+        loop {
+            interval.tick().await;
+            cursor = active_expire_cycle(&db, cursor, effort, hz).await;
+        }
+    });
+}
+
+/// 从cursor指向的(数据库, 分片)开始依次检查各个分片，直到检查完所有分片或者用完时间预算。
+/// effort越大，每轮抽样的键越多、可接受的过期键比例越低、时间预算越多。返回下一次开始的位置
+pub async fn active_expire_cycle(db: &Db, cursor: usize, effort: u64, hz: u64) -> usize {
+    let keys_per_loop =
+        ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP + ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP / 4 * effort as usize;
+    let acceptable_stale = ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE - effort as usize;
+    let time_limit = Duration::from_micros(
+        1_000_000 * (ACTIVE_EXPIRE_CYCLE_SLOW_TIME_PERC + 2 * effort) / hz / 100,
+    );
+    let mut cycle = ExpireCycle {
+        keys_per_loop,
+        acceptable_stale,
+        deadline: Instant::now() + time_limit,
+        iterations: 0,
+        sampled: 0,
+        expired: 0,
+    };
+
+    let total = db.len() * db.num_shards();
+    let mut cursor = cursor % total.max(1);
+    for _ in 0..total {
+        let (index, shard) = (cursor / db.num_shards(), cursor % db.num_shards());
+        cursor = (cursor + 1) % total;
+        let mut inner = db
+            .lock_shard(index, shard)
+            .await
+            .expect("index is in range");
+        if inner.active_expire(&mut cycle) {
+            EXPIRED_TIME_CAP_REACHED_COUNT.fetch_add(1, Ordering::Relaxed);
+            break;
+        }
+    }
+
+    // 过期但还未被删除的键的比例，取移动平均值
+    let current_perc = if cycle.sampled > 0 {
+        cycle.expired as f64 / cycle.sampled as f64
+    } else {
+        0.0
+    };
+    let stale_perc = f64::from_bits(EXPIRED_STALE_PERC.load(Ordering::Relaxed));
+    let stale_perc = current_perc * 0.05 + stale_perc * 0.95;
+    EXPIRED_STALE_PERC.store(stale_perc.to_bits(), Ordering::Relaxed);
+    cursor
+}
+
+// 一个主动过期周期的参数和进度。抽样轮数在整个周期中累计，因此即使每个分片都只抽样几轮，
+// 时间预算也会被定期检查
+struct ExpireCycle {
+    keys_per_loop: usize,
+    acceptable_stale: usize,
+    deadline: Instant,
+    iterations: usize,
+    sampled: usize,
+    expired: usize,
+}
+
+impl DbInner<'_> {
+    // 对已锁住的每个分片，随机抽样keys_per_loop个设置了过期时间的键并删除其中已过期的键，
+    // 直到抽样中已过期的键的比例不超过acceptable_stale%。返回是否用完了时间预算
+    fn active_expire(&mut self, cycle: &mut ExpireCycle) -> bool {
+        let mut rng = rand::thread_rng();
+        for pos in 0..self.shards.len() {
+            loop {
+                let num = self.shards[pos].1.expires.len().min(cycle.keys_per_loop);
+                if num == 0 {
+                    break;
+                }
+                let mut expired_in_loop = 0;
+                for _ in 0..num {
+                    let key = self.shards[pos].1.expires.sample(&mut rng).cloned();
+                    if key.is_some_and(|key| self.expire_if_needed(&key)) {
+                        expired_in_loop += 1;
+                    }
+                }
+                cycle.sampled += num;
+                cycle.expired += expired_in_loop;
+
+                cycle.iterations += 1;
+                if cycle
+                    .iterations
+                    .is_multiple_of(ACTIVE_EXPIRE_CYCLE_CHECK_TIME_LOOPS)
+                    && Instant::now() >= cycle.deadline
+                {
+                    return true;
+                }
+                if expired_in_loop * 100 / num <= cycle.acceptable_stale {
+                    break;
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod expire_test {
+    use super::*;
+
+    #[test]
+    fn test_expires_index() {
+        let mut expires = Expires::default();
+        let mut rng = rand::thread_rng();
+        assert!(expires.sample(&mut rng).is_none());
+
+        for i in 0..10 {
+            expires.insert(Bytes::from(format!("key{}", i)));
+        }
+        expires.insert("key0".into());
+        assert_eq!(expires.len(), 10);
+
+        expires.remove(b"key0");
+        expires.remove(b"key5");
+        expires.remove(b"nope");
+        assert_eq!(expires.len(), 8);
+        for _ in 0..100 {
+            let key = expires.sample(&mut rng).unwrap();
+            assert!(key != "key0" && key != "key5");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_active_expire_cycle() {
+        let db = Db::with_shards(2, 4);
+        for index in 0..2 {
+            let mut inner = db.lock_dbs(&[index], None).await.unwrap().pop().unwrap();
+            for i in 0..100 {
+                let ttl = Some(Duration::from_millis(10));
+                inner.set_string(format!("volatile{}", i).into(), "v".into(), ttl, false);
+                inner.set_string(format!("persistent{}", i).into(), "v".into(), None, false);
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let expired_before = expire_stats().expired_keys;
+        // 时间预算足够时，一个周期就可以删除所有已过期的键
+        let cursor = active_expire_cycle(&db, 0, 0, 1).await;
+        assert_eq!(cursor, 0);
+        assert!(expire_stats().expired_keys - expired_before >= 200);
+        for inner in db.write_all().await {
+            assert_eq!(inner.len(), 100);
+            assert!(inner.keys().all(|key| key.starts_with(b"persistent")));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_time_limit_across_shards() {
+        let db = Db::with_shards(1, 32);
+        let mut inner = db.lock(None).await;
+        for i in 0..320 {
+            let ttl = Some(Duration::from_secs(100));
+            inner.set_string(format!("key{}", i).into(), "v".into(), ttl, false);
+        }
+
+        // 每个分片只需要抽样一轮，抽样轮数在整个周期中累计，用完时间预算后仍会停止
+        let mut cycle = ExpireCycle {
+            keys_per_loop: ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP,
+            acceptable_stale: ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE,
+            deadline: Instant::now(),
+            iterations: 0,
+            sampled: 0,
+            expired: 0,
+        };
+        assert!(inner.active_expire(&mut cycle));
+        assert_eq!(cycle.iterations, ACTIVE_EXPIRE_CYCLE_CHECK_TIME_LOOPS);
+    }
+}
@@ -0,0 +1,269 @@
+use super::{Collection, Listpack, ObjValue};
+use crate::{conf::CONFIG, util::ScanIndex};
+use bytes::Bytes;
+use std::{collections::HashMap, mem::size_of};
+
+/// 哈希表，保存字段与值的映射。字段较少且较短时依次将字段和值保存在listpack中，否则使用哈希表
+#[derive(Debug, Clone)]
+pub enum Hash {
+    Listpack(Listpack),
+    // bytes为所有字段和值的字节数之和，index按扫描顺序保存所有字段
+    HashTable {
+        map: HashMap<Bytes, Bytes>,
+        bytes: usize,
+        index: ScanIndex,
+    },
+}
+
+impl Default for Hash {
+    fn default() -> Self {
+        Hash::Listpack(Listpack::default())
+    }
+}
+
+impl Hash {
+    /// 使用RDB文件中的listpack，字段数量超出限制时转换为哈希表
+    pub fn from_listpack(lp: Listpack) -> Self {
+        let mut hash = Hash::Listpack(lp);
+        if hash.len() > CONFIG.advanced.hash_max_listpack_entries {
+            hash.convert_to_hashtable();
+        }
+        hash
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Hash::Listpack(_) => "listpack",
+            Hash::HashTable { .. } => "hashtable",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Hash::Listpack(lp) => lp.len() / 2,
+            Hash::HashTable { map, .. } => map.len(),
+        }
+    }
+
+    /// 估算的内存占用(字节)
+    pub fn mem_usage(&self) -> usize {
+        match self {
+            Hash::Listpack(lp) => lp.bytes_len(),
+            Hash::HashTable { map, bytes, .. } => {
+                map.len() * (3 * size_of::<Bytes>() + size_of::<u64>()) + bytes
+            }
+        }
+    }
+
+    pub fn get(&self, field: &Bytes) -> Option<Bytes> {
+        match self {
+            Hash::Listpack(lp) => {
+                let pos = listpack_find(lp, field)?;
+                lp.get(pos * 2 + 1)
+            }
+            Hash::HashTable { map, .. } => map.get(field).cloned(),
+        }
+    }
+
+    /// 设置字段的值，返回字段是否为新字段
+    pub fn insert(&mut self, field: Bytes, value: Bytes) -> bool {
+        if let Hash::Listpack(lp) = self {
+            let max_value = CONFIG.advanced.hash_max_listpack_value;
+            if field.len() <= max_value && value.len() <= max_value {
+                match listpack_find(lp, &field) {
+                    Some(pos) => {
+                        lp.replace(pos * 2 + 1, &value);
+                        return false;
+                    }
+                    None if lp.len() / 2 < CONFIG.advanced.hash_max_listpack_entries => {
+                        lp.push_back(&field);
+                        lp.push_back(&value);
+                        return true;
+                    }
+                    // 字段数量超出限制，转换为哈希表
+                    None => self.convert_to_hashtable(),
+                }
+            } else {
+                // 字段或值过长，转换为哈希表
+                self.convert_to_hashtable();
+            }
+        }
+
+        match self {
+            Hash::HashTable { map, bytes, index } => {
+                *bytes += value.len();
+                match map.insert(field.clone(), value) {
+                    Some(old) => {
+                        *bytes -= old.len();
+                        false
+                    }
+                    None => {
+                        *bytes += field.len();
+                        index.insert(field);
+                        true
+                    }
+                }
+            }
+            Hash::Listpack(_) => unreachable!("listpack should be converted to hashtable"),
+        }
+    }
+
+    /// 删除字段，返回字段是否存在
+    pub fn remove(&mut self, field: &Bytes) -> bool {
+        match self {
+            Hash::Listpack(lp) => match listpack_find(lp, field) {
+                Some(pos) => {
+                    lp.remove_range(pos * 2, 2);
+                    true
+                }
+                None => false,
+            },
+            Hash::HashTable { map, bytes, index } => match map.remove(field) {
+                Some(value) => {
+                    *bytes -= field.len() + value.len();
+                    index.remove(field);
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    pub fn contains(&self, field: &Bytes) -> bool {
+        match self {
+            Hash::Listpack(lp) => listpack_find(lp, field).is_some(),
+            Hash::HashTable { map, .. } => map.contains_key(field),
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (Bytes, Bytes)> + '_> {
+        match self {
+            Hash::Listpack(lp) => {
+                let mut items = lp.iter();
+                Box::new(std::iter::from_fn(move || {
+                    Some((items.next()?, items.next()?))
+                }))
+            }
+            Hash::HashTable { map, .. } => Box::new(
+                map.iter()
+                    .map(|(field, value)| (field.clone(), value.clone())),
+            ),
+        }
+    }
+
+    /// 从游标开始按扫描顺序返回约count个字段与值，以及下一次扫描的游标，游标为0代表扫描结束。
+    /// listpack中的字段较少，总是一次全部返回
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(Bytes, Bytes)>) {
+        match self {
+            Hash::Listpack(_) => (0, self.iter().collect()),
+            Hash::HashTable { map, index, .. } => {
+                let (next, fields) = index.scan(cursor, count);
+                let pairs = fields
+                    .into_iter()
+                    .map(|field| {
+                        let value = map[&field].clone();
+                        (field, value)
+                    })
+                    .collect();
+                (next.unwrap_or(0), pairs)
+            }
+        }
+    }
+
+    fn convert_to_hashtable(&mut self) {
+        if let Hash::Listpack(_) = self {
+            let map: HashMap<Bytes, Bytes> = self.iter().collect();
+            let bytes = map
+                .iter()
+                .map(|(field, value)| field.len() + value.len())
+                .sum();
+            let mut index = ScanIndex::default();
+            for field in map.keys() {
+                index.insert(field.clone());
+            }
+            *self = Hash::HashTable { map, bytes, index };
+        }
+    }
+}
+
+// 返回字段在listpack中是第几个字段
+fn listpack_find(lp: &Listpack, field: &Bytes) -> Option<usize> {
+    lp.iter().step_by(2).position(|f| f == field)
+}
+
+impl PartialEq for Hash {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(field, value)| other.get(&field) == Some(value))
+    }
+}
+
+impl FromIterator<(Bytes, Bytes)> for Hash {
+    fn from_iter<I: IntoIterator<Item = (Bytes, Bytes)>>(iter: I) -> Self {
+        let mut hash = Hash::default();
+        for (field, value) in iter {
+            hash.insert(field, value);
+        }
+        hash
+    }
+}
+
+impl Collection for Hash {
+    fn new_value() -> ObjValue {
+        ObjValue::Hash(Hash::default())
+    }
+
+    fn from_value(value: &ObjValue) -> Option<&Self> {
+        match value {
+            ObjValue::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut ObjValue) -> Option<&mut Self> {
+        match value {
+            ObjValue::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Hash::Listpack(lp) => lp.is_empty(),
+            Hash::HashTable { map, .. } => map.is_empty(),
+        }
+    }
+}
+
+#[test]
+fn test_hash_encoding() {
+    let mut hash = Hash::default();
+    assert!(hash.insert("f".into(), "v".into()));
+    assert!(!hash.insert("f".into(), "12345".into()));
+    assert!(hash.insert("n".into(), "1".into()));
+    assert_eq!(hash.encoding(), "listpack");
+    assert_eq!(hash.get(&"f".into()), Some("12345".into()));
+    assert!(hash.remove(&"n".into()));
+    assert!(!hash.contains(&"n".into()));
+    assert_eq!(hash.len(), 1);
+
+    // 值过长时转换为哈希表
+    let listpack = hash.clone();
+    let long = Bytes::from("v".repeat(CONFIG.advanced.hash_max_listpack_value + 1));
+    hash.insert("long".into(), long.clone());
+    assert_eq!(hash.encoding(), "hashtable");
+    assert_eq!(hash.get(&"long".into()), Some(long));
+    hash.remove(&"long".into());
+    assert_eq!(hash, listpack);
+
+    // 字段数量超出限制时转换为哈希表
+    let mut hash: Hash = (0..CONFIG.advanced.hash_max_listpack_entries)
+        .map(|i| (Bytes::from(i.to_string()), Bytes::from("v")))
+        .collect();
+    assert_eq!(hash.encoding(), "listpack");
+    hash.insert("new".into(), "v".into());
+    assert_eq!(hash.encoding(), "hashtable");
+    assert_eq!(hash.len(), CONFIG.advanced.hash_max_listpack_entries + 1);
+}
@@ -0,0 +1,465 @@
+use super::{
+    expire::incr_expired_keys, free_value, incr_dirty, shard_index, Collection, DbInner, ObjValue,
+    RedisObject, Shard, WRONGTYPE,
+};
+use crate::{
+    conf::CONFIG,
+    util::{
+        self, notify_keyspace_event, NOTIFY_EXPIRED, NOTIFY_GENERIC, NOTIFY_KEY_MISS, NOTIFY_NEW,
+        NOTIFY_STRING,
+    },
+};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use std::time::{Duration, SystemTime};
+
+impl<'a> DbInner<'a> {
+    /// 创建一个不属于任何Db的空数据库，只有一个分片
+    #[cfg(test)]
+    pub fn new(id: usize) -> Self {
+        Self {
+            id,
+            num_shards: 1,
+            shards: vec![(0, super::ShardRef::Owned(Shard::default()))],
+            expired: Vec::new(),
+        }
+    }
+
+    /// 数据库的编号
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    // 键所在的分片。键所在的分片必须已被锁住
+    pub(super) fn shard(&self, key: &[u8]) -> &Shard {
+        &self.shards[self.position(key)].1
+    }
+
+    pub(super) fn shard_mut(&mut self, key: &[u8]) -> &mut Shard {
+        let pos = self.position(key);
+        &mut self.shards[pos].1
+    }
+
+    fn position(&self, key: &[u8]) -> usize {
+        self.locate(shard_index(key, self.num_shards))
+    }
+
+    fn locate(&self, index: usize) -> usize {
+        self.shards
+            .binary_search_by_key(&index, |(i, _)| *i)
+            .expect("the shard should be locked")
+    }
+
+    /// 从游标开始按扫描顺序返回游标所在分片中约count个键，以及下一次扫描的游标，游标为0代表扫描结束。
+    /// 一次只扫描一个分片，游标所在的分片必须已被锁住。游标超出分片范围时扫描结束
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Bytes>) {
+        let (shard, pos) = util::split_shard_cursor(cursor);
+        if shard >= self.num_shards {
+            return (0, vec![]);
+        }
+        let (next, keys) = self.shards[self.locate(shard)].1.scan(pos, count);
+        let next = match next {
+            Some(pos) => util::shard_cursor(shard, pos),
+            None if shard + 1 < self.num_shards => util::shard_cursor(shard + 1, 0),
+            None => 0,
+        };
+        (next, keys)
+    }
+
+    /// 已锁住的分片中键的数量(包括已过期但还未被删除的键)
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|(_, shard)| shard.len()).sum()
+    }
+
+    /// 已锁住的分片中设置了过期时间的键的数量
+    pub fn expires_len(&self) -> usize {
+        self.shards.iter().map(|(_, shard)| shard.expires.len()).sum()
+    }
+
+    /// 已锁住的分片中键值对估算的内存占用(字节)
+    pub fn used_memory(&self) -> usize {
+        self.shards.iter().map(|(_, shard)| shard.used_memory()).sum()
+    }
+
+    /// 清空已锁住的分片，返回被清空的分片。调用者可以选择在其它线程中释放它们
+    pub fn flush(&mut self) -> Vec<Shard> {
+        incr_dirty(self.len() as u64);
+        self.shards
+            .iter_mut()
+            .map(|(_, shard)| shard.take())
+            .collect()
+    }
+
+    /// 交换两个数据库的数据，数据库的编号保持不变。两个数据库都需要被完整地锁住
+    pub fn swap(&mut self, other: &mut DbInner) {
+        debug_assert_eq!(self.shards.len(), other.shards.len());
+        incr_dirty(1);
+        for ((_, a), (_, b)) in self.shards.iter_mut().zip(other.shards.iter_mut()) {
+            a.swap_data(b);
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Bytes> {
+        self.shards.iter().flat_map(|(_, shard)| shard.keys.keys())
+    }
+
+    /// 直接写入键值对，不检查类型也不发布通知。用于载入RDB文件以及MOVE命令
+    pub fn insert(&mut self, key: Bytes, obj: RedisObject) -> Option<RedisObject> {
+        self.shard_mut(&key).insert(key, obj)
+    }
+
+    /// 直接移除键值对，不发布通知
+    pub fn remove(&mut self, key: &Bytes) -> Option<RedisObject> {
+        self.shard_mut(key).remove(key)
+    }
+
+    /// 查看键值对，不检查是否过期，也不更新访问信息。用于SCAN、OBJECT等内省命令
+    pub fn peek(&self, key: &Bytes) -> Option<&RedisObject> {
+        self.shard(key).get(key)
+    }
+
+    /// 如果键已经过期则删除它并发布expired事件，返回键是否已过期。键所在的分片只持有读锁时
+    /// 不能删除键，键被记录下来，由Db::expire_found在换成写锁之后删除
+    pub fn expire_if_needed(&mut self, key: &Bytes) -> bool {
+        if self.shard(key).get(key).is_some_and(|obj| obj.is_expired()) {
+            if self.shards[self.position(key)].1.is_read() {
+                self.expired.push(key.clone());
+                return true;
+            }
+            if let Some(obj) = self.shard_mut(key).remove(key) {
+                free_value(obj.value, CONFIG.lazyfree.lazyfree_lazy_expire);
+            }
+            incr_expired_keys();
+            notify_keyspace_event(NOTIFY_EXPIRED, "expired", key, self.id);
+            return true;
+        }
+        false
+    }
+
+    /// 键是否存在，已过期的键会被删除
+    pub fn exists(&mut self, key: &Bytes) -> bool {
+        !self.expire_if_needed(key) && self.shard(key).contains_key(key)
+    }
+
+    /// 查找键并更新其访问信息。键不存在时发布keymiss事件
+    pub fn lookup(&mut self, key: &Bytes) -> Option<&RedisObject> {
+        let id = self.id;
+        let obj = if self.expire_if_needed(key) {
+            None
+        } else {
+            self.shard(key).touch(key)
+        };
+        match obj {
+            Some(obj) => Some(obj),
+            None => {
+                notify_keyspace_event(NOTIFY_KEY_MISS, "keymiss", key, id);
+                None
+            }
+        }
+    }
+
+    /// 返回键的版本号，键不存在或已过期时返回None
+    pub fn version(&self, key: &Bytes) -> Option<u64> {
+        self.shard(key)
+            .get(key)
+            .filter(|obj| !obj.is_expired())
+            .map(|obj| obj.version)
+    }
+
+    /// 删除键，返回键是否存在。lazy为true时，释放代价较大的值交给后台线程释放
+    pub fn del(&mut self, key: &Bytes, lazy: bool) -> bool {
+        if self.exists(key) {
+            if let Some(obj) = self.shard_mut(key).remove(key) {
+                free_value(obj.value, lazy);
+            }
+            incr_dirty(1);
+            notify_keyspace_event(NOTIFY_GENERIC, "del", key, self.id);
+            return true;
+        }
+        false
+    }
+
+    /// 返回键的剩余存活时间。键不存在时返回None，键永不过期时返回Some(None)
+    pub fn ttl(&mut self, key: &Bytes) -> Option<Option<Duration>> {
+        if !self.exists(key) {
+            return None;
+        }
+        let expire_at = self.shard(key).get(key).and_then(|obj| obj.expire_at);
+        Some(expire_at.map(|at| at.duration_since(SystemTime::now()).unwrap_or_default()))
+    }
+
+    /// 设置键的过期时间，expire_at为None时移除过期时间。返回键是否存在
+    pub fn set_expire(&mut self, key: &Bytes, expire_at: Option<SystemTime>) -> bool {
+        if !self.exists(key) {
+            return false;
+        }
+        let shard = self.shard_mut(key);
+        shard.set_expire(key, expire_at);
+        shard.get_mut(key).expect("key should exist").bump_version();
+        incr_dirty(1);
+        let event = if expire_at.is_some() {
+            "expire"
+        } else {
+            "persist"
+        };
+        notify_keyspace_event(NOTIFY_GENERIC, event, key, self.id);
+        true
+    }
+
+    /// 获取字符串的值，键不存在时返回None，键不是字符串时返回WRONGTYPE错误
+    pub fn get_string(&mut self, key: &Bytes) -> Result<Option<Bytes>> {
+        match self.lookup(key) {
+            Some(obj) => obj
+                .value
+                .as_bytes()
+                .map(Some)
+                .ok_or_else(|| anyhow!(WRONGTYPE)),
+            None => Ok(None),
+        }
+    }
+
+    /// 设置字符串的值，无论键原来保存的是什么类型。expire为None时，
+    /// keep_ttl决定是否保留原来的过期时间
+    pub fn set_string(
+        &mut self,
+        key: Bytes,
+        value: Bytes,
+        expire: Option<Duration>,
+        keep_ttl: bool,
+    ) {
+        let expire_at = expire.map(|e| SystemTime::now() + e);
+        self.expire_if_needed(&key);
+        let id = self.id;
+        let shard = self.shard_mut(&key);
+        match shard.get_mut(&key) {
+            Some(obj) => {
+                let before = obj.mem_usage();
+                let old = std::mem::replace(&mut obj.value, ObjValue::from_bytes(value));
+                obj.touch();
+                obj.bump_version();
+                let after = obj.mem_usage();
+                shard.resize(before, after);
+                if expire_at.is_some() || !keep_ttl {
+                    shard.set_expire(&key, expire_at);
+                }
+                free_value(old, CONFIG.lazyfree.lazyfree_lazy_server_del);
+            }
+            None => {
+                shard.insert(
+                    key.clone(),
+                    RedisObject::new(ObjValue::from_bytes(value), expire_at),
+                );
+                notify_keyspace_event(NOTIFY_NEW, "new", &key, id);
+            }
+        }
+
+        incr_dirty(1);
+        notify_keyspace_event(NOTIFY_STRING, "set", &key, self.id);
+        if expire_at.is_some() {
+            notify_keyspace_event(NOTIFY_GENERIC, "expire", &key, self.id);
+        }
+    }
+
+    /// 读取集合类型的值。键不存在时返回None，键的类型不是T时返回WRONGTYPE错误
+    pub fn read<T: Collection>(&mut self, key: &Bytes) -> Result<Option<&T>> {
+        match self.lookup(key) {
+            Some(obj) => T::from_value(&obj.value)
+                .map(Some)
+                .ok_or_else(|| anyhow!(WRONGTYPE)),
+            None => Ok(None),
+        }
+    }
+
+    /// 修改集合类型的值。f返回(结果, 是否修改了集合)，只有确实修改了集合时才会计入修改次数、
+    /// 使WATCH失效以及发送键空间通知。键不存在时，create为true则在一个空集合上调用f，修改后集合不为空
+    /// 才会创建键，否则返回None；键的类型不是T时返回WRONGTYPE错误。修改后集合为空时，键会被删除
+    pub fn write<T: Collection, R>(
+        &mut self,
+        key: &Bytes,
+        create: bool,
+        f: impl FnOnce(&mut T) -> (R, bool),
+    ) -> Result<Option<R>> {
+        self.expire_if_needed(key);
+        let id = self.id;
+        let shard = self.shard_mut(key);
+        if !shard.contains_key(key) {
+            if !create {
+                return Ok(None);
+            }
+            let mut obj = RedisObject::new(T::new_value(), None);
+            let value = T::from_value_mut(&mut obj.value).expect("new value should have type T");
+            let (res, modified) = f(value);
+            if modified && !value.is_empty() {
+                incr_dirty(1);
+                shard.insert(key.clone(), obj);
+                notify_keyspace_event(NOTIFY_NEW, "new", key, id);
+            }
+            return Ok(Some(res));
+        }
+
+        let obj = shard.get_mut(key).expect("key should exist");
+        let before = obj.mem_usage();
+        let value = T::from_value_mut(&mut obj.value).ok_or_else(|| anyhow!(WRONGTYPE))?;
+        let (res, modified) = f(value);
+        if !modified {
+            obj.touch();
+            return Ok(Some(res));
+        }
+        incr_dirty(1);
+        let is_empty = value.is_empty();
+        let after = obj.mem_usage();
+        shard.resize(before, after);
+        if is_empty {
+            shard.remove(key);
+            notify_keyspace_event(NOTIFY_GENERIC, "del", key, id);
+        } else {
+            let obj = shard.get_mut(key).expect("key should exist");
+            obj.touch();
+            obj.bump_version();
+        }
+        Ok(Some(res))
+    }
+}
+
+#[cfg(test)]
+mod keyspace_test {
+    use super::*;
+    use crate::db::{List, ObjType};
+
+    #[test]
+    fn test_get_and_set_and_del() {
+        let mut db = DbInner::new(0);
+        let key = |k: &'static str| Bytes::from(k);
+
+        db.set_string(key("key1"), "value1".into(), None, false);
+        db.set_string(key("key2"), "value2".into(), None, false);
+        db.set_string(
+            key("key3"),
+            "value3".into(),
+            Some(Duration::from_secs(1)),
+            false,
+        );
+
+        // 测试get，获取值
+        assert_eq!(Some("value1".into()), db.get_string(&key("key1")).unwrap());
+        assert_eq!(Some("value2".into()), db.get_string(&key("key2")).unwrap());
+        assert_eq!(Some("value3".into()), db.get_string(&key("key3")).unwrap());
+
+        // 测试set，修改值
+        db.set_string(key("key1"), "value11".into(), None, false);
+        db.set_string(key("key2"), "value22".into(), None, false);
+        assert_eq!(Some("value11".into()), db.get_string(&key("key1")).unwrap());
+        assert_eq!(Some("value22".into()), db.get_string(&key("key2")).unwrap());
+
+        // 测试del，删除值。修改次数是全局的，其它测试也会增加修改次数
+        let dirty = crate::db::dirty();
+        db.del(&key("key1"), false);
+        db.del(&key("key2"), false);
+        assert!(crate::db::dirty() >= dirty + 2);
+        assert_eq!(None, db.get_string(&key("key1")).unwrap());
+        assert_eq!(None, db.get_string(&key("key2")).unwrap());
+
+        // 测试get，等待key3过期
+        std::thread::sleep(Duration::from_secs(1)); // waiting for key3 expire
+        assert_eq!(None, db.get_string(&key("key3")).unwrap());
+    }
+
+    #[test]
+    fn test_check_exist() {
+        let mut db = DbInner::new(0);
+
+        db.set_string("key1".into(), "value1".into(), None, false);
+        db.set_string("key2".into(), "value2".into(), None, false);
+        db.set_string(
+            "key3".into(),
+            "value3".into(),
+            Some(Duration::from_secs(1)),
+            false,
+        );
+
+        assert!(db.exists(&"key1".into()));
+        assert!(db.exists(&"key2".into()));
+        std::thread::sleep(Duration::from_secs(1)); // waiting for key3 expire
+        assert!(!db.exists(&"key3".into()));
+    }
+
+    #[test]
+    fn test_get_ttl() {
+        let mut db = DbInner::new(0);
+
+        db.set_string("key1".into(), "value1".into(), None, false);
+        db.set_string(
+            "key2".into(),
+            "value2".into(),
+            Some(Duration::from_secs(1)),
+            false,
+        );
+
+        assert_eq!(Some(None), db.ttl(&"key1".into()));
+        let ttl = db.ttl(&"key2".into()).flatten().expect("should be Some");
+        assert!(Duration::from_secs(1) - ttl < Duration::from_millis(10));
+
+        // KEEPTTL保留原来的过期时间，否则移除过期时间
+        db.set_string("key2".into(), "value3".into(), None, true);
+        assert!(db.ttl(&"key2".into()).flatten().is_some());
+        db.set_string("key2".into(), "value4".into(), None, false);
+        assert_eq!(Some(None), db.ttl(&"key2".into()));
+    }
+
+    #[test]
+    fn test_version() {
+        let mut db = DbInner::new(0);
+        let key: Bytes = "key1".into();
+
+        assert_eq!(None, db.version(&key));
+        db.set_string(key.clone(), "value1".into(), None, false);
+        let v1 = db.version(&key).expect("should be Some");
+        db.get_string(&key).unwrap();
+        assert_eq!(Some(v1), db.version(&key));
+
+        db.set_string(key.clone(), "value2".into(), None, false);
+        assert!(db.version(&key).expect("should be Some") > v1);
+        db.del(&key, false);
+        assert_eq!(None, db.version(&key));
+    }
+
+    #[test]
+    fn test_one_key_one_type() {
+        let mut db = DbInner::new(0);
+        let key: Bytes = "key".into();
+
+        db.write::<List, _>(&key, true, |list| (list.push_back("a".into()), true))
+            .unwrap();
+        assert_eq!(db.peek(&key).unwrap().value.obj_type(), ObjType::List);
+        assert!(db.get_string(&key).is_err());
+
+        // 列表被清空后键被删除
+        db.write::<List, _>(&key, false, |list| (list.pop_front(), true))
+            .unwrap();
+        assert!(!db.exists(&key));
+        assert_eq!(
+            None,
+            db.write::<List, _>(&key, false, |list| (list.len(), false))
+                .unwrap()
+        );
+
+        // 没有修改时不会创建键，也不会改变版本号
+        db.write::<List, _>(&key, true, |list| (list.len(), false))
+            .unwrap();
+        assert!(!db.exists(&key));
+        db.write::<List, _>(&key, true, |list| (list.push_back("a".into()), true))
+            .unwrap();
+        let version = db.version(&key);
+        db.write::<List, _>(&key, false, |list| (list.len(), false))
+            .unwrap();
+        assert_eq!(db.version(&key), version);
+        db.del(&key, false);
+
+        // SET会覆盖任何类型的值
+        db.write::<List, _>(&key, true, |list| (list.push_back("a".into()), true))
+            .unwrap();
+        db.set_string(key.clone(), "1".into(), None, false);
+        assert_eq!(db.peek(&key).unwrap().value.encoding(), "int");
+        assert!(db.read::<List>(&key).is_err());
+    }
+}
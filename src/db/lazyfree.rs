@@ -0,0 +1,91 @@
+//! 惰性释放。释放包含大量元素的值可能很耗时，为了避免在持有分片锁时阻塞其它命令，
+//! 将这些值交给后台线程释放。释放的代价较小的值仍然直接释放，因为交给后台线程的开销更大
+
+use super::{Hash, List, ObjValue, Set, ZSet};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    mpsc::{self, Sender},
+    Mutex,
+};
+
+// 释放的代价超过该值的值才会交给后台线程释放
+const LAZYFREE_THRESHOLD: usize = 64;
+
+static PENDING_OBJECTS: AtomicUsize = AtomicUsize::new(0);
+
+type Job = (Box<dyn Send>, usize); // (待释放的数据, 其中的对象数量)
+
+// 首次使用时启动后台线程，后台线程依次释放收到的数据
+static FREER: once_cell::sync::Lazy<Mutex<Sender<Job>>> = once_cell::sync::Lazy::new(|| {
+    let (tx, rx) = mpsc::channel::<Job>();
+    std::thread::Builder::new()
+        .name("lazyfree".to_string())
+        .spawn(move || {
+            for (data, objects) in rx {
+                drop(data);
+                PENDING_OBJECTS.fetch_sub(objects, Ordering::Relaxed);
+            }
+        })
+        .expect("Failed to spawn lazyfree thread");
+    Mutex::new(tx)
+});
+
+/// 等待后台线程释放的对象数量
+pub fn lazyfree_pending_objects() -> usize {
+    PENDING_OBJECTS.load(Ordering::Relaxed)
+}
+
+/// 释放值的代价，近似为需要释放的内存块的数量。紧凑编码的值只需要释放一块内存
+pub fn free_effort(value: &ObjValue) -> usize {
+    match value {
+        ObjValue::List(List::Quicklist(ql)) => ql.node_count(),
+        ObjValue::Hash(Hash::HashTable { map, .. }) => map.len(),
+        ObjValue::Set(Set::HashTable { members, .. }) => members.len(),
+        ObjValue::ZSet(zset @ ZSet::SkipList(_)) => zset.len(),
+        _ => 1,
+    }
+}
+
+/// 释放被删除的值。lazy为true且释放的代价较大时交给后台线程释放
+pub fn free_value(value: ObjValue, lazy: bool) {
+    if lazy && free_effort(&value) > LAZYFREE_THRESHOLD {
+        free_async(value, 1);
+    } else {
+        drop(value);
+    }
+}
+
+/// 交给后台线程释放，objects为其中的对象数量，计入lazyfree_pending_objects
+pub fn free_async<T: Send + 'static>(data: T, objects: usize) {
+    PENDING_OBJECTS.fetch_add(objects, Ordering::Relaxed);
+    let sender = FREER.lock().expect("Failed to lock lazyfree sender");
+    if let Err(mpsc::SendError((data, objects))) = sender.send((Box::new(data), objects)) {
+        // 后台线程不存在时直接释放
+        drop(data);
+        PENDING_OBJECTS.fetch_sub(objects, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod lazyfree_test {
+    use super::*;
+    use bytes::Bytes;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_lazyfree() {
+        let small: Set = (0..10).map(|i| Bytes::from(i.to_string())).collect();
+        let big: Set = (0..1000).map(|i| Bytes::from(format!("m{}", i))).collect();
+        assert_eq!(free_effort(&ObjValue::Set(small)), 1);
+        assert_eq!(free_effort(&ObjValue::Set(big.clone())), 1000);
+
+        free_value(ObjValue::Set(big), true);
+        free_async(vec![0_u8; 1024], 10);
+        // 后台线程释放之后，等待释放的对象数量回到0
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while lazyfree_pending_objects() > 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(lazyfree_pending_objects(), 0);
+    }
+}
@@ -0,0 +1,190 @@
+use super::{
+    listpack_fits, listpack_size_limit, normalize_range, Collection, Listpack, ObjValue, Quicklist,
+};
+use crate::conf::CONFIG;
+use bytes::Bytes;
+
+/// 列表。元素较少且较短时使用listpack保存，否则使用由多个listpack节点组成的quicklist
+#[derive(Debug, Clone)]
+pub enum List {
+    Listpack(Listpack),
+    Quicklist(Quicklist),
+}
+
+impl Default for List {
+    fn default() -> Self {
+        List::Listpack(Listpack::default())
+    }
+}
+
+impl List {
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            List::Listpack(_) => "listpack",
+            List::Quicklist(_) => "quicklist",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            List::Listpack(lp) => lp.len(),
+            List::Quicklist(ql) => ql.len(),
+        }
+    }
+
+    /// 估算的内存占用(字节)
+    pub fn mem_usage(&self) -> usize {
+        match self {
+            List::Listpack(lp) => lp.bytes_len(),
+            List::Quicklist(ql) => ql.mem_usage(),
+        }
+    }
+
+    pub fn push_front(&mut self, value: Bytes) {
+        self.convert_if_needed(&value);
+        match self {
+            List::Listpack(lp) => lp.push_front(&value),
+            List::Quicklist(ql) => ql.push_front(&value),
+        }
+    }
+
+    pub fn push_back(&mut self, value: Bytes) {
+        self.convert_if_needed(&value);
+        match self {
+            List::Listpack(lp) => lp.push_back(&value),
+            List::Quicklist(ql) => ql.push_back(&value),
+        }
+    }
+
+    pub fn pop_front(&mut self) -> Option<Bytes> {
+        let value = match self {
+            List::Listpack(lp) => lp.pop_front(),
+            List::Quicklist(ql) => ql.pop_front(),
+        };
+        self.try_convert_to_listpack();
+        value
+    }
+
+    pub fn pop_back(&mut self) -> Option<Bytes> {
+        let value = match self {
+            List::Listpack(lp) => lp.pop_back(),
+            List::Quicklist(ql) => ql.pop_back(),
+        };
+        self.try_convert_to_listpack();
+        value
+    }
+
+    /// 返回下标对应的元素，负数下标从列表尾部开始计算
+    pub fn get(&self, index: i64) -> Option<Bytes> {
+        let index = if index < 0 {
+            self.len().checked_sub(index.unsigned_abs() as usize)?
+        } else {
+            index as usize
+        };
+        match self {
+            List::Listpack(lp) => lp.get(index),
+            List::Quicklist(ql) => ql.get(index),
+        }
+    }
+
+    /// 返回[start, stop]之间的元素，负数下标从列表尾部开始计算
+    pub fn range(&self, start: i64, stop: i64) -> Vec<Bytes> {
+        let Some((start, stop)) = normalize_range(start, stop, self.len()) else {
+            return vec![];
+        };
+        match self {
+            List::Listpack(lp) => lp.iter().skip(start).take(stop - start + 1).collect(),
+            List::Quicklist(ql) => ql.iter_from(start).take(stop - start + 1).collect(),
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        match self {
+            List::Listpack(lp) => Box::new(lp.iter()),
+            List::Quicklist(ql) => Box::new(ql.iter_from(0)),
+        }
+    }
+
+    // 插入value后listpack超出list_max_listpack_size时，转换为quicklist
+    fn convert_if_needed(&mut self, value: &[u8]) {
+        if let List::Listpack(lp) = self {
+            if !listpack_fits(lp, value) {
+                *self = List::Quicklist(Quicklist::from_listpack(std::mem::take(lp)));
+            }
+        }
+    }
+
+    // quicklist只剩一个节点，且不超过限制的一半时转换回listpack，避免在临界点附近反复转换
+    fn try_convert_to_listpack(&mut self) {
+        if let List::Quicklist(ql) = self {
+            let Some(lp) = ql.single_listpack() else {
+                return;
+            };
+            let size = CONFIG.advanced.list_max_listpack_size;
+            if lp.bytes_len() <= listpack_size_limit() / 2 && (size <= 0 || lp.len() <= size as usize / 2)
+            {
+                *self = List::Listpack(lp.clone());
+            }
+        }
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl FromIterator<Bytes> for List {
+    fn from_iter<I: IntoIterator<Item = Bytes>>(iter: I) -> Self {
+        let mut list = List::default();
+        for item in iter {
+            list.push_back(item);
+        }
+        list
+    }
+}
+
+impl Collection for List {
+    fn new_value() -> ObjValue {
+        ObjValue::List(List::default())
+    }
+
+    fn from_value(value: &ObjValue) -> Option<&Self> {
+        match value {
+            ObjValue::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut ObjValue) -> Option<&mut Self> {
+        match value {
+            ObjValue::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[test]
+fn test_list_encoding() {
+    let mut list: List = ["b", "c"].into_iter().map(Bytes::from).collect();
+    list.push_front("a".into());
+    assert_eq!(list.encoding(), "listpack");
+    assert_eq!(list.get(-1), Some("c".into()));
+    assert_eq!(list.range(0, 1), ["a", "b"]);
+
+    // listpack超出8kb(list_max_listpack_size = -2)时转换为quicklist
+    let listpack = list.clone();
+    list.push_back("x".repeat(8192).into());
+    assert_eq!(list.encoding(), "quicklist");
+    assert_eq!(list.pop_back().map(|v| v.len()), Some(8192));
+    // 只剩一个较小的节点时转换回listpack
+    assert_eq!(list.encoding(), "listpack");
+    assert_eq!(list, listpack);
+    assert_eq!(list.pop_front(), Some("a".into()));
+    assert_eq!(list.len(), 2);
+}
@@ -0,0 +1,468 @@
+//! listpack：将元素依次紧凑地保存在一块连续的内存中，每个元素只有1到5字节的额外开销，
+//! 用于保存元素较少、元素较短的集合。格式与Redis一致，因此可以直接写入RDB文件：
+//!
+//! <总字节数:u32> <元素数量:u16> <元素> ... <元素> <0xFF>
+//!
+//! 每个元素由编码、数据以及backlen组成。backlen为编码和数据的长度，用于从后向前遍历。
+//! 可以被解析为整数的字符串以整数编码保存
+
+use bytes::Bytes;
+
+const HEADER_SIZE: usize = 6;
+const EOF: u8 = 0xFF;
+// 元素数量超出u16时，头部的元素数量为该值，需要遍历才能得到元素数量
+const NUMELE_UNKNOWN: u16 = u16::MAX;
+
+const ENCODING_7BIT_UINT: u8 = 0x00; // 0xxxxxxx
+const ENCODING_6BIT_STR: u8 = 0x80; // 10xxxxxx
+const ENCODING_13BIT_INT: u8 = 0xC0; // 110xxxxx yyyyyyyy
+const ENCODING_12BIT_STR: u8 = 0xE0; // 1110xxxx yyyyyyyy
+const ENCODING_32BIT_STR: u8 = 0xF0;
+const ENCODING_16BIT_INT: u8 = 0xF1;
+const ENCODING_24BIT_INT: u8 = 0xF2;
+const ENCODING_32BIT_INT: u8 = 0xF3;
+const ENCODING_64BIT_INT: u8 = 0xF4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listpack(Vec<u8>);
+
+impl Default for Listpack {
+    fn default() -> Self {
+        let mut buf = Vec::with_capacity(HEADER_SIZE + 1);
+        buf.extend_from_slice(&((HEADER_SIZE + 1) as u32).to_le_bytes());
+        buf.extend_from_slice(&0_u16.to_le_bytes());
+        buf.push(EOF);
+        Self(buf)
+    }
+}
+
+impl Listpack {
+    /// 检查RDB文件中的listpack是否完整，所有元素都不能越界
+    pub fn from_bytes(buf: Vec<u8>) -> Option<Self> {
+        if buf.len() < HEADER_SIZE + 1
+            || u32::from_le_bytes(buf[..4].try_into().ok()?) as usize != buf.len()
+            || buf.last() != Some(&EOF)
+        {
+            return None;
+        }
+        let lp = Self(buf);
+        let (mut pos, mut count) = (HEADER_SIZE, 0);
+        while lp.0[pos] != EOF {
+            let (header, data) = entry_header(&lp.0[pos..])?;
+            let size = header + data;
+            let entry_len = size + backlen_size(size);
+            if pos + entry_len >= lp.0.len() || decode_backlen(&lp.0[..pos + entry_len]) != size {
+                return None;
+            }
+            pos += entry_len;
+            count += 1;
+        }
+        let numele = lp.numele();
+        (numele == NUMELE_UNKNOWN || numele as usize == count).then_some(lp)
+    }
+
+    /// 包括头部和结束符在内的所有字节
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        match self.numele() {
+            NUMELE_UNKNOWN => self.offsets().count(),
+            numele => numele as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0[HEADER_SIZE] == EOF
+    }
+
+    /// 占用的字节数
+    pub fn bytes_len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// 插入value之后listpack占用的字节数
+    pub fn bytes_len_after_insert(&self, value: &[u8]) -> usize {
+        let size = encoded_size(value);
+        self.0.len() + size + backlen_size(size)
+    }
+
+    pub fn get(&self, index: usize) -> Option<Bytes> {
+        self.offsets().nth(index).map(|pos| self.decode(pos))
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Bytes> + '_ {
+        // 元素较少，先收集所有元素的位置，以便支持从后向前遍历
+        self.offsets()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|pos| self.decode(pos))
+    }
+
+    pub fn push_back(&mut self, value: &[u8]) {
+        self.insert_at(self.0.len() - 1, value);
+    }
+
+    pub fn push_front(&mut self, value: &[u8]) {
+        self.insert_at(HEADER_SIZE, value);
+    }
+
+    /// 在下标为index的位置插入元素，index等于元素数量时插入到末尾
+    pub fn insert(&mut self, index: usize, value: &[u8]) {
+        let pos = self.offsets().nth(index).unwrap_or(self.0.len() - 1);
+        self.insert_at(pos, value);
+    }
+
+    pub fn pop_front(&mut self) -> Option<Bytes> {
+        self.remove(0)
+    }
+
+    pub fn pop_back(&mut self) -> Option<Bytes> {
+        if self.is_empty() {
+            return None;
+        }
+        // 通过最后一个元素的backlen找到它的开头
+        let end = self.0.len() - 1;
+        let size = decode_backlen(&self.0[..end]);
+        let pos = end - backlen_size(size) - size;
+        let value = self.decode(pos);
+        self.remove_at(pos, 1);
+        Some(value)
+    }
+
+    /// 删除下标为index的元素
+    pub fn remove(&mut self, index: usize) -> Option<Bytes> {
+        let pos = self.offsets().nth(index)?;
+        let value = self.decode(pos);
+        self.remove_at(pos, 1);
+        Some(value)
+    }
+
+    /// 从下标index开始删除count个元素
+    pub fn remove_range(&mut self, index: usize, count: usize) {
+        let pos = self.offsets().nth(index);
+        if let Some(pos) = pos {
+            self.remove_at(pos, count);
+        }
+    }
+
+    /// 替换下标为index的元素
+    pub fn replace(&mut self, index: usize, value: &[u8]) {
+        let pos = self.offsets().nth(index);
+        if let Some(pos) = pos {
+            self.remove_at(pos, 1);
+            self.insert_at(pos, value);
+        }
+    }
+
+    fn numele(&self) -> u16 {
+        u16::from_le_bytes([self.0[4], self.0[5]])
+    }
+
+    fn set_header(&mut self, numele: usize) {
+        let total = self.0.len() as u32;
+        self.0[..4].copy_from_slice(&total.to_le_bytes());
+        let numele = if numele < NUMELE_UNKNOWN as usize {
+            numele as u16
+        } else {
+            NUMELE_UNKNOWN
+        };
+        self.0[4..6].copy_from_slice(&numele.to_le_bytes());
+    }
+
+    // 依次返回每个元素的开头在listpack中的位置
+    fn offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut pos = HEADER_SIZE;
+        std::iter::from_fn(move || {
+            if self.0[pos] == EOF {
+                return None;
+            }
+            let current = pos;
+            pos += self.entry_len(pos);
+            Some(current)
+        })
+    }
+
+    // 位于pos的元素的总长度，包括编码、数据以及backlen
+    fn entry_len(&self, pos: usize) -> usize {
+        let (header, data) = entry_header(&self.0[pos..]).expect("listpack should be valid");
+        header + data + backlen_size(header + data)
+    }
+
+    fn decode(&self, pos: usize) -> Bytes {
+        let buf = &self.0[pos..];
+        let b = buf[0];
+        let int = if b & 0x80 == ENCODING_7BIT_UINT {
+            b as i64
+        } else if b & 0xC0 == ENCODING_6BIT_STR {
+            let len = (b & 0x3F) as usize;
+            return Bytes::copy_from_slice(&buf[1..1 + len]);
+        } else if b & 0xE0 == ENCODING_13BIT_INT {
+            let uv = (((b & 0x1F) as i64) << 8) | buf[1] as i64;
+            if uv >= 1 << 12 {
+                uv - (1 << 13)
+            } else {
+                uv
+            }
+        } else if b & 0xF0 == ENCODING_12BIT_STR {
+            let len = (((b & 0x0F) as usize) << 8) | buf[1] as usize;
+            return Bytes::copy_from_slice(&buf[2..2 + len]);
+        } else {
+            match b {
+                ENCODING_32BIT_STR => {
+                    let len = u32::from_le_bytes(buf[1..5].try_into().unwrap()) as usize;
+                    return Bytes::copy_from_slice(&buf[5..5 + len]);
+                }
+                ENCODING_16BIT_INT => i16::from_le_bytes([buf[1], buf[2]]) as i64,
+                ENCODING_24BIT_INT => {
+                    let uv = u32::from_le_bytes([buf[1], buf[2], buf[3], 0]) as i64;
+                    if uv >= 1 << 23 {
+                        uv - (1 << 24)
+                    } else {
+                        uv
+                    }
+                }
+                ENCODING_32BIT_INT => i32::from_le_bytes(buf[1..5].try_into().unwrap()) as i64,
+                ENCODING_64BIT_INT => i64::from_le_bytes(buf[1..9].try_into().unwrap()),
+                _ => unreachable!("listpack should be valid"),
+            }
+        };
+        int.to_string().into()
+    }
+
+    fn insert_at(&mut self, pos: usize, value: &[u8]) {
+        let numele = self.len() + 1;
+        let entry = encode_entry(value);
+        self.0.splice(pos..pos, entry);
+        self.set_header(numele);
+    }
+
+    // 从pos开始删除最多count个元素
+    fn remove_at(&mut self, pos: usize, count: usize) {
+        let mut end = pos;
+        let mut removed = 0;
+        while removed < count && self.0[end] != EOF {
+            end += self.entry_len(end);
+            removed += 1;
+        }
+        let numele = self.len() - removed;
+        self.0.drain(pos..end);
+        self.set_header(numele);
+    }
+}
+
+// 返回元素编码部分的长度以及数据部分的长度。buf越界时返回None
+fn entry_header(buf: &[u8]) -> Option<(usize, usize)> {
+    let b = *buf.first()?;
+    let (header, data) = if b & 0x80 == ENCODING_7BIT_UINT {
+        (1, 0)
+    } else if b & 0xC0 == ENCODING_6BIT_STR {
+        (1, (b & 0x3F) as usize)
+    } else if b & 0xE0 == ENCODING_13BIT_INT {
+        (2, 0)
+    } else if b & 0xF0 == ENCODING_12BIT_STR {
+        (2, (((b & 0x0F) as usize) << 8) | *buf.get(1)? as usize)
+    } else {
+        match b {
+            ENCODING_32BIT_STR => (
+                5,
+                u32::from_le_bytes(buf.get(1..5)?.try_into().ok()?) as usize,
+            ),
+            ENCODING_16BIT_INT => (3, 0),
+            ENCODING_24BIT_INT => (4, 0),
+            ENCODING_32BIT_INT => (5, 0),
+            ENCODING_64BIT_INT => (9, 0),
+            _ => return None,
+        }
+    };
+    (buf.len() >= header + data).then_some((header, data))
+}
+
+// 编码一个元素，包括backlen
+fn encode_entry(value: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(value.len() + 10);
+    match string_to_int(value) {
+        Some(v @ 0..=127) => buf.push(v as u8),
+        Some(v @ -4096..=4095) => {
+            let uv = (v as u16) & 0x1FFF;
+            buf.push(ENCODING_13BIT_INT | (uv >> 8) as u8);
+            buf.push(uv as u8);
+        }
+        Some(v) if i16::try_from(v).is_ok() => {
+            buf.push(ENCODING_16BIT_INT);
+            buf.extend_from_slice(&(v as i16).to_le_bytes());
+        }
+        Some(v @ -8388608..=8388607) => {
+            buf.push(ENCODING_24BIT_INT);
+            buf.extend_from_slice(&(v as i32).to_le_bytes()[..3]);
+        }
+        Some(v) if i32::try_from(v).is_ok() => {
+            buf.push(ENCODING_32BIT_INT);
+            buf.extend_from_slice(&(v as i32).to_le_bytes());
+        }
+        Some(v) => {
+            buf.push(ENCODING_64BIT_INT);
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        None => {
+            let len = value.len();
+            if len < 64 {
+                buf.push(ENCODING_6BIT_STR | len as u8);
+            } else if len < 4096 {
+                buf.push(ENCODING_12BIT_STR | (len >> 8) as u8);
+                buf.push(len as u8);
+            } else {
+                buf.push(ENCODING_32BIT_STR);
+                buf.extend_from_slice(&(len as u32).to_le_bytes());
+            }
+            buf.extend_from_slice(value);
+        }
+    }
+    let size = buf.len();
+    encode_backlen(&mut buf, size);
+    buf
+}
+
+// 元素编码和数据部分的长度，不包括backlen
+fn encoded_size(value: &[u8]) -> usize {
+    match string_to_int(value) {
+        Some(0..=127) => 1,
+        Some(-4096..=4095) => 2,
+        Some(v) if i16::try_from(v).is_ok() => 3,
+        Some(-8388608..=8388607) => 4,
+        Some(v) if i32::try_from(v).is_ok() => 5,
+        Some(_) => 9,
+        None if value.len() < 64 => 1 + value.len(),
+        None if value.len() < 4096 => 2 + value.len(),
+        None => 5 + value.len(),
+    }
+}
+
+// 只有与整数的字符串形式完全一致的字符串才以整数编码保存
+fn string_to_int(value: &[u8]) -> Option<i64> {
+    if value.is_empty() || value.len() > 20 {
+        return None;
+    }
+    let i = std::str::from_utf8(value).ok()?.parse::<i64>().ok()?;
+    (i.to_string().as_bytes() == value).then_some(i)
+}
+
+fn backlen_size(size: usize) -> usize {
+    match size {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+// backlen从后向前读取，每个字节的低7位为数据，除了第一个字节以外最高位都为1
+fn encode_backlen(buf: &mut Vec<u8>, size: usize) {
+    let n = backlen_size(size);
+    for i in (0..n).rev() {
+        let byte = ((size >> (7 * i)) & 127) as u8;
+        buf.push(if i == n - 1 { byte } else { byte | 128 });
+    }
+}
+
+// 从buf的末尾读取backlen
+fn decode_backlen(buf: &[u8]) -> usize {
+    let mut size = 0;
+    let mut shift = 0;
+    for &byte in buf.iter().rev() {
+        size |= ((byte & 127) as usize) << shift;
+        if byte & 128 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    size
+}
+
+#[cfg(test)]
+mod listpack_test {
+    use super::*;
+
+    #[test]
+    fn test_listpack_encodings() {
+        let values: Vec<Bytes> = [
+            "0",
+            "127",
+            "128",
+            "-1",
+            "-4096",
+            "4095",
+            "30000",
+            "-30000",
+            "8388607",
+            "-8388608",
+            "2147483647",
+            "-2147483648",
+            "9223372036854775807",
+            "-9223372036854775808",
+            "007",
+            "",
+            "hello",
+        ]
+        .into_iter()
+        .map(Bytes::from)
+        .chain([Bytes::from("a".repeat(100)), Bytes::from("b".repeat(5000))])
+        .collect();
+
+        let mut lp = Listpack::default();
+        for value in &values {
+            lp.push_back(value);
+        }
+        assert_eq!(lp.len(), values.len());
+        assert_eq!(lp.iter().collect::<Vec<_>>(), values);
+        assert_eq!(lp.iter().rev().collect::<Vec<_>>().len(), values.len());
+        // 整数编码比字符串更紧凑
+        assert_eq!(encode_entry(b"127").len(), 2);
+        assert_eq!(encode_entry(b"-4096").len(), 3);
+        assert_eq!(
+            lp.bytes_len(),
+            u32::from_le_bytes(lp.0[..4].try_into().unwrap()) as usize
+        );
+
+        // 通过backlen从后向前弹出
+        let mut reversed = Vec::new();
+        while let Some(value) = lp.pop_back() {
+            reversed.push(value);
+        }
+        reversed.reverse();
+        assert_eq!(reversed, values);
+        assert!(lp.is_empty());
+        assert_eq!(lp, Listpack::default());
+    }
+
+    #[test]
+    fn test_listpack_modify() {
+        let mut lp = Listpack::default();
+        lp.push_back(b"b");
+        lp.push_front(b"a");
+        lp.insert(2, b"d");
+        lp.insert(2, b"c");
+        assert_eq!(lp.iter().collect::<Vec<_>>(), ["a", "b", "c", "d"]);
+
+        lp.replace(1, b"B");
+        assert_eq!(lp.get(1), Some("B".into()));
+        assert_eq!(lp.remove(0), Some("a".into()));
+        assert_eq!(lp.remove(10), None);
+        lp.remove_range(1, 5);
+        assert_eq!(lp.iter().collect::<Vec<_>>(), ["B"]);
+        assert_eq!(lp.pop_front(), Some("B".into()));
+        assert_eq!(lp.pop_front(), None);
+
+        // 序列化后的字节可以被还原
+        lp.push_back(b"x");
+        lp.push_back(b"12345");
+        let restored = Listpack::from_bytes(lp.as_bytes().to_vec()).unwrap();
+        assert_eq!(restored, lp);
+        let mut corrupted = lp.as_bytes().to_vec();
+        corrupted[HEADER_SIZE] = 0x80 | 60;
+        assert!(Listpack::from_bytes(corrupted).is_none());
+        assert!(Listpack::from_bytes(vec![1, 2, 3]).is_none());
+    }
+}
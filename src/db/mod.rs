@@ -1,134 +1,435 @@
-#![allow(dead_code)]
+mod evict;
+mod expire;
+mod hash;
+mod keyspace;
+mod lazyfree;
+mod list;
+mod listpack;
+mod object;
+mod quicklist;
+mod set;
+mod shard;
+mod snapshot;
+mod zset;
 
-// mod list_db;
-mod string_kvs;
+pub use evict::*;
+pub use expire::*;
+pub use hash::*;
+pub use lazyfree::*;
+pub use list::*;
+pub use listpack::*;
+pub use object::*;
+pub use quicklist::*;
+pub use set::*;
+pub use shard::*;
+pub use snapshot::*;
+pub use zset::*;
 
 use bytes::Bytes;
-// use skiplist::SkipList;
-use std::{
-    collections::{HashMap, VecDeque},
-    sync::Arc,
-    time::SystemTime,
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
 };
 
 use tokio::sync::RwLock;
 
-pub const MAX_KVPAIRS_NUMS: u64 = u64::MAX;
+use crate::{conf::CONFIG, util};
 
-#[derive(Debug, Clone)]
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+
+/// 键每次被写入时都会获得一个全局递增的版本号，WATCH通过比较版本号判断键是否被修改过
+pub fn next_version() -> u64 {
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+static DIRTY: AtomicU64 = AtomicU64::new(0);
+
+/// 键空间被修改的总次数，用于计算上次保存RDB文件之后的修改次数
+pub fn dirty() -> u64 {
+    DIRTY.load(Ordering::Relaxed)
+}
+
+pub fn incr_dirty(changes: u64) {
+    DIRTY.fetch_add(changes, Ordering::Relaxed);
+}
+
+/// 所有的逻辑数据库。每个连接持有一个Db的克隆，克隆之间共享数据库，但各自记录所选择的数据库
+#[derive(Debug)]
 pub struct Db {
-    pub inner: Arc<RwLock<DbInner>>,
+    dbs: Arc<Vec<Vec<RwLock<Shard>>>>,
+    num_shards: usize,
+    index: AtomicUsize,            // 当前连接所选择的数据库，默认为0号数据库
+    used_memory: Arc<AtomicUsize>, // 所有数据库中键值对估算的内存占用之和
+    pool: Arc<Mutex<EvictionPool>>,
 }
 
-#[derive(Debug, Clone)]
-pub struct DbInner {
-    pub string_kvs: KvPairs<String>,
+/// 一个逻辑数据库中被锁住的分片。所有类型的键共享同一个键空间，每个键只能保存一种类型的值。
+/// 命令只能访问已被锁住的分片中的键
+#[derive(Debug)]
+pub struct DbInner<'a> {
+    id: usize,
+    num_shards: usize,
+    shards: Vec<(usize, ShardRef<'a>)>, // 按分片编号排序
+    expired: Vec<Bytes>,                // 只持有读锁时发现的已过期的键
 }
 
 impl Db {
     pub fn new() -> Self {
+        Self::with_shards(CONFIG.server.databases, CONFIG.server.shards)
+    }
+
+    #[cfg(test)]
+    pub fn with_databases(databases: usize) -> Self {
+        Self::with_shards(databases, CONFIG.server.shards)
+    }
+
+    pub fn with_shards(databases: usize, num_shards: usize) -> Self {
+        // SCAN的游标中分片编号只占高16位
+        let num_shards = num_shards.clamp(1, util::MAX_SCAN_SHARDS);
+        let used_memory = Arc::new(AtomicUsize::new(0));
         Self {
-            inner: Arc::new(RwLock::new(DbInner {
-                string_kvs: KvPairs::<String>(HashMap::new()),
-            })),
+            dbs: Arc::new(
+                (0..databases)
+                    .map(|_| {
+                        (0..num_shards)
+                            .map(|_| RwLock::new(Shard::with_counter(used_memory.clone())))
+                            .collect()
+                    })
+                    .collect(),
+            ),
+            num_shards,
+            index: AtomicUsize::new(0),
+            used_memory,
+            pool: Arc::default(),
         }
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct String;
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct List;
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct ZSet;
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Hash;
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Set;
-
-#[derive(Debug, Clone)]
-pub struct KvPairs<T: PartialEq + Eq>(pub HashMap<Bytes, Object<T>>);
-
-#[derive(Debug, Clone, Eq)]
-pub struct Object<T> {
-    pub value: ObjValue<T>,
-    pub expire_at: Option<SystemTime>, // None代表永不过期
+    pub fn index(&self) -> usize {
+        self.index.load(Ordering::Relaxed)
+    }
+
+    /// 数据库的数量
+    pub fn len(&self) -> usize {
+        self.dbs.len()
+    }
+
+    /// 每个数据库的分片数量
+    pub fn num_shards(&self) -> usize {
+        self.num_shards
+    }
+
+    /// 所有数据库中键值对估算的内存占用(字节)
+    pub fn used_memory(&self) -> usize {
+        self.used_memory.load(Ordering::Relaxed)
+    }
+
+    /// 检查数据库编号是否超出范围
+    pub fn check_index(&self, index: usize) -> anyhow::Result<()> {
+        if index >= self.dbs.len() {
+            anyhow::bail!("ERR DB index is out of range");
+        }
+        Ok(())
+    }
+
+    /// 切换当前连接所选择的数据库
+    pub fn select(&self, index: usize) -> anyhow::Result<()> {
+        self.check_index(index)?;
+        self.index.store(index, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// 以写锁锁住当前数据库中keys所在的分片，keys为None时锁住整个数据库
+    pub async fn lock(&self, keys: Option<Vec<&Bytes>>) -> DbInner<'_> {
+        self.lock_dbs(&[self.index()], keys.as_deref())
+            .await
+            .expect("selected db is in range")
+            .pop()
+            .expect("one db is locked")
+    }
+
+    /// 以读锁锁住当前数据库中keys所在的分片，keys为None时锁住整个数据库。
+    /// 读锁下发现的已过期的键需要在释放读锁之后调用expire_found删除
+    pub async fn read(&self, keys: Option<Vec<&Bytes>>) -> DbInner<'_> {
+        let index = self.index();
+        let mut shards = Vec::new();
+        for shard in self.shard_ids(keys.as_deref()) {
+            shards.push((shard, ShardRef::Read(self.dbs[index][shard].read().await)));
+        }
+        DbInner {
+            id: index,
+            num_shards: self.num_shards,
+            shards,
+            expired: Vec::new(),
+        }
+    }
+
+    /// 以写锁锁住编号为index的数据库中的一个分片
+    pub async fn lock_shard(&self, index: usize, shard: usize) -> anyhow::Result<DbInner<'_>> {
+        self.check_index(index)?;
+        Ok(DbInner {
+            id: index,
+            num_shards: self.num_shards,
+            shards: vec![(
+                shard,
+                ShardRef::Locked(self.dbs[index][shard].write().await),
+            )],
+            expired: Vec::new(),
+        })
+    }
+
+    /// 以读锁锁住编号为index的数据库中的一个分片
+    pub async fn read_shard(&self, index: usize, shard: usize) -> anyhow::Result<DbInner<'_>> {
+        self.check_index(index)?;
+        Ok(DbInner {
+            id: index,
+            num_shards: self.num_shards,
+            shards: vec![(shard, ShardRef::Read(self.dbs[index][shard].read().await))],
+            expired: Vec::new(),
+        })
+    }
+
+    /// 释放读锁，再以写锁删除读锁下发现的已过期的键。换成写锁之前键可能已被其它命令重新写入，
+    /// 因此删除前会再次检查是否过期
+    pub async fn expire_found(&self, mut inner: DbInner<'_>) {
+        let (id, expired) = (inner.id, std::mem::take(&mut inner.expired));
+        drop(inner);
+        if expired.is_empty() {
+            return;
+        }
+        let keys: Vec<&Bytes> = expired.iter().collect();
+        let mut inners = self
+            .lock_dbs(&[id], Some(&keys))
+            .await
+            .expect("db index is in range");
+        for key in &expired {
+            inners[0].expire_if_needed(key);
+        }
+    }
+
+    // keys所在的分片编号，从小到大排列且不重复。keys为None时返回所有分片
+    fn shard_ids(&self, keys: Option<&[&Bytes]>) -> Vec<usize> {
+        match keys {
+            Some(keys) => {
+                let mut shard_ids: Vec<usize> = keys
+                    .iter()
+                    .map(|key| shard_index(key, self.num_shards))
+                    .collect();
+                shard_ids.sort_unstable();
+                shard_ids.dedup();
+                shard_ids
+            }
+            None => (0..self.num_shards).collect(),
+        }
+    }
+
+    /// 锁住多个数据库中keys所在的分片，keys为None时锁住整个数据库。ids必须从小到大排列且不重复。
+    /// 所有需要锁住多个分片的地方都按照(数据库编号, 分片编号)的顺序加锁，因此不会产生死锁
+    pub async fn lock_dbs(
+        &self,
+        ids: &[usize],
+        keys: Option<&[&Bytes]>,
+    ) -> anyhow::Result<Vec<DbInner<'_>>> {
+        debug_assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        for &id in ids {
+            self.check_index(id)?;
+        }
+        let shard_ids = self.shard_ids(keys);
+
+        let mut inners = Vec::with_capacity(ids.len());
+        for &id in ids {
+            let mut shards = Vec::with_capacity(shard_ids.len());
+            for &shard in &shard_ids {
+                shards.push((shard, ShardRef::Locked(self.dbs[id][shard].write().await)));
+            }
+            inners.push(DbInner {
+                id,
+                num_shards: self.num_shards,
+                shards,
+                expired: Vec::new(),
+            });
+        }
+        Ok(inners)
+    }
+
+    /// 锁住两个数据库中keys所在的分片，按(a, b)的顺序返回。调用者需要保证a与b不相同
+    pub async fn lock_pair(
+        &self,
+        a: usize,
+        b: usize,
+        keys: Option<&[&Bytes]>,
+    ) -> anyhow::Result<(DbInner<'_>, DbInner<'_>)> {
+        let mut inners = self.lock_dbs(&[a.min(b), a.max(b)], keys).await?;
+        let (high, low) = (inners.pop(), inners.pop());
+        let (low, high) = (
+            low.expect("two dbs are locked"),
+            high.expect("two dbs are locked"),
+        );
+        Ok(if a < b { (low, high) } else { (high, low) })
+    }
+
+    /// 以读锁锁住所有数据库，用于统计信息
+    pub async fn read_all(&self) -> Vec<DbInner<'_>> {
+        let mut inners = Vec::with_capacity(self.dbs.len());
+        for (id, shards) in self.dbs.iter().enumerate() {
+            let mut locked = Vec::with_capacity(shards.len());
+            for (i, shard) in shards.iter().enumerate() {
+                locked.push((i, ShardRef::Read(shard.read().await)));
+            }
+            inners.push(DbInner {
+                id,
+                num_shards: self.num_shards,
+                shards: locked,
+                expired: Vec::new(),
+            });
+        }
+        inners
+    }
+
+    /// 锁住所有数据库
+    pub async fn write_all(&self) -> Vec<DbInner<'_>> {
+        let ids: Vec<usize> = (0..self.dbs.len()).collect();
+        self.lock_dbs(&ids, None)
+            .await
+            .expect("all db indexes are in range")
+    }
 }
 
-impl<T: PartialEq + Eq> PartialEq for Object<T> {
-    fn eq(&self, other: &Self) -> bool {
-        if self.expire_at.is_none() && other.expire_at.is_none() {
-            self.value == other.value
-        } else if self.expire_at.is_some() && other.expire_at.is_some() {
-            let time1 = self.expire_at.unwrap();
-            let time2 = other.expire_at.unwrap();
-            let cmp_res = match time1.duration_since(time2) {
-                Ok(duration) => duration.as_secs() < 1,
-                Err(e) => e.duration().as_secs() < 1,
-            };
-            cmp_res && self.value == other.value
-        } else {
-            false
+impl Clone for Db {
+    // 新的克隆与原来的Db共享数据库，并从原来所选择的数据库开始
+    fn clone(&self) -> Self {
+        Self {
+            dbs: self.dbs.clone(),
+            num_shards: self.num_shards,
+            index: AtomicUsize::new(self.index()),
+            used_memory: self.used_memory.clone(),
+            pool: self.pool.clone(),
         }
     }
 }
 
-// struct ZSetDb(RwLock<HashMap<String, ObjValueCODEC>>);
-//
-// struct HashDb(RwLock<HashMap<String, ObjValueCODEC>>);
-//
-// struct SetDb(RwLock<HashMap<String, ObjValueCODEC>>);
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum ObjValue<T> {
-    Int(i64),
-    Raw(Bytes),
-    LinkedList(VecDeque<Bytes>),
-    // SkipList(SkipList<Bytes>),
-    // TODO:  ZipList()
-    HT(HashMap<Bytes, Bytes>),
-    IntSet(VecDeque<i64>),
-    PhantomData(std::marker::PhantomData<T>),
+/// 将[start, stop]形式的下标(负数从末尾开始计算)转换为有效的闭区间，区间为空时返回None
+pub fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((start as usize, stop as usize))
 }
 
-// impl ObjValueCODEC<StringObj> {
-//     pub fn decode(value: &ObjValueCODEC<StringObj>) -> Bytes {
-//         match value {
-//             ObjValueCODEC::Raw(raw) => raw.clone() ,
-//             ObjValueCODEC::Int(i) =>  i.to_string().into(),
-//             _ => unreachable!("Server error: cann't get stringobj value because stringobj was encoded in wrong type")
-//
-//         }
-//     }
-//
-//     pub fn encode(value: Bytes) -> ObjValueCODEC<StringObj> {
-//         if let Ok(s) = std::str::from_utf8(&value) {
-//             if let Ok(i) = s.parse::<i64>() {
-//                 return ObjValueCODEC::Int(i);
-//             }
-//         }
-//         ObjValueCODEC::Raw(value)
-//     }
-// }
-
-// impl ObjValueEncoding {
-//     pub fn stringobj_decode_value(&self) -> Option<Bytes> {
-//         match self {
-//             ObjValueEncoding::Raw(raw) => Some(raw.clone()),
-//             ObjValueEncoding::Int(i) => Some(i.to_string().into()),
-//             _ => {
-//                 tracing::error!("Server error: cann't get stringobj value because stringobj was encoded in wrong type");
-//                 None
-//             }
-//         }
-//     }
-//
-//     pub fn stringobj_encoding_value(value: Bytes) -> ObjValueEncoding {
-//         if let Ok(s) = std::str::from_utf8(&value) {
-//             if let Ok(i) = s.parse::<i64>() {
-//                 return ObjValueEncoding::Int(i);
-//             }
-//         }
-//         ObjValueEncoding::Raw(value)
-//     }
-// }
+#[cfg(test)]
+mod db_test {
+    use super::*;
+    use crate::{
+        cmd::{self, CmdExecutor},
+        frame::Frame,
+    };
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_shard_locks() {
+        let db = Db::with_shards(1, 4);
+        let key1 = Bytes::from("key1");
+        let key2 = (0..)
+            .map(|i| Bytes::from(format!("key{}", i)))
+            .find(|key| shard_index(key, 4) != shard_index(&key1, 4))
+            .unwrap();
+
+        let mut inner = db.lock(Some(vec![&key1])).await;
+        inner.set_string(key1.clone(), "v1".into(), None, false);
+
+        // 不同分片中的键可以被同时访问
+        let timeout = Duration::from_millis(100);
+        let other = tokio::time::timeout(timeout, db.lock(Some(vec![&key2]))).await;
+        assert!(other.is_ok());
+        drop(other);
+        // 同一个分片中的键需要等待锁被释放
+        assert!(tokio::time::timeout(timeout, db.lock(None)).await.is_err());
+        drop(inner);
+
+        let mut inner = db.lock(None).await;
+        assert_eq!(inner.len(), 1);
+        assert_eq!(Some("v1".into()), inner.get_string(&key1).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_scan_shards() {
+        let db = Db::with_shards(1, 4);
+        let mut inner = db.lock(None).await;
+        for i in 0..100 {
+            inner.set_string(format!("key{i}").into(), "v".into(), None, false);
+        }
+
+        // 每次只扫描一个分片中的约count个键，所有分片扫描完之后游标为0
+        let mut seen = std::collections::HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = inner.scan(cursor, 5);
+            assert!(keys.len() <= 6);
+            seen.extend(keys);
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+        assert_eq!(seen.len(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_read_locks() {
+        let db = Db::with_shards(1, 4);
+        let key = Bytes::from("key");
+        cmd::Set {
+            key: key.clone(),
+            value: "v".into(),
+            expire: None,
+            keep_ttl: false,
+        }
+        .execute(&db)
+        .await
+        .unwrap();
+
+        // 持有读锁时，其它只读命令可以访问同一个分片，写命令需要等待读锁被释放
+        let timeout = Duration::from_millis(100);
+        let inner = db.read(Some(vec![&key])).await;
+        let get = cmd::Get { key: key.clone() };
+        let res = tokio::time::timeout(timeout, get.execute(&db)).await;
+        assert_eq!(res.unwrap().unwrap(), Some(Frame::Bulk("v".into())));
+        assert!(tokio::time::timeout(timeout, db.lock(Some(vec![&key])))
+            .await
+            .is_err());
+        drop(inner);
+    }
+
+    #[tokio::test]
+    async fn test_expire_under_read_lock() {
+        let db = Db::with_shards(1, 4);
+        let key = Bytes::from("key");
+        cmd::Set {
+            key: key.clone(),
+            value: "v".into(),
+            expire: Some(Duration::from_millis(10)),
+            keep_ttl: false,
+        }
+        .execute(&db)
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // 读锁下已过期的键被视为不存在，释放读锁之后才被删除
+        let res = cmd::Get { key: key.clone() }.execute(&db).await.unwrap();
+        assert_eq!(res, Some(Frame::Null));
+        assert_eq!(db.lock(None).await.len(), 0);
+    }
+}
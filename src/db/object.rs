@@ -0,0 +1,243 @@
+use super::{next_version, Hash, List, Set, ZSet};
+use bytes::Bytes;
+use std::{
+    mem::size_of,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// LFU计数器的初始值，使新写入的键不会立即被淘汰
+pub const LFU_INIT_VAL: u8 = 5;
+// 计数器越大，增长越慢。计数器为255时大约对应一百万次访问
+const LFU_LOG_FACTOR: f64 = 10.0;
+// 每经过这么多分钟没有被访问，计数器减1
+const LFU_DECAY_TIME: u16 = 1;
+
+// 不超过该长度的字符串使用embstr编码
+const OBJ_ENCODING_EMBSTR_SIZE_LIMIT: usize = 44;
+
+/// 值的类型，每个键只能保存一种类型的值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjType {
+    String,
+    List,
+    Hash,
+    Set,
+    ZSet,
+}
+
+impl ObjType {
+    /// TYPE命令返回的类型名
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObjType::String => "string",
+            ObjType::List => "list",
+            ObjType::Hash => "hash",
+            ObjType::Set => "set",
+            ObjType::ZSet => "zset",
+        }
+    }
+}
+
+/// 值及其编码方式，值的类型由变体决定
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjValue {
+    // 字符串，可以被解析为i64时使用整数编码
+    Int(i64),
+    Raw(Bytes),
+    List(List),
+    Hash(Hash),
+    Set(Set),
+    ZSet(ZSet),
+}
+
+impl ObjValue {
+    /// 编码字符串。只有与整数的字符串形式完全一致时(如"10"而不是"010")才使用整数编码
+    pub fn from_bytes(value: Bytes) -> Self {
+        if let Some(i) = std::str::from_utf8(&value)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+        {
+            if i.to_string().as_bytes() == value.as_ref() {
+                return ObjValue::Int(i);
+            }
+        }
+        ObjValue::Raw(value)
+    }
+
+    pub fn obj_type(&self) -> ObjType {
+        match self {
+            ObjValue::Int(_) | ObjValue::Raw(_) => ObjType::String,
+            ObjValue::List(_) => ObjType::List,
+            ObjValue::Hash(_) => ObjType::Hash,
+            ObjValue::Set(_) => ObjType::Set,
+            ObjValue::ZSet(_) => ObjType::ZSet,
+        }
+    }
+
+    /// 值的编码方式的名称
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            ObjValue::Int(_) => "int",
+            ObjValue::Raw(raw) if raw.len() <= OBJ_ENCODING_EMBSTR_SIZE_LIMIT => "embstr",
+            ObjValue::Raw(_) => "raw",
+            ObjValue::List(list) => list.encoding(),
+            ObjValue::Hash(hash) => hash.encoding(),
+            ObjValue::Set(set) => set.encoding(),
+            ObjValue::ZSet(zset) => zset.encoding(),
+        }
+    }
+
+    /// 值本身估算的内存占用(字节)，整数编码的字符串不占用额外的内存
+    pub fn mem_usage(&self) -> usize {
+        match self {
+            ObjValue::Int(_) => 0,
+            ObjValue::Raw(raw) => raw.len(),
+            ObjValue::List(list) => list.mem_usage(),
+            ObjValue::Hash(hash) => hash.mem_usage(),
+            ObjValue::Set(set) => set.mem_usage(),
+            ObjValue::ZSet(zset) => zset.mem_usage(),
+        }
+    }
+
+    /// 解码字符串的值，值不是字符串时返回None
+    pub fn as_bytes(&self) -> Option<Bytes> {
+        match self {
+            ObjValue::Int(i) => Some(i.to_string().into()),
+            ObjValue::Raw(raw) => Some(raw.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// 集合类型的值。Keyspace通过该trait对值的类型进行检查
+pub trait Collection: Sized {
+    fn new_value() -> ObjValue;
+
+    fn from_value(value: &ObjValue) -> Option<&Self>;
+
+    fn from_value_mut(value: &mut ObjValue) -> Option<&mut Self>;
+
+    // 集合为空时，键会被删除
+    fn is_empty(&self) -> bool;
+}
+
+// 访问信息在只持有分片读锁时也会被更新，因此使用原子类型。并发的访问可能使LFU计数器少增长几次，
+// 淘汰只需要近似的访问频率，不影响结果
+#[derive(Debug)]
+pub struct RedisObject {
+    pub value: ObjValue,
+    pub expire_at: Option<SystemTime>, // None代表永不过期
+    pub version: u64,                  // 最后一次写入时的版本号
+    lru: AtomicU32,                    // 最后一次被访问时的LRU时钟(秒)
+    lfu: AtomicU32, // 高8位为对数增长的访问频率计数器，低16位为计数器最后一次衰减的时间(分钟)
+}
+
+impl Clone for RedisObject {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            expire_at: self.expire_at,
+            version: self.version,
+            lru: AtomicU32::new(self.lru.load(Ordering::Relaxed)),
+            lfu: AtomicU32::new(self.lfu.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl RedisObject {
+    pub fn new(value: ObjValue, expire_at: Option<SystemTime>) -> Self {
+        Self {
+            value,
+            expire_at,
+            version: next_version(),
+            lru: AtomicU32::new(lru_clock()),
+            lfu: AtomicU32::new(lfu_pack(LFU_INIT_VAL, lfu_time_in_minutes())),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expire_at.is_some_and(|at| at < SystemTime::now())
+    }
+
+    /// 键被访问时更新LRU时钟和LFU计数器
+    pub fn touch(&self) {
+        self.lru.store(lru_clock(), Ordering::Relaxed);
+        let counter = lfu_log_incr(self.lfu_decayed_counter());
+        self.lfu
+            .store(lfu_pack(counter, lfu_time_in_minutes()), Ordering::Relaxed);
+    }
+
+    /// 经过衰减之后的LFU计数器
+    pub fn lfu_decayed_counter(&self) -> u8 {
+        let lfu = self.lfu.load(Ordering::Relaxed);
+        let (counter, decr_time) = ((lfu >> 16) as u8, lfu as u16);
+        let elapsed = lfu_time_in_minutes().wrapping_sub(decr_time);
+        let periods = (elapsed / LFU_DECAY_TIME).min(u8::MAX as u16) as u8;
+        counter.saturating_sub(periods)
+    }
+
+    /// 键多久没有被访问
+    pub fn idle_time(&self) -> Duration {
+        let lru = self.lru.load(Ordering::Relaxed);
+        Duration::from_secs(lru_clock().saturating_sub(lru) as u64)
+    }
+
+    /// 对象估算的内存占用(字节)，包括对象头和值
+    pub fn mem_usage(&self) -> usize {
+        size_of::<RedisObject>() + self.value.mem_usage()
+    }
+
+    /// 值被修改时更新版本号
+    pub fn bump_version(&mut self) {
+        self.version = next_version();
+    }
+}
+
+impl PartialEq for RedisObject {
+    fn eq(&self, other: &Self) -> bool {
+        if self.expire_at.is_none() && other.expire_at.is_none() {
+            self.value == other.value
+        } else if self.expire_at.is_some() && other.expire_at.is_some() {
+            let time1 = self.expire_at.unwrap();
+            let time2 = other.expire_at.unwrap();
+            let cmp_res = match time1.duration_since(time2) {
+                Ok(duration) => duration.as_secs() < 1,
+                Err(e) => e.duration().as_secs() < 1,
+            };
+            cmp_res && self.value == other.value
+        } else {
+            false
+        }
+    }
+}
+
+/// LRU时钟，即当前的Unix时间(秒)
+pub fn lru_clock() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as u32
+}
+
+fn lfu_time_in_minutes() -> u16 {
+    (lru_clock() / 60) as u16
+}
+
+fn lfu_pack(counter: u8, decr_time: u16) -> u32 {
+    ((counter as u32) << 16) | decr_time as u32
+}
+
+// 计数器以对数方式增长：计数器越大，一次访问使其加1的概率越小
+fn lfu_log_incr(counter: u8) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+    let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    let p = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+    if rand::random::<f64>() < p {
+        counter + 1
+    } else {
+        counter
+    }
+}
@@ -0,0 +1,347 @@
+//! quicklist：由listpack节点组成的双向链表。每个节点保存多个元素，避免为每个元素单独分配内存。
+//! 距离两端超过list_compress_depth个节点的内部节点会使用LZF压缩，访问时再临时解压
+
+use super::Listpack;
+use crate::{
+    conf::CONFIG,
+    util::{lzf_compress, lzf_decompress},
+};
+use bytes::Bytes;
+use std::{borrow::Cow, collections::VecDeque, mem::size_of};
+
+// 元素数量限制的listpack也不能超过该字节数
+const SIZE_SAFETY_LIMIT: usize = 8192;
+// 小于该字节数的节点不压缩
+const MIN_COMPRESS_BYTES: usize = 48;
+// 压缩至少要节省该字节数，否则不压缩
+const MIN_COMPRESS_IMPROVE: usize = 8;
+
+/// 按照list_max_listpack_size，listpack插入value之后是否仍然可以作为列表的listpack或quicklist的节点
+pub fn listpack_fits(lp: &Listpack, value: &[u8]) -> bool {
+    lp.bytes_len_after_insert(value) <= listpack_size_limit()
+        && match CONFIG.advanced.list_max_listpack_size {
+            size if size > 0 => lp.len() < size as usize,
+            _ => true,
+        }
+}
+
+/// listpack最多占用的字节数。list_max_listpack_size为-1到-5时分别为4kb到64kb
+pub fn listpack_size_limit() -> usize {
+    match CONFIG.advanced.list_max_listpack_size {
+        size if size > 0 => SIZE_SAFETY_LIMIT,
+        size => 4096 << (size.unsigned_abs().clamp(1, 5) - 1),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Quicklist {
+    nodes: VecDeque<Node>,
+    len: usize,   // 所有节点中元素数量之和
+    bytes: usize, // 所有节点占用的字节数之和，压缩的节点按压缩后的字节数计算
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Raw(Listpack),
+    // 使用LZF压缩后的listpack，count为其中元素的数量
+    Compressed { data: Bytes, count: usize },
+}
+
+impl Node {
+    fn count(&self) -> usize {
+        match self {
+            Node::Raw(lp) => lp.len(),
+            Node::Compressed { count, .. } => *count,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Node::Raw(lp) => lp.bytes_len(),
+            Node::Compressed { data, .. } => data.len(),
+        }
+    }
+
+    fn listpack(&self) -> Cow<'_, Listpack> {
+        match self {
+            Node::Raw(lp) => Cow::Borrowed(lp),
+            Node::Compressed { data, .. } => Cow::Owned(
+                lzf_decompress(data)
+                    .and_then(|raw| Listpack::from_bytes(raw.to_vec()))
+                    .expect("compressed node should be valid"),
+            ),
+        }
+    }
+
+    fn compress(&mut self) {
+        if let Node::Raw(lp) = self {
+            if lp.bytes_len() < MIN_COMPRESS_BYTES {
+                return;
+            }
+            let data = lzf_compress(lp.as_bytes());
+            if data.len() + MIN_COMPRESS_IMPROVE <= lp.bytes_len() {
+                *self = Node::Compressed {
+                    data,
+                    count: lp.len(),
+                };
+            }
+        }
+    }
+
+    fn decompress(&mut self) {
+        if let Node::Compressed { .. } = self {
+            *self = Node::Raw(self.listpack().into_owned());
+        }
+    }
+}
+
+impl Quicklist {
+    /// 使用已有的listpack作为第一个节点
+    pub fn from_listpack(lp: Listpack) -> Self {
+        let mut ql = Quicklist::default();
+        if !lp.is_empty() {
+            ql.len = lp.len();
+            ql.bytes = lp.bytes_len();
+            ql.nodes.push_back(Node::Raw(lp));
+        }
+        ql
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// 估算的内存占用(字节)
+    pub fn mem_usage(&self) -> usize {
+        self.nodes.len() * size_of::<Node>() + self.bytes
+    }
+
+    pub fn push_front(&mut self, value: &[u8]) {
+        // 两端的节点总是未压缩的
+        if matches!(self.nodes.front(), Some(Node::Raw(lp)) if listpack_fits(lp, value)) {
+            self.update_node(0, |lp| lp.push_front(value));
+        } else {
+            let mut lp = Listpack::default();
+            lp.push_front(value);
+            self.bytes += lp.bytes_len();
+            self.nodes.push_front(Node::Raw(lp));
+        }
+        self.len += 1;
+        self.recompress();
+    }
+
+    pub fn push_back(&mut self, value: &[u8]) {
+        if matches!(self.nodes.back(), Some(Node::Raw(lp)) if listpack_fits(lp, value)) {
+            self.update_node(self.nodes.len() - 1, |lp| lp.push_back(value));
+        } else {
+            let mut lp = Listpack::default();
+            lp.push_back(value);
+            self.bytes += lp.bytes_len();
+            self.nodes.push_back(Node::Raw(lp));
+        }
+        self.len += 1;
+        self.recompress();
+    }
+
+    pub fn pop_front(&mut self) -> Option<Bytes> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let value = self.update_node(0, |lp| lp.pop_front());
+        self.after_pop(0, value)
+    }
+
+    pub fn pop_back(&mut self) -> Option<Bytes> {
+        let last = self.nodes.len().checked_sub(1)?;
+        let value = self.update_node(last, |lp| lp.pop_back());
+        self.after_pop(last, value)
+    }
+
+    /// 返回下标对应的元素，从距离下标较近的一端开始查找节点
+    pub fn get(&self, index: usize) -> Option<Bytes> {
+        if index >= self.len {
+            return None;
+        }
+        if index < self.len / 2 {
+            self.iter_from(index).next()
+        } else {
+            // 从尾部开始查找，跳过下标之后的节点
+            let mut rest = self.len - index;
+            for node in self.nodes.iter().rev() {
+                if rest <= node.count() {
+                    return node.listpack().get(node.count() - rest);
+                }
+                rest -= node.count();
+            }
+            None
+        }
+    }
+
+    /// 从下标start开始依次迭代元素，跳过start之前的节点时不需要解压
+    pub fn iter_from(&self, start: usize) -> impl Iterator<Item = Bytes> + '_ {
+        let (mut first, mut skip) = (0, start);
+        while first < self.nodes.len() && skip >= self.nodes[first].count() {
+            skip -= self.nodes[first].count();
+            first += 1;
+        }
+        self.nodes
+            .iter()
+            .skip(first)
+            .enumerate()
+            .flat_map(move |(i, node)| {
+                let items: Vec<Bytes> = node.listpack().iter().collect();
+                items.into_iter().skip(if i == 0 { skip } else { 0 })
+            })
+    }
+
+    /// 依次返回每个节点的listpack，压缩的节点会被解压
+    pub fn listpacks(&self) -> impl Iterator<Item = Cow<'_, Listpack>> {
+        self.nodes.iter().map(|node| node.listpack())
+    }
+
+    /// 只有一个节点时返回该节点的listpack，用于列表变短后转换回listpack编码
+    pub fn single_listpack(&self) -> Option<&Listpack> {
+        match self.nodes.front() {
+            Some(Node::Raw(lp)) if self.nodes.len() == 1 => Some(lp),
+            _ => None,
+        }
+    }
+
+    // 解压并修改下标为i的节点，同时更新字节数
+    fn update_node<R>(&mut self, i: usize, f: impl FnOnce(&mut Listpack) -> R) -> R {
+        let node = &mut self.nodes[i];
+        let before = node.size();
+        node.decompress();
+        let res = match node {
+            Node::Raw(lp) => f(lp),
+            Node::Compressed { .. } => unreachable!("node should be decompressed"),
+        };
+        self.bytes = self.bytes + node.size() - before;
+        res
+    }
+
+    fn after_pop(&mut self, i: usize, value: Option<Bytes>) -> Option<Bytes> {
+        value.as_ref()?;
+        self.len -= 1;
+        if self.nodes[i].count() == 0 {
+            let node = self.nodes.remove(i).expect("node exists");
+            self.bytes -= node.size();
+        }
+        self.recompress();
+        value
+    }
+
+    // 两端各depth个节点保持未压缩，之后的节点压缩。两端的节点增删后，
+    // 只有靠近两端的节点的状态需要改变
+    fn recompress(&mut self) {
+        self.compress_nodes(CONFIG.advanced.list_compress_depth);
+    }
+
+    fn compress_nodes(&mut self, depth: usize) {
+        if depth == 0 {
+            return;
+        }
+        let n = self.nodes.len();
+        let mut changes = Vec::with_capacity(depth * 2 + 2);
+        for i in 0..depth.min(n) {
+            changes.push((i, false));
+            changes.push((n - 1 - i, false));
+        }
+        if n > depth * 2 {
+            changes.push((depth, true));
+            changes.push((n - 1 - depth, true));
+        }
+        for (i, compress) in changes {
+            let node = &mut self.nodes[i];
+            let before = node.size();
+            if compress {
+                node.compress();
+            } else {
+                node.decompress();
+            }
+            self.bytes = self.bytes + node.size() - before;
+        }
+    }
+}
+
+#[cfg(test)]
+mod quicklist_test {
+    use super::*;
+
+    #[test]
+    fn test_quicklist() {
+        let mut ql = Quicklist::default();
+        for i in 0..1000 {
+            ql.push_back(format!("value{}", i).as_bytes());
+            ql.push_front(format!("value{}", -i).as_bytes());
+        }
+        assert_eq!(ql.len(), 2000);
+        // 节点的大小受list_max_listpack_size(8kb)限制
+        assert!(ql.node_count() > 1);
+        assert!(ql.listpacks().all(|lp| lp.bytes_len() <= listpack_size_limit()));
+        assert_eq!(ql.get(0), Some("value-999".into()));
+        assert_eq!(ql.get(1999), Some("value999".into()));
+        assert_eq!(ql.get(1000), Some("value0".into()));
+        assert_eq!(ql.get(2000), None);
+        assert_eq!(
+            ql.iter_from(998).take(4).collect::<Vec<_>>(),
+            ["value-1", "value0", "value0", "value1"]
+        );
+        assert_eq!(ql.iter_from(0).count(), 2000);
+
+        let bytes: usize = ql.listpacks().map(|lp| lp.bytes_len()).sum();
+        assert_eq!(ql.bytes, bytes);
+        for i in 0..1000 {
+            assert_eq!(ql.pop_front(), Some(format!("value{}", -999 + i).into()));
+        }
+        assert_eq!(ql.pop_back(), Some("value999".into()));
+        assert_eq!(ql.len(), 999);
+        while ql.pop_back().is_some() {}
+        assert_eq!((ql.len(), ql.node_count(), ql.bytes), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_quicklist_compress() {
+        let mut lp = Listpack::default();
+        for _ in 0..100 {
+            lp.push_back(b"compressible");
+        }
+        let mut node = Node::Raw(lp.clone());
+        node.compress();
+        assert!(matches!(node, Node::Compressed { count: 100, .. }));
+        assert!(node.size() < lp.bytes_len());
+        assert_eq!(node.listpack().as_ref(), &lp);
+        node.decompress();
+        assert!(matches!(&node, Node::Raw(raw) if raw == &lp));
+
+        // 两端各depth个节点不压缩
+        let mut ql = Quicklist::default();
+        for _ in 0..10 {
+            let mut node = lp.clone();
+            node.push_back(b"x");
+            ql.len += node.len();
+            ql.bytes += node.bytes_len();
+            ql.nodes.push_back(Node::Raw(node));
+            ql.compress_nodes(2);
+        }
+        let compressed: Vec<bool> = ql
+            .nodes
+            .iter()
+            .map(|node| matches!(node, Node::Compressed { .. }))
+            .collect();
+        assert_eq!(compressed, [false, false, true, true, true, true, true, true, false, false]);
+        let bytes: usize = ql.nodes.iter().map(|node| node.size()).sum();
+        assert_eq!(ql.bytes, bytes);
+        assert_eq!(ql.get(550), Some("compressible".into()));
+        assert_eq!(ql.iter_from(1009).collect::<Vec<_>>(), ["x"]);
+
+        // 过小的节点不压缩
+        let mut node = Node::Raw(Listpack::default());
+        node.compress();
+        assert!(matches!(node, Node::Raw(_)));
+    }
+}
@@ -0,0 +1,211 @@
+use super::{Collection, ObjValue};
+use crate::util::ScanIndex;
+use bytes::Bytes;
+use std::{collections::HashSet, mem::size_of};
+
+// 整数集合最多保存的成员数量，超过后转换为哈希表
+const SET_MAX_INTSET_ENTRIES: usize = 512;
+
+/// 集合。成员都是整数且数量较少时使用有序的整数数组保存，否则使用哈希表
+#[derive(Debug, Clone, PartialEq)]
+pub enum Set {
+    IntSet(Vec<i64>),
+    // bytes为所有成员的字节数之和，index按扫描顺序保存所有成员
+    HashTable {
+        members: HashSet<Bytes>,
+        bytes: usize,
+        index: ScanIndex,
+    },
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Set::IntSet(Vec::new())
+    }
+}
+
+impl Set {
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Set::IntSet(_) => "intset",
+            Set::HashTable { .. } => "hashtable",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Set::IntSet(ints) => ints.len(),
+            Set::HashTable { members, .. } => members.len(),
+        }
+    }
+
+    /// 估算的内存占用(字节)
+    pub fn mem_usage(&self) -> usize {
+        match self {
+            Set::IntSet(ints) => ints.len() * size_of::<i64>(),
+            Set::HashTable { members, bytes, .. } => {
+                members.len() * (2 * size_of::<Bytes>() + size_of::<u64>()) + bytes
+            }
+        }
+    }
+
+    /// 添加成员，返回成员是否为新成员
+    pub fn insert(&mut self, member: Bytes) -> bool {
+        if let Set::IntSet(ints) = self {
+            match as_int(&member) {
+                Some(i) => match ints.binary_search(&i) {
+                    Ok(_) => return false,
+                    Err(pos) if ints.len() < SET_MAX_INTSET_ENTRIES => {
+                        ints.insert(pos, i);
+                        return true;
+                    }
+                    // 成员数量超出限制，转换为哈希表
+                    Err(_) => self.convert_to_hashtable(),
+                },
+                // 成员不是整数，转换为哈希表
+                None => self.convert_to_hashtable(),
+            }
+        }
+
+        match self {
+            Set::HashTable {
+                members,
+                bytes,
+                index,
+            } => {
+                let added = members.insert(member.clone());
+                if added {
+                    *bytes += member.len();
+                    index.insert(member);
+                }
+                added
+            }
+            Set::IntSet(_) => unreachable!("intset should be converted to hashtable"),
+        }
+    }
+
+    /// 删除成员，返回成员是否存在
+    pub fn remove(&mut self, member: &Bytes) -> bool {
+        match self {
+            Set::IntSet(ints) => match as_int(member).map(|i| ints.binary_search(&i)) {
+                Some(Ok(pos)) => {
+                    ints.remove(pos);
+                    true
+                }
+                _ => false,
+            },
+            Set::HashTable {
+                members,
+                bytes,
+                index,
+            } => {
+                let removed = members.remove(member);
+                if removed {
+                    *bytes -= member.len();
+                    index.remove(member);
+                }
+                removed
+            }
+        }
+    }
+
+    pub fn contains(&self, member: &Bytes) -> bool {
+        match self {
+            Set::IntSet(ints) => as_int(member).is_some_and(|i| ints.binary_search(&i).is_ok()),
+            Set::HashTable { members, .. } => members.contains(member),
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        match self {
+            Set::IntSet(ints) => Box::new(ints.iter().map(|i| Bytes::from(i.to_string()))),
+            Set::HashTable { members, .. } => Box::new(members.iter().cloned()),
+        }
+    }
+
+    /// 从游标开始按扫描顺序返回约count个成员，以及下一次扫描的游标，游标为0代表扫描结束。
+    /// 整数集合中的成员较少，总是一次全部返回
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Bytes>) {
+        match self {
+            Set::IntSet(_) => (0, self.iter().collect()),
+            Set::HashTable { index, .. } => {
+                let (next, members) = index.scan(cursor, count);
+                (next.unwrap_or(0), members)
+            }
+        }
+    }
+
+    fn convert_to_hashtable(&mut self) {
+        if let Set::IntSet(ints) = self {
+            let members: HashSet<Bytes> =
+                ints.iter().map(|i| Bytes::from(i.to_string())).collect();
+            let bytes = members.iter().map(|member| member.len()).sum();
+            let mut index = ScanIndex::default();
+            for member in &members {
+                index.insert(member.clone());
+            }
+            *self = Set::HashTable {
+                members,
+                bytes,
+                index,
+            };
+        }
+    }
+}
+
+impl FromIterator<Bytes> for Set {
+    fn from_iter<I: IntoIterator<Item = Bytes>>(iter: I) -> Self {
+        let mut set = Set::default();
+        for member in iter {
+            set.insert(member);
+        }
+        set
+    }
+}
+
+// 只有与整数的字符串形式完全一致的成员才能保存在整数集合中
+fn as_int(member: &Bytes) -> Option<i64> {
+    let i = std::str::from_utf8(member).ok()?.parse::<i64>().ok()?;
+    (i.to_string().as_bytes() == member.as_ref()).then_some(i)
+}
+
+impl Collection for Set {
+    fn new_value() -> ObjValue {
+        ObjValue::Set(Set::default())
+    }
+
+    fn from_value(value: &ObjValue) -> Option<&Self> {
+        match value {
+            ObjValue::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut ObjValue) -> Option<&mut Self> {
+        match value {
+            ObjValue::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[test]
+fn test_set_encoding() {
+    let mut set: Set = ["1", "3", "2"].into_iter().map(Bytes::from).collect();
+    assert_eq!(set.encoding(), "intset");
+    assert!(!set.insert("2".into()));
+    assert!(set.contains(&"3".into()));
+    // "03"不是整数的标准形式
+    assert!(!set.contains(&"03".into()));
+
+    assert!(set.insert("a".into()));
+    assert_eq!(set.encoding(), "hashtable");
+    assert_eq!(set.len(), 4);
+    assert!(set.contains(&"1".into()));
+    assert!(set.remove(&"1".into()));
+    assert!(!set.remove(&"1".into()));
+}
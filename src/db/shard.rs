@@ -0,0 +1,281 @@
+use super::{Expires, RedisObject, ShardSnapshot};
+use crate::util::{key_hash_slot, ScanIndex};
+use bytes::Bytes;
+use indexmap::IndexMap;
+use rand::Rng;
+use std::{
+    mem::size_of,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+use tokio::sync::{RwLockReadGuard, RwLockWriteGuard};
+
+/// 逻辑数据库的一个分片。键按照哈希值被分配到各个分片，每个分片有独立的锁
+#[derive(Debug, Default)]
+pub struct Shard {
+    // 键值对同时保存在数组中，支持O(1)的随机抽样，用于淘汰键
+    pub(super) keys: IndexMap<Bytes, RedisObject>,
+    // 设置了过期时间的键，用于主动过期时随机抽样
+    pub(super) expires: Expires,
+    // 按扫描顺序排列的键，SCAN每次只需要访问返回的键
+    scan_index: ScanIndex,
+    // 分片中所有键值对估算的内存占用(字节)
+    used_memory: usize,
+    // 所属Db的内存计数器。不属于任何Db的分片为None
+    counter: Option<Arc<AtomicUsize>>,
+    // 登记在该分片上的快照。键被修改之前需要为还未遍历该分片的快照保存旧值
+    pub(super) snapshots: Vec<Arc<ShardSnapshot>>,
+}
+
+// 克隆出的分片不属于任何Db，不计入Db的内存占用
+impl Clone for Shard {
+    fn clone(&self) -> Self {
+        Self {
+            keys: self.keys.clone(),
+            expires: self.expires.clone(),
+            scan_index: self.scan_index.clone(),
+            used_memory: self.used_memory,
+            counter: None,
+            snapshots: Vec::new(),
+        }
+    }
+}
+
+impl Shard {
+    /// 创建属于某个Db的空分片，分片内存占用的变化会同步到counter
+    pub fn with_counter(counter: Arc<AtomicUsize>) -> Self {
+        Self {
+            counter: Some(counter),
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&RedisObject> {
+        self.keys.get(key)
+    }
+
+    /// 调用者不能通过返回的引用修改过期时间，否则过期键索引会与键值对不一致；
+    /// 修改值之后需要调用resize更新内存占用
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut RedisObject> {
+        self.preserve(key);
+        self.keys.get_mut(key)
+    }
+
+    /// 更新键的访问信息。访问信息不会被保存到RDB文件中，因此不需要为快照保存旧值
+    pub fn touch(&self, key: &[u8]) -> Option<&RedisObject> {
+        let obj = self.keys.get(key)?;
+        obj.touch();
+        Some(obj)
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.keys.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: Bytes, obj: RedisObject) -> Option<RedisObject> {
+        self.preserve(&key);
+        if obj.expire_at.is_some() {
+            self.expires.insert(key.clone());
+        } else {
+            self.expires.remove(&key);
+        }
+        if !self.keys.contains_key(&key) {
+            self.scan_index.insert(key.clone());
+        }
+        self.grow(entry_mem_usage(&key, &obj));
+        let old = self.keys.insert(key.clone(), obj)?;
+        self.shrink(entry_mem_usage(&key, &old));
+        Some(old)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<RedisObject> {
+        self.preserve(key);
+        let (key, obj) = self.keys.swap_remove_entry(key)?;
+        if obj.expire_at.is_some() {
+            self.expires.remove(&key);
+        }
+        self.scan_index.remove(&key);
+        self.shrink(entry_mem_usage(&key, &obj));
+        Some(obj)
+    }
+
+    /// 值被原地修改之后更新内存占用。before和after为修改前后RedisObject::mem_usage的结果
+    pub fn resize(&mut self, before: usize, after: usize) {
+        if after > before {
+            self.grow(after - before);
+        } else {
+            self.shrink(before - after);
+        }
+    }
+
+    /// 从扫描位置开始按扫描顺序返回count个键以及下一次扫描的位置，None代表分片已扫描完
+    pub fn scan(&self, pos: u64, count: usize) -> (Option<u64>, Vec<Bytes>) {
+        self.scan_index.scan(pos, count)
+    }
+
+    /// 随机返回一个键值对
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<(&Bytes, &RedisObject)> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        self.keys.get_index(rng.gen_range(0..self.keys.len()))
+    }
+
+    /// 取出分片中所有的键值对，返回的分片不再属于任何Db
+    pub fn take(&mut self) -> Shard {
+        let used_memory = std::mem::take(&mut self.used_memory);
+        self.shrink_counter(used_memory);
+        let mut taken = Shard {
+            keys: std::mem::take(&mut self.keys),
+            expires: std::mem::take(&mut self.expires),
+            scan_index: std::mem::take(&mut self.scan_index),
+            used_memory,
+            counter: None,
+            snapshots: Vec::new(),
+        };
+
+        // 被取出的键值对直接移交给最后一个快照，其它快照保存它们的克隆
+        self.snapshots.retain(|snapshot| !snapshot.is_done());
+        if let Some((last, others)) = self.snapshots.split_last() {
+            for snapshot in others {
+                for (key, obj) in &taken.keys {
+                    snapshot.preserve(key, || Some(obj.clone()));
+                }
+            }
+            for (key, obj) in taken.keys.drain(..) {
+                last.preserve(&key, || Some(obj));
+            }
+        }
+        taken
+    }
+
+    /// 与同一个Db中的另一个分片交换所有的键值对，登记的快照不会被交换。
+    /// 同一个Db中的分片共享内存计数器，因此计数器不需要调整
+    pub fn swap_data(&mut self, other: &mut Shard) {
+        let keys: Vec<Bytes> = self.keys.keys().chain(other.keys.keys()).cloned().collect();
+        for key in &keys {
+            self.preserve(key);
+            other.preserve(key);
+        }
+        std::mem::swap(&mut self.keys, &mut other.keys);
+        std::mem::swap(&mut self.expires, &mut other.expires);
+        std::mem::swap(&mut self.scan_index, &mut other.scan_index);
+        std::mem::swap(&mut self.used_memory, &mut other.used_memory);
+    }
+
+    /// 登记一个快照，之后键被修改之前会为它保存旧值
+    pub fn register_snapshot(&mut self) -> Arc<ShardSnapshot> {
+        self.snapshots.retain(|snapshot| !snapshot.is_done());
+        let snapshot = Arc::new(ShardSnapshot::default());
+        self.snapshots.push(snapshot.clone());
+        snapshot
+    }
+
+    // 键第一次被修改之前，为还未遍历该分片的快照保存它原来的值
+    fn preserve(&mut self, key: &[u8]) {
+        if self.snapshots.is_empty() {
+            return;
+        }
+        self.snapshots.retain(|snapshot| !snapshot.is_done());
+        let (key, old) = match self.keys.get_key_value(key) {
+            Some((key, obj)) => (key.clone(), Some(obj)),
+            None => (Bytes::copy_from_slice(key), None),
+        };
+        for snapshot in &self.snapshots {
+            snapshot.preserve(&key, || old.cloned());
+        }
+    }
+
+    fn grow(&mut self, size: usize) {
+        self.used_memory += size;
+        if let Some(counter) = &self.counter {
+            counter.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+
+    fn shrink(&mut self, size: usize) {
+        self.used_memory -= size;
+        self.shrink_counter(size);
+    }
+
+    fn shrink_counter(&self, size: usize) {
+        if let Some(counter) = &self.counter {
+            counter.fetch_sub(size, Ordering::Relaxed);
+        }
+    }
+
+    /// 修改已存在的键的过期时间
+    pub fn set_expire(&mut self, key: &Bytes, expire_at: Option<SystemTime>) {
+        self.preserve(key);
+        let Some(obj) = self.keys.get_mut(key) else {
+            return;
+        };
+        match (obj.expire_at.is_some(), expire_at.is_some()) {
+            (false, true) => self.expires.insert(key.clone()),
+            (true, false) => self.expires.remove(key),
+            _ => {}
+        }
+        obj.expire_at = expire_at;
+    }
+}
+
+/// 键值对估算的内存占用(字节)，包括键在扫描索引中的一项
+pub fn entry_mem_usage(key: &[u8], obj: &RedisObject) -> usize {
+    size_of::<Bytes>() + size_of::<(u64, Bytes)>() + key.len() + obj.mem_usage()
+}
+
+/// 键所属的分片编号。同一个哈希标签({tag})中的键总是属于同一个分片
+pub fn shard_index(key: &[u8], num_shards: usize) -> usize {
+    key_hash_slot(key) as usize % num_shards
+}
+
+/// 被锁住的分片，或者不属于任何Db的分片。只持有读锁的分片不能被修改
+#[derive(Debug)]
+pub enum ShardRef<'a> {
+    Locked(RwLockWriteGuard<'a, Shard>),
+    Read(RwLockReadGuard<'a, Shard>),
+    #[cfg(test)]
+    Owned(Shard),
+}
+
+impl ShardRef<'_> {
+    pub fn is_read(&self) -> bool {
+        matches!(self, ShardRef::Read(_))
+    }
+}
+
+impl Deref for ShardRef<'_> {
+    type Target = Shard;
+
+    fn deref(&self) -> &Shard {
+        match self {
+            ShardRef::Locked(guard) => guard,
+            ShardRef::Read(guard) => guard,
+            #[cfg(test)]
+            ShardRef::Owned(shard) => shard,
+        }
+    }
+}
+
+impl DerefMut for ShardRef<'_> {
+    fn deref_mut(&mut self) -> &mut Shard {
+        match self {
+            ShardRef::Locked(guard) => guard,
+            ShardRef::Read(_) => panic!("the shard is only locked for reading"),
+            #[cfg(test)]
+            ShardRef::Owned(shard) => shard,
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! 时间点快照，用于生成RDB文件。生成快照时不复制数据，只在每个分片上登记快照；之后分片中的键
+//! 第一次被修改之前，修改前的值会被保存到快照中。遍历快照时依次读锁住每个分片，被修改过的键使用
+//! 保存的旧值，因此额外的内存只与快照期间被修改的键成正比。分片被遍历之后不再需要保存旧值
+
+use super::{Db, RedisObject, Shard};
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
+use tokio::sync::RwLock;
+
+// 遍历快照时每次读锁住分片之后遍历的键的数量。值越小，写命令等待的时间越短
+const SNAPSHOT_KEYS_PER_LOCK: usize = 128;
+
+/// 快照在一个分片上的登记，保存快照时刻之后被修改的键原来的值
+#[derive(Debug, Default)]
+pub struct ShardSnapshot {
+    // 快照已经遍历过该分片，或者快照已被丢弃，不再需要保存旧值
+    done: AtomicBool,
+    // 键在快照时刻的值，None代表快照时刻键不存在
+    old_values: Mutex<HashMap<Bytes, Option<RedisObject>>>,
+}
+
+impl ShardSnapshot {
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+
+    /// 键第一次被修改之前保存它原来的值，之后的修改不会覆盖已保存的值
+    pub fn preserve(&self, key: &Bytes, old: impl FnOnce() -> Option<RedisObject>) {
+        let mut old_values = self.lock();
+        if !old_values.contains_key(key) {
+            old_values.insert(key.clone(), old());
+        }
+    }
+
+    // 不再保存旧值，返回已保存的旧值
+    fn finish(&self) -> HashMap<Bytes, Option<RedisObject>> {
+        self.done.store(true, Ordering::Release);
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Bytes, Option<RedisObject>>> {
+        self.old_values
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// 快照时刻一个数据库的统计信息
+#[derive(Debug, Clone, Copy)]
+struct DbStats {
+    len: usize,
+    expires_len: usize,
+}
+
+/// 所有数据库在同一时刻的快照。快照只能被遍历一次，遍历过的分片不再保存旧值
+#[derive(Debug)]
+pub struct Snapshot {
+    dbs: Arc<Vec<Vec<RwLock<Shard>>>>,
+    shards: Vec<Vec<Arc<ShardSnapshot>>>,
+    stats: Vec<DbStats>,
+    used_memory: usize,
+}
+
+impl Db {
+    /// 生成所有数据库在同一时刻的快照。需要短暂地锁住所有分片，但不会复制数据
+    pub async fn snapshot(&self) -> Snapshot {
+        let mut guards = self.write_all().await;
+        let shards = guards
+            .iter_mut()
+            .map(|inner| {
+                inner
+                    .shards
+                    .iter_mut()
+                    .map(|(_, shard)| shard.register_snapshot())
+                    .collect()
+            })
+            .collect();
+        let stats = guards
+            .iter()
+            .map(|inner| DbStats {
+                len: inner.len(),
+                expires_len: inner.expires_len(),
+            })
+            .collect();
+        let used_memory = guards.iter().map(|inner| inner.used_memory()).sum();
+
+        Snapshot {
+            dbs: self.dbs.clone(),
+            shards,
+            stats,
+            used_memory,
+        }
+    }
+}
+
+impl Snapshot {
+    /// 数据库的数量
+    pub fn databases(&self) -> usize {
+        self.stats.len()
+    }
+
+    /// 快照时刻数据库中键的数量(包括已过期但还未被删除的键)
+    pub fn db_len(&self, id: usize) -> usize {
+        self.stats[id].len
+    }
+
+    /// 快照时刻数据库中设置了过期时间的键的数量
+    pub fn db_expires_len(&self, id: usize) -> usize {
+        self.stats[id].expires_len
+    }
+
+    /// 快照时刻所有数据库中键值对估算的内存占用(字节)
+    pub fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    /// 遍历数据库在快照时刻的所有键值对，f返回错误时停止遍历。每次读锁住一个分片并遍历其中的一批键，
+    /// 遍历期间该分片的写命令需要等待。会阻塞当前线程，不能在异步任务中调用
+    pub fn try_for_each<E>(
+        &self,
+        id: usize,
+        mut f: impl FnMut(&Bytes, &RedisObject) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for (shard, snapshot) in self.dbs[id].iter().zip(&self.shards[id]) {
+            // 需要遍历的键：分片中现有的键，以及快照之后被删除的键
+            let keys: Vec<Bytes> = {
+                let shard = shard.blocking_read();
+                let old_values = snapshot.lock();
+                let deleted = old_values
+                    .iter()
+                    .filter(|(key, old)| old.is_some() && !shard.contains_key(key))
+                    .map(|(key, _)| key);
+                shard.keys.keys().chain(deleted).cloned().collect()
+            };
+
+            // 遍历完该分片之前，键被修改时仍需要保存旧值
+            for batch in keys.chunks(SNAPSHOT_KEYS_PER_LOCK) {
+                let shard = shard.blocking_read();
+                let old_values = snapshot.lock();
+                for key in batch {
+                    match old_values.get(key) {
+                        Some(Some(old)) => f(key, old)?,
+                        Some(None) => {} // 快照之后新增的键
+                        None => {
+                            // 没有旧值说明键在快照之后没有被修改过
+                            if let Some(obj) = shard.get(key) {
+                                f(key, obj)?;
+                            }
+                        }
+                    }
+                }
+            }
+            drop(snapshot.finish());
+        }
+        Ok(())
+    }
+}
+
+// 快照被丢弃后不再保存旧值，并释放已保存的旧值
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.shards
+            .iter()
+            .flatten()
+            .for_each(|snapshot| drop(snapshot.finish()));
+    }
+}
+
+#[cfg(test)]
+mod snapshot_test {
+    use super::*;
+    use crate::db::ObjValue;
+
+    fn collect(snapshot: &Snapshot, id: usize) -> Vec<(Bytes, ObjValue)> {
+        let mut entries = Vec::new();
+        let _ = snapshot.try_for_each(id, |key, obj| {
+            entries.push((key.clone(), obj.value.clone()));
+            Ok::<_, ()>(())
+        });
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let db = Db::with_shards(2, 4);
+        let key = |i: usize| Bytes::from(format!("key{}", i));
+        let value = |s: &str| ObjValue::from_bytes(Bytes::copy_from_slice(s.as_bytes()));
+        runtime.block_on(async {
+            let mut dbs = db.write_all().await;
+            for i in 0..10 {
+                dbs[0].set_string(key(i), "old".into(), None, false);
+            }
+        });
+        let expected: Vec<_> = (0..10).map(|i| (key(i), value("old"))).collect();
+
+        let snapshot = runtime.block_on(db.snapshot());
+        assert_eq!(snapshot.db_len(0), 10);
+        runtime.block_on(async {
+            let mut dbs = db.write_all().await;
+            // 快照之后的修改、删除、新增以及清空数据库都不会影响快照
+            dbs[0].set_string(key(0), "new".into(), None, false);
+            dbs[0].set_string(key(0), "newer".into(), None, false);
+            dbs[0].del(&key(1), false);
+            dbs[0].set_string(key(100), "new".into(), None, false);
+            dbs[0].set_expire(&key(2), Some(std::time::SystemTime::now()));
+            let (first, second) = dbs.split_at_mut(1);
+            first[0].swap(&mut second[0]);
+            dbs[1].flush();
+        });
+        assert_eq!(collect(&snapshot, 0), expected);
+        assert!(collect(&snapshot, 1).is_empty());
+
+        // 遍历之后的修改不再保存旧值
+        runtime.block_on(async {
+            let mut dbs = db.write_all().await;
+            dbs[0].set_string(key(3), "new".into(), None, false);
+            assert!(dbs[0].shard(&key(3)).snapshots.is_empty());
+        });
+        drop(snapshot);
+    }
+}
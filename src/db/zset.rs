@@ -0,0 +1,377 @@
+use super::{normalize_range, Collection, Listpack, ObjValue};
+use crate::{conf::CONFIG, util::ScanIndex};
+use bytes::Bytes;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+    mem::size_of,
+};
+
+/// 有序集合。成员较少且较短时按分数(分数相同时按成员)从小到大依次将成员和分数保存在listpack中，
+/// 否则使用SkipList
+#[derive(Debug, Clone)]
+pub enum ZSet {
+    Listpack(Listpack),
+    SkipList(SkipList),
+}
+
+/// 哈希表用于通过成员查找分数，有序树用于按分数(分数相同时按成员)排序，
+/// 扫描索引用于ZSCAN按扫描顺序遍历成员
+#[derive(Debug, Clone, Default)]
+pub struct SkipList {
+    scores: HashMap<Bytes, f64>,
+    sorted: BTreeSet<(Score, Bytes)>,
+    index: ScanIndex,
+    bytes: usize, // 所有成员的字节数之和。三个索引中的成员共享同一块内存
+}
+
+// 分数不会是NaN，因此可以全序比较
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl SkipList {
+    fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    /// 估算的内存占用(字节)，每个成员及其分数(扫描索引中为扫描位置)在三个索引中各保存一次
+    fn mem_usage(&self) -> usize {
+        self.scores.len() * 3 * (size_of::<Bytes>() + size_of::<f64>()) + self.bytes
+    }
+
+    /// 添加成员或更新成员的分数，返回成员是否为新成员
+    fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        match self.scores.insert(member.clone(), score) {
+            Some(old) => {
+                self.sorted.remove(&(Score(old), member.clone()));
+                self.sorted.insert((Score(score), member));
+                false
+            }
+            None => {
+                self.bytes += member.len();
+                self.index.insert(member.clone());
+                self.sorted.insert((Score(score), member));
+                true
+            }
+        }
+    }
+
+    /// 删除成员，返回成员是否存在
+    fn remove(&mut self, member: &Bytes) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.sorted.remove(&(Score(score), member.clone()));
+                self.index.remove(member);
+                self.bytes -= member.len();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn score(&self, member: &Bytes) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// 成员按分数从小到大排序的排名，从0开始
+    fn rank(&self, member: &Bytes) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(self.sorted.range(..(Score(score), member.clone())).count())
+    }
+
+    /// 按分数从小到大迭代成员及其分数
+    fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.sorted.iter().map(|(score, member)| (member, score.0))
+    }
+}
+
+impl Default for ZSet {
+    fn default() -> Self {
+        ZSet::Listpack(Listpack::default())
+    }
+}
+
+impl ZSet {
+    /// 使用RDB文件中的listpack，成员数量超出限制时转换为SkipList
+    pub fn from_listpack(lp: Listpack) -> Self {
+        let mut zset = ZSet::Listpack(lp);
+        if zset.len() > CONFIG.advanced.zset_max_listpack_entries {
+            zset.convert_to_skiplist();
+        }
+        zset
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            ZSet::Listpack(_) => "listpack",
+            ZSet::SkipList(_) => "skiplist",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            ZSet::Listpack(lp) => lp.len() / 2,
+            ZSet::SkipList(sl) => sl.len(),
+        }
+    }
+
+    /// 估算的内存占用(字节)
+    pub fn mem_usage(&self) -> usize {
+        match self {
+            ZSet::Listpack(lp) => lp.bytes_len(),
+            ZSet::SkipList(sl) => sl.mem_usage(),
+        }
+    }
+
+    /// 添加成员或更新成员的分数，返回成员是否为新成员
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        if let ZSet::Listpack(lp) = self {
+            let pairs = listpack_pairs(lp);
+            let old = pairs.iter().position(|(m, _)| *m == member);
+            if member.len() > CONFIG.advanced.zset_max_listpack_value
+                || (old.is_none() && pairs.len() >= CONFIG.advanced.zset_max_listpack_entries)
+            {
+                // 成员过长或数量超出限制，转换为SkipList
+                self.convert_to_skiplist();
+            } else {
+                if let Some(pos) = old {
+                    if pairs[pos].1 == score {
+                        return false;
+                    }
+                    lp.remove_range(pos * 2, 2);
+                }
+                // 找到第一个排在新成员之后的成员，插入到它之前
+                let key = (Score(score), &member);
+                let pos = pairs
+                    .iter()
+                    .filter(|(m, _)| *m != member)
+                    .take_while(|(m, s)| (Score(*s), m) < key)
+                    .count();
+                lp.insert(pos * 2, &member);
+                lp.insert(pos * 2 + 1, format_score(score).as_bytes());
+                return old.is_none();
+            }
+        }
+
+        match self {
+            ZSet::SkipList(sl) => sl.insert(member, score),
+            ZSet::Listpack(_) => unreachable!("listpack should be converted to skiplist"),
+        }
+    }
+
+    /// 删除成员，返回成员是否存在
+    pub fn remove(&mut self, member: &Bytes) -> bool {
+        match self {
+            ZSet::Listpack(lp) => match listpack_pairs(lp).iter().position(|(m, _)| m == member) {
+                Some(pos) => {
+                    lp.remove_range(pos * 2, 2);
+                    true
+                }
+                None => false,
+            },
+            ZSet::SkipList(sl) => sl.remove(member),
+        }
+    }
+
+    pub fn score(&self, member: &Bytes) -> Option<f64> {
+        match self {
+            ZSet::Listpack(lp) => listpack_pairs(lp)
+                .into_iter()
+                .find(|(m, _)| m == member)
+                .map(|(_, score)| score),
+            ZSet::SkipList(sl) => sl.score(member),
+        }
+    }
+
+    /// 成员按分数从小到大排序的排名，从0开始
+    pub fn rank(&self, member: &Bytes) -> Option<usize> {
+        match self {
+            ZSet::Listpack(lp) => listpack_pairs(lp).iter().position(|(m, _)| m == member),
+            ZSet::SkipList(sl) => sl.rank(member),
+        }
+    }
+
+    /// 返回排名在[start, stop]之间的成员及其分数，负数排名从最后一名开始计算
+    pub fn range(&self, start: i64, stop: i64) -> Vec<(Bytes, f64)> {
+        match normalize_range(start, stop, self.len()) {
+            Some((start, stop)) => self.iter().skip(start).take(stop - start + 1).collect(),
+            None => vec![],
+        }
+    }
+
+    /// 从游标开始按扫描顺序返回约count个成员及其分数，以及下一次扫描的游标，游标为0代表扫描结束。
+    /// listpack中的成员较少，总是一次全部返回
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(Bytes, f64)>) {
+        match self {
+            ZSet::Listpack(lp) => (0, listpack_pairs(lp)),
+            ZSet::SkipList(sl) => {
+                let (next, members) = sl.index.scan(cursor, count);
+                let pairs = members
+                    .into_iter()
+                    .map(|member| {
+                        let score = sl.scores[&member];
+                        (member, score)
+                    })
+                    .collect();
+                (next.unwrap_or(0), pairs)
+            }
+        }
+    }
+
+    /// 按分数从小到大迭代成员及其分数
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (Bytes, f64)> + '_> {
+        match self {
+            ZSet::Listpack(lp) => Box::new(listpack_pairs(lp).into_iter()),
+            ZSet::SkipList(sl) => {
+                Box::new(sl.iter().map(|(member, score)| (member.clone(), score)))
+            }
+        }
+    }
+
+    fn convert_to_skiplist(&mut self) {
+        if let ZSet::Listpack(lp) = self {
+            let mut sl = SkipList::default();
+            for (member, score) in listpack_pairs(lp) {
+                sl.insert(member, score);
+            }
+            *self = ZSet::SkipList(sl);
+        }
+    }
+}
+
+// listpack中的所有成员及其分数，分数以字符串形式保存
+fn listpack_pairs(lp: &Listpack) -> Vec<(Bytes, f64)> {
+    let mut items = lp.iter();
+    std::iter::from_fn(|| {
+        let member = items.next()?;
+        let score = std::str::from_utf8(&items.next()?)
+            .ok()
+            .and_then(|score| score.parse().ok())
+            .unwrap_or_default();
+        Some((member, score))
+    })
+    .collect()
+}
+
+impl PartialEq for ZSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl FromIterator<(Bytes, f64)> for ZSet {
+    fn from_iter<I: IntoIterator<Item = (Bytes, f64)>>(iter: I) -> Self {
+        let mut zset = ZSet::default();
+        for (member, score) in iter {
+            zset.insert(member, score);
+        }
+        zset
+    }
+}
+
+/// 分数的字符串形式
+pub fn format_score(score: f64) -> String {
+    if score.is_infinite() {
+        if score > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        score.to_string()
+    }
+}
+
+impl Collection for ZSet {
+    fn new_value() -> ObjValue {
+        ObjValue::ZSet(ZSet::default())
+    }
+
+    fn from_value(value: &ObjValue) -> Option<&Self> {
+        match value {
+            ObjValue::ZSet(zset) => Some(zset),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut ObjValue) -> Option<&mut Self> {
+        match value {
+            ObjValue::ZSet(zset) => Some(zset),
+            _ => None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[test]
+fn test_zset() {
+    let mut zset: ZSet = [("b", 2.0), ("a", 2.0), ("c", 1.0)]
+        .into_iter()
+        .map(|(m, s)| (Bytes::from(m), s))
+        .collect();
+    // 分数相同时按成员排序
+    assert_eq!(zset.rank(&"c".into()), Some(0));
+    assert_eq!(zset.rank(&"a".into()), Some(1));
+    assert_eq!(zset.rank(&"b".into()), Some(2));
+
+    assert!(!zset.insert("c".into(), 3.0));
+    assert_eq!(zset.score(&"c".into()), Some(3.0));
+    assert_eq!(
+        zset.range(-2, -1),
+        vec![(Bytes::from("b"), 2.0), (Bytes::from("c"), 3.0)]
+    );
+    assert!(zset.remove(&"a".into()));
+    assert_eq!(zset.len(), 2);
+    assert_eq!(zset.range(5, 10), vec![]);
+}
+
+#[test]
+fn test_zset_encoding() {
+    let mut zset = ZSet::default();
+    for i in (0..100).rev() {
+        assert!(zset.insert(format!("m{:03}", i).into(), (i % 10) as f64));
+    }
+    assert_eq!(zset.encoding(), "listpack");
+    let listpack = zset.clone();
+    // 成员过长时转换为SkipList，排名和分数保持不变
+    zset.insert("x".repeat(100).into(), 0.5);
+    assert_eq!(zset.encoding(), "skiplist");
+    zset.remove(&"x".repeat(100).into());
+    assert_eq!(zset, listpack);
+    assert_eq!(zset.range(0, -1), listpack.range(0, -1));
+    assert_eq!(zset.rank(&"m019".into()), listpack.rank(&"m019".into()));
+
+    // 成员数量超出限制时转换为SkipList
+    let mut zset = listpack;
+    for i in 100..CONFIG.advanced.zset_max_listpack_entries {
+        zset.insert(format!("m{:03}", i).into(), 1.5);
+    }
+    assert_eq!(zset.encoding(), "listpack");
+    assert!(!zset.insert("m000".into(), f64::INFINITY));
+    assert_eq!(
+        zset.range(-1, -1),
+        vec![(Bytes::from("m000"), f64::INFINITY)]
+    );
+    zset.insert("new".into(), 0.0);
+    assert_eq!(zset.encoding(), "skiplist");
+    assert_eq!(zset.len(), CONFIG.advanced.zset_max_listpack_entries + 1);
+}
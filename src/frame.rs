@@ -1,16 +1,16 @@
 use crate::{
-    cmd::{self, CmdExecutor, Section},
-    util::{bytes_to_string, bytes_to_u64},
+    cmd::{self, CmdExecutor, Section, SubscriptionKind},
+    util::{bytes_to_i64, bytes_to_string, bytes_to_u64, sha1hex, RestorePolicy},
 };
 use anyhow::{anyhow, bail, Error, Result};
-use bytes::Bytes;
-use std::{fmt::Display, time::Duration, u64};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::{fmt::Display, time::Duration};
 
 #[derive(Clone, Debug, Default, PartialEq)]
 pub enum Frame {
     Simple(String), // +<str>\r\n
     Error(String),  // -<err>\r\n
-    Integer(u64),   // :<num>\r\n
+    Integer(i64),   // :<num>\r\n
     Bulk(Bytes),    // $<len>\r\n<bytes>\r\n
     #[default]
     Null, // $-1\r\n
@@ -24,17 +24,11 @@ impl Frame {
         let cmd_name = bytes_to_string(bulks[0].clone())?;
         match cmd_name.to_lowercase().as_str() {
             "command" => return Ok(Box::new(cmd::Command)),
-            "ping" => {
-                if len == 1 {
-                    return Ok(Box::new(cmd::Ping));
-                }
-            }
-            "echo" => {
-                if len == 2 {
-                    return Ok(Box::new(cmd::Echo {
-                        msg: bulks[1].clone(),
-                    }));
-                }
+            "ping" if len == 1 => return Ok(Box::new(cmd::Ping)),
+            "echo" if len == 2 => {
+                return Ok(Box::new(cmd::Echo {
+                    msg: bulks[1].clone(),
+                }))
             }
             "get" => {
                 if len == 2 {
@@ -44,6 +38,20 @@ impl Frame {
                 }
                 bail!("ERR wrong number of arguments for 'get' command")
             }
+            "del" => {
+                if len >= 2 {
+                    return Ok(Box::new(cmd::Del {
+                        keys: bulks[1..].to_vec(),
+                    }));
+                }
+                bail!("ERR wrong number of arguments for 'del' command")
+            }
+            "unlink" => {
+                check_arity("unlink", len >= 2)?;
+                return Ok(Box::new(cmd::Unlink {
+                    keys: bulks[1..].to_vec(),
+                }));
+            }
             "set" => return Ok(Box::new(cmd::Set::try_from(bulks)?) as Box<dyn CmdExecutor>),
             "info" => return Ok(Box::new(cmd::Info::try_from(bulks)?) as Box<dyn CmdExecutor>),
             "replconf" => match bulks[1].to_ascii_lowercase().as_slice() {
@@ -54,6 +62,21 @@ impl Frame {
                 }
                 _ => return Ok(Box::<cmd::Replconf>::default()),
             },
+            "auth" => match &bulks[1..] {
+                [password] => {
+                    return Ok(Box::new(cmd::Auth {
+                        username: None,
+                        password: bytes_to_string(password.clone())?,
+                    }))
+                }
+                [username, password] => {
+                    return Ok(Box::new(cmd::Auth {
+                        username: Some(bytes_to_string(username.clone())?),
+                        password: bytes_to_string(password.clone())?,
+                    }))
+                }
+                _ => bail!("ERR wrong number of arguments for 'auth' command"),
+            },
             "psync" => {
                 if let Some(replid) = bulks.get(1) {
                     // 如果replid为"?"，则表示replicate请求master进行全量同步
@@ -86,7 +109,355 @@ impl Frame {
                     }
                 }
             }
-            "bgsave" => return Ok(Box::new(cmd::BgSave)),
+            "bgsave" => {
+                let schedule = match bulks.get(1) {
+                    None => false,
+                    Some(arg) if len == 2 && arg.eq_ignore_ascii_case(b"schedule") => true,
+                    Some(_) => bail!("ERR syntax error"),
+                };
+                return Ok(Box::new(cmd::BgSave { schedule }));
+            }
+            "bgrewriteaof" => {
+                check_arity("bgrewriteaof", len == 1)?;
+                return Ok(Box::new(cmd::BgRewriteAof));
+            }
+            "save" => {
+                check_arity("save", len == 1)?;
+                return Ok(Box::new(cmd::Save));
+            }
+            "shutdown" => {
+                let (mut save, mut force) = (None, false);
+                for arg in &bulks[1..] {
+                    match arg.to_ascii_lowercase().as_slice() {
+                        b"nosave" if save.is_none() => save = Some(false),
+                        b"save" if save.is_none() => save = Some(true),
+                        b"force" => force = true,
+                        b"now" => {} // 没有需要等待的从节点，NOW没有作用
+                        _ => bail!("ERR syntax error"),
+                    }
+                }
+                return Ok(Box::new(cmd::Shutdown { save, force }));
+            }
+            "lastsave" if len == 1 => return Ok(Box::new(cmd::LastSave)),
+            "select" => {
+                if len == 2 {
+                    return Ok(Box::new(cmd::Select {
+                        index: parse_db_index(&bulks[1])?,
+                    }));
+                }
+                bail!("ERR wrong number of arguments for 'select' command")
+            }
+            "swapdb" => {
+                if len == 3 {
+                    return Ok(Box::new(cmd::SwapDb {
+                        index1: parse_db_index(&bulks[1])?,
+                        index2: parse_db_index(&bulks[2])?,
+                    }));
+                }
+                bail!("ERR wrong number of arguments for 'swapdb' command")
+            }
+            "move" => {
+                if len == 3 {
+                    return Ok(Box::new(cmd::Move {
+                        key: bulks[1].clone(),
+                        index: parse_db_index(&bulks[2])?,
+                    }));
+                }
+                bail!("ERR wrong number of arguments for 'move' command")
+            }
+            "dbsize" => {
+                if len == 1 {
+                    return Ok(Box::new(cmd::DbSize));
+                }
+                bail!("ERR wrong number of arguments for 'dbsize' command")
+            }
+            "keys" => {
+                if len == 2 {
+                    return Ok(Box::new(cmd::Keys {
+                        pattern: bulks[1].clone(),
+                    }));
+                }
+                bail!("ERR wrong number of arguments for 'keys' command")
+            }
+            "scan" => return Ok(Box::new(cmd::Scan::try_from(bulks)?) as Box<dyn CmdExecutor>),
+            "hscan" | "sscan" | "zscan" => {
+                return Ok(Box::new(cmd::CollectionScan::try_from(bulks)?) as Box<dyn CmdExecutor>)
+            }
+            "flushdb" => {
+                return Ok(Box::new(cmd::FlushDb {
+                    lazy: parse_flush_mode(&bulks)?,
+                }))
+            }
+            "flushall" => {
+                return Ok(Box::new(cmd::FlushAll {
+                    lazy: parse_flush_mode(&bulks)?,
+                }))
+            }
+            "type" => {
+                check_arity("type", len == 2)?;
+                return Ok(Box::new(cmd::Type {
+                    key: bulks[1].clone(),
+                }));
+            }
+            "exists" => {
+                check_arity("exists", len >= 2)?;
+                return Ok(Box::new(cmd::Exists {
+                    keys: bulks[1..].to_vec(),
+                }));
+            }
+            "expire" | "pexpire" | "expireat" | "pexpireat" => {
+                let name = cmd_name.to_lowercase();
+                check_arity(&name, len == 3)?;
+                return Ok(Box::new(cmd::Expire {
+                    key: bulks[1].clone(),
+                    expire_at_ms: parse_expire_at(&name, &bulks[2])?,
+                }));
+            }
+            "ttl" | "pttl" => {
+                let millis = cmd_name.eq_ignore_ascii_case("pttl");
+                check_arity(if millis { "pttl" } else { "ttl" }, len == 2)?;
+                return Ok(Box::new(cmd::Ttl {
+                    key: bulks[1].clone(),
+                    millis,
+                }));
+            }
+            "persist" => {
+                check_arity("persist", len == 2)?;
+                return Ok(Box::new(cmd::Persist {
+                    key: bulks[1].clone(),
+                }));
+            }
+            "lpush" | "rpush" => {
+                let end = parse_list_end(&cmd_name);
+                check_arity(&cmd_name.to_lowercase(), len >= 3)?;
+                return Ok(Box::new(cmd::Push {
+                    end,
+                    key: bulks[1].clone(),
+                    values: bulks[2..].to_vec(),
+                }));
+            }
+            "lpop" | "rpop" => {
+                let end = parse_list_end(&cmd_name);
+                check_arity(&cmd_name.to_lowercase(), len == 2 || len == 3)?;
+                let count = match bulks.get(2) {
+                    Some(count) => {
+                        let count = parse_int(count)?;
+                        if count < 0 {
+                            bail!("ERR value is out of range, must be positive");
+                        }
+                        Some(count as usize)
+                    }
+                    None => None,
+                };
+                return Ok(Box::new(cmd::Pop {
+                    end,
+                    key: bulks[1].clone(),
+                    count,
+                }));
+            }
+            "llen" => {
+                check_arity("llen", len == 2)?;
+                return Ok(Box::new(cmd::LLen {
+                    key: bulks[1].clone(),
+                }));
+            }
+            "lrange" => {
+                check_arity("lrange", len == 4)?;
+                return Ok(Box::new(cmd::LRange {
+                    key: bulks[1].clone(),
+                    start: parse_int(&bulks[2])?,
+                    stop: parse_int(&bulks[3])?,
+                }));
+            }
+            "lindex" => {
+                check_arity("lindex", len == 3)?;
+                return Ok(Box::new(cmd::LIndex {
+                    key: bulks[1].clone(),
+                    index: parse_int(&bulks[2])?,
+                }));
+            }
+            "hset" => {
+                check_arity("hset", len >= 4 && len.is_multiple_of(2))?;
+                return Ok(Box::new(cmd::HSet {
+                    key: bulks[1].clone(),
+                    pairs: bulks[2..]
+                        .chunks(2)
+                        .map(|pair| (pair[0].clone(), pair[1].clone()))
+                        .collect(),
+                }));
+            }
+            "hget" => {
+                check_arity("hget", len == 3)?;
+                return Ok(Box::new(cmd::HGet {
+                    key: bulks[1].clone(),
+                    field: bulks[2].clone(),
+                }));
+            }
+            "hdel" => {
+                check_arity("hdel", len >= 3)?;
+                return Ok(Box::new(cmd::HDel {
+                    key: bulks[1].clone(),
+                    fields: bulks[2..].to_vec(),
+                }));
+            }
+            "hlen" => {
+                check_arity("hlen", len == 2)?;
+                return Ok(Box::new(cmd::HLen {
+                    key: bulks[1].clone(),
+                }));
+            }
+            "hgetall" => {
+                check_arity("hgetall", len == 2)?;
+                return Ok(Box::new(cmd::HGetAll {
+                    key: bulks[1].clone(),
+                }));
+            }
+            "hexists" => {
+                check_arity("hexists", len == 3)?;
+                return Ok(Box::new(cmd::HExists {
+                    key: bulks[1].clone(),
+                    field: bulks[2].clone(),
+                }));
+            }
+            "sadd" => {
+                check_arity("sadd", len >= 3)?;
+                return Ok(Box::new(cmd::SAdd {
+                    key: bulks[1].clone(),
+                    members: bulks[2..].to_vec(),
+                }));
+            }
+            "srem" => {
+                check_arity("srem", len >= 3)?;
+                return Ok(Box::new(cmd::SRem {
+                    key: bulks[1].clone(),
+                    members: bulks[2..].to_vec(),
+                }));
+            }
+            "smembers" => {
+                check_arity("smembers", len == 2)?;
+                return Ok(Box::new(cmd::SMembers {
+                    key: bulks[1].clone(),
+                }));
+            }
+            "sismember" => {
+                check_arity("sismember", len == 3)?;
+                return Ok(Box::new(cmd::SIsMember {
+                    key: bulks[1].clone(),
+                    member: bulks[2].clone(),
+                }));
+            }
+            "scard" => {
+                check_arity("scard", len == 2)?;
+                return Ok(Box::new(cmd::SCard {
+                    key: bulks[1].clone(),
+                }));
+            }
+            "zadd" => {
+                check_arity("zadd", len >= 4 && len.is_multiple_of(2))?;
+                let pairs = bulks[2..]
+                    .chunks(2)
+                    .map(|pair| Ok((parse_score(&pair[0])?, pair[1].clone())))
+                    .collect::<Result<_>>()?;
+                return Ok(Box::new(cmd::ZAdd {
+                    key: bulks[1].clone(),
+                    pairs,
+                }));
+            }
+            "zrem" => {
+                check_arity("zrem", len >= 3)?;
+                return Ok(Box::new(cmd::ZRem {
+                    key: bulks[1].clone(),
+                    members: bulks[2..].to_vec(),
+                }));
+            }
+            "zscore" => {
+                check_arity("zscore", len == 3)?;
+                return Ok(Box::new(cmd::ZScore {
+                    key: bulks[1].clone(),
+                    member: bulks[2].clone(),
+                }));
+            }
+            "zcard" => {
+                check_arity("zcard", len == 2)?;
+                return Ok(Box::new(cmd::ZCard {
+                    key: bulks[1].clone(),
+                }));
+            }
+            "zrange" => {
+                check_arity("zrange", len == 4 || len == 5)?;
+                let with_scores = match bulks.get(4) {
+                    Some(option) if option.eq_ignore_ascii_case(b"withscores") => true,
+                    Some(_) => bail!("ERR syntax error"),
+                    None => false,
+                };
+                return Ok(Box::new(cmd::ZRange {
+                    key: bulks[1].clone(),
+                    start: parse_int(&bulks[2])?,
+                    stop: parse_int(&bulks[3])?,
+                    with_scores,
+                }));
+            }
+            "zrank" => {
+                check_arity("zrank", len == 3)?;
+                return Ok(Box::new(cmd::ZRank {
+                    key: bulks[1].clone(),
+                    member: bulks[2].clone(),
+                }));
+            }
+            "subscribe" | "psubscribe" | "ssubscribe" => {
+                let kind = match cmd_name.to_lowercase().as_str() {
+                    "subscribe" => SubscriptionKind::Channel,
+                    "psubscribe" => SubscriptionKind::Pattern,
+                    _ => SubscriptionKind::ShardChannel,
+                };
+                let names = bulks[1..].to_vec();
+                cmd::check_subscribe_args(kind, &names)?;
+                return Ok(Box::new(cmd::Subscribe { kind, names }));
+            }
+            "unsubscribe" | "punsubscribe" | "sunsubscribe" => {
+                let kind = match cmd_name.to_lowercase().as_str() {
+                    "unsubscribe" => SubscriptionKind::Channel,
+                    "punsubscribe" => SubscriptionKind::Pattern,
+                    _ => SubscriptionKind::ShardChannel,
+                };
+                return Ok(Box::new(cmd::Unsubscribe {
+                    kind,
+                    names: bulks[1..].to_vec(),
+                }));
+            }
+            "publish" => {
+                if len == 3 {
+                    return Ok(Box::new(cmd::Publish {
+                        channel: bulks[1].clone(),
+                        message: bulks[2].clone(),
+                    }));
+                }
+                bail!("ERR wrong number of arguments for 'publish' command")
+            }
+            "spublish" => {
+                if len == 3 {
+                    return Ok(Box::new(cmd::SPublish {
+                        channel: bulks[1].clone(),
+                        message: bulks[2].clone(),
+                    }));
+                }
+                bail!("ERR wrong number of arguments for 'spublish' command")
+            }
+            "eval" | "evalsha" | "eval_ro" | "evalsha_ro" => {
+                return Ok(Box::new(cmd::Eval::try_from(bulks)?) as Box<dyn CmdExecutor>)
+            }
+            "script" => return Ok(Box::new(cmd::Script::try_from(bulks)?) as Box<dyn CmdExecutor>),
+            "object" => return Ok(Box::new(cmd::Object::try_from(bulks)?) as Box<dyn CmdExecutor>),
+            "memory" => return Ok(Box::new(cmd::Memory::try_from(bulks)?) as Box<dyn CmdExecutor>),
+            "fcall" | "fcall_ro" => {
+                return Ok(Box::new(cmd::FCall::try_from(bulks)?) as Box<dyn CmdExecutor>)
+            }
+            "function" => {
+                return Ok(Box::new(cmd::FunctionCmd::try_from(bulks)?) as Box<dyn CmdExecutor>)
+            }
+            "pubsub" => {
+                return Ok(Box::new(cmd::PubSubCmd::try_from(bulks)?) as Box<dyn CmdExecutor>)
+            }
             _ => {}
         }
 
@@ -113,8 +484,29 @@ impl Frame {
         }
     }
 
+    /// 编码为RESP格式，与写入连接的内容一致。值中可以包含任意字节
     pub fn to_bytes(&self) -> Bytes {
-        self.to_string().replace("\\r\\n", "\r\n").into()
+        let mut buf = BytesMut::with_capacity(self.num_of_bytes() as usize);
+        self.encode(&mut buf);
+        buf.freeze()
+    }
+
+    fn encode(&self, buf: &mut BytesMut) {
+        match self {
+            Frame::Simple(s) => buf.put_slice(format!("+{}\r\n", s).as_bytes()),
+            Frame::Error(e) => buf.put_slice(format!("-{}\r\n", e).as_bytes()),
+            Frame::Integer(n) => buf.put_slice(format!(":{}\r\n", n).as_bytes()),
+            Frame::Bulk(b) => {
+                buf.put_slice(format!("${}\r\n", b.len()).as_bytes());
+                buf.put_slice(b);
+                buf.put_slice(b"\r\n");
+            }
+            Frame::Null => buf.put_slice(b"$-1\r\n"),
+            Frame::Array(frames) => {
+                buf.put_slice(format!("*{}\r\n", frames.len()).as_bytes());
+                frames.iter().for_each(|frame| frame.encode(buf));
+            }
+        }
     }
 }
 
@@ -161,7 +553,7 @@ impl TryFrom<Vec<Bytes>> for cmd::Set {
             }
             if len == 4 {
                 #[allow(clippy::single_match)]
-                match bulks[4].to_ascii_lowercase().as_slice() {
+                match bulks[3].to_ascii_lowercase().as_slice() {
                     b"keepttl" => {
                         return Ok(cmd::Set {
                             key,
@@ -233,6 +625,370 @@ impl TryFrom<Vec<Bytes>> for cmd::Info {
     }
 }
 
+impl TryFrom<Vec<Bytes>> for cmd::PubSubCmd {
+    type Error = Error;
+    fn try_from(bulks: Vec<Bytes>) -> Result<Self, Self::Error> {
+        let len = bulks.len();
+        if len < 2 {
+            bail!("ERR wrong number of arguments for 'pubsub' command")
+        }
+        match bulks[1].to_ascii_lowercase().as_slice() {
+            b"channels" if len <= 3 => Ok(cmd::PubSubCmd::Channels(bulks.get(2).cloned())),
+            b"numsub" => Ok(cmd::PubSubCmd::NumSub(bulks[2..].to_vec())),
+            b"numpat" if len == 2 => Ok(cmd::PubSubCmd::NumPat),
+            b"shardchannels" if len <= 3 => {
+                Ok(cmd::PubSubCmd::ShardChannels(bulks.get(2).cloned()))
+            }
+            b"shardnumsub" => Ok(cmd::PubSubCmd::ShardNumSub(bulks[2..].to_vec())),
+            _ => Err(anyhow!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'",
+                bytes_to_string(bulks[1].clone())?
+            )),
+        }
+    }
+}
+
+impl TryFrom<Vec<Bytes>> for cmd::Eval {
+    type Error = Error;
+
+    fn try_from(bulks: Vec<Bytes>) -> Result<Self, Self::Error> {
+        let name = bytes_to_string(bulks[0].clone())?.to_lowercase();
+        let (keys, args) = split_keys_and_args(&name, &bulks)?;
+        let (sha, body) = if name.starts_with("evalsha") {
+            (bytes_to_string(bulks[1].clone())?, None)
+        } else {
+            (sha1hex(&bulks[1]), Some(bulks[1].clone()))
+        };
+        Ok(cmd::Eval {
+            sha,
+            body,
+            keys,
+            args,
+            read_only: name.ends_with("_ro"),
+            effects: std::sync::Mutex::new(Vec::new()),
+        })
+    }
+}
+
+impl TryFrom<Vec<Bytes>> for cmd::FCall {
+    type Error = Error;
+
+    fn try_from(bulks: Vec<Bytes>) -> Result<Self, Self::Error> {
+        let name = bytes_to_string(bulks[0].clone())?.to_lowercase();
+        let (keys, args) = split_keys_and_args(&name, &bulks)?;
+        Ok(cmd::FCall {
+            name: bytes_to_string(bulks[1].clone())?,
+            keys,
+            args,
+            read_only: name.ends_with("_ro"),
+            effects: std::sync::Mutex::new(Vec::new()),
+        })
+    }
+}
+
+impl TryFrom<Vec<Bytes>> for cmd::Scan {
+    type Error = Error;
+    // SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+    fn try_from(bulks: Vec<Bytes>) -> Result<Self, Self::Error> {
+        if bulks.len() < 2 {
+            bail!("ERR wrong number of arguments for 'scan' command");
+        }
+        let mut type_name = None;
+        let args = parse_scan_args(&bulks[1], &bulks[2..], |option, value| {
+            if option != b"type" {
+                return Ok(false);
+            }
+            let name = bytes_to_string(value.clone())?.to_lowercase();
+            if !["string", "list", "set", "zset", "hash", "stream"].contains(&name.as_str()) {
+                bail!("ERR unknown type name '{}'", name);
+            }
+            type_name = Some(name);
+            Ok(true)
+        })?;
+        Ok(cmd::Scan { args, type_name })
+    }
+}
+
+impl TryFrom<Vec<Bytes>> for cmd::CollectionScan {
+    type Error = Error;
+    // HSCAN|SSCAN|ZSCAN key cursor [MATCH pattern] [COUNT count]
+    fn try_from(bulks: Vec<Bytes>) -> Result<Self, Self::Error> {
+        let name = bytes_to_string(bulks[0].clone())?.to_lowercase();
+        if bulks.len() < 3 {
+            bail!("ERR wrong number of arguments for '{}' command", name);
+        }
+        let kind = match name.as_str() {
+            "hscan" => cmd::ScanKind::Hash,
+            "sscan" => cmd::ScanKind::Set,
+            _ => cmd::ScanKind::ZSet,
+        };
+        Ok(cmd::CollectionScan {
+            kind,
+            key: bulks[1].clone(),
+            args: parse_scan_args(&bulks[2], &bulks[3..], |_, _| Ok(false))?,
+        })
+    }
+}
+
+// 解析SCAN系列命令的游标以及[MATCH pattern] [COUNT count]，其它选项交给extra处理，
+// extra返回false代表不认识该选项
+fn parse_scan_args(
+    cursor: &Bytes,
+    options: &[Bytes],
+    mut extra: impl FnMut(&[u8], &Bytes) -> Result<bool>,
+) -> Result<cmd::ScanArgs> {
+    let cursor = bytes_to_u64(cursor.clone()).map_err(|_| anyhow!("ERR invalid cursor"))?;
+    let mut args = cmd::ScanArgs {
+        cursor,
+        pattern: None,
+        count: 10,
+    };
+    for pair in options.chunks(2) {
+        let [option, value] = pair else {
+            bail!("ERR syntax error");
+        };
+        match option.to_ascii_lowercase().as_slice() {
+            b"match" => args.pattern = Some(value.clone()),
+            b"count" => {
+                let count = bytes_to_u64(value.clone())
+                    .map_err(|_| anyhow!("ERR value is not an integer or out of range"))?;
+                if count < 1 {
+                    bail!("ERR syntax error");
+                }
+                args.count = count as usize;
+            }
+            other => {
+                if !extra(other, value)? {
+                    bail!("ERR syntax error");
+                }
+            }
+        }
+    }
+    Ok(args)
+}
+
+// 数据库编号必须是整数，是否超出数据库数量的范围由执行命令时检查
+fn parse_db_index(index: &Bytes) -> Result<usize> {
+    let index = parse_int(index)?;
+    usize::try_from(index).map_err(|_| anyhow!("ERR DB index is out of range"))
+}
+
+fn check_arity(name: &str, ok: bool) -> Result<()> {
+    if !ok {
+        bail!("ERR wrong number of arguments for '{}' command", name);
+    }
+    Ok(())
+}
+
+fn parse_int(value: &Bytes) -> Result<i64> {
+    bytes_to_i64(value.clone()).map_err(|_| anyhow!("ERR value is not an integer or out of range"))
+}
+
+// 分数可以是inf, +inf, -inf，但不能是NaN
+fn parse_score(value: &Bytes) -> Result<f64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|score| !score.is_nan())
+        .ok_or_else(|| anyhow!("ERR value is not a valid float"))
+}
+
+fn parse_list_end(name: &str) -> cmd::ListEnd {
+    if name.to_ascii_lowercase().starts_with('l') {
+        cmd::ListEnd::Left
+    } else {
+        cmd::ListEnd::Right
+    }
+}
+
+// 将EXPIRE, PEXPIRE, EXPIREAT, PEXPIREAT的参数转换为以毫秒为单位的Unix时间
+fn parse_expire_at(name: &str, value: &Bytes) -> Result<i64> {
+    let value = parse_int(value)?;
+    let expire_at = match name {
+        "expire" => value
+            .checked_mul(1000)
+            .and_then(|ms| ms.checked_add(cmd::unix_time_ms())),
+        "pexpire" => value.checked_add(cmd::unix_time_ms()),
+        "expireat" => value.checked_mul(1000),
+        _ => Some(value),
+    };
+    expire_at.ok_or_else(|| anyhow!("ERR invalid expire time in '{}' command", name))
+}
+
+// FLUSHDB和FLUSHALL的[ASYNC|SYNC]参数，返回是否在后台释放数据
+fn parse_flush_mode(bulks: &[Bytes]) -> Result<bool> {
+    match bulks.len() {
+        1 => Ok(false),
+        2 => match bulks[1].to_ascii_lowercase().as_slice() {
+            b"async" => Ok(true),
+            b"sync" => Ok(false),
+            _ => bail!("ERR syntax error"),
+        },
+        _ => bail!("ERR syntax error"),
+    }
+}
+
+// EVAL/FCALL的参数格式为：<script|sha1|function> numkeys [key ...] [arg ...]
+fn split_keys_and_args(name: &str, bulks: &[Bytes]) -> Result<(Vec<Bytes>, Vec<Bytes>)> {
+    if bulks.len() < 3 {
+        bail!("ERR wrong number of arguments for '{}' command", name);
+    }
+    let numkeys: i64 = bytes_to_string(bulks[2].clone())?
+        .parse()
+        .map_err(|_| Error::msg("ERR value is not an integer or out of range"))?;
+    if numkeys < 0 {
+        bail!("ERR Number of keys can't be negative");
+    }
+    if numkeys as usize > bulks.len() - 3 {
+        bail!("ERR Number of keys can't be greater than number of args");
+    }
+    let keys_end = 3 + numkeys as usize;
+    Ok((bulks[3..keys_end].to_vec(), bulks[keys_end..].to_vec()))
+}
+
+impl TryFrom<Vec<Bytes>> for cmd::FunctionCmd {
+    type Error = Error;
+
+    fn try_from(bulks: Vec<Bytes>) -> Result<Self, Self::Error> {
+        let sub = match bulks.get(1) {
+            Some(sub) => bytes_to_string(sub.clone())?.to_lowercase(),
+            None => bail!("ERR wrong number of arguments for 'function' command"),
+        };
+        let args: Vec<Vec<u8>> = bulks[2..].iter().map(|b| b.to_ascii_lowercase()).collect();
+        let args: Vec<&[u8]> = args.iter().map(|a| a.as_slice()).collect();
+        let syntax_err = || anyhow!("ERR syntax error");
+
+        let res = match (sub.as_str(), args.as_slice()) {
+            ("load", [b"replace", _]) => cmd::FunctionCmd::Load {
+                code: bulks[3].clone(),
+                replace: true,
+            },
+            ("load", [_]) => cmd::FunctionCmd::Load {
+                code: bulks[2].clone(),
+                replace: false,
+            },
+            ("list", _) => {
+                let (mut pattern, mut with_code) = (None, false);
+                let mut i = 0;
+                while i < args.len() {
+                    match args[i] {
+                        b"withcode" => with_code = true,
+                        b"libraryname" if i + 1 < args.len() => {
+                            i += 1;
+                            pattern = Some(bulks[2 + i].clone());
+                        }
+                        _ => return Err(syntax_err()),
+                    }
+                    i += 1;
+                }
+                cmd::FunctionCmd::List { pattern, with_code }
+            }
+            ("delete", [_]) => cmd::FunctionCmd::Delete(bytes_to_string(bulks[2].clone())?),
+            ("flush", [] | [b"sync" | b"async"]) => cmd::FunctionCmd::Flush,
+            ("dump", []) => cmd::FunctionCmd::Dump,
+            ("restore", [_, policy @ ..]) => {
+                let policy = match policy {
+                    [] | [b"append"] => RestorePolicy::Append,
+                    [b"replace"] => RestorePolicy::Replace,
+                    [b"flush"] => RestorePolicy::Flush,
+                    _ => return Err(syntax_err()),
+                };
+                cmd::FunctionCmd::Restore {
+                    payload: bulks[2].clone(),
+                    policy,
+                }
+            }
+            ("kill", []) => cmd::FunctionCmd::Kill,
+            _ => bail!(
+                "ERR unknown subcommand or wrong number of arguments for 'function|{}' command",
+                sub
+            ),
+        };
+        Ok(res)
+    }
+}
+
+impl TryFrom<Vec<Bytes>> for cmd::Script {
+    type Error = Error;
+
+    fn try_from(bulks: Vec<Bytes>) -> Result<Self, Self::Error> {
+        let sub = match bulks.get(1) {
+            Some(sub) => bytes_to_string(sub.clone())?.to_lowercase(),
+            None => bail!("ERR wrong number of arguments for 'script' command"),
+        };
+        let res = match (sub.as_str(), bulks.len()) {
+            ("load", 3) => cmd::Script::Load(bulks[2].clone()),
+            ("exists", len) if len >= 3 => cmd::Script::Exists(bulks[2..].to_vec()),
+            ("flush", 2) => cmd::Script::Flush,
+            ("flush", 3)
+                if bulks[2].eq_ignore_ascii_case(b"sync")
+                    || bulks[2].eq_ignore_ascii_case(b"async") =>
+            {
+                cmd::Script::Flush
+            }
+            ("kill", 2) => cmd::Script::Kill,
+            _ => bail!(
+                "ERR unknown subcommand or wrong number of arguments for 'script|{}' command",
+                sub
+            ),
+        };
+        Ok(res)
+    }
+}
+
+impl TryFrom<Vec<Bytes>> for cmd::Object {
+    type Error = Error;
+
+    fn try_from(bulks: Vec<Bytes>) -> Result<Self, Self::Error> {
+        let sub = match bulks.get(1) {
+            Some(sub) => bytes_to_string(sub.clone())?.to_lowercase(),
+            None => bail!("ERR wrong number of arguments for 'object' command"),
+        };
+        let res = match (sub.as_str(), bulks.len()) {
+            ("encoding", 3) => cmd::Object::Encoding(bulks[2].clone()),
+            ("refcount", 3) => cmd::Object::RefCount(bulks[2].clone()),
+            ("idletime", 3) => cmd::Object::IdleTime(bulks[2].clone()),
+            ("freq", 3) => cmd::Object::Freq(bulks[2].clone()),
+            ("help", 2) => cmd::Object::Help,
+            _ => bail!(
+                "ERR unknown subcommand or wrong number of arguments for 'object|{}' command",
+                sub
+            ),
+        };
+        Ok(res)
+    }
+}
+
+impl TryFrom<Vec<Bytes>> for cmd::Memory {
+    type Error = Error;
+
+    fn try_from(bulks: Vec<Bytes>) -> Result<Self, Self::Error> {
+        let sub = match bulks.get(1) {
+            Some(sub) => bytes_to_string(sub.clone())?.to_lowercase(),
+            None => bail!("ERR wrong number of arguments for 'memory' command"),
+        };
+        let res = match (sub.as_str(), bulks.len()) {
+            ("usage", 3) => cmd::Memory::Usage(bulks[2].clone()),
+            // SAMPLES只检查参数是否合法
+            ("usage", 5) if bulks[3].eq_ignore_ascii_case(b"samples") => {
+                if parse_int(&bulks[4])? < 0 {
+                    bail!("ERR value is out of range, must be positive");
+                }
+                cmd::Memory::Usage(bulks[2].clone())
+            }
+            ("usage", _) => bail!("ERR syntax error"),
+            ("stats", 2) => cmd::Memory::Stats,
+            ("doctor", 2) => cmd::Memory::Doctor,
+            ("help", 2) => cmd::Memory::Help,
+            _ => bail!(
+                "ERR unknown subcommand or wrong number of arguments for 'memory|{}' command",
+                sub
+            ),
+        };
+        Ok(res)
+    }
+}
+
 impl TryInto<Vec<Bytes>> for Frame {
     type Error = Error;
 
@@ -286,5 +1042,14 @@ mod test_frame {
             Frame::Null,
         ]);
         assert_eq!(frame.num_of_bytes(), 37);
+        assert_eq!(
+            frame.to_bytes(),
+            "*5\r\n+OK\r\n-ERR\r\n:100\r\n$5\r\nHello\r\n$-1\r\n".as_bytes()
+        );
+
+        // 值中的\r\n以及非UTF-8的字节会被原样编码
+        let frame = Frame::Bulk(bytes::Bytes::from_static(b"a\r\n\xff"));
+        assert_eq!(frame.to_bytes(), b"$4\r\na\r\n\xff\r\n".as_slice());
+        assert_eq!(frame.to_bytes().len() as u64, frame.num_of_bytes());
     }
 }
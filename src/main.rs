@@ -56,5 +56,15 @@ mod util;
 async fn main() {
     init::init();
 
+    // --check-rdb只检查RDB文件，不启动服务器
+    if let Some(path) = <cli::Cli as clap::Parser>::parse().check_rdb {
+        if let Err(e) = util::rdb_check(&path) {
+            println!("--- RDB ERROR DETECTED ---");
+            println!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     server::run().await;
 }
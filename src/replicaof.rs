@@ -1,9 +1,10 @@
 use crate::{
+    cmd::Transaction,
     conf::{CONFIG, OFFSET},
     db::Db,
     frame::Frame,
     stream::FrameHandler,
-    util,
+    util::{self, bytes_to_u64},
 };
 use anyhow::{bail, Result};
 use bytes::Bytes;
@@ -11,7 +12,6 @@ use std::sync::atomic::Ordering;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
-    select,
     sync::broadcast::Sender,
 };
 
@@ -40,10 +40,18 @@ pub async fn enable_replicaof(
         }
     }
 
+    // 握手完成后进行全量复制，之后master会将写命令传播过来
+    if let Err(e) = full_replication(&mut to_master, &db).await {
+        panic!("Fail to replicate: {e:?}");
+    }
+
+    // master会将事务包裹在MULTI/EXEC中传播过来
+    let mut txn = Transaction::default();
     loop {
         // 处理master server发送过来的命令
         match handle_master_connection(
             &mut to_master,
+            &mut txn,
             &db,
             &psync_to_others_sender,
             &others_to_psync_sender,
@@ -64,7 +72,7 @@ async fn full_replication(to_master: &mut TcpStream, db: &Db) -> Result<()> {
     to_master
         .write_frame(vec!["PSYNC".into(), "?".into(), "-1".into()].into())
         .await?;
-    // recv {FULLRESYNC <REPL_ID> 0}
+    // recv {FULLRESYNC <REPL_ID> <OFFSET>}
     if let Some(Frame::Simple(s)) = to_master.read_frame().await? {
         if !s.starts_with("FULLRESYNC") {
             bail!("Fail to replicate.");
@@ -74,62 +82,11 @@ async fn full_replication(to_master: &mut TcpStream, db: &Db) -> Result<()> {
         bail!("Fail to replicate.");
     }
 
-    // 从master server接收RDB文件，并写入本地
-    let rdb = get_rdb(to_master).await.expect("Fail to get rdb");
-    tokio::fs::write("dump.rdb", rdb)
-        .await
-        .expect("Fail to write rdb.");
-
-    // 从本地加载RDB文件
-    let mut retry = 3;
-    while let Err(e) = util::rdb_load(&mut db.inner.write().await) {
-        tracing::error!("{} Trying again.", e);
-        retry -= 1;
-        if retry == 0 {
-            panic!("Fail to load rdb.");
-        }
-    }
-
-    // 从master server接收RDB文件，并写入本地
+    // 从master server接收RDB数据，清空原有的数据后载入
     let rdb = get_rdb(to_master).await?;
-    tokio::fs::write("dump.rdb", rdb).await?;
-
-    Ok(())
-}
-
-async fn partial_replication(to_master: &mut TcpStream, db: &Db, replid: String) -> Result<()> {
-    // // send {PSYNC <REPL_ID> <OFFSET>}
-    // to_master
-    //     .write_frame(
-    //         vec![
-    //             "PSYNC".into(),
-    //             replid.into(),
-    //             OFFSET.load(Ordering::SeqCst).into(),
-    //         ]
-    //         .into(),
-    //     )
-    //     .await?;
-    // // recv {CONTINUE}
-    // if to_master.read_frame().await? != Some(Frame::Simple("CONTINUE".to_string())) {
-    //     bail!("Fail to replicate.");
-    // }
-    //
-    // // 从master server接收增量数据
-    // loop {
-    //     let frame = to_master.read_frame().await?;
-    //     if let Some(frame) = frame {
-    //         tracing::info!("received from master: {}", frame);
-    //         let cmd = frame.clone().parse_cmd()?;
-    //         if let Some(res) = cmd.replicate_execute(db).await? {
-    //             tracing::info!("sending to master: {}", res);
-    //             to_master.write_frame(res).await?;
-    //         }
-    //         cmd.hook(to_master, db, frame.clone()).await?;
-    //         OFFSET.fetch_add(frame.num_of_bytes(), Ordering::SeqCst);
-    //     } else {
-    //         break;
-    //     }
-    // }
+    let mut dbs = db.write_all().await;
+    dbs.iter_mut().for_each(|db| drop(db.flush()));
+    util::rdb_load_preamble(&mut dbs, rdb)?;
 
     Ok(())
 }
@@ -153,6 +110,8 @@ async fn replicaof_hanshake(to_master: &mut TcpStream, port: u16) -> Result<()>
                     bail!("Master server does not respond correctly");
                 }
             }
+            // 与Redis一致，master设置了requirepass时PING会返回NOAUTH，之后再进行身份验证
+            Some(Frame::Error(e)) if e.starts_with("NOAUTH") => {}
             Some(Frame::Error(e)) => {
                 // master server返回一个错误
                 bail!("Master server responds an error——{}", e);
@@ -209,16 +168,53 @@ async fn replicaof_hanshake(to_master: &mut TcpStream, port: u16) -> Result<()>
     Ok(())
 }
 
+// $<length_of_file>\r\n<contents_of_file>，或者$EOF:<分隔符>\r\n<contents_of_file><分隔符>。
+// RDB数据之后没有\r\n
 async fn get_rdb(to_master: &mut TcpStream) -> Result<Vec<u8>> {
-    let _ = to_master.read_u8().await;
-    let rdb_len = to_master.read_decimal().await?;
-    let mut buf = vec![0u8; rdb_len as usize];
-    to_master.read_exact(&mut buf).await?;
-    Ok(buf)
+    if to_master.read_u8().await? != b'$' {
+        bail!("Master server responds invaildly");
+    }
+    let line = to_master.read_line().await?;
+    let Some(mark) = line.strip_prefix(b"EOF:") else {
+        let rdb_len = bytes_to_u64(line)?;
+        let mut buf = vec![0u8; rdb_len as usize];
+        to_master.read_exact(&mut buf).await?;
+        return Ok(buf);
+    };
+    if mark.is_empty() {
+        bail!("Master server responds invaildly");
+    }
+
+    // RDB数据之后紧接着是master传播的命令，因此先窥视数据，找到分隔符之后只读取到分隔符为止
+    let mut buf = Vec::new();
+    let mut chunk = vec![0u8; 64 * 1024];
+    loop {
+        let n = to_master.peek(&mut chunk).await?;
+        if n == 0 {
+            bail!("Connection closed while receiving RDB from master");
+        }
+        // 分隔符可能被分成两次接收，需要在已接收数据的末尾和新数据中一起查找
+        let tail = buf.len().saturating_sub(mark.len() - 1);
+        let window = [&buf[tail..], &chunk[..n]].concat();
+        match window.windows(mark.len()).position(|w| w == mark) {
+            Some(pos) => {
+                let len = tail + pos + mark.len() - buf.len();
+                to_master.read_exact(&mut chunk[..len]).await?;
+                buf.extend_from_slice(&chunk[..len]);
+                buf.truncate(buf.len() - mark.len());
+                return Ok(buf);
+            }
+            None => {
+                to_master.read_exact(&mut chunk[..n]).await?;
+                buf.extend_from_slice(&chunk[..n]);
+            }
+        }
+    }
 }
 
 async fn handle_master_connection(
     stream: &mut TcpStream,
+    txn: &mut Transaction,
     db: &Db,
     psync_to_others_sender: &Sender<Frame>,
     others_to_psync_sender: &Sender<Frame>,
@@ -226,6 +222,16 @@ async fn handle_master_connection(
     if let Some(frame) = stream.read_frame().await? {
         tracing::info!("received from master: {}", frame);
 
+        // 事务中的命令不需要回复master
+        if txn
+            .process(&frame, db, others_to_psync_sender)
+            .await?
+            .is_some()
+        {
+            OFFSET.fetch_add(frame.num_of_bytes(), Ordering::SeqCst);
+            return Ok(Some(()));
+        }
+
         let cmd = frame.clone().parse_cmd()?;
         if let Some(res) = cmd.replicate_execute(db).await? {
             tracing::info!("sending to master: {}", res);
@@ -1,11 +1,17 @@
-use crate::util;
-use crate::{conf::CONFIG, db::Db, frame::Frame, stream::FrameHandler};
+use crate::{
+    cmd::{self, Transaction},
+    conf::{CONFIG, SCRIPTING},
+    db::{self, Db},
+    frame::Frame,
+    stream::FrameHandler,
+    util,
+};
 use anyhow::Result;
 use std::net::SocketAddr;
-use std::time::Duration;
 use tokio::sync::broadcast::Sender;
 use tokio::{
     net::{TcpListener, TcpStream},
+    signal::unix::{signal, SignalKind},
     sync::broadcast::channel,
 };
 use tracing::{debug, error};
@@ -15,6 +21,7 @@ pub async fn run() {
     // return;
 
     let db = Db::new();
+    util::rdb_status_init();
 
     // 创建一个广播通道，当replacate回复消息给Psync协程时，它可以向其它协程广播该消息
     let (replacate_msg_sender, _replacate_msg_receiver) =
@@ -30,29 +37,39 @@ pub async fn run() {
         write_cmd_sender.clone(),
     );
 
-    let finished_notify = std::sync::Arc::new(tokio::sync::Notify::new());
-    // 如果配置了AOF持久化，则加载AOF文件
-    CONFIG
-        .may_enable_aof(write_cmd_sender.clone(), finished_notify.clone())
-        .await;
+    // 如果配置了AOF持久化，则加载AOF文件，之后的写命令会被追加到AOF文件中
+    CONFIG.may_enable_aof(&db).await;
 
     // 如果配置了RDB持久化，则加载RDB文件。(当RDB和AOF同时开启时，只会加载AOF文件)
-    CONFIG.may_enable_rdb(&mut db.inner.write().await);
+    CONFIG.may_enable_rdb(&mut db.write_all().await);
 
-    // 开启一个异步任务，定时检查过期键
-    util::check_expiration_periodical(
-        Duration::from_secs(CONFIG.server.expire_check_interval_secs),
-        &db,
-    )
-    .await;
+    // 开启一个异步任务，定期随机抽样并删除过期键
+    db::spawn_active_expire(&db);
+    // 开启一个异步任务，按照save规则自动进行后台保存
+    util::spawn_auto_save(&db);
+    // 开启一个异步任务，执行推迟的AOF重写，并在AOF文件增长到一定大小时自动重写
+    util::spawn_auto_rewrite(&db);
 
     let listener = TcpListener::bind(format!("127.0.0.1:{}", CONFIG.server.port))
         .await
         .expect("Fail to connect");
     tracing::info!("server is running on port {}", CONFIG.server.port);
 
+    let mut sigterm = signal(SignalKind::terminate()).expect("Fail to listen for SIGTERM");
     loop {
-        match listener.accept().await {
+        // 收到SIGINT或SIGTERM时，保存数据后退出
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = tokio::signal::ctrl_c() => {
+                shutdown_on_signal(&db, "SIGINT").await;
+                continue;
+            }
+            _ = sigterm.recv() => {
+                shutdown_on_signal(&db, "SIGTERM").await;
+                continue;
+            }
+        };
+        match accepted {
             Ok((mut stream, addr)) => {
                 debug!("accepted new connection from {addr}");
 
@@ -60,9 +77,13 @@ pub async fn run() {
                 let replacate_msg_sender = replacate_msg_sender.clone();
                 let write_cmd_sender = write_cmd_sender.clone();
                 tokio::spawn(async move {
+                    let mut txn = Transaction::default();
+                    let mut authenticated = false;
                     loop {
                         match handle(
                             &mut stream,
+                            &mut txn,
+                            &mut authenticated,
                             &db,
                             &replacate_msg_sender,
                             &write_cmd_sender,
@@ -80,7 +101,6 @@ pub async fn run() {
                         }
                     }
                 });
-                finished_notify.notify_one();
             }
             Err(e) => {
                 error!("error: {}", e);
@@ -89,8 +109,19 @@ pub async fn run() {
     }
 }
 
+// 保存失败时不退出，与SHUTDOWN命令一致
+async fn shutdown_on_signal(db: &Db, signal: &str) {
+    tracing::warn!("Received {signal} scheduling shutdown...");
+    if cmd::prepare_for_shutdown(db, None).await.is_ok() {
+        std::process::exit(0);
+    }
+    error!("{signal} received but errors trying to shut down the server, check the logs for more information");
+}
+
 async fn handle(
     stream: &mut TcpStream,
+    txn: &mut Transaction,
+    authenticated: &mut bool,
     db: &Db,
     psync_to_others_sender: &Sender<Frame>,
     others_to_psync_sender: &Sender<Frame>,
@@ -103,10 +134,50 @@ async fn handle(
     if let Some(frame) = stream.read_frame().await? {
         tracing::info!("received from client: {}", frame);
 
+        // 设置了requirepass时，通过AUTH认证之前只能执行AUTH
+        let is_auth = is_auth(&frame);
+        if CONFIG.security.requirepass.is_some() && !*authenticated && !is_auth {
+            anyhow::bail!("NOAUTH Authentication required.");
+        }
+
+        // 脚本执行超时后，只接受少数命令
+        SCRIPTING.check_busy(&frame)?;
+
+        // 命令执行并传播之前不能开始AOF重写
+        let propagation_guard = util::aof_propagation_guard().await;
+
+        // 事务相关的命令，以及处于事务中时入队的命令
+        if let Some(res) = txn.process(&frame, db, others_to_psync_sender).await? {
+            drop(propagation_guard);
+            util::aof_wait_fsync().await;
+            tracing::info!("sending to client: {}", res);
+            stream.write_frame(res).await?;
+            return Ok(Some(()));
+        }
+
         let cmd = frame.clone().parse_cmd()?; // 解析Frame为一个命令
 
-        // 执行命令，如果命令需要返回结果，则将结果写入stream
-        if let Some(res) = cmd.execute(db).await? {
+        // 后台保存失败时拒绝写命令
+        cmd::deny_write_on_bgsave_error(cmd.is_write())?;
+        // 内存超出maxmemory时先淘汰键
+        cmd::evict_before_execute(db, others_to_psync_sender, cmd.deny_oom()).await?;
+
+        let res = cmd.execute(db).await?;
+        if is_auth && res == Some(Frame::Simple("OK".to_string())) {
+            *authenticated = true;
+        }
+
+        // 如果该节点是主节点，则将写命令传播给从节点和AOF
+        cmd::propagate(
+            others_to_psync_sender,
+            db.index(),
+            cmd.propagation(frame.clone()),
+        )?;
+        drop(propagation_guard);
+
+        // 如果命令需要返回结果，则将结果写入stream。append_fsync为always时，写命令fsync之后才回复
+        if let Some(res) = res {
+            util::aof_wait_fsync().await;
             tracing::info!("sending to client: {}", res);
             stream.write_frame(res).await?;
         }
@@ -127,3 +198,12 @@ async fn handle(
         Ok(None)
     }
 }
+
+fn is_auth(frame: &Frame) -> bool {
+    match frame {
+        Frame::Array(args) => {
+            matches!(args.first(), Some(Frame::Bulk(name)) if name.eq_ignore_ascii_case(b"auth"))
+        }
+        _ => false,
+    }
+}
@@ -1,8 +1,6 @@
-use std::usize;
-
 use crate::{
     frame::Frame,
-    util::{bytes_to_string, bytes_to_u64},
+    util::{bytes_to_i64, bytes_to_string, bytes_to_u64},
 };
 use anyhow::{bail, Result};
 use bytes::{BufMut, Bytes};
@@ -50,20 +48,7 @@ impl FrameHandler for TcpStream {
     }
 
     async fn write_frame(&mut self, frame: Frame) -> Result<()> {
-        match frame {
-            // *<len>\r\n<Frame>...
-            Frame::Array(frames) => {
-                let header = format!("*{}\r\n", frames.len());
-                self.write_all(header.as_bytes()).await?;
-
-                for frame in frames {
-                    write_value(self, frame).await?;
-                }
-            }
-            _ => write_value(self, frame).await?,
-        }
-
-        Ok(())
+        write_value(self, frame).await
     }
 
     async fn read_line(&mut self) -> Result<Bytes> {
@@ -128,7 +113,7 @@ async fn read_value(stream: &mut TcpStream) -> Result<Frame> {
         b':' => {
             debug!("reading integer");
 
-            let res = stream.read_decimal().await?;
+            let res = bytes_to_i64(stream.read_line().await?)?;
 
             debug!(?res);
 
@@ -145,7 +130,17 @@ async fn read_value(stream: &mut TcpStream) -> Result<Frame> {
 
             Ok(res)
         }
-        b'*' => unreachable!(),
+        b'*' => {
+            debug!("reading nested array");
+
+            let len = stream.read_decimal().await? as usize;
+            let mut frames = Vec::with_capacity(len);
+            for _ in 0..len {
+                frames.push(Box::pin(read_value(stream)).await?);
+            }
+
+            Ok(Frame::Array(frames))
+        }
         somthing => {
             error!("read invaild prefix {}", somthing);
             bail!("ERR syntax error")
@@ -186,7 +181,15 @@ async fn write_value(stream: &mut TcpStream, frame: Frame) -> Result<()> {
             stream.write_all(b"$-1\r\n").await?;
             stream.flush().await?;
         }
-        Frame::Array(_) => unreachable!(),
+        // *<len>\r\n<Frame>...，数组中可以嵌套数组
+        Frame::Array(frames) => {
+            let header = format!("*{}\r\n", frames.len());
+            stream.write_all(header.as_bytes()).await?;
+
+            for frame in frames {
+                Box::pin(write_value(stream, frame)).await?;
+            }
+        }
     }
 
     Ok(())
@@ -0,0 +1,279 @@
+//! 启动时载入AOF文件。AOF中保存的是RESP格式的写命令，与客户端发送的命令格式相同，
+//! 载入时逐条读取并直接交给命令执行器执行，不经过网络连接。重写后的AOF文件以RDB格式的数据开头，
+//! 先载入RDB数据，再执行之后的命令
+
+use crate::{cmd::Transaction, conf::CONFIG, db::Db, frame::Frame, util::rdb_load_preamble};
+use anyhow::{bail, Context};
+use bytes::Bytes;
+use std::{
+    io::{self, SeekFrom},
+    path::Path,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, BufReader},
+    sync::broadcast,
+};
+
+/// 载入AOF文件，AOF文件不存在时不载入任何数据。与Redis的aof-load-truncated一致，文件末尾的命令
+/// 不完整(如写入时宕机)或者事务缺少EXEC时，忽略这部分命令并将文件截断到最后一条完整的命令
+pub async fn aof_load(db: &Db) -> anyhow::Result<()> {
+    aof_load_from(Path::new(&CONFIG.aof.file_path), db).await
+}
+
+pub(super) async fn aof_load_from(path: &Path, db: &Db) -> anyhow::Result<()> {
+    let file = match File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut reader = AofReader {
+        reader: BufReader::new(file),
+        offset: 0,
+    };
+
+    if reader.reader.fill_buf().await?.starts_with(b"REDIS") {
+        let buf = tokio::fs::read(path).await?;
+        let len = rdb_load_preamble(&mut db.write_all().await, buf).with_context(|| {
+            format!(
+                "Bad RDB preamble in the append only file {}",
+                path.display()
+            )
+        })?;
+        reader.reader.seek(SeekFrom::Start(len)).await?;
+        reader.offset = len;
+    }
+
+    // AOF从0号数据库开始执行
+    let db = db.clone();
+    db.select(0)?;
+    let mut txn = Transaction::default();
+    // 载入时执行的写命令不需要再次传播
+    let (write_cmd_sender, _write_cmd_receiver) = broadcast::channel(1);
+    // 最后一条完整的命令(不在事务中)结束的位置
+    let mut valid_len = reader.offset;
+    loop {
+        let start = reader.offset;
+        let frame = match reader.read_command().await? {
+            AofCommand::Command(frame) => frame,
+            AofCommand::Eof => break,
+            AofCommand::Truncated => {
+                tracing::warn!(
+                    "!!! Warning: short read while loading the AOF file {}!!!",
+                    path.display()
+                );
+                break;
+            }
+        };
+
+        // 事务相关的命令，以及事务中入队的命令
+        match txn.process(&frame, &db, &write_cmd_sender).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                let cmd = frame.parse_cmd().with_context(|| {
+                    format!("Bad command reading the append only file at offset {start}")
+                })?;
+                if let Err(e) = cmd.execute(&db).await {
+                    tracing::warn!(
+                        "Error executing the command at offset {start} of the AOF file: {e}"
+                    );
+                }
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Error executing the command at offset {start} of the AOF file: {e}"
+                );
+            }
+        }
+        if !txn.in_multi() {
+            valid_len = reader.offset;
+        }
+    }
+
+    if valid_len < reader.offset {
+        if txn.in_multi() {
+            tracing::warn!("Revert incomplete MULTI/EXEC transaction in AOF file");
+        }
+        tracing::warn!(
+            "AOF loaded anyway because aof-load-truncated is enabled, truncating {} to {valid_len} bytes",
+            path.display()
+        );
+        let file = std::fs::OpenOptions::new().write(true).open(path)?;
+        file.set_len(valid_len)?;
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+// 与Redis的proto-max-bulk-len默认值一致，避免损坏的长度导致分配过多的内存
+const PROTO_MAX_BULK_LEN: u64 = 512 * 1024 * 1024;
+
+enum AofCommand {
+    Command(Frame),
+    Eof,
+    Truncated, // 文件在一条命令的中间结束
+}
+
+struct AofReader {
+    reader: BufReader<File>,
+    offset: u64, // 已读取的字节数
+}
+
+impl AofReader {
+    // 读取一条命令：*<argc>\r\n($<len>\r\n<arg>\r\n)*
+    async fn read_command(&mut self) -> anyhow::Result<AofCommand> {
+        if self.reader.fill_buf().await?.is_empty() {
+            return Ok(AofCommand::Eof);
+        }
+        let start = self.offset;
+        let Some(argc) = self.read_len(b'*').await? else {
+            return Ok(AofCommand::Truncated);
+        };
+        if argc == 0 {
+            bail!("Bad file format reading the append only file at offset {start}");
+        }
+
+        let mut args = Vec::with_capacity(argc.min(1024) as usize);
+        for _ in 0..argc {
+            let Some(len) = self.read_len(b'$').await? else {
+                return Ok(AofCommand::Truncated);
+            };
+            if len > PROTO_MAX_BULK_LEN {
+                bail!("Bad file format reading the append only file at offset {start}");
+            }
+            let mut arg = vec![0; len as usize + 2];
+            match self.reader.read_exact(&mut arg).await {
+                Ok(_) => self.offset += arg.len() as u64,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(AofCommand::Truncated)
+                }
+                Err(e) => return Err(e.into()),
+            }
+            if !arg.ends_with(b"\r\n") {
+                bail!("Bad file format reading the append only file at offset {start}");
+            }
+            arg.truncate(len as usize);
+            args.push(Frame::Bulk(Bytes::from(arg)));
+        }
+        Ok(AofCommand::Command(Frame::Array(args)))
+    }
+
+    // 读取<prefix><len>\r\n，文件在行的中间结束时返回None
+    async fn read_len(&mut self, prefix: u8) -> anyhow::Result<Option<u64>> {
+        let start = self.offset;
+        let mut line = Vec::new();
+        self.offset += self.reader.read_until(b'\n', &mut line).await? as u64;
+        let Some(line) = line.strip_suffix(b"\r\n") else {
+            return Ok(None);
+        };
+        match line.split_first() {
+            Some((&p, len)) if p == prefix => std::str::from_utf8(len)
+                .ok()
+                .and_then(|len| len.parse().ok())
+                .map(Some)
+                .with_context(|| {
+                    format!("Bad file format reading the append only file at offset {start}")
+                }),
+            _ => bail!("Bad file format reading the append only file at offset {start}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod aof_load_test {
+    use super::*;
+    use crate::db::ObjValue;
+
+    #[test]
+    fn test_aof_load() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let path = std::env::temp_dir().join(format!("aof-load-{}.aof", std::process::id()));
+        let cmd = |args: &[&[u8]]| {
+            Frame::Array(
+                args.iter()
+                    .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg)))
+                    .collect(),
+            )
+            .to_bytes()
+        };
+        let mut aof = Vec::new();
+        aof.extend(cmd(&[b"SET", b"key", b"a\r\nb\n\xff"]));
+        aof.extend(cmd(&[b"SELECT", b"1"]));
+        aof.extend(cmd(&[b"MULTI"]));
+        aof.extend(cmd(&[b"LPUSH", b"list", b"a"]));
+        aof.extend(cmd(&[b"LPUSH", b"list", b"b"]));
+        aof.extend(cmd(&[b"EXEC"]));
+        let complete = aof.len() as u64;
+        // 缺少EXEC的事务以及不完整的命令会被忽略
+        aof.extend(cmd(&[b"MULTI"]));
+        aof.extend(cmd(&[b"DEL", b"list"]));
+        aof.extend(&cmd(&[b"SET", b"other", b"value"])[..10]);
+        std::fs::write(&path, &aof).unwrap();
+
+        let db = Db::with_databases(2);
+        runtime.block_on(aof_load_from(&path, &db)).unwrap();
+        runtime.block_on(async {
+            let dbs = db.write_all().await;
+            assert_eq!(
+                dbs[0].peek(&Bytes::from("key")).unwrap().value,
+                ObjValue::from_bytes(Bytes::from_static(b"a\r\nb\n\xff"))
+            );
+            let list = dbs[1].peek(&Bytes::from("list")).unwrap();
+            assert_eq!(
+                list.value,
+                ObjValue::List(["b", "a"].into_iter().map(Bytes::from).collect())
+            );
+            assert_eq!(dbs[1].len(), 1);
+        });
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), complete);
+
+        // 格式错误的文件不会被载入
+        std::fs::write(&path, b"SET key value\r\n").unwrap();
+        assert!(runtime.block_on(aof_load_from(&path, &db)).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        // AOF文件不存在时不载入任何数据
+        assert!(runtime.block_on(aof_load_from(&path, &db)).is_ok());
+    }
+
+    #[test]
+    fn test_aof_load_preamble() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let path = std::env::temp_dir().join(format!("aof-preamble-{}.aof", std::process::id()));
+        let db = Db::with_databases(2);
+        runtime.block_on(async {
+            let mut dbs = db.write_all().await;
+            dbs[0].set_string("key".into(), "old".into(), None, false);
+            dbs[1].set_string("other".into(), "value".into(), None, false);
+        });
+        let snapshot = runtime.block_on(db.snapshot());
+        let mut aof = crate::util::rdb_encode_aof_preamble(&snapshot, Vec::new()).unwrap();
+        drop(snapshot);
+        // 重写期间追加的命令
+        let cmd = |args: &[&str]| {
+            Frame::Array(
+                args.iter()
+                    .map(|arg| Frame::Bulk(Bytes::from(arg.to_string())))
+                    .collect(),
+            )
+            .to_bytes()
+        };
+        aof.extend(cmd(&["SET", "key", "new"]));
+        aof.extend(cmd(&["SELECT", "1"]));
+        aof.extend(cmd(&["DEL", "other"]));
+        std::fs::write(&path, &aof).unwrap();
+
+        let db = Db::with_databases(2);
+        runtime.block_on(aof_load_from(&path, &db)).unwrap();
+        runtime.block_on(async {
+            let dbs = db.write_all().await;
+            assert_eq!(
+                dbs[0].peek(&Bytes::from("key")).unwrap().value,
+                ObjValue::from_bytes(Bytes::from("new"))
+            );
+            assert_eq!(dbs[1].len(), 0);
+        });
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), aof.len() as u64);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+//! AOF重写(BGREWRITEAOF)。重写时生成键空间的快照，在后台线程中将快照以RDB格式写入临时文件，
+//! 作为新AOF文件的开头(与Redis的aof-use-rdb-preamble相同)；之后追加重写期间缓存的写命令，
+//! 最后原子地替换AOF文件。开启AOF时，文件相对于上一次重写增长到一定比例后会自动重写
+
+use super::{Aof, AOF};
+use crate::{
+    conf::CONFIG,
+    db::{Db, Snapshot},
+    util::{rdb_encode_aof_preamble, rdb_status},
+};
+use anyhow::bail;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{RwLock, RwLockReadGuard};
+
+// 重写缓冲区中的命令少于该值时，才锁住AOF缓冲区追加剩余的命令并替换文件，缩短写命令等待的时间
+const REWRITE_DIFF_FINAL_SIZE: usize = 64 * 1024;
+// 不锁住AOF缓冲区时最多追加的批数，避免写命令持续较多时重写无法结束
+const REWRITE_DIFF_MAX_ROUNDS: usize = 16;
+// 自动重写失败后，至少等待该时间才会再次自动重写
+const REWRITE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+static REWRITE_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+static REWRITE_SCHEDULED: AtomicBool = AtomicBool::new(false); // 因后台保存正在进行而推迟的重写
+static LAST_REWRITE_TIME: AtomicI64 = AtomicI64::new(-1); // 最后一次重写的耗时(秒)，-1代表还没有重写过
+static LAST_REWRITE_OK: AtomicBool = AtomicBool::new(true);
+
+// 执行写命令并传播期间持有读锁，开始重写时持有写锁。因此快照之前执行的写命令已被追加到AOF缓冲区，
+// 快照之后执行的写命令都会被追加到重写缓冲区，新的AOF文件既不会缺少也不会重复写命令
+static PROPAGATION_BARRIER: RwLock<()> = RwLock::const_new(());
+
+/// AOF重写的状态，用于INFO persistence
+pub struct AofRewriteStatus {
+    pub in_progress: bool,
+    pub scheduled: bool,
+    pub last_rewrite_time_sec: i64,
+    pub last_rewrite_ok: bool,
+}
+
+pub fn aof_rewrite_status() -> AofRewriteStatus {
+    AofRewriteStatus {
+        in_progress: REWRITE_IN_PROGRESS.load(Ordering::Relaxed),
+        scheduled: REWRITE_SCHEDULED.load(Ordering::Relaxed),
+        last_rewrite_time_sec: LAST_REWRITE_TIME.load(Ordering::Relaxed),
+        last_rewrite_ok: LAST_REWRITE_OK.load(Ordering::Relaxed),
+    }
+}
+
+/// 执行写命令并传播之前获取，传播之后释放。持有期间不会开始AOF重写
+pub async fn aof_propagation_guard() -> RwLockReadGuard<'static, ()> {
+    PROPAGATION_BARRIER.read().await
+}
+
+/// 后台保存完成后再开始重写，用于BGREWRITEAOF
+pub fn aof_schedule_rewrite() {
+    REWRITE_SCHEDULED.store(true, Ordering::Relaxed);
+}
+
+/// 在后台重写AOF文件，完成后更新aof_last_bgrewrite_status。已经有重写正在进行时返回错误。
+/// 调用者可能持有aof_propagation_guard，因此在另一个异步任务中等待写命令传播完成后再生成快照
+pub fn aof_bgrewrite(db: &Db) -> anyhow::Result<()> {
+    if REWRITE_IN_PROGRESS
+        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        bail!("ERR Background append only file rewriting already in progress");
+    }
+    REWRITE_SCHEDULED.store(false, Ordering::Relaxed);
+
+    let db = db.clone();
+    tokio::spawn(async move {
+        let start = Instant::now();
+        let snapshot = {
+            let _barrier = PROPAGATION_BARRIER.write().await;
+            let snapshot = db.snapshot().await;
+            if let Some(aof) = AOF.get() {
+                aof.lock().rewrite_buf = Some(Vec::new());
+            }
+            // 新的AOF文件从0号数据库开始执行，之后的写命令需要重新传播SELECT
+            crate::cmd::reset_propagated_db();
+            snapshot
+        };
+
+        std::thread::spawn(move || {
+            let res = rewrite(snapshot);
+            if let Some(aof) = AOF.get() {
+                aof.lock().rewrite_buf = None;
+            }
+            match &res {
+                Ok(_) => tracing::info!("Background AOF rewrite finished successfully"),
+                Err(e) => tracing::error!("Background AOF rewrite error: {:?}", e),
+            }
+            LAST_REWRITE_OK.store(res.is_ok(), Ordering::Relaxed);
+            LAST_REWRITE_TIME.store(start.elapsed().as_secs() as i64, Ordering::Relaxed);
+            REWRITE_IN_PROGRESS.store(false, Ordering::Release);
+        });
+    });
+    Ok(())
+}
+
+// 写入临时文件并替换AOF文件，失败时删除临时文件
+fn rewrite(snapshot: Snapshot) -> io::Result<()> {
+    let path = Path::new(&CONFIG.aof.file_path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let temp = dir.join(format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+
+    let res = write_and_replace(snapshot, AOF.get(), path, dir, &temp);
+    if res.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    res
+}
+
+fn write_and_replace(
+    snapshot: Snapshot,
+    aof: Option<&Aof>,
+    path: &Path,
+    dir: &Path,
+    temp: &Path,
+) -> io::Result<()> {
+    let mut file = File::create(temp)?;
+    rdb_encode_aof_preamble(&snapshot, &mut file)?;
+    drop(snapshot);
+
+    let Some(aof) = aof else {
+        // 未开启AOF时没有需要追加的写命令
+        file.sync_all()?;
+        fs::rename(temp, path)?;
+        return File::open(dir)?.sync_all();
+    };
+
+    // 分批追加重写期间缓存的写命令
+    for _ in 0..REWRITE_DIFF_MAX_ROUNDS {
+        let diff = aof
+            .lock()
+            .rewrite_buf
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default();
+        file.write_all(&diff)?;
+        if diff.len() < REWRITE_DIFF_FINAL_SIZE {
+            break;
+        }
+    }
+    file.sync_all()?;
+
+    // 锁住AOF缓冲区，追加剩余的命令之后替换文件，之后的写命令会被写入新的AOF文件。
+    // 缓冲区中还未写入的命令也在重写缓冲区中，已经被写入新的AOF文件，需要清空缓冲区，否则会被重复写入
+    let mut state = aof.lock();
+    let diff = state.rewrite_buf.take().unwrap_or_default();
+    file.write_all(&diff)?;
+    file.sync_data()?;
+    let len = file.metadata()?.len();
+    let new_file = OpenOptions::new().append(true).open(temp)?;
+    fs::rename(temp, path)?;
+    state.file = Arc::new(new_file);
+    state.buf.clear();
+    let end = aof.appended.load(Ordering::Acquire);
+    aof.mark_written(end);
+    aof.base_size.store(len, Ordering::Relaxed);
+    drop(state);
+
+    tracing::info!("Successfully renamed the temporary AOF file");
+    File::open(dir)?.sync_all()?;
+    aof.mark_synced(end);
+    Ok(())
+}
+
+/// 开启一个异步任务，每秒执行hz次检查：没有后台保存正在进行时，执行推迟的重写；开启AOF时，
+/// AOF文件不小于auto_aof_rewrite_min_size，且相对于上一次重写之后的大小增长了
+/// auto_aof_rewrite_percentage%时自动重写
+pub fn spawn_auto_rewrite(db: &Db) {
+    let db = db.clone();
+    let hz = CONFIG.server.hz.clamp(1, 500);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_micros(1_000_000 / hz));
+        let mut last_try = None;
+        loop {
+            interval.tick().await;
+            if REWRITE_IN_PROGRESS.load(Ordering::Acquire) || rdb_status().bgsave_in_progress {
+                continue;
+            }
+
+            if REWRITE_SCHEDULED.load(Ordering::Relaxed) {
+                if let Err(e) = aof_bgrewrite(&db) {
+                    tracing::error!("Failed to start scheduled AOF rewrite: {e}");
+                }
+                continue;
+            }
+
+            let Some(aof) = AOF.get() else {
+                continue;
+            };
+            let percentage = CONFIG.aof.auto_aof_rewrite_percentage;
+            if percentage == 0 {
+                continue;
+            }
+            if !LAST_REWRITE_OK.load(Ordering::Relaxed)
+                && last_try.is_some_and(|t: Instant| t.elapsed() < REWRITE_RETRY_DELAY)
+            {
+                continue;
+            }
+            let size = match aof.file().metadata() {
+                Ok(metadata) => metadata.len(),
+                Err(_) => continue,
+            };
+            if size < CONFIG.aof.auto_aof_rewrite_min_size {
+                continue;
+            }
+            let base = aof.base_size.load(Ordering::Relaxed).max(1);
+            let growth = size.saturating_sub(base) * 100 / base;
+            if growth >= percentage {
+                tracing::info!("Starting automatic rewriting of AOF on {growth}% growth");
+                last_try = Some(Instant::now());
+                if let Err(e) = aof_bgrewrite(&db) {
+                    tracing::error!("Failed to start automatic AOF rewrite: {e}");
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod aof_rewrite_test {
+    use super::*;
+    use crate::{conf::AppendFSync, db::ObjValue, frame::Frame, util::aof::write_all};
+    use bytes::Bytes;
+
+    #[test]
+    fn test_rewrite_pending_commands() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("aof-rewrite-{}.aof", std::process::id()));
+        let temp = dir.join(format!("aof-rewrite-{}.temp", std::process::id()));
+        let rpush = Frame::Array(vec![
+            Frame::Bulk(Bytes::from("RPUSH")),
+            Frame::Bulk(Bytes::from("list")),
+            Frame::Bulk(Bytes::from("a")),
+        ])
+        .to_bytes();
+        std::fs::write(&path, b"").unwrap();
+        let aof = Aof::new(OpenOptions::new().append(true).open(&path).unwrap()).unwrap();
+
+        let db = Db::with_databases(1);
+        let snapshot = runtime.block_on(db.snapshot());
+        aof.lock().rewrite_buf = Some(Vec::new());
+        // 重写期间追加、还未写入旧文件的命令
+        aof.append(&rpush);
+        aof.append(&rpush);
+        write_and_replace(snapshot, Some(&aof), &path, &dir, &temp).unwrap();
+        assert!(aof.lock().buf.is_empty());
+        assert_eq!(
+            aof.written.load(Ordering::Acquire),
+            aof.appended.load(Ordering::Acquire)
+        );
+
+        // 替换之后追加的命令写入新的AOF文件
+        aof.append(&rpush);
+        let (buf, _, file) = aof.take_buf();
+        write_all(&aof, &file, &buf, AppendFSync::No);
+
+        let db = Db::with_databases(1);
+        runtime
+            .block_on(super::super::aof_load::aof_load_from(&path, &db))
+            .unwrap();
+        runtime.block_on(async {
+            let dbs = db.write_all().await;
+            // 每条命令只执行一次
+            assert_eq!(
+                dbs[0].peek(&Bytes::from("list")).unwrap().value,
+                ObjValue::List(vec![Bytes::from("a"); 3].into_iter().collect())
+            );
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+}
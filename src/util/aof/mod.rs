@@ -1,66 +1,307 @@
-use std::{io::Write, time::Duration};
+//! AOF持久化。写命令在传播时以RESP格式追加到缓冲区，后台线程将缓冲区写入AOF文件，
+//! 并按照append_fsync进行fsync：
+//! - always：每次写入之后立即fsync，客户端在fsync完成之后才会收到回复。写入和fsync期间
+//!   其它客户端追加的写命令会在下一次写入时一起提交(group commit)
+//! - everysec：另一个后台线程每秒fsync一次。上一次fsync还未完成时写入最多推迟2秒，
+//!   超时后仍然写入，并计入aof_delayed_fsync
+//! - no：只写入文件，由操作系统决定何时刷新到磁盘
+//!
+//! BGREWRITEAOF重写AOF文件期间追加的命令还会被保存到重写缓冲区，重写完成后追加到新的AOF文件
 
-use crate::{conf::CONFIG, frame::Frame};
-use bytes::BytesMut;
-use tokio::{fs::File, io::AsyncWriteExt, sync::broadcast::Receiver};
+mod aof_load;
+mod aof_rewrite;
 
-pub struct Aof {
-    pub file: File,
-    pub buffer: BytesMut,
-    pub write_cmd_receiver: Receiver<Frame>, // 从写命令的通道中接收命令, 并写入到buffer中
+pub use aof_load::aof_load;
+pub use aof_rewrite::{
+    aof_bgrewrite, aof_propagation_guard, aof_rewrite_status, aof_schedule_rewrite,
+    spawn_auto_rewrite,
+};
+
+use crate::{
+    conf::{AppendFSync, CONFIG},
+    frame::Frame,
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+// everysec策略下，上一次fsync还未完成时写入最多推迟的时间
+const AOF_MAX_WRITE_DELAY: Duration = Duration::from_secs(2);
+// 写入失败后重试的间隔
+const AOF_WRITE_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+static AOF: OnceLock<Aof> = OnceLock::new();
+
+// 以下的偏移量都是启动之后追加的命令的总字节数，重写AOF文件不会改变偏移量
+struct Aof {
+    state: Mutex<AofState>,
+    buf_ready: Condvar,
+    appended: AtomicU64,        // 已追加到缓冲区的命令的结束位置
+    written: AtomicU64,         // 已写入文件的命令的结束位置
+    synced: watch::Sender<u64>, // 已fsync的命令的结束位置
+    base_size: AtomicU64,       // 启动或最后一次重写之后AOF文件的大小，用于自动重写
+    fsync_in_progress: AtomicBool,
+    delayed_fsync: AtomicU64,
+    last_write_ok: AtomicBool,
+}
+
+struct AofState {
+    buf: Vec<u8>,                 // 已追加但还未写入文件的命令
+    rewrite_buf: Option<Vec<u8>>, // 重写期间追加的命令，不在重写时为None
+    file: Arc<File>,              // 重写完成后替换为新的AOF文件
 }
 
 impl Aof {
-    pub async fn new(write_cmd_receiver: Receiver<Frame>) -> anyhow::Result<Self> {
+    fn new(file: File) -> io::Result<Aof> {
+        let len = file.metadata()?.len();
         Ok(Aof {
-            file: tokio::fs::OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(&CONFIG.aof.file_path)
-                .await?,
-            buffer: BytesMut::with_capacity(1024 * 1024 * 1024),
-            write_cmd_receiver,
+            state: Mutex::new(AofState {
+                buf: Vec::new(),
+                rewrite_buf: None,
+                file: Arc::new(file),
+            }),
+            buf_ready: Condvar::new(),
+            appended: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+            synced: watch::Sender::new(0),
+            base_size: AtomicU64::new(len),
+            fsync_in_progress: AtomicBool::new(false),
+            delayed_fsync: AtomicU64::new(0),
+            last_write_ok: AtomicBool::new(true),
         })
     }
 
-    pub fn append(&mut self, cmd: &str) {
-        self.buffer.extend_from_slice(cmd.as_bytes());
+    fn lock(&self) -> MutexGuard<'_, AofState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn file(&self) -> Arc<File> {
+        self.lock().file.clone()
+    }
+
+    fn append(&self, bytes: &[u8]) {
+        let mut state = self.lock();
+        state.buf.extend_from_slice(bytes);
+        if let Some(rewrite_buf) = &mut state.rewrite_buf {
+            rewrite_buf.extend_from_slice(bytes);
+        }
+        self.appended
+            .fetch_add(bytes.len() as u64, Ordering::Release);
+        self.buf_ready.notify_one();
+    }
+
+    // 等待缓冲区中有命令，取出缓冲区中的命令，返回命令的结束位置以及需要写入的文件
+    fn take_buf(&self) -> (Vec<u8>, u64, Arc<File>) {
+        let mut state = self.lock();
+        while state.buf.is_empty() {
+            state = self
+                .buf_ready
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        (
+            std::mem::take(&mut state.buf),
+            self.appended.load(Ordering::Acquire),
+            state.file.clone(),
+        )
+    }
+
+    // 重写完成时会直接推进偏移量，因此偏移量只增不减
+    fn mark_written(&self, end: u64) {
+        self.written.fetch_max(end, Ordering::Release);
+    }
+
+    fn mark_synced(&self, end: u64) {
+        self.synced.send_if_modified(|synced| {
+            let modified = end > *synced;
+            *synced = (*synced).max(end);
+            modified
+        });
+    }
+}
+
+/// AOF持久化的状态，用于INFO persistence
+pub struct AofStatus {
+    pub last_write_ok: bool,
+    pub delayed_fsync: u64,
+}
+
+pub fn aof_status() -> AofStatus {
+    AOF.get().map_or(
+        AofStatus {
+            last_write_ok: true,
+            delayed_fsync: 0,
+        },
+        |aof| AofStatus {
+            last_write_ok: aof.last_write_ok.load(Ordering::Relaxed),
+            delayed_fsync: aof.delayed_fsync.load(Ordering::Relaxed),
+        },
+    )
+}
+
+/// 打开AOF文件并启动写入AOF文件的后台线程，需要在载入AOF文件之后调用
+pub fn aof_start() -> io::Result<()> {
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&CONFIG.aof.file_path)?;
+    if AOF.set(Aof::new(file)?).is_err() {
+        return Err(io::Error::other("AOF is already started"));
+    }
+
+    thread::Builder::new()
+        .name("aof-write".into())
+        .spawn(write_loop)?;
+    if CONFIG.aof.append_fsync == AppendFSync::EverySec {
+        thread::Builder::new()
+            .name("aof-fsync".into())
+            .spawn(fsync_loop)?;
     }
+    Ok(())
+}
 
-    pub async fn write_in(&mut self) -> anyhow::Result<()> {
-        if self.buffer.is_empty() {
-            return Ok(());
+/// 将写命令以RESP格式追加到AOF缓冲区。未开启AOF时不做任何事
+pub fn aof_append(cmd: &Frame) {
+    let Some(aof) = AOF.get() else {
+        return;
+    };
+    // 发布的消息只通过复制流传播给从节点，不需要持久化
+    if let Frame::Array(frames) = cmd {
+        if let Some(Frame::Bulk(name)) = frames.first() {
+            if name.eq_ignore_ascii_case(b"spublish") {
+                return;
+            }
         }
-        // 清空buffer, 并将buffer中的数据写入到文件中
-        let buffer = std::mem::take(&mut self.buffer);
-        self.file.write_all(&buffer).await?;
+    }
 
-        Ok(())
+    aof.append(&cmd.to_bytes());
+}
+
+/// append_fsync为always时，等待已追加的写命令fsync完成，之后才能回复客户端。
+/// 其它策略以及未开启AOF时立即返回
+pub async fn aof_wait_fsync() {
+    let Some(aof) = AOF.get() else {
+        return;
+    };
+    if CONFIG.aof.append_fsync != AppendFSync::Always {
+        return;
     }
+    let target = aof.appended.load(Ordering::Acquire);
+    let mut synced = aof.synced.subscribe();
+    let _ = synced.wait_for(|&synced| synced >= target).await;
+}
 
-    pub async fn fsync(&mut self) -> anyhow::Result<()> {
-        self.file.sync_all().await?;
-        Ok(())
+/// 关闭服务器前将缓冲区中的写命令全部写入AOF文件并fsync
+pub async fn aof_flush() -> anyhow::Result<()> {
+    let Some(aof) = AOF.get() else {
+        return Ok(());
+    };
+    let target = aof.appended.load(Ordering::Acquire);
+    while aof.written.load(Ordering::Acquire) < target {
+        if !aof.last_write_ok.load(Ordering::Relaxed) {
+            anyhow::bail!("Failed to write the AOF file");
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
     }
+    let file = aof.file();
+    tokio::task::spawn_blocking(move || file.sync_data()).await??;
+    Ok(())
+}
+
+// 不断地将缓冲区写入文件。写入期间追加的命令会在下一次循环中一起写入
+fn write_loop() {
+    let aof = AOF.get().expect("AOF should be started");
+    let policy = CONFIG.aof.append_fsync;
+    loop {
+        // 重写完成后，已取出但还未写入旧文件的命令已经在新文件中，仍写入旧文件即可
+        let (buf, end, file) = aof.take_buf();
+
+        if policy == AppendFSync::EverySec {
+            postpone_write(aof);
+        }
+        write_all(aof, &file, &buf, policy);
+        aof.mark_written(end);
 
-    pub fn load(&self) -> anyhow::Result<()> {
-        let mut client = loop {
-            std::thread::sleep(Duration::from_millis(500)); // 等待server启动
-            if let Ok(client) =
-                std::net::TcpStream::connect(format!("127.0.0.1:{}", CONFIG.server.port))
-            {
-                break client;
+        if policy == AppendFSync::Always {
+            if let Err(e) = file.sync_data() {
+                // 与Redis一致，always策略下无法保证已回复的写命令被持久化，只能退出
+                tracing::error!("Can't recover from AOF fsync error when the AOF fsync policy is 'always': {e}. Exiting...");
+                std::process::exit(1);
             }
-        };
+            aof.mark_synced(end);
+        }
+    }
+}
 
-        let cmds = std::fs::read_to_string(&CONFIG.aof.file_path)?;
-        for cmd in cmds.lines() {
-            if cmd.is_empty() {
-                break;
+// 写入失败时截断已写入的部分并重试，直到写入成功
+fn write_all(aof: &Aof, mut file: &File, buf: &[u8], policy: AppendFSync) {
+    // 写入之前的文件大小，写入失败时截断到该大小
+    let len = file.metadata().map(|metadata| metadata.len()).ok();
+    loop {
+        match file.write_all(buf) {
+            Ok(_) => {
+                aof.last_write_ok.store(true, Ordering::Relaxed);
+                return;
             }
-            client.write_all(cmd.replace("\\r\\n", "\r\n").as_bytes())?;
+            Err(e) if policy == AppendFSync::Always => {
+                tracing::error!("Can't recover from AOF write error when the AOF fsync policy is 'always': {e}. Exiting...");
+                std::process::exit(1);
+            }
+            Err(e) => {
+                tracing::error!("Failed to write the AOF file: {e}");
+                aof.last_write_ok.store(false, Ordering::Relaxed);
+                if let Some(Err(e)) = len.map(|len| file.set_len(len)) {
+                    tracing::error!("Failed to truncate the AOF file after a short write: {e}");
+                }
+                thread::sleep(AOF_WRITE_RETRY_DELAY);
+            }
+        }
+    }
+}
+
+// 上一次fsync还未完成时推迟写入，避免write被fsync阻塞。最多推迟AOF_MAX_WRITE_DELAY
+fn postpone_write(aof: &Aof) {
+    let start = Instant::now();
+    while aof.fsync_in_progress.load(Ordering::Acquire) {
+        if start.elapsed() >= AOF_MAX_WRITE_DELAY {
+            tracing::warn!("Asynchronous AOF fsync is taking too long (disk is busy?). Writing the AOF buffer without waiting for fsync to complete, this may slow down the server.");
+            aof.delayed_fsync.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+// everysec策略下每秒fsync一次已写入的命令
+fn fsync_loop() {
+    let aof = AOF.get().expect("AOF should be started");
+    let mut next = Instant::now();
+    loop {
+        next += Duration::from_secs(1);
+        let now = Instant::now();
+        match next.checked_duration_since(now) {
+            Some(delay) => thread::sleep(delay),
+            None => next = now, // fsync耗时超过1秒
+        }
+
+        let written = aof.written.load(Ordering::Acquire);
+        if written <= *aof.synced.borrow() {
+            continue;
+        }
+        aof.fsync_in_progress.store(true, Ordering::Release);
+        let res = aof.file().sync_data();
+        aof.fsync_in_progress.store(false, Ordering::Release);
+        match res {
+            Ok(_) => aof.mark_synced(written),
+            Err(e) => tracing::error!("Failed to fsync the AOF file: {e}"),
         }
-        client.shutdown(std::net::Shutdown::Both)?;
-        Ok(())
     }
 }
@@ -0,0 +1,129 @@
+/// Redis风格的glob匹配，与Redis的stringmatchlen()行为一致。支持：
+/// `*` 匹配任意数量的字符，`?` 匹配单个字符，`[abc]`/`[^abc]`/`[a-z]` 匹配字符集合，
+/// `\` 转义下一个字符
+pub fn glob_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let (mut p, mut s) = (0, 0);
+
+    while p < pattern.len() && s < string.len() {
+        match pattern[p] {
+            b'*' => {
+                // 合并连续的'*'
+                while pattern.get(p + 1) == Some(&b'*') {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                // 尝试让'*'匹配0个或多个字符
+                return (s..=string.len())
+                    .any(|i| glob_match(&pattern[p + 1..], &string[i..], nocase));
+            }
+            b'?' => {}
+            b'[' => {
+                p += 1;
+                let not = pattern.get(p) == Some(&b'^');
+                if not {
+                    p += 1;
+                }
+                let mut matched = false;
+                loop {
+                    match pattern.get(p) {
+                        // 没有闭合的']'，将其视为集合的结尾
+                        None => {
+                            p -= 1;
+                            break;
+                        }
+                        Some(b']') => break,
+                        Some(b'\\') if p + 1 < pattern.len() => {
+                            p += 1;
+                            if eq(pattern[p], string[s], nocase) {
+                                matched = true;
+                            }
+                        }
+                        Some(&start)
+                            if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() =>
+                        {
+                            let end = pattern[p + 2];
+                            let (start, end) = if start > end {
+                                (end, start)
+                            } else {
+                                (start, end)
+                            };
+                            let c = string[s];
+                            let in_range = if nocase {
+                                let c = c.to_ascii_lowercase();
+                                c >= start.to_ascii_lowercase() && c <= end.to_ascii_lowercase()
+                            } else {
+                                c >= start && c <= end
+                            };
+                            if in_range {
+                                matched = true;
+                            }
+                            p += 2;
+                        }
+                        Some(&c) => {
+                            if eq(c, string[s], nocase) {
+                                matched = true;
+                            }
+                        }
+                    }
+                    p += 1;
+                }
+                if matched == not {
+                    return false;
+                }
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if !eq(pattern[p], string[s], nocase) {
+                    return false;
+                }
+            }
+            c => {
+                if !eq(c, string[s], nocase) {
+                    return false;
+                }
+            }
+        }
+        p += 1;
+        s += 1;
+    }
+
+    // 字符串已经匹配完，剩余的模式只能是'*'
+    s == string.len() && pattern[p.min(pattern.len())..].iter().all(|&c| c == b'*')
+}
+
+fn eq(a: u8, b: u8, nocase: bool) -> bool {
+    if nocase {
+        a.eq_ignore_ascii_case(&b)
+    } else {
+        a == b
+    }
+}
+
+#[cfg(test)]
+mod test_glob {
+    use super::glob_match;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*", b"", false));
+        assert!(glob_match(b"*", b"foo", false));
+        assert!(glob_match(b"h?llo", b"hello", false));
+        assert!(!glob_match(b"h?llo", b"hllo", false));
+        assert!(glob_match(b"h*llo", b"heeeello", false));
+        assert!(glob_match(b"h*llo", b"hllo", false));
+        assert!(glob_match(b"h[ae]llo", b"hallo", false));
+        assert!(!glob_match(b"h[ae]llo", b"hillo", false));
+        assert!(glob_match(b"h[^e]llo", b"hallo", false));
+        assert!(!glob_match(b"h[^e]llo", b"hello", false));
+        assert!(glob_match(b"h[a-b]llo", b"hbllo", false));
+        assert!(!glob_match(b"h[a-b]llo", b"hcllo", false));
+        assert!(glob_match(b"h\\*llo", b"h*llo", false));
+        assert!(!glob_match(b"h\\*llo", b"hello", false));
+        assert!(glob_match(b"news.*", b"news.tech", false));
+        assert!(!glob_match(b"news.*", b"new", false));
+        assert!(glob_match(b"NEWS.*", b"news.tech", true));
+        assert!(!glob_match(b"a*b", b"acbd", false));
+    }
+}
@@ -1,19 +1,40 @@
 mod aof;
+mod glob;
+mod notify;
+mod pubsub;
 mod rdb;
 mod repl_log;
+mod scan;
+mod script;
 
-use crate::db::Db;
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
-    time::Duration,
 };
 
 pub use aof::*;
+pub use glob::*;
+pub use notify::*;
+pub use pubsub::*;
 pub use rdb::*;
 pub use repl_log::*;
+pub use scan::*;
+pub use script::*;
+
+// 正在从RDB或AOF文件载入数据
+static LOADING: AtomicBool = AtomicBool::new(false);
+
+/// 是否正在从RDB或AOF文件载入数据，用于INFO persistence的loading字段
+pub fn is_loading() -> bool {
+    LOADING.load(Ordering::Relaxed)
+}
+
+pub fn set_loading(loading: bool) {
+    LOADING.store(loading, Ordering::Relaxed);
+}
 
 // 测试客户端，向服务端发送指定命令
 #[allow(dead_code)]
@@ -49,43 +70,37 @@ pub fn bytes_to_u64(bytes: Bytes) -> Result<u64> {
         .map_err(|_| anyhow!("bytes to u64 failed"))
 }
 
-pub async fn check_expiration_periodical(period: Duration, db: &Db) {
-    let db = db.clone();
-    tokio::spawn(async move {
-        // 循环检查字符串，列表，集合，有序集合，哈希表的键
-        loop {
-            {
-                tokio::time::sleep(period).await;
+pub fn bytes_to_i64(bytes: Bytes) -> Result<i64> {
+    String::from_utf8(bytes.into())
+        .map_err(|_| anyhow!("bytes to i64 failed"))?
+        .parse::<i64>()
+        .map_err(|_| anyhow!("bytes to i64 failed"))
+}
+
+/// Redis Cluster中key的slot数量
+pub const CLUSTER_SLOTS: u16 = 16384;
 
-                let mut writer_guard = db.inner.write().await;
-                let keys_to_check: Vec<_> = writer_guard.string_kvs.0.keys().cloned().collect();
+static SLOT_CRC: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_XMODEM);
 
-                for key in keys_to_check {
-                    writer_guard.string_kvs.check_exist(key);
-                }
-            }
-            {
-                // TODO: list
-                // tokio::time::sleep(period).await;
-            }
-        }
-    });
+/// 计算key所属的slot，算法与Redis Cluster一致：CRC16(key) mod 16384。
+/// 如果key中包含非空的hash tag（即第一个'{'与其后第一个'}'之间的内容），则只对hash tag进行哈希
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let key = match key.iter().position(|&b| b == b'{') {
+        Some(start) => match key[start + 1..].iter().position(|&b| b == b'}') {
+            Some(len) if len > 0 => &key[start + 1..start + 1 + len],
+            _ => key,
+        },
+        None => key,
+    };
+    SLOT_CRC.checksum(key) & (CLUSTER_SLOTS - 1)
 }
 
-#[tokio::test]
-async fn test_check_expiration_periodical() {
-    let db = Db::new();
-    check_expiration_periodical(Duration::from_millis(500), &db).await;
-    {
-        let string_db = &mut db.inner.write().await.string_kvs;
-        string_db.set("foo", "bar", Some(Duration::from_millis(300)));
-        assert_eq!(Some("bar".into()), string_db.get("foo"));
-    }
-    tokio::time::sleep(Duration::from_secs(1)).await;
-
-    let string_db = &mut db.inner.write().await.string_kvs;
-    // "foo"过期后应当被检查程序删除
-    if string_db.0.get(&Bytes::from("foo")).is_some() {
-        panic!("key foo should be deleted");
-    }
+#[test]
+fn test_key_hash_slot() {
+    assert_eq!(key_hash_slot(b"foo"), 12182);
+    assert_eq!(
+        key_hash_slot(b"{user1000}.following"),
+        key_hash_slot(b"user1000")
+    );
+    assert_ne!(key_hash_slot(b"foo{}{bar}"), key_hash_slot(b"bar"));
 }
@@ -0,0 +1,86 @@
+//! 键空间通知。当键被修改时，向`__keyspace@<db>__:<key>`频道发布事件名，
+//! 向`__keyevent@<db>__:<event>`频道发布键名
+
+use crate::conf::{CONFIG, PUBSUB};
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+pub const NOTIFY_KEYSPACE: u32 = 1 << 0; // K
+pub const NOTIFY_KEYEVENT: u32 = 1 << 1; // E
+pub const NOTIFY_GENERIC: u32 = 1 << 2; // g
+pub const NOTIFY_STRING: u32 = 1 << 3; // $
+pub const NOTIFY_LIST: u32 = 1 << 4; // l
+pub const NOTIFY_SET: u32 = 1 << 5; // s
+pub const NOTIFY_HASH: u32 = 1 << 6; // h
+pub const NOTIFY_ZSET: u32 = 1 << 7; // z
+pub const NOTIFY_EXPIRED: u32 = 1 << 8; // x
+pub const NOTIFY_EVICTED: u32 = 1 << 9; // e
+pub const NOTIFY_STREAM: u32 = 1 << 10; // t
+pub const NOTIFY_KEY_MISS: u32 = 1 << 11; // m (不包含在A中)
+pub const NOTIFY_NEW: u32 = 1 << 12; // n (不包含在A中)
+pub const NOTIFY_ALL: u32 = NOTIFY_GENERIC
+    | NOTIFY_STRING
+    | NOTIFY_LIST
+    | NOTIFY_SET
+    | NOTIFY_HASH
+    | NOTIFY_ZSET
+    | NOTIFY_EXPIRED
+    | NOTIFY_EVICTED
+    | NOTIFY_STREAM; // A
+
+/// 将`notify-keyspace-events`的配置字符串解析为标志位
+pub fn keyspace_events_from_str(classes: &str) -> Result<u32> {
+    let mut flags = 0;
+    for c in classes.chars() {
+        flags |= match c {
+            'A' => NOTIFY_ALL,
+            'g' => NOTIFY_GENERIC,
+            '$' => NOTIFY_STRING,
+            'l' => NOTIFY_LIST,
+            's' => NOTIFY_SET,
+            'h' => NOTIFY_HASH,
+            'z' => NOTIFY_ZSET,
+            'x' => NOTIFY_EXPIRED,
+            'e' => NOTIFY_EVICTED,
+            'K' => NOTIFY_KEYSPACE,
+            'E' => NOTIFY_KEYEVENT,
+            't' => NOTIFY_STREAM,
+            'm' => NOTIFY_KEY_MISS,
+            'n' => NOTIFY_NEW,
+            _ => bail!("Invalid keyspace event class '{}'", c),
+        };
+    }
+    Ok(flags)
+}
+
+/// 发布一个键空间事件。只有当配置中开启了该类型的事件，并且开启了K或E时才会发布
+pub fn notify_keyspace_event(event_type: u32, event: &str, key: &Bytes, dbid: usize) {
+    let flags = CONFIG.server.notify_keyspace_events;
+    if flags & event_type == 0 {
+        return;
+    }
+
+    if flags & NOTIFY_KEYSPACE != 0 {
+        let mut channel = format!("__keyspace@{}__:", dbid).into_bytes();
+        channel.extend_from_slice(key);
+        PUBSUB.publish(channel.into(), Bytes::copy_from_slice(event.as_bytes()));
+    }
+    if flags & NOTIFY_KEYEVENT != 0 {
+        let channel = format!("__keyevent@{}__:{}", dbid, event);
+        PUBSUB.publish(channel.into(), key.clone());
+    }
+}
+
+#[test]
+fn test_keyspace_events_codec() {
+    assert_eq!(keyspace_events_from_str("").unwrap(), 0);
+    assert_eq!(
+        keyspace_events_from_str("KEA").unwrap(),
+        NOTIFY_KEYSPACE | NOTIFY_KEYEVENT | NOTIFY_ALL
+    );
+    assert_eq!(
+        keyspace_events_from_str("Ex$").unwrap(),
+        NOTIFY_KEYEVENT | NOTIFY_EXPIRED | NOTIFY_STRING
+    );
+    assert!(keyspace_events_from_str("Kq").is_err());
+}
@@ -0,0 +1,254 @@
+use super::glob_match;
+use crate::frame::Frame;
+use bytes::Bytes;
+use crossbeam::sync::ShardedLock;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// 发布订阅中心。每个处于订阅模式的连接都是一个订阅者，订阅者通过id标识，
+/// 并持有一个接收消息的通道
+pub struct PubSub {
+    channels: Channels,
+    /// 模式订阅，每条发布到普通频道的消息都会与所有模式进行匹配
+    patterns: Channels,
+    /// 分片频道，频道会被哈希到key slot上，消息只会在拥有该slot的节点及其从节点上传播
+    shard_channels: Channels,
+    next_subscriber_id: AtomicU64,
+}
+
+pub struct Subscriber {
+    pub id: u64,
+    pub sender: UnboundedSender<Frame>,
+    pub receiver: UnboundedReceiver<Frame>,
+}
+
+/// 频道名 -> (订阅者id -> 订阅者的消息通道)
+#[derive(Default)]
+struct Channels(ShardedLock<HashMap<Bytes, HashMap<u64, UnboundedSender<Frame>>>>);
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self {
+            channels: Channels::default(),
+            patterns: Channels::default(),
+            shard_channels: Channels::default(),
+            next_subscriber_id: AtomicU64::new(0),
+        }
+    }
+
+    pub fn new_subscriber(&self) -> Subscriber {
+        let (sender, receiver) = unbounded_channel();
+        Subscriber {
+            id: self.next_subscriber_id.fetch_add(1, Ordering::SeqCst),
+            sender,
+            receiver,
+        }
+    }
+
+    pub fn subscribe(&self, channel: Bytes, subscriber: &Subscriber) {
+        self.channels.subscribe(channel, subscriber);
+    }
+
+    pub fn unsubscribe(&self, channel: &Bytes, subscriber_id: u64) {
+        self.channels.unsubscribe(channel, subscriber_id);
+    }
+
+    pub fn psubscribe(&self, pattern: Bytes, subscriber: &Subscriber) {
+        self.patterns.subscribe(pattern, subscriber);
+    }
+
+    pub fn punsubscribe(&self, pattern: &Bytes, subscriber_id: u64) {
+        self.patterns.unsubscribe(pattern, subscriber_id);
+    }
+
+    /// 向普通频道发布消息，返回接收到消息的订阅者数量（包括模式订阅者）
+    pub fn publish(&self, channel: Bytes, message: Bytes) -> usize {
+        let msg = Frame::from(vec!["message".into(), channel.clone(), message.clone()]);
+        let mut receivers = self.channels.publish(&channel, msg);
+
+        let patterns = self.patterns.0.read().expect("Failed to lock channels");
+        for (pattern, subscribers) in patterns.iter() {
+            if !glob_match(pattern, &channel, false) {
+                continue;
+            }
+            let msg = Frame::from(vec![
+                "pmessage".into(),
+                pattern.clone(),
+                channel.clone(),
+                message.clone(),
+            ]);
+            receivers += subscribers
+                .values()
+                .filter(|sender| sender.send(msg.clone()).is_ok())
+                .count();
+        }
+        receivers
+    }
+
+    /// 返回所有活跃的普通频道，pattern为None时返回全部
+    pub fn channels(&self, pattern: Option<&Bytes>) -> Vec<Bytes> {
+        self.channels.names(pattern)
+    }
+
+    pub fn numsub(&self, channel: &Bytes) -> usize {
+        self.channels.numsub(channel)
+    }
+
+    /// 返回被订阅的模式的数量
+    pub fn numpat(&self) -> usize {
+        self.patterns
+            .0
+            .read()
+            .expect("Failed to lock channels")
+            .len()
+    }
+
+    pub fn ssubscribe(&self, channel: Bytes, subscriber: &Subscriber) {
+        self.shard_channels.subscribe(channel, subscriber);
+    }
+
+    pub fn sunsubscribe(&self, channel: &Bytes, subscriber_id: u64) {
+        self.shard_channels.unsubscribe(channel, subscriber_id);
+    }
+
+    /// 向分片频道发布消息，返回接收到消息的订阅者数量
+    pub fn spublish(&self, channel: Bytes, message: Bytes) -> usize {
+        let msg = Frame::from(vec!["smessage".into(), channel.clone(), message]);
+        self.shard_channels.publish(&channel, msg)
+    }
+
+    /// 返回所有活跃的（至少有一个订阅者）分片频道，pattern为None时返回全部
+    pub fn shard_channels(&self, pattern: Option<&Bytes>) -> Vec<Bytes> {
+        self.shard_channels.names(pattern)
+    }
+
+    pub fn shard_numsub(&self, channel: &Bytes) -> usize {
+        self.shard_channels.numsub(channel)
+    }
+}
+
+impl Default for PubSub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Channels {
+    fn subscribe(&self, channel: Bytes, subscriber: &Subscriber) {
+        self.0
+            .write()
+            .expect("Failed to lock channels")
+            .entry(channel)
+            .or_default()
+            .insert(subscriber.id, subscriber.sender.clone());
+    }
+
+    fn unsubscribe(&self, channel: &Bytes, subscriber_id: u64) {
+        let mut channels = self.0.write().expect("Failed to lock channels");
+        if let Some(subscribers) = channels.get_mut(channel) {
+            subscribers.remove(&subscriber_id);
+            // 没有订阅者的频道不再是活跃频道
+            if subscribers.is_empty() {
+                channels.remove(channel);
+            }
+        }
+    }
+
+    fn publish(&self, channel: &Bytes, msg: Frame) -> usize {
+        let channels = self.0.read().expect("Failed to lock channels");
+        channels.get(channel).map_or(0, |subscribers| {
+            subscribers
+                .values()
+                .filter(|sender| sender.send(msg.clone()).is_ok())
+                .count()
+        })
+    }
+
+    fn names(&self, pattern: Option<&Bytes>) -> Vec<Bytes> {
+        let channels = self.0.read().expect("Failed to lock channels");
+        channels
+            .keys()
+            .filter(|name| pattern.is_none_or(|p| glob_match(p, name, false)))
+            .cloned()
+            .collect()
+    }
+
+    fn numsub(&self, channel: &Bytes) -> usize {
+        let channels = self.0.read().expect("Failed to lock channels");
+        channels
+            .get(channel)
+            .map_or(0, |subscribers| subscribers.len())
+    }
+}
+
+#[tokio::test]
+async fn test_pubsub() {
+    let pubsub = PubSub::new();
+    let mut sub1 = pubsub.new_subscriber();
+    let mut sub2 = pubsub.new_subscriber();
+
+    pubsub.subscribe("news.tech".into(), &sub1);
+    pubsub.psubscribe("news.*".into(), &sub2);
+    assert_eq!(pubsub.numsub(&"news.tech".into()), 1);
+    assert_eq!(pubsub.numpat(), 1);
+
+    assert_eq!(pubsub.publish("news.tech".into(), "hello".into()), 2);
+    assert_eq!(
+        sub1.receiver.recv().await,
+        Some(Frame::from(vec![
+            "message".into(),
+            "news.tech".into(),
+            "hello".into()
+        ]))
+    );
+    assert_eq!(
+        sub2.receiver.recv().await,
+        Some(Frame::from(vec![
+            "pmessage".into(),
+            "news.*".into(),
+            "news.tech".into(),
+            "hello".into()
+        ]))
+    );
+    assert_eq!(pubsub.publish("sport".into(), "hello".into()), 0);
+
+    pubsub.unsubscribe(&"news.tech".into(), sub1.id);
+    pubsub.punsubscribe(&"news.*".into(), sub2.id);
+    assert_eq!(pubsub.publish("news.tech".into(), "hello".into()), 0);
+    assert!(pubsub.channels(None).is_empty());
+}
+
+#[tokio::test]
+async fn test_shard_pubsub() {
+    let pubsub = PubSub::new();
+    let mut sub1 = pubsub.new_subscriber();
+    let sub2 = pubsub.new_subscriber();
+
+    pubsub.ssubscribe("news".into(), &sub1);
+    pubsub.ssubscribe("news".into(), &sub2);
+    pubsub.ssubscribe("sport".into(), &sub2);
+    assert_eq!(pubsub.shard_numsub(&"news".into()), 2);
+    assert_eq!(
+        pubsub.shard_channels(Some(&"n*".into())),
+        vec![Bytes::from("news")]
+    );
+
+    assert_eq!(pubsub.spublish("news".into(), "hello".into()), 2);
+    assert_eq!(
+        sub1.receiver.recv().await,
+        Some(Frame::from(vec![
+            "smessage".into(),
+            "news".into(),
+            "hello".into()
+        ]))
+    );
+
+    pubsub.sunsubscribe(&"news".into(), sub1.id);
+    pubsub.sunsubscribe(&"sport".into(), sub2.id);
+    assert_eq!(pubsub.spublish("news".into(), "hello".into()), 1);
+    assert_eq!(pubsub.spublish("sport".into(), "hello".into()), 0);
+    assert!(pubsub.shard_channels(None) == vec![Bytes::from("news")]);
+}
@@ -0,0 +1,43 @@
+use std::io;
+
+/// 载入或检查RDB文件时的错误
+#[derive(Debug, thiserror::Error)]
+pub enum RdbError {
+    #[error("failed to read RDB file: {0}")]
+    Io(#[from] io::Error),
+    #[error("wrong signature trying to load DB from file: '{}'", .0.escape_ascii())]
+    InvalidHeader(Vec<u8>),
+    #[error("can't handle RDB format version {0}")]
+    UnsupportedVersion(u16),
+    #[error("unexpected end of file at offset {offset}")]
+    Truncated { offset: u64 },
+    #[error("{reason} at offset {offset} (opcode {opcode:#04x})")]
+    Corrupt {
+        offset: u64, // 检测到错误时的位置
+        opcode: u8,  // 出错的数据项的第一个字节，即操作码或值的类型
+        reason: Corruption,
+    },
+    #[error("DB index {dbid} is out of range at offset {offset}, databases is {databases}")]
+    DbIndexOutOfRange {
+        offset: u64,
+        dbid: usize,
+        databases: usize,
+    },
+    #[error("wrong RDB checksum: expected {expected:#018x}, got {actual:#018x}")]
+    ChecksumMismatch { expected: u64, actual: u64 },
+    #[error("failed to restore functions: {0}")]
+    Functions(String),
+}
+
+/// 数据项损坏的原因
+#[derive(Debug, thiserror::Error)]
+pub enum Corruption {
+    #[error("unexpected end of file")]
+    UnexpectedEof,
+    #[error("unknown value type {0}")]
+    UnknownType(u8),
+    #[error("invalid length encoding {0:#04x}")]
+    InvalidLength(u8),
+    #[error("invalid {0}")]
+    Invalid(&'static str), // 无法解析的值，如listpack、ziplist、LZF压缩的字符串等
+}
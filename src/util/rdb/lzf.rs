@@ -1,4 +1,3 @@
-#![allow(dead_code)]
 use bytes::{BufMut, Bytes, BytesMut};
 use std::collections::HashMap;
 
@@ -8,7 +7,7 @@ const MAX_LIT: usize = 1 << 5; // 最大字面量长度=32
 const MAX_OFF: usize = 1 << 13; //  最大偏移量。off <= 0001 1111 1111 1111, 高三位用来存放长度
 const MAX_REF: usize = (1 << 8) + (1 << 3); // 最大引用长度=264
 
-fn lzf_compress(input: &[u8]) -> Bytes {
+pub fn lzf_compress(input: &[u8]) -> Bytes {
     let mut output = BytesMut::with_capacity(input.len());
     // hash_table键：滑动窗口中的字节序列，值：滑动窗口中首个字节的索引
     let mut hash_table: HashMap<&[u8], usize> = HashMap::with_capacity(input.len());
@@ -43,8 +42,8 @@ fn lzf_compress(input: &[u8]) -> Bytes {
 
             // println!("iidx: {}, reference: {}", iidx, reference);
             let off = iidx - reference - 1;
-            // 若偏移量大于最大偏移量，则当作字面量处理
-            if off > MAX_OFF {
+            // 偏移量只有13位，超出时当作字面量处理
+            if off >= MAX_OFF {
                 lit += 1;
                 iidx += 1;
 
@@ -62,7 +61,7 @@ fn lzf_compress(input: &[u8]) -> Bytes {
             // NOTE: 还可以匹配一个字符("d")；len=4
             //
             // 继续匹配直到匹配长度达到最大值或者匹配失败
-            while let (Some(ch), true) = (input.get(iidx + len), len <= MAX_REF) {
+            while let (Some(ch), true) = (input.get(iidx + len), len < MAX_REF) {
                 if ch != &input[reference + len] {
                     break;
                 }
@@ -93,7 +92,7 @@ fn lzf_compress(input: &[u8]) -> Bytes {
 
                 // 长度大于等于7则用两个字节表示偏移量和重复长度
                 output.put_u8(((off >> 8) + (7 << 5)) as u8);
-                output.put_u8(repeat_len as u8 - 7);
+                output.put_u8((repeat_len - 7) as u8);
             }
             // 长度(3bit)，偏移量(5bit)，偏移量(8bit)或者长度(3bit)，偏移量(5bit)，长度(8bit)，偏移量(8bit)
             output.put_u8(off as u8);
@@ -137,22 +136,21 @@ fn lzf_compress(input: &[u8]) -> Bytes {
     output.freeze()
 }
 
-fn lzf_decompress(input: &[u8]) -> Bytes {
+/// 解压缩。输入不是有效的LZF压缩数据时返回None
+pub fn lzf_decompress(input: &[u8]) -> Option<Bytes> {
     // NOTE: input: 5 a a b c d e (4,4) 0 f
     let mut output = BytesMut::with_capacity(input.len() * 2);
     let mut iidx = 0;
     while iidx < input.len() {
         let ctrl = input[iidx]; // 读取控制字节
-                                // iidx += 1; // 指向第二个控制字节
 
         // 控制字节小于等于32，表示这是一个字面量序列
         if ctrl < MAX_LIT as u8 {
             // 字面量长度(1B) + 字面量数据
             // NOTE: len=6; output放入aabcde, output_len=6; iidx=7
             let len = ctrl as usize + 1; // 读取字面量长度
-                                         // TEST: println!("iidx={iidx}, len={}", len);
             iidx += 1;
-            output.extend(&input[iidx..iidx + len]);
+            output.extend(input.get(iidx..iidx + len)?);
             iidx += len; // 指向下一个控制字节
         } else {
             // 否则表示这是一个重复序列。长度和偏移量(2B或3B)
@@ -165,12 +163,12 @@ fn lzf_decompress(input: &[u8]) -> Bytes {
                                                        // 如果len=7+WINDOW_SIZE-1，表示需要再读取一个字节来获取len
             if len == 7 + WINDOW_SIZE - 1 {
                 iidx += 1; // 指向第二个控制字节
-                len += input[iidx] as usize;
+                len += *input.get(iidx)? as usize;
             }
             iidx += 1; // 指向第二个或第三个控制字节
-            off += input[iidx] as usize;
-            // TEST: println!("out_put={}, off={}, len={}", output.len(), off, len);
-            let refrence = output.len() - off - 1;
+            off += *input.get(iidx)? as usize;
+            // 引用的位置不能在已解压的数据之前
+            let refrence = output.len().checked_sub(off + 1)?;
 
             // NOTE: len=4, off=4, output中有aabcde; reference=1; 向output写入abcd
             for i in 0..len {
@@ -181,7 +179,7 @@ fn lzf_decompress(input: &[u8]) -> Bytes {
         }
     }
 
-    output.freeze()
+    Some(output.freeze())
 }
 
 #[test]
@@ -205,10 +203,24 @@ fn test_lzf() {
             compressed.len(),
             compressibility * 100.0
         );
-        let decompressed = lzf_decompress(&compressed);
+        let decompressed = lzf_decompress(&compressed).unwrap();
         // assert_eq!(Bytes::from_static(input), decompressed);
         assert_eq!(Bytes::from(input), decompressed);
 
         // }
     }
+
+    // 可压缩的数据会产生较远的偏移量和较长的重复序列
+    let mut input = Vec::new();
+    for i in 0..3000_u32 {
+        input.extend_from_slice(&(i % 1000).to_le_bytes());
+        input.extend(std::iter::repeat_n(b'a', (i % 300) as usize));
+    }
+    let compressed = lzf_compress(&input);
+    assert!(compressed.len() < input.len() / 10);
+    assert_eq!(lzf_decompress(&compressed).unwrap(), input);
+
+    // 引用了不存在的数据
+    assert_eq!(lzf_decompress(&[0x20, 0x00]), None);
+    assert_eq!(lzf_decompress(&[0x05, b'a']), None);
 }
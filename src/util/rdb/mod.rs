@@ -1,77 +1,122 @@
-#![allow(dead_code)]
+mod error;
 mod lzf;
+mod rdb_bgsave;
+mod rdb_check;
 mod rdb_load;
 mod rdb_save;
 
-pub use rdb_load::rdb_load;
-pub use rdb_save::rdb_save;
+pub use error::{Corruption, RdbError};
+pub use lzf::{lzf_compress, lzf_decompress};
+pub use rdb_bgsave::{
+    auto_save_enabled, rdb_bgsave, rdb_save_foreground, rdb_save_on_shutdown,
+    rdb_schedule_bgsave, rdb_status, rdb_status_init, spawn_auto_save,
+};
+pub use rdb_check::rdb_check;
+pub use rdb_load::{decode_functions_payload, rdb_load, rdb_load_preamble};
+pub use rdb_save::{encode_functions_payload, rdb_encode_aof_preamble, rdb_encode_async};
 
+const RDB_VERSION: u16 = 11; // 与Redis 7.2相同的RDB版本
+const REDIS_VERSION: &str = "7.2.0"; // 保存在redis-ver辅助字段中，表示兼容的Redis版本
+
+const FUNCTION2: u8 = 0xf5; // 函数库的代码
+const IDLE: u8 = 0xf8; // 键的空闲时间(LRU)
+const FREQ: u8 = 0xf9; // 键的访问频率(LFU)
 const EOF: u8 = 0xff;
-const SELECTDB: u8 = 0xfe; // 只允许一个数据库
+const SELECTDB: u8 = 0xfe; // 之后的键值对属于该数据库
 const EXPIRETIME: u8 = 0xfd;
 const EXPIRETIME_MS: u8 = 0xfc;
-const RESIZEDB: u8 = 0xfb;
-const AUX: u8 = 0xfa;
+const RESIZEDB: u8 = 0xfb; // 数据库中键的数量以及设置了过期时间的键的数量
+const AUX: u8 = 0xfa; // 辅助字段，key(string), value(string)
 
-// 进行类型编码时，如果是252(EXPIRETIME_MS)，则后面的数据是过期时间，如果是以下值，则后面的数据是该类型的kv编码
+// 进行类型编码时，如果是252(EXPIRETIME_MS)或253(EXPIRETIME)，则后面的数据是过期时间，如果是以下值，则后面的数据是该类型的kv编码
 const RUREDIS_RDB_TYPE_STRING: u8 = 0;
 const RUREDIS_RDB_TYPE_LIST: u8 = 1;
 const RUREDIS_RDB_TYPE_SET: u8 = 2;
 const RUREDIS_RDB_TYPE_ZSET: u8 = 3;
 const RUREDIS_RDB_TYPE_HASH: u8 = 4;
-const RUREDIS_RDB_TYPE_ZIPMAP: u8 = 9;
+const RUREDIS_RDB_TYPE_ZSET_2: u8 = 5; // 分数以二进制double保存
 const RUREDIS_RDB_TYPE_ZIPLIST: u8 = 10;
 const RUREDIS_RDB_TYPE_INTSET: u8 = 11;
 const RUREDIS_RDB_TYPE_ZSET_ZIPLIST: u8 = 12;
 const RUREDIS_RDB_TYPE_HASH_ZIPLIST: u8 = 13;
 const RUREDIS_RDB_TYPE_LIST_QUICKLIST: u8 = 14;
+const RUREDIS_RDB_TYPE_HASH_LISTPACK: u8 = 16; // 整个listpack作为一个字符串保存
+const RUREDIS_RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RUREDIS_RDB_TYPE_LIST_QUICKLIST_2: u8 = 18; // 节点数量, (节点类型, 节点内容(string))*
+const RUREDIS_RDB_TYPE_SET_LISTPACK: u8 = 20;
+
+// RUREDIS_RDB_TYPE_LIST_QUICKLIST_2中节点的类型
+const QUICKLIST_NODE_CONTAINER_PLAIN: u8 = 1; // 节点只保存一个元素
+const QUICKLIST_NODE_CONTAINER_PACKED: u8 = 2; // 节点是一个listpack
+
+// 进行长度编码时，如果开头是0x80或0x81，则之后的4B或8B(大端序)是长度
+const RUREDIS_RDB_32BITLEN: u8 = 0x80;
+const RUREDIS_RDB_64BITLEN: u8 = 0x81;
 
 // 进行长度编码时，如果开头2bit是11，则后面的数据不是字符串，而是特殊的编码格式
 const RUREDIS_RDB_SPECTIAL_FORMAT_INT8: u8 = 0;
 const RUREDIS_RDB_SPECTIAL_FORMAT_INT16: u8 = 1;
 const RUREDIS_RDB_SPECTIAL_FORMAT_INT32: u8 = 2;
+const RUREDIS_RDB_SPECTIAL_FORMAT_LZF: u8 = 3; // 压缩后的长度, 原始长度, LZF压缩后的数据
+
+const LZF_MIN_LEN: usize = 20; // 超过该长度的字符串才尝试压缩
 
 #[cfg(test)]
 mod test_rdb {
-    use crate::db::{Db, ObjValue, Object};
+    use crate::{
+        conf::CONFIG,
+        db::{Db, ObjValue, RedisObject},
+    };
 
-    use super::{rdb_load::*, rdb_save::*};
+    use super::{rdb_load::*, rdb_save::*, *};
     use bytes::Bytes;
     use std::io::Cursor;
-    use std::time::SystemTime;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
     #[test]
     fn test_rdb_length_codec() {
         let mut buf = Vec::new();
         encode_length(&mut buf, 0, None);
         assert_eq!(buf, [0]);
-        let len = decode_length(&mut Cursor::new(buf.clone()));
+        let len = decode_length(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(len, 0);
         buf.clear();
 
         encode_length(&mut buf, 63, None);
         assert_eq!(buf, [63]);
-        let len = decode_length(&mut Cursor::new(buf.clone()));
+        let len = decode_length(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(len, 63);
         buf.clear();
 
         encode_length(&mut buf, 64, None);
         assert_eq!(buf, [0x40, 64]);
-        let len = decode_length(&mut Cursor::new(buf.clone()));
+        let len = decode_length(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(len, 64);
         buf.clear();
 
         encode_length(&mut buf, 16383, None);
         assert_eq!(buf, [0x7f, 0xff]);
-        let len = decode_length(&mut Cursor::new(buf.clone()));
+        let len = decode_length(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(len, 16383);
         buf.clear();
 
         encode_length(&mut buf, 16384, None);
         assert_eq!(buf, [0x80, 0, 0, 0x40, 0]);
-        let len = decode_length(&mut Cursor::new(buf.clone()));
+        let len = decode_length(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(len, 16384);
         buf.clear();
+
+        encode_length(&mut buf, u32::MAX as u64, None);
+        assert_eq!(buf, [0x80, 0xff, 0xff, 0xff, 0xff]);
+        let len = decode_length(&mut Cursor::new(buf.clone())).unwrap();
+        assert_eq!(len, u32::MAX as usize);
+        buf.clear();
+
+        // 64位的长度
+        encode_length(&mut buf, 1 << 32, None);
+        assert_eq!(buf, [0x81, 0, 0, 0, 1, 0, 0, 0, 0]);
+        let len = decode_length(&mut Cursor::new(buf.clone())).unwrap();
+        assert_eq!(len, 1 << 32);
     }
 
     #[test]
@@ -79,7 +124,7 @@ mod test_rdb {
         let mut buf = Vec::new();
         encode_key(&mut buf, "key".into());
         assert_eq!(buf, [3, 107, 101, 121]);
-        let key = decode_key(&mut Cursor::new(buf.clone()));
+        let key = decode_key(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(key, "key".as_bytes());
         buf.clear();
     }
@@ -90,31 +135,31 @@ mod test_rdb {
 
         encode_int(&mut buf, 0);
         assert_eq!(buf, [0xc0, 0]);
-        let i = decode_int(&mut Cursor::new(buf.clone()));
+        let i = decode_int(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(i, 0);
         buf.clear();
 
         encode_int(&mut buf, -10);
         assert_eq!(buf, [0xc0, (-10_i8) as u8]);
-        let i = decode_int(&mut Cursor::new(buf.clone()));
+        let i = decode_int(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(i, -10);
         buf.clear();
 
         encode_int(&mut buf, 10);
         assert_eq!(buf, [0xc0, 10]);
-        let i = decode_int(&mut Cursor::new(buf.clone()));
+        let i = decode_int(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(i, 10);
         buf.clear();
 
         encode_int(&mut buf, 200);
-        assert_eq!(buf, [0xc1, 0, 200]);
-        let i = decode_int(&mut Cursor::new(buf.clone()));
+        assert_eq!(buf, [0xc1, 200, 0]);
+        let i = decode_int(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(i, 200);
         buf.clear();
 
         encode_int(&mut buf, 200000);
-        assert_eq!(buf, [0xc2, 0, 3, 13, 64]);
-        let i = decode_int(&mut Cursor::new(buf.clone()));
+        assert_eq!(buf, [0xc2, 64, 13, 3, 0]);
+        let i = decode_int(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(i, 200000);
     }
 
@@ -123,110 +168,382 @@ mod test_rdb {
         let mut buf = Vec::new();
         encode_raw(&mut buf, "hello".into());
         assert_eq!(buf, [5, 104, 101, 108, 108, 111]);
-        let raw = decode_raw(&mut Cursor::new(buf.clone()));
+        let raw = decode_raw(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(raw, "hello".as_bytes());
+
+        // 整数编码和LZF压缩的字符串都会被转换为原始的字符串
+        let raw = decode_raw(&mut Cursor::new(vec![0xc1, 0x2c, 0x01])).unwrap();
+        assert_eq!(raw, "300".as_bytes());
+        let value = "abc".repeat(100);
+        let compressed = lzf_compress(value.as_bytes());
+        buf.clear();
+        buf.push(0xc3);
+        encode_length(&mut buf, compressed.len() as u64, None);
+        encode_length(&mut buf, value.len() as u64, None);
+        buf.extend(compressed);
+        assert_eq!(decode_raw(&mut Cursor::new(buf.clone())).unwrap(), value.as_bytes());
+        assert_eq!(
+            decode_string(&mut Cursor::new(buf.clone())).unwrap(),
+            ObjValue::Raw(value.clone().into())
+        );
+
+        // 启用rdbcompression时，较长且可压缩的字符串使用LZF压缩保存
+        let mut encoded = Vec::new();
+        encode_raw(&mut encoded, value.clone().into());
+        assert_eq!(encoded, buf);
+        // 较短或无法压缩的字符串仍然保存原始字符串
+        let short = "a".repeat(LZF_MIN_LEN);
+        encoded.clear();
+        encode_raw(&mut encoded, short.clone().into());
+        assert_eq!(encoded[1..], *short.as_bytes());
+        let random: Vec<u8> = (0..100).map(|_| rand::random()).collect();
+        encoded.clear();
+        encode_raw(&mut encoded, random.clone().into());
+        assert_eq!(encoded[2..], random);
+    }
+
+    #[test]
+    fn test_decode_intset() {
+        let mut intset = vec![4, 0, 0, 0, 3, 0, 0, 0];
+        for i in [-70000_i32, 1, 70000] {
+            intset.extend(i.to_le_bytes());
+        }
+        assert_eq!(decode_intset(&intset).unwrap(), ["-70000", "1", "70000"]);
     }
 
     #[test]
     fn test_rdb_string_kv() {
         let mut buf = Vec::new();
         let key: Bytes = "key".into();
-        let object = Object {
-            value: ObjValue::Raw("hello".into()),
-            expire_at: None,
-        };
+        let object = RedisObject::new(ObjValue::Raw("hello".into()), None);
 
-        encode_string_kv(&mut buf, key.clone(), &object);
+        encode_kv(&mut buf, key.clone(), &object);
         assert_eq!(buf, [0, 3, 107, 101, 121, 5, 104, 101, 108, 108, 111]);
-        let (k, obj) = decode_kv(&mut Cursor::new(buf.clone()));
+        let (k, obj) = decode_kv(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(k, key);
         assert_eq!(obj, object);
         buf.clear();
 
-        let object = Object {
-            value: ObjValue::Raw("hello".into()),
-            expire_at: Some(SystemTime::now() + std::time::Duration::from_secs(10)),
-        };
-        encode_string_kv(&mut buf, key.clone(), &object);
-        assert_eq!(
-            buf,
-            [252, 0, 0, 0, 0, 0, 0, 39, 15, 0, 3, 107, 101, 121, 5, 104, 101, 108, 108, 111]
-        );
-        let (k, obj) = decode_kv(&mut Cursor::new(buf.clone()));
+        // 过期时间以unix时间戳(ms)的小端序保存
+        let expire_at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_000);
+        let expire_ms = 1_700_000_000_000_u64.to_le_bytes();
+        let object = RedisObject::new(ObjValue::Raw("hello".into()), Some(expire_at));
+        encode_kv(&mut buf, key.clone(), &object);
+        assert_eq!(buf[0], 252);
+        assert_eq!(buf[1..9], expire_ms);
+        assert_eq!(buf[9..], [0, 3, 107, 101, 121, 5, 104, 101, 108, 108, 111]);
+        let (k, obj) = decode_kv(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(k, key);
         assert_eq!(obj, object);
         buf.clear();
 
-        let object = Object {
-            value: ObjValue::Int(10),
-            expire_at: None,
-        };
-        encode_string_kv(&mut buf, key.clone(), &object);
+        let object = RedisObject::new(ObjValue::Int(10), None);
+        encode_kv(&mut buf, key.clone(), &object);
         assert_eq!(buf, [0, 3, 107, 101, 121, 192, 10]);
-        let (k, obj) = decode_kv(&mut Cursor::new(buf.clone()));
+        let (k, obj) = decode_kv(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(k, key);
         assert_eq!(obj, object);
         buf.clear();
 
-        let object = Object {
-            value: ObjValue::Int(10),
-            expire_at: Some(SystemTime::now() + std::time::Duration::from_secs(10)),
-        };
-        encode_string_kv(&mut buf, key.clone(), &object);
-        assert_eq!(
-            buf,
-            [252, 0, 0, 0, 0, 0, 0, 39, 15, 0, 3, 107, 101, 121, 192, 10]
-        );
-        let (k, obj) = decode_kv(&mut Cursor::new(buf.clone()));
+        let object = RedisObject::new(ObjValue::Int(10), Some(expire_at));
+        encode_kv(&mut buf, key.clone(), &object);
+        assert_eq!(buf[1..9], expire_ms);
+        assert_eq!(buf[9..], [0, 3, 107, 101, 121, 192, 10]);
+        let (k, obj) = decode_kv(&mut Cursor::new(buf.clone())).unwrap();
         assert_eq!(k, key);
         assert_eq!(obj, object);
+        buf.clear();
+
+        // 以秒为单位的过期时间(4B小端序)
+        buf.push(253);
+        buf.extend(1_700_000_000_u32.to_le_bytes());
+        buf.extend([0, 3, 107, 101, 121, 192, 10]);
+        let (_, obj) = decode_kv(&mut Cursor::new(buf.clone())).unwrap();
+        assert_eq!(obj.expire_at, Some(expire_at));
     }
 
     #[test]
-    fn test_rdb_save_and_load() {
-        let runtime = tokio::runtime::Runtime::new().unwrap();
-        let db = Db::new();
-        let mut db_inner = runtime.block_on(db.inner.write());
-        let obj1 = Object {
-            value: ObjValue::Raw("hello".into()),
-            expire_at: None,
-        };
-        let obj2 = Object {
-            value: ObjValue::Int(10),
-            expire_at: None,
-        };
-        let obj3 = Object {
-            value: ObjValue::Int(200),
-            expire_at: Some(SystemTime::now() + std::time::Duration::from_secs(10)),
+    fn test_decode_ziplist() {
+        let mut ziplist = vec![0; 10];
+        ziplist.extend([0, 0x01, b'a']); // 字符串"a"
+        ziplist.extend([3, 0xf6]); // 立即数5
+        ziplist.extend([2, 0xc0, 0x2c, 0x01]); // int16 300
+        ziplist.extend([4, 0xfe, 0xfe]); // int8 -2
+        ziplist.extend([3, 0xf0, 0x60, 0x79, 0xfe]); // int24 -100000
+        ziplist.push(0xff);
+        assert_eq!(decode_ziplist(&ziplist).unwrap(), ["a", "5", "300", "-2", "-100000"]);
+    }
+
+    #[test]
+    fn test_rdb_corrupt() {
+        // 构造包含各种类型和编码的RDB文件
+        let mut buf = b"REDIS0011".to_vec();
+        encode_aux(&mut buf, "redis-ver", REDIS_VERSION.into());
+        encode_aux(&mut buf, "unknown-field", "ignored".into()); // 无法识别的辅助字段会被跳过
+        buf.push(SELECTDB);
+        encode_length(&mut buf, 0, None);
+        let expire_at = Some(UNIX_EPOCH + Duration::from_millis(4_000_000_000_000));
+        let long = || Bytes::from("v".repeat(100));
+        let values = [
+            ObjValue::Raw("hello".into()),
+            ObjValue::Int(300),
+            ObjValue::List((0..20).map(|i| Bytes::from(i.to_string())).collect()),
+            ObjValue::Set(["1", "-70000"].into_iter().map(Bytes::from).collect()),
+            ObjValue::Set(["a", "b"].into_iter().map(Bytes::from).collect()),
+            ObjValue::Hash([("f".into(), "v".into())].into_iter().collect()),
+            ObjValue::Hash([("f".into(), long())].into_iter().collect()),
+            ObjValue::ZSet([("m".into(), 1.5)].into_iter().collect()),
+            ObjValue::ZSet([(long(), 2.0)].into_iter().collect()),
+        ];
+        for (i, value) in values.into_iter().enumerate() {
+            encode_kv(&mut buf, i.to_string().into(), &RedisObject::new(value, expire_at));
+        }
+        buf.push(EOF);
+        let checksum = crc::Crc::<u64>::new(&crc::CRC_64_REDIS).checksum(&buf);
+        buf.extend(checksum.to_le_bytes());
+
+        let parse = |buf: &[u8]| -> Result<usize, RdbError> {
+            let mut parser = RdbParser::new(buf.to_vec())?;
+            let mut keys = 0;
+            while let Some(entry) = parser.next_entry()? {
+                if let RdbEntry::KeyValue(..) = entry {
+                    keys += 1;
+                }
+            }
+            parser.verify_checksum()?;
+            Ok(keys)
         };
-        let obj4 = Object {
-            value: ObjValue::Raw("hello".into()),
-            expire_at: Some(SystemTime::now() + std::time::Duration::from_secs(10)),
+        assert_eq!(parse(&buf).unwrap(), 9);
+        // 截断或修改任意一个字节都会返回错误，而不是panic
+        for len in 0..buf.len() {
+            assert!(parse(&buf[..len]).is_err());
+        }
+        for i in 0..buf.len() {
+            let mut corrupt = buf.clone();
+            corrupt[i] ^= 0xff;
+            assert!(parse(&corrupt).is_err());
+        }
+
+        // 错误中包含出错的位置和操作码
+        let mut buf = b"REDIS0011".to_vec();
+        buf.extend([RUREDIS_RDB_TYPE_STRING, 3, b'k', b'e', b'y', 10, b'v']);
+        let err = parse(&buf).unwrap_err();
+        assert!(matches!(
+            err,
+            RdbError::Corrupt {
+                offset: 15,
+                opcode: RUREDIS_RDB_TYPE_STRING,
+                reason: Corruption::UnexpectedEof
+            }
+        ));
+        assert_eq!(err.to_string(), "unexpected end of file at offset 15 (opcode 0x00)");
+        let err = parse(b"REDIS0011\x0f\x01k").unwrap_err();
+        assert!(matches!(err, RdbError::Corrupt { reason: Corruption::UnknownType(15), .. }));
+        assert!(matches!(parse(b"REDIS0012"), Err(RdbError::UnsupportedVersion(12))));
+        assert!(matches!(parse(b"RUREDIS"), Err(RdbError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_rdb_encode_stream() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let db = Db::with_databases(2);
+        runtime.block_on(async {
+            let mut dbs = db.write_all().await;
+            for i in 0..2000 {
+                let value: Vec<u8> = (0..100).map(|_| rand::random()).collect();
+                let obj = RedisObject::new(ObjValue::Raw(value.into()), None);
+                dbs[i % 2].insert(i.to_string().into(), obj);
+            }
+        });
+        let parse = |buf: Vec<u8>| {
+            let mut parser = RdbParser::new(buf).unwrap();
+            let mut keys = 0;
+            while let Some(entry) = parser.next_entry().unwrap() {
+                if let RdbEntry::KeyValue(..) = entry {
+                    keys += 1;
+                }
+            }
+            assert_eq!(parser.verify_checksum().unwrap(), CONFIG.rdb.enable_checksum);
+            keys
         };
-        db_inner.string_kvs.0.insert("key".into(), obj1.clone());
-        db_inner.string_kvs.0.insert("key1".into(), obj2.clone());
-        db_inner.string_kvs.0.insert("key2".into(), obj3.clone());
-        db_inner.string_kvs.0.insert("key3".into(), obj4.clone());
-        rdb_save(db_inner.clone()).unwrap();
-
-        let db = Db::new();
-        let mut db_inner = runtime.block_on(db.inner.write());
-        rdb_load(&mut db_inner).unwrap();
-        assert_eq!(
-            db_inner.string_kvs.0.get(&Bytes::from("key")).unwrap(),
-            &obj1
+
+        // 数据分多次写入，校验和覆盖所有数据
+        struct Chunks(Vec<Vec<u8>>);
+        impl std::io::Write for Chunks {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.push(buf.to_vec());
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let snapshot = runtime.block_on(db.snapshot());
+        let chunks = rdb_encode(&snapshot, Chunks(Vec::new())).unwrap().0;
+        assert!(chunks.len() > 2);
+        assert!(chunks.iter().all(|chunk| chunk.len() < RDB_WRITE_CHUNK * 2));
+        assert_eq!(parse(chunks.concat()), 2000);
+
+        let mut buf = Vec::new();
+        runtime
+            .block_on(rdb_encode_async(runtime.block_on(db.snapshot()), &mut buf))
+            .unwrap();
+        assert_eq!(parse(buf), 2000);
+
+        // writer出错时停止编码并返回错误
+        let mut buf = [0; 1024];
+        let snapshot = runtime.block_on(db.snapshot());
+        assert!(rdb_encode(&snapshot, &mut buf[..]).is_err());
+    }
+
+    #[test]
+    fn test_rdb_load_truncated() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let db = Db::with_databases(1);
+        runtime.block_on(async {
+            let mut dbs = db.write_all().await;
+            for i in 0..100 {
+                dbs[0].insert(i.to_string().into(), RedisObject::new(ObjValue::Int(i), None));
+            }
+        });
+        let rdb = rdb_encode(&runtime.block_on(db.snapshot()), Vec::new()).unwrap();
+        let path = std::env::temp_dir().join(format!("rdb-truncated-{}.rdb", std::process::id()));
+        std::fs::write(&path, &rdb[..rdb.len() / 2]).unwrap();
+
+        // 载入失败时返回错误，不会以空的数据库继续运行，RDB文件保持不变
+        let db = Db::with_databases(1);
+        let mut dbs = runtime.block_on(db.write_all());
+        assert!(matches!(rdb_load_from(&mut dbs, &path), Err(RdbError::Truncated { .. })));
+        assert_eq!(std::fs::read(&path).unwrap(), rdb[..rdb.len() / 2]);
+        std::fs::remove_file(&path).unwrap();
+
+        // RDB文件不存在时不载入任何数据
+        assert!(rdb_load_from(&mut dbs, &path).is_ok());
+        assert_eq!(dbs[0].len(), 0);
+    }
+
+    #[test]
+    fn test_rdb_save_and_load() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let db = Db::with_databases(2);
+        let mut dbs = runtime.block_on(db.write_all());
+        let obj1 = RedisObject::new(ObjValue::Raw("hello".into()), None);
+        let obj2 = RedisObject::new(ObjValue::Int(10), None);
+        let obj3 = RedisObject::new(
+            ObjValue::Int(200),
+            Some(SystemTime::now() + Duration::from_secs(10)),
         );
-        assert_eq!(
-            db_inner.string_kvs.0.get(&Bytes::from("key1")).unwrap(),
-            &obj2
+        let obj4 = RedisObject::new(
+            ObjValue::Raw("hello".into()),
+            Some(SystemTime::now() + Duration::from_secs(10)),
         );
-        assert_eq!(
-            db_inner.string_kvs.0.get(&Bytes::from("key2")).unwrap(),
-            &obj3
+        dbs[0].insert("key".into(), obj1.clone());
+        dbs[0].insert("key1".into(), obj2.clone());
+        dbs[1].insert("key2".into(), obj3.clone());
+        dbs[1].insert("key3".into(), obj4.clone());
+        let list = RedisObject::new(
+            ObjValue::List(["a", "b"].into_iter().map(Bytes::from).collect()),
+            None,
+        );
+        let hash = RedisObject::new(
+            ObjValue::Hash([("f".into(), "v".into())].into_iter().collect()),
+            Some(SystemTime::now() + Duration::from_secs(10)),
+        );
+        let set = RedisObject::new(
+            ObjValue::Set(["1", "x"].into_iter().map(Bytes::from).collect()),
+            None,
         );
+        let zset = RedisObject::new(
+            ObjValue::ZSet(
+                [("m".into(), 1.5), ("n".into(), -2.0)]
+                    .into_iter()
+                    .collect(),
+            ),
+            None,
+        );
+        // 超出listpack限制的集合使用完整的编码保存
+        let big_list = RedisObject::new(
+            ObjValue::List(
+                (0..2000)
+                    .map(|i| Bytes::from(format!("item{}", i)))
+                    .collect(),
+            ),
+            None,
+        );
+        let big_hash = RedisObject::new(
+            ObjValue::Hash([("f".into(), "v".repeat(100).into())].into_iter().collect()),
+            None,
+        );
+        let big_zset = RedisObject::new(
+            ObjValue::ZSet(
+                (0..200)
+                    .map(|i| (Bytes::from(i.to_string()), i as f64))
+                    .collect(),
+            ),
+            None,
+        );
+        dbs[0].insert("big_list".into(), big_list.clone());
+        dbs[0].insert("big_hash".into(), big_hash.clone());
+        dbs[0].insert("big_zset".into(), big_zset.clone());
+        dbs[0].insert("list".into(), list.clone());
+        dbs[0].insert("hash".into(), hash.clone());
+        dbs[1].insert("set".into(), set.clone());
+        dbs[1].insert("zset".into(), zset.clone());
+        let intset = RedisObject::new(
+            ObjValue::Set(["1", "-70000"].into_iter().map(Bytes::from).collect()),
+            None,
+        );
+        dbs[1].insert("intset".into(), intset.clone());
+        // 已过期的键会被保存，但不会被载入
+        let expired = RedisObject::new(
+            ObjValue::Int(1),
+            Some(SystemTime::now() - Duration::from_secs(1)),
+        );
+        dbs[1].insert("expired".into(), expired);
+        drop(dbs);
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rdb-save-{}.rdb", std::process::id()));
+        rdb_save_to(runtime.block_on(db.snapshot()), crate::db::dirty(), &path).unwrap();
+        assert!(rdb_status().last_save_time > 0);
+        let rdb = std::fs::read(&path).unwrap();
+        // 保存完成后临时文件已被重命名
+        assert!(!dir.join(format!("temp-{}.rdb", std::process::id())).exists());
+        assert!(rdb.starts_with(b"REDIS0011"));
+
+        let db = Db::with_databases(2);
+        let mut dbs = runtime.block_on(db.write_all());
+        rdb_load_from(&mut dbs, &path).unwrap();
+        assert_eq!(dbs[0].peek(&Bytes::from("key")).unwrap(), &obj1);
+        assert_eq!(dbs[0].peek(&Bytes::from("key1")).unwrap(), &obj2);
+        assert_eq!(dbs[1].peek(&Bytes::from("key2")).unwrap(), &obj3);
+        assert_eq!(dbs[1].peek(&Bytes::from("key3")).unwrap(), &obj4);
+        assert_eq!(dbs[0].peek(&Bytes::from("list")).unwrap(), &list);
+        assert_eq!(dbs[0].peek(&Bytes::from("hash")).unwrap(), &hash);
+        assert_eq!(dbs[1].peek(&Bytes::from("set")).unwrap(), &set);
+        assert_eq!(dbs[1].peek(&Bytes::from("zset")).unwrap(), &zset);
+        for (key, obj, encoding) in [
+            ("list", &list, "listpack"),
+            ("hash", &hash, "listpack"),
+            ("big_list", &big_list, "quicklist"),
+            ("big_hash", &big_hash, "hashtable"),
+            ("big_zset", &big_zset, "skiplist"),
+        ] {
+            let loaded = dbs[0].peek(&Bytes::from(key)).unwrap();
+            assert_eq!(loaded, obj);
+            assert_eq!(loaded.value.encoding(), encoding);
+        }
         assert_eq!(
-            db_inner.string_kvs.0.get(&Bytes::from("key3")).unwrap(),
-            &obj4
+            dbs[1].peek(&Bytes::from("zset")).unwrap().value.encoding(),
+            "listpack"
         );
+        assert_eq!(dbs[0].len(), 7);
+        let loaded = dbs[1].peek(&Bytes::from("intset")).unwrap();
+        assert_eq!((loaded, loaded.value.encoding()), (&intset, "intset"));
+        assert_eq!(dbs[1].len(), 5);
+
+        // 数据库的数量少于RDB文件中的数据库编号
+        let db = Db::with_databases(1);
+        assert!(rdb_load_from(&mut runtime.block_on(db.write_all()), &path).is_err());
+        std::fs::remove_file(&path).unwrap();
     }
 }
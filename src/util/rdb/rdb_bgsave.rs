@@ -0,0 +1,224 @@
+//! RDB持久化的状态，以及SAVE、BGSAVE和按照save规则自动进行的后台保存。
+//! 同一时刻最多只有一个保存在进行，保存的是生成快照时的数据
+
+use super::rdb_save::{rdb_save, unix_secs};
+use crate::{
+    conf::CONFIG,
+    db::{self, Db},
+};
+use anyhow::bail;
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+    time::Duration,
+};
+
+// 正在进行的保存
+const STATE_IDLE: u8 = 0;
+const STATE_SAVE: u8 = 1; // SAVE或关闭服务器前的保存，客户端等待保存完成
+const STATE_BGSAVE: u8 = 2;
+
+// 后台保存失败后，至少等待该时间(秒)才会再次自动保存
+const BGSAVE_RETRY_DELAY: u64 = 5;
+
+static STATE: AtomicU8 = AtomicU8::new(STATE_IDLE);
+static BGSAVE_SCHEDULED: AtomicBool = AtomicBool::new(false); // BGSAVE SCHEDULE推迟的后台保存
+static LAST_SAVE_TIME: AtomicU64 = AtomicU64::new(0); // 最后一次成功保存的unix时间戳(秒)
+static LAST_BGSAVE_TRY: AtomicU64 = AtomicU64::new(0); // 最后一次开始后台保存的unix时间戳(秒)
+static LAST_BGSAVE_OK: AtomicBool = AtomicBool::new(true);
+static DIRTY_AT_LAST_SAVE: AtomicU64 = AtomicU64::new(0); // 最后一次保存的快照生成时键空间的修改次数
+static LOAD_FAILED: AtomicBool = AtomicBool::new(false); // 启动时载入RDB文件失败
+
+/// RDB持久化的状态，用于LASTSAVE和INFO persistence
+pub struct RdbStatus {
+    pub last_save_time: u64,
+    pub last_bgsave_ok: bool,
+    pub bgsave_in_progress: bool,
+    pub changes_since_last_save: u64,
+}
+
+pub fn rdb_status() -> RdbStatus {
+    RdbStatus {
+        last_save_time: LAST_SAVE_TIME.load(Ordering::Relaxed),
+        last_bgsave_ok: LAST_BGSAVE_OK.load(Ordering::Relaxed),
+        bgsave_in_progress: STATE.load(Ordering::Relaxed) == STATE_BGSAVE,
+        changes_since_last_save: db::dirty()
+            .saturating_sub(DIRTY_AT_LAST_SAVE.load(Ordering::Relaxed)),
+    }
+}
+
+/// 与Redis一致，启动时将最后一次保存的时间设置为启动时间
+pub fn rdb_status_init() {
+    LAST_SAVE_TIME.store(unix_secs(), Ordering::Relaxed);
+}
+
+/// 是否配置了自动保存。关闭服务器时只有配置了自动保存才会默认保存
+pub fn auto_save_enabled() -> bool {
+    CONFIG.rdb.enable && !CONFIG.rdb.save.is_empty()
+}
+
+// 启动时载入RDB文件失败后调用。此时数据库中没有RDB文件中的数据，自动保存和关闭服务器前的保存
+// 会用空的数据库覆盖可能可以修复的RDB文件，因此之后不再进行这两种保存
+pub(super) fn set_load_failed() {
+    LOAD_FAILED.store(true, Ordering::Relaxed);
+}
+
+// 保存成功后更新状态。与Redis一致，SAVE成功也会将rdb_last_bgsave_status重置为ok
+pub(super) fn save_succeeded(dirty: u64) {
+    DIRTY_AT_LAST_SAVE.fetch_max(dirty, Ordering::Relaxed);
+    LAST_SAVE_TIME.store(unix_secs(), Ordering::Relaxed);
+    LAST_BGSAVE_OK.store(true, Ordering::Relaxed);
+}
+
+fn try_begin(state: u8) -> bool {
+    STATE
+        .compare_exchange(STATE_IDLE, state, Ordering::AcqRel, Ordering::Acquire)
+        .is_ok()
+}
+
+/// 生成快照并在后台线程中保存，完成后更新rdb_last_bgsave_status。已经有保存正在进行时返回错误
+pub async fn rdb_bgsave(db: &Db) -> anyhow::Result<()> {
+    if !try_begin(STATE_BGSAVE) {
+        bail!("ERR Background save already in progress");
+    }
+    LAST_BGSAVE_TRY.store(unix_secs(), Ordering::Relaxed);
+
+    // 先记录修改次数再生成快照，保存期间的修改会计入下一次保存
+    let dirty = db::dirty();
+    let snapshot = db.snapshot().await;
+    std::thread::spawn(move || {
+        match rdb_save(snapshot, dirty) {
+            Ok(_) => tracing::info!("Background saving terminated with success"),
+            Err(e) => {
+                tracing::error!("Background saving error: {:?}", e);
+                LAST_BGSAVE_OK.store(false, Ordering::Relaxed);
+            }
+        }
+        STATE.store(STATE_IDLE, Ordering::Release);
+    });
+    Ok(())
+}
+
+/// 当前的后台保存完成后再进行一次后台保存，用于BGSAVE SCHEDULE
+pub fn rdb_schedule_bgsave() {
+    BGSAVE_SCHEDULED.store(true, Ordering::Relaxed);
+}
+
+/// 保存RDB文件，保存完成后才返回，用于SAVE命令。已经有保存正在进行时返回错误
+pub async fn rdb_save_foreground(db: &Db) -> anyhow::Result<()> {
+    if !try_begin(STATE_SAVE) {
+        bail!("ERR Background save already in progress");
+    }
+    save_foreground(db).await
+}
+
+/// 关闭服务器前保存RDB文件。会等待正在进行的后台保存完成，然后保存最新的数据。
+/// 启动时载入RDB文件失败时返回错误，不覆盖原来的RDB文件
+pub async fn rdb_save_on_shutdown(db: &Db) -> anyhow::Result<()> {
+    if LOAD_FAILED.load(Ordering::Relaxed) {
+        tracing::error!(
+            "Refusing to save the final RDB snapshot: the RDB file failed to load on startup."
+        );
+        bail!("ERR RDB file failed to load on startup, refusing to overwrite it");
+    }
+    while !try_begin(STATE_SAVE) {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tracing::info!("Saving the final RDB snapshot before exiting.");
+    save_foreground(db).await
+}
+
+// 调用前需要先将状态设置为STATE_SAVE
+async fn save_foreground(db: &Db) -> anyhow::Result<()> {
+    let dirty = db::dirty();
+    let snapshot = db.snapshot().await;
+    let res = tokio::task::spawn_blocking(move || rdb_save(snapshot, dirty)).await;
+    STATE.store(STATE_IDLE, Ordering::Release);
+    match res? {
+        Ok(_) => {
+            tracing::info!("DB saved on disk");
+            Ok(())
+        }
+        Err(e) => {
+            tracing::error!("Failed to save RDB file: {:?}", e);
+            bail!("ERR Failed to save RDB file, check the logs for details")
+        }
+    }
+}
+
+/// 开启一个异步任务，每秒执行hz次检查：没有AOF重写正在进行时，执行BGSAVE SCHEDULE推迟的后台保存；满足任意一条save规则
+/// (距离上次保存至少经过了seconds秒，且至少有changes次修改)时开始后台保存。
+/// 后台保存失败后，至少等待BGSAVE_RETRY_DELAY秒才会再次自动保存。启动时载入RDB文件失败时不自动保存
+pub fn spawn_auto_save(db: &Db) {
+    let db = db.clone();
+    let hz = CONFIG.server.hz.clamp(1, 500);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_micros(1_000_000 / hz));
+        loop {
+            interval.tick().await;
+            // AOF重写期间不进行后台保存
+            if STATE.load(Ordering::Acquire) != STATE_IDLE
+                || crate::util::aof_rewrite_status().in_progress
+            {
+                continue;
+            }
+
+            if BGSAVE_SCHEDULED.swap(false, Ordering::Relaxed) {
+                if let Err(e) = rdb_bgsave(&db).await {
+                    tracing::error!("Failed to start scheduled background saving: {e}");
+                }
+                continue;
+            }
+
+            if !CONFIG.rdb.enable || LOAD_FAILED.load(Ordering::Relaxed) {
+                continue;
+            }
+            let status = rdb_status();
+            let now = unix_secs();
+            if !status.last_bgsave_ok
+                && now.saturating_sub(LAST_BGSAVE_TRY.load(Ordering::Relaxed)) < BGSAVE_RETRY_DELAY
+            {
+                continue;
+            }
+            let elapsed = now.saturating_sub(status.last_save_time);
+            if let Some((seconds, changes)) = CONFIG.rdb.save.iter().find(|(seconds, changes)| {
+                status.changes_since_last_save >= *changes && elapsed >= *seconds
+            }) {
+                tracing::info!("{changes} changes in {seconds} seconds. Saving...");
+                if let Err(e) = rdb_bgsave(&db).await {
+                    tracing::error!("Failed to start background saving: {e}");
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod rdb_bgsave_test {
+    use super::*;
+
+    #[test]
+    fn test_save_state() {
+        // 同一时刻只能有一个保存在进行
+        assert!(try_begin(STATE_BGSAVE));
+        assert!(rdb_status().bgsave_in_progress);
+        assert!(!try_begin(STATE_SAVE));
+        assert!(!try_begin(STATE_BGSAVE));
+        STATE.store(STATE_IDLE, Ordering::Release);
+        assert!(!rdb_status().bgsave_in_progress);
+
+        save_succeeded(db::dirty());
+        let status = rdb_status();
+        assert!(status.last_bgsave_ok);
+        assert!(status.last_save_time > 0);
+    }
+
+    #[test]
+    fn test_save_on_shutdown_after_load_failed() {
+        // 启动时载入RDB文件失败后，关闭服务器前不保存
+        set_load_failed();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let db = Db::with_databases(1);
+        assert!(runtime.block_on(rdb_save_on_shutdown(&db)).is_err());
+        LOAD_FAILED.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,61 @@
+use super::{
+    rdb_load::{RdbEntry, RdbParser},
+    RdbError,
+};
+
+/// 检查RDB文件的结构和校验和，不载入数据。用于--check-rdb模式，检查过程输出到标准输出
+pub fn rdb_check(path: &str) -> Result<(), RdbError> {
+    println!("[offset 0] Checking RDB file {path}");
+    let mut parser = RdbParser::new(std::fs::read(path)?)?;
+    println!(
+        "[offset {}] RDB version {}",
+        parser.offset(),
+        parser.version()
+    );
+
+    let (mut keys, mut expires, mut expired, mut functions) = (0, 0, 0, 0);
+    loop {
+        let offset = parser.offset();
+        let Some(entry) = parser.next_entry()? else {
+            break;
+        };
+        match entry {
+            RdbEntry::Aux(key, value) => println!(
+                "[offset {offset}] AUX FIELD {} = '{}'",
+                key.escape_ascii(),
+                value.escape_ascii()
+            ),
+            RdbEntry::Function(_) => functions += 1,
+            RdbEntry::SelectDb(dbid) => println!("[offset {offset}] Selecting DB ID {dbid}"),
+            RdbEntry::ResizeDb {
+                db_size,
+                expires_size,
+            } => println!("[offset {offset}] RESIZEDB {db_size} keys, {expires_size} expires"),
+            RdbEntry::KeyValue(_, obj) => {
+                keys += 1;
+                if obj.expire_at.is_some() {
+                    expires += 1;
+                }
+                if obj.is_expired() {
+                    expired += 1;
+                }
+            }
+        }
+    }
+
+    // 无论是否启用了校验和都进行校验
+    if parser.verify_checksum()? {
+        println!("[offset {}] Checksum OK", parser.offset());
+    } else {
+        println!(
+            "[offset {}] RDB file was saved with checksum disabled: no check performed.",
+            parser.offset()
+        );
+    }
+    println!("[offset {}] \\o/ RDB looks OK! \\o/", parser.offset());
+    println!("[info] {keys} keys read");
+    println!("[info] {expires} expires");
+    println!("[info] {expired} already expired");
+    println!("[info] {functions} functions read");
+    Ok(())
+}
@@ -1,143 +1,595 @@
 use std::{
-    io::{Cursor, Read},
-    time::SystemTime,
-    usize,
+    io::{self, Cursor, Read},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use super::*;
 use crate::{
-    conf::CONFIG,
-    db::{self, DbInner, ObjValue, Object},
+    conf::{CONFIG, SCRIPTING},
+    db::{DbInner, Hash, List, Listpack, ObjValue, RedisObject, ZSet},
+    util::RestorePolicy,
 };
 use bytes::{Buf, Bytes};
-use tokio::sync::RwLockWriteGuard;
 
-pub fn rdb_load(db: &mut RwLockWriteGuard<DbInner>) -> anyhow::Result<()> {
-    let mut rdb = std::fs::File::open("dump.rdb")?;
+type DecodeResult<T> = Result<T, Corruption>;
 
-    let mut buf = Vec::with_capacity(1024);
-    rdb.read_to_end(&mut buf)?;
-    println!("{:?}", buf);
-
-    let mut cursor = Cursor::new(buf);
-    let mut magic = [0; 5];
-    cursor.read_exact(&mut magic)?;
-    if magic != b"REDIS"[..] {
-        anyhow::bail!("Failed to load RDB file: magic string should be RUREDIS, but got {magic:?}");
+/// 载入RDB文件，RDB文件不存在时不载入任何数据。dbs为按编号排列的所有数据库。载入失败时已载入的
+/// 数据会被清空，调用者不应继续使用空的数据库，否则之后的保存会覆盖原来的RDB文件
+pub fn rdb_load(dbs: &mut [DbInner]) -> Result<(), RdbError> {
+    let res = rdb_load_from(dbs, &CONFIG.rdb_path());
+    if res.is_err() {
+        rdb_bgsave::set_load_failed();
     }
-    let _rdb_version = cursor.get_u32();
-    cursor.advance(5);
+    res
+}
+
+pub(super) fn rdb_load_from(dbs: &mut [DbInner], path: &Path) -> Result<(), RdbError> {
+    let buf = match std::fs::read(path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    rdb_load_preamble(dbs, buf).map(drop)
+}
 
-    let len = cursor.get_ref().len();
-    while cursor.get_ref()[cursor.position() as usize] != EOF {
-        let (key, obj) = decode_kv(&mut cursor);
-        db.string_kvs.0.insert(key, obj);
+/// 载入buf开头的RDB格式数据，返回RDB数据的长度。重写后的AOF文件以RDB格式的数据开头，
+/// 之后是RESP格式的写命令。载入失败时已载入的数据会被清空
+pub fn rdb_load_preamble(dbs: &mut [DbInner], buf: Vec<u8>) -> Result<u64, RdbError> {
+    let res = load(dbs, buf);
+    if res.is_err() {
+        dbs.iter_mut().for_each(|db| drop(db.flush()));
     }
+    res
+}
 
-    cursor.advance(1);
+fn load(dbs: &mut [DbInner], buf: Vec<u8>) -> Result<u64, RdbError> {
+    let mut parser = RdbParser::new(buf)?;
+    let mut functions = Vec::new();
+    let mut dbid = 0; // 当前载入的数据库
+    while let Some(entry) = parser.next_entry()? {
+        match entry {
+            RdbEntry::Aux(key, value) => report_aux(&key, &value),
+            RdbEntry::Function(code) => functions.push(code),
+            RdbEntry::SelectDb(id) => {
+                if id >= dbs.len() {
+                    return Err(RdbError::DbIndexOutOfRange {
+                        offset: parser.offset(),
+                        dbid: id,
+                        databases: dbs.len(),
+                    });
+                }
+                dbid = id;
+            }
+            // 键的数量只用于预先分配空间，载入时忽略
+            RdbEntry::ResizeDb { .. } => {}
+            RdbEntry::KeyValue(key, obj) => {
+                // 保存RDB文件时已过期的键不再载入
+                if !obj.is_expired() {
+                    dbs[dbid].insert(key, obj);
+                }
+            }
+        }
+    }
     if CONFIG.rdb.enable_checksum {
-        let mut checksum = [0; 8];
-        cursor.read_exact(&mut checksum)?;
-        let checksum = u64::from_be_bytes(checksum);
-        let crc = crc::Crc::<u64>::new(&crc::CRC_64_REDIS);
-        if checksum != crc.checksum(&cursor.get_ref()[..len - 8]) {
-            anyhow::bail!("Failed to load RDB file: checksum failed");
+        parser.verify_checksum()?;
+    } else {
+        parser.skip_checksum()?;
+    }
+    let len = parser.offset();
+
+    // RDB文件中的函数库替换当前所有的函数库
+    SCRIPTING
+        .function_restore(functions, RestorePolicy::Flush)
+        .map_err(|e| RdbError::Functions(e.to_string()))?;
+    Ok(len)
+}
+
+// 与Redis一致，记录部分辅助字段的信息，无法识别的辅助字段会被忽略
+fn report_aux(key: &[u8], value: &[u8]) {
+    let value = String::from_utf8_lossy(value);
+    match key {
+        b"redis-ver" => tracing::info!("Loading RDB produced by version {value}"),
+        b"ctime" => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            if let Ok(ctime) = value.parse::<u64>() {
+                tracing::info!("RDB age {} seconds", now.saturating_sub(ctime));
+            }
+        }
+        b"used-mem" => {
+            if let Ok(used_mem) = value.parse::<u64>() {
+                let mb = used_mem as f64 / 1024.0 / 1024.0;
+                tracing::info!("RDB memory usage when created {mb:.2} Mb");
+            }
         }
+        b"redis-bits" | b"aof-base" | b"aof-preamble" | b"repl-stream-db" | b"repl-id"
+        | b"repl-offset" | b"lua" => {}
+        _ => tracing::warn!("Unrecognized RDB AUX field: '{}'", key.escape_ascii()),
     }
+}
 
-    Ok(())
+/// RDB文件中的一项数据
+pub(super) enum RdbEntry {
+    Aux(Bytes, Bytes),
+    Function(Bytes),
+    SelectDb(usize),
+    ResizeDb { db_size: usize, expires_size: usize },
+    KeyValue(Bytes, RedisObject),
 }
 
-pub(super) fn decode_kv(cursor: &mut Cursor<Vec<u8>>) -> (Bytes, Object<db::String>) {
-    match cursor.get_u8() {
-        EXPIRETIME_MS => {
-            let ms = cursor.get_u64();
-            let expire_at = Some(SystemTime::now() + std::time::Duration::from_millis(ms));
-            match cursor.get_u8() {
-                RUREDIS_RDB_TYPE_STRING => decode_string(cursor, expire_at),
-                _ => unimplemented!(),
+/// 依次解析RDB文件中的每一项数据，不会因为文件损坏而panic
+pub(super) struct RdbParser {
+    cursor: Cursor<Vec<u8>>,
+    version: u16,
+}
+
+impl RdbParser {
+    /// 解析文件头：REDIS加上4位ASCII数字的版本号，如REDIS0011
+    pub(super) fn new(buf: Vec<u8>) -> Result<Self, RdbError> {
+        let header = buf.get(..9).unwrap_or(&buf);
+        if header.len() < 9 || header[..5] != b"REDIS"[..] {
+            return Err(RdbError::InvalidHeader(header.to_vec()));
+        }
+        let version = std::str::from_utf8(&header[5..])
+            .ok()
+            .and_then(|v| v.parse::<u16>().ok())
+            .ok_or_else(|| RdbError::InvalidHeader(header.to_vec()))?;
+        if version == 0 || version > RDB_VERSION {
+            return Err(RdbError::UnsupportedVersion(version));
+        }
+
+        let mut cursor = Cursor::new(buf);
+        cursor.set_position(9);
+        Ok(Self { cursor, version })
+    }
+
+    pub(super) fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// 当前解析到的位置
+    pub(super) fn offset(&self) -> u64 {
+        self.cursor.position()
+    }
+
+    /// 返回下一项数据，遇到EOF时返回None
+    pub(super) fn next_entry(&mut self) -> Result<Option<RdbEntry>, RdbError> {
+        let cursor = &mut self.cursor;
+        let Some(&opcode) = cursor.chunk().first() else {
+            return Err(RdbError::Truncated {
+                offset: cursor.position(),
+            });
+        };
+        if opcode == EOF {
+            cursor.advance(1);
+            return Ok(None);
+        }
+
+        let entry = match opcode {
+            AUX => {
+                cursor.advance(1);
+                decode_raw(cursor).and_then(|key| Ok(RdbEntry::Aux(key, decode_raw(cursor)?)))
+            }
+            FUNCTION2 => {
+                cursor.advance(1);
+                decode_raw(cursor).map(RdbEntry::Function)
+            }
+            SELECTDB => {
+                cursor.advance(1);
+                decode_length(cursor).map(RdbEntry::SelectDb)
+            }
+            RESIZEDB => {
+                cursor.advance(1);
+                decode_length(cursor).and_then(|db_size| {
+                    Ok(RdbEntry::ResizeDb {
+                        db_size,
+                        expires_size: decode_length(cursor)?,
+                    })
+                })
             }
+            _ => decode_kv(cursor).map(|(key, obj)| RdbEntry::KeyValue(key, obj)),
+        };
+        entry.map(Some).map_err(|reason| RdbError::Corrupt {
+            offset: cursor.position(),
+            opcode,
+            reason,
+        })
+    }
+
+    /// 跳过EOF之后的校验和，不进行校验
+    pub(super) fn skip_checksum(&mut self) -> Result<(), RdbError> {
+        if self.version >= 5 {
+            let offset = self.offset();
+            read_array::<8>(&mut self.cursor).map_err(|_| RdbError::Truncated { offset })?;
+        }
+        Ok(())
+    }
+
+    /// 在EOF之后校验整个文件的校验和，返回是否进行了校验。版本5之前的RDB文件
+    /// 没有校验和，未启用校验和时保存的文件校验和为0，都不进行校验
+    pub(super) fn verify_checksum(&mut self) -> Result<bool, RdbError> {
+        if self.version < 5 {
+            return Ok(false);
+        }
+        let end = self.cursor.position() as usize;
+        let expected = read_array::<8>(&mut self.cursor)
+            .map(u64::from_le_bytes)
+            .map_err(|_| RdbError::Truncated { offset: end as u64 })?;
+        if expected == 0 {
+            return Ok(false);
         }
-        RUREDIS_RDB_TYPE_STRING => decode_string(cursor, None),
-        other => unimplemented!("Unknow type: {}", other),
+        let actual =
+            crc::Crc::<u64>::new(&crc::CRC_64_REDIS).checksum(&self.cursor.get_ref()[..end]);
+        if expected != actual {
+            return Err(RdbError::ChecksumMismatch { expected, actual });
+        }
+        Ok(true)
     }
 }
 
-pub(super) fn decode_string(
-    cursor: &mut Cursor<Vec<u8>>,
-    expire_at: Option<SystemTime>,
-) -> (Bytes, Object<db::String>) {
-    let key = decode_key(cursor);
-    let pos = cursor.position();
-    match cursor.get_ref()[pos as usize] >> 6 {
-        0..=2 => {
-            let value = decode_raw(cursor);
-            (
-                key,
-                Object {
-                    value: ObjValue::Raw(value),
-                    expire_at,
-                },
+/// 解析FUNCTION DUMP的结果，返回其中所有函数库的代码
+pub fn decode_functions_payload(payload: &[u8]) -> anyhow::Result<Vec<Bytes>> {
+    let err = || anyhow::anyhow!("ERR payload version or checksum are wrong");
+    if payload.len() < 10 {
+        return Err(err());
+    }
+    let (body, checksum) = payload.split_at(payload.len() - 8);
+    let checksum = u64::from_le_bytes(checksum.try_into()?);
+    if checksum != crc::Crc::<u64>::new(&crc::CRC_64_REDIS).checksum(body) {
+        return Err(err());
+    }
+    // 校验和之前是2B的RDB版本号
+    let (body, version) = body.split_at(body.len() - 2);
+    if u16::from_le_bytes(version.try_into()?) > RDB_VERSION {
+        return Err(err());
+    }
+
+    let mut cursor = Cursor::new(body.to_vec());
+    let mut codes = Vec::new();
+    while cursor.has_remaining() {
+        if cursor.get_u8() != FUNCTION2 {
+            return Err(err());
+        }
+        codes.push(decode_raw(&mut cursor).map_err(|_| err())?);
+    }
+    Ok(codes)
+}
+
+pub(super) fn decode_kv(cursor: &mut Cursor<Vec<u8>>) -> DecodeResult<(Bytes, RedisObject)> {
+    let mut expire_at = None;
+    let obj_type = loop {
+        match read_u8(cursor)? {
+            EXPIRETIME_MS => {
+                let ms = u64::from_le_bytes(read_array(cursor)?);
+                expire_at = Some(UNIX_EPOCH + Duration::from_millis(ms));
+            }
+            EXPIRETIME => {
+                let secs = u32::from_le_bytes(read_array(cursor)?);
+                expire_at = Some(UNIX_EPOCH + Duration::from_secs(secs as u64));
+            }
+            // 键的空闲时间和访问频率，载入时忽略
+            IDLE => {
+                decode_length(cursor)?;
+            }
+            FREQ => {
+                read_u8(cursor)?;
+            }
+            obj_type => break obj_type,
+        }
+    };
+
+    let key = decode_key(cursor)?;
+    let value = match obj_type {
+        RUREDIS_RDB_TYPE_STRING => decode_string(cursor)?,
+        RUREDIS_RDB_TYPE_LIST => {
+            let len = decode_length(cursor)?;
+            ObjValue::List(
+                (0..len)
+                    .map(|_| decode_raw(cursor))
+                    .collect::<DecodeResult<_>>()?,
+            )
+        }
+        RUREDIS_RDB_TYPE_SET => {
+            let len = decode_length(cursor)?;
+            ObjValue::Set(
+                (0..len)
+                    .map(|_| decode_raw(cursor))
+                    .collect::<DecodeResult<_>>()?,
+            )
+        }
+        RUREDIS_RDB_TYPE_ZSET => {
+            let len = decode_length(cursor)?;
+            ObjValue::ZSet(
+                (0..len)
+                    .map(|_| Ok((decode_raw(cursor)?, decode_double(cursor)?)))
+                    .collect::<DecodeResult<_>>()?,
+            )
+        }
+        RUREDIS_RDB_TYPE_ZSET_2 => {
+            let len = decode_length(cursor)?;
+            ObjValue::ZSet(
+                (0..len)
+                    .map(|_| {
+                        let member = decode_raw(cursor)?;
+                        Ok((member, f64::from_le_bytes(read_array(cursor)?)))
+                    })
+                    .collect::<DecodeResult<_>>()?,
             )
         }
-        3 => {
-            let value = decode_int(cursor);
-            (
-                key,
-                Object {
-                    value: ObjValue::Int(value),
-                    expire_at,
-                },
+        RUREDIS_RDB_TYPE_HASH => {
+            let len = decode_length(cursor)?;
+            ObjValue::Hash(
+                (0..len)
+                    .map(|_| Ok((decode_raw(cursor)?, decode_raw(cursor)?)))
+                    .collect::<DecodeResult<_>>()?,
             )
         }
-        _ => unimplemented!(),
+        // 旧版本RDB文件中紧凑编码的值，转换为当前的编码
+        RUREDIS_RDB_TYPE_ZIPLIST => {
+            ObjValue::List(decode_ziplist(&decode_raw(cursor)?)?.into_iter().collect())
+        }
+        RUREDIS_RDB_TYPE_INTSET => {
+            ObjValue::Set(decode_intset(&decode_raw(cursor)?)?.into_iter().collect())
+        }
+        RUREDIS_RDB_TYPE_SET_LISTPACK => ObjValue::Set(decode_listpack(cursor)?.iter().collect()),
+        RUREDIS_RDB_TYPE_ZSET_ZIPLIST => {
+            let items = decode_pairs(decode_ziplist(&decode_raw(cursor)?)?, "ziplist")?;
+            ObjValue::ZSet(
+                items
+                    .into_iter()
+                    .map(|(member, score)| Ok((member, parse_score(&score)?)))
+                    .collect::<DecodeResult<_>>()?,
+            )
+        }
+        RUREDIS_RDB_TYPE_HASH_ZIPLIST => {
+            let items = decode_pairs(decode_ziplist(&decode_raw(cursor)?)?, "ziplist")?;
+            ObjValue::Hash(items.into_iter().collect())
+        }
+        RUREDIS_RDB_TYPE_HASH_LISTPACK => {
+            let lp = decode_listpack(cursor)?;
+            if !lp.len().is_multiple_of(2) {
+                return Err(Corruption::Invalid("hash listpack"));
+            }
+            ObjValue::Hash(Hash::from_listpack(lp))
+        }
+        RUREDIS_RDB_TYPE_ZSET_LISTPACK => {
+            let lp = decode_listpack(cursor)?;
+            if !lp.len().is_multiple_of(2)
+                || !lp
+                    .iter()
+                    .skip(1)
+                    .step_by(2)
+                    .all(|s| parse_score(&s).is_ok())
+            {
+                return Err(Corruption::Invalid("zset listpack"));
+            }
+            ObjValue::ZSet(ZSet::from_listpack(lp))
+        }
+        // 旧版本的quicklist，节点为ziplist
+        RUREDIS_RDB_TYPE_LIST_QUICKLIST => {
+            let len = decode_length(cursor)?;
+            let mut list = List::default();
+            for _ in 0..len {
+                decode_ziplist(&decode_raw(cursor)?)?
+                    .into_iter()
+                    .for_each(|item| list.push_back(item));
+            }
+            ObjValue::List(list)
+        }
+        RUREDIS_RDB_TYPE_LIST_QUICKLIST_2 => {
+            let len = decode_length(cursor)?;
+            let mut items = Vec::new();
+            for _ in 0..len {
+                match decode_length(cursor)? {
+                    len if len == QUICKLIST_NODE_CONTAINER_PACKED as usize => {
+                        items.extend(decode_listpack(cursor)?.iter())
+                    }
+                    len if len == QUICKLIST_NODE_CONTAINER_PLAIN as usize => {
+                        items.push(decode_raw(cursor)?)
+                    }
+                    _ => return Err(Corruption::Invalid("quicklist node container")),
+                }
+            }
+            ObjValue::List(items.into_iter().collect::<List>())
+        }
+        other => return Err(Corruption::UnknownType(other)),
+    };
+    Ok((key, RedisObject::new(value, expire_at)))
+}
+
+pub(super) fn decode_listpack(cursor: &mut Cursor<Vec<u8>>) -> DecodeResult<Listpack> {
+    Listpack::from_bytes(decode_raw(cursor)?.to_vec()).ok_or(Corruption::Invalid("listpack"))
+}
+
+// 将元素两两组成(field, value)或(member, score)
+fn decode_pairs(items: Vec<Bytes>, what: &'static str) -> DecodeResult<Vec<(Bytes, Bytes)>> {
+    if !items.len().is_multiple_of(2) {
+        return Err(Corruption::Invalid(what));
     }
+    let mut iter = items.into_iter();
+    Ok(std::iter::from_fn(|| Some((iter.next()?, iter.next()?))).collect())
 }
 
-pub(super) fn decode_raw(cursor: &mut Cursor<Vec<u8>>) -> Bytes {
-    let len = decode_length(cursor);
-    let mut raw = vec![0; len];
-    let _ = cursor.read_exact(&mut raw);
-    raw.into()
+// ziplist: <zlbytes:u32> <zltail:u32> <zllen:u16> (prevlen, 编码, 数据)* <0xFF>
+pub(super) fn decode_ziplist(buf: &[u8]) -> DecodeResult<Vec<Bytes>> {
+    let invalid = || Corruption::Invalid("ziplist");
+    let slice = |start: usize, len: usize| buf.get(start..start + len).ok_or_else(invalid);
+    let byte = |pos: usize| buf.get(pos).copied().ok_or_else(invalid);
+
+    let mut items = Vec::new();
+    let mut pos = 10;
+    while byte(pos)? != 0xff {
+        // 前一个元素的长度小于254时占1字节，否则为0xFE加4字节
+        pos += if byte(pos)? < 254 { 1 } else { 5 };
+        let enc = byte(pos)?;
+        let (header, len) = match enc >> 6 {
+            0 => (1, (enc & 0x3f) as usize),
+            1 => (2, ((enc as usize & 0x3f) << 8) | byte(pos + 1)? as usize),
+            2 => {
+                let len = u32::from_be_bytes(slice(pos + 1, 4)?.try_into().unwrap());
+                (5, len as usize)
+            }
+            _ => {
+                let (size, int) = match enc {
+                    0xc0 => (
+                        2,
+                        i16::from_le_bytes(slice(pos + 1, 2)?.try_into().unwrap()) as i64,
+                    ),
+                    0xd0 => (
+                        4,
+                        i32::from_le_bytes(slice(pos + 1, 4)?.try_into().unwrap()) as i64,
+                    ),
+                    0xe0 => (
+                        8,
+                        i64::from_le_bytes(slice(pos + 1, 8)?.try_into().unwrap()),
+                    ),
+                    0xf0 => {
+                        let data = slice(pos + 1, 3)?;
+                        (
+                            3,
+                            i32::from_le_bytes([0, data[0], data[1], data[2]]) as i64 >> 8,
+                        )
+                    }
+                    0xfe => (1, byte(pos + 1)? as i8 as i64),
+                    // 1111xxxx，xxxx减1为0到12之间的整数
+                    0xf1..=0xfd => (0, (enc & 0x0f) as i64 - 1),
+                    _ => return Err(invalid()),
+                };
+                items.push(Bytes::from(int.to_string()));
+                pos += 1 + size;
+                continue;
+            }
+        };
+        pos += header;
+        items.push(Bytes::copy_from_slice(slice(pos, len)?));
+        pos += len;
+    }
+    Ok(items)
+}
+
+// intset: 每个整数的字节数(4B小端序), 整数数量(4B小端序), 有序的整数(小端序)*
+pub(super) fn decode_intset(buf: &[u8]) -> DecodeResult<Vec<Bytes>> {
+    let invalid = || Corruption::Invalid("intset");
+    let header = buf.get(..8).ok_or_else(invalid)?;
+    let width = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+    if ![2, 4, 8].contains(&width) || buf.len() != 8 + width * len {
+        return Err(invalid());
+    }
+    // 按照整数的字节数进行符号扩展
+    let shift = 64 - width as u32 * 8;
+    Ok(buf[8..]
+        .chunks(width)
+        .map(|mut int| {
+            ((int.get_uint_le(width) << shift) as i64 >> shift)
+                .to_string()
+                .into()
+        })
+        .collect())
+}
+
+// RUREDIS_RDB_TYPE_ZSET中的分数：长度(1B), 分数的字符串形式。长度为253、254、255时分别表示NaN、+inf、-inf
+fn decode_double(cursor: &mut Cursor<Vec<u8>>) -> DecodeResult<f64> {
+    match read_u8(cursor)? {
+        253 => Ok(f64::NAN),
+        254 => Ok(f64::INFINITY),
+        255 => Ok(f64::NEG_INFINITY),
+        len => parse_score(&read_bytes(cursor, len as usize)?),
+    }
+}
+
+fn parse_score(score: &[u8]) -> DecodeResult<f64> {
+    std::str::from_utf8(score)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(Corruption::Invalid("zset score"))
+}
+
+pub(super) fn decode_string(cursor: &mut Cursor<Vec<u8>>) -> DecodeResult<ObjValue> {
+    let ctrl = *cursor.chunk().first().ok_or(Corruption::UnexpectedEof)?;
+    if ctrl >> 6 == 3 && ctrl & 0x3f != RUREDIS_RDB_SPECTIAL_FORMAT_LZF {
+        Ok(ObjValue::Int(decode_int(cursor)?))
+    } else {
+        Ok(ObjValue::from_bytes(decode_raw(cursor)?))
+    }
 }
 
-pub(super) fn decode_int(cursor: &mut Cursor<Vec<u8>>) -> i64 {
-    match cursor.get_u8() & 0x3f {
-        RUREDIS_RDB_SPECTIAL_FORMAT_INT8 => cursor.get_i8() as i64,
-        RUREDIS_RDB_SPECTIAL_FORMAT_INT16 => cursor.get_i16() as i64,
-        RUREDIS_RDB_SPECTIAL_FORMAT_INT32 => cursor.get_i32() as i64,
-        _ => unreachable!(),
+/// 解析任意编码的字符串，整数编码的字符串会被转换为字符串形式，LZF压缩的字符串会被解压
+pub(super) fn decode_raw(cursor: &mut Cursor<Vec<u8>>) -> DecodeResult<Bytes> {
+    let ctrl = *cursor.chunk().first().ok_or(Corruption::UnexpectedEof)?;
+    if ctrl >> 6 == 3 {
+        return match ctrl & 0x3f {
+            RUREDIS_RDB_SPECTIAL_FORMAT_LZF => decode_lzf(cursor),
+            _ => Ok(decode_int(cursor)?.to_string().into()),
+        };
     }
+    let len = decode_length(cursor)?;
+    Ok(read_bytes(cursor, len)?.into())
 }
 
-pub(super) fn decode_key(cursor: &mut Cursor<Vec<u8>>) -> Bytes {
-    let len = decode_length(cursor);
-    let mut key = vec![0; len];
-    let _ = cursor.read_exact(&mut key);
-    key.into()
+fn decode_lzf(cursor: &mut Cursor<Vec<u8>>) -> DecodeResult<Bytes> {
+    cursor.advance(1);
+    let compressed_len = decode_length(cursor)?;
+    let len = decode_length(cursor)?;
+    let compressed = read_bytes(cursor, compressed_len)?;
+    lzf_decompress(&compressed)
+        .filter(|raw| raw.len() == len)
+        .ok_or(Corruption::Invalid("LZF compressed string"))
+}
+
+pub(super) fn decode_int(cursor: &mut Cursor<Vec<u8>>) -> DecodeResult<i64> {
+    match read_u8(cursor)? & 0x3f {
+        RUREDIS_RDB_SPECTIAL_FORMAT_INT8 => Ok(i8::from_le_bytes(read_array(cursor)?) as i64),
+        RUREDIS_RDB_SPECTIAL_FORMAT_INT16 => Ok(i16::from_le_bytes(read_array(cursor)?) as i64),
+        RUREDIS_RDB_SPECTIAL_FORMAT_INT32 => Ok(i32::from_le_bytes(read_array(cursor)?) as i64),
+        other => Err(Corruption::InvalidLength(0xc0 | other)),
+    }
 }
 
-pub(super) fn decode_length(cursor: &mut Cursor<Vec<u8>>) -> usize {
-    let ctrl = cursor.get_u8();
+pub(super) fn decode_key(cursor: &mut Cursor<Vec<u8>>) -> DecodeResult<Bytes> {
+    decode_raw(cursor)
+}
+
+pub(super) fn decode_length(cursor: &mut Cursor<Vec<u8>>) -> DecodeResult<usize> {
+    let ctrl = read_u8(cursor)?;
     match ctrl >> 6 {
         // 00
-        0 => ctrl as usize,
+        0 => Ok(ctrl as usize),
         // 01
         1 => {
             let mut res = 0_usize;
             res |= ((ctrl & 0x3f) as usize) << 8; // ctrl & 0011 1111
-            res |= (cursor.get_u8()) as usize;
-            res
+            res |= read_u8(cursor)? as usize;
+            Ok(res)
         }
         // 10
-        2 => <u32>::from_be_bytes([
-            cursor.get_u8(),
-            cursor.get_u8(),
-            cursor.get_u8(),
-            cursor.get_u8(),
-        ]) as usize,
-        // 11
-        3 => ctrl as usize & 0x3f,
-        _ => unreachable!(),
+        _ if ctrl == RUREDIS_RDB_32BITLEN => Ok(u32::from_be_bytes(read_array(cursor)?) as usize),
+        _ if ctrl == RUREDIS_RDB_64BITLEN => Ok(u64::from_be_bytes(read_array(cursor)?) as usize),
+        // 11开头的是特殊编码的字符串，不是长度
+        _ => Err(Corruption::InvalidLength(ctrl)),
+    }
+}
+
+fn read_u8(cursor: &mut Cursor<Vec<u8>>) -> DecodeResult<u8> {
+    read_array::<1>(cursor).map(|[b]| b)
+}
+
+fn read_array<const N: usize>(cursor: &mut Cursor<Vec<u8>>) -> DecodeResult<[u8; N]> {
+    let mut buf = [0; N];
+    cursor
+        .read_exact(&mut buf)
+        .map_err(|_| Corruption::UnexpectedEof)?;
+    Ok(buf)
+}
+
+// 先检查剩余的字节数，避免损坏的长度导致分配过多内存
+fn read_bytes(cursor: &mut Cursor<Vec<u8>>, len: usize) -> DecodeResult<Vec<u8>> {
+    if cursor.remaining() < len {
+        return Err(Corruption::UnexpectedEof);
     }
+    let mut buf = vec![0; len];
+    cursor.copy_to_slice(&mut buf);
+    Ok(buf)
 }
@@ -1,93 +1,384 @@
-use super::*;
+use super::{rdb_bgsave::save_succeeded, *};
 use crate::{
-    conf::CONFIG,
-    db::{self, DbInner, ObjValue, Object},
+    conf::{CONFIG, SCRIPTING},
+    db::{Hash, List, Listpack, ObjValue, RedisObject, Set, Snapshot, ZSet},
 };
+use anyhow::Context;
 use bytes::{BufMut, Bytes};
-use std::{io::Write, time::SystemTime};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{Mutex, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
 
-// REDIS  kvpair* EOF checksum
+// REDIS0011 aux* function* (SELECTDB dbid RESIZEDB db_size expires_size kvpair*)* EOF checksum
+// aux: AUX(250), key(string), value(string)
+// function: FUNCTION2(245), 库的代码(string)
 // kvpair:
-// 1. EXPIRETIME_MS(252), ms(8B), TYPE(0~14), key(string), value(根据类型不同而不同)
-// 2. TYPE(0~14), key(string), value(根据类型不同而不同)
+// 1. EXPIRETIME_MS(252), 过期时间的unix时间戳(ms, 8B小端序), TYPE, key(string), value(根据类型不同而不同)
+// 2. TYPE, key(string), value(根据类型不同而不同)
 // string:
-// 1. int8|int16|int32(1B), num
-// 2. len, string
-
-pub fn rdb_save(db: DbInner) -> anyhow::Result<()> {
-    let mut buf = Vec::with_capacity(1024);
-    buf.extend_from_slice(b"REDIS");
-    buf.put_u32(1); // 版本号
-    buf.put_u8(SELECTDB); // 选择数据库
-    buf.put_u32(0); // 选择0号数据库
-
-    // 保存string_kv{kvs_with_expire[ObjValue::Raw_nums expire len key len data ObjValue::Int_nums expire len key int8/int16/int32 data]}
-    db.string_kvs.0.iter().for_each(|(k, obj)| {
-        encode_string_kv(&mut buf, k.clone(), obj);
-    });
+// 1. int8|int16|int32(1B), num(小端序)
+// 2. LZF(1B), 压缩后的长度, 原始长度, 压缩后的数据(启用rdbcompression且长度超过20时)
+// 3. len, string
+// set: len, string*
+// intset编码的set: intset(string)
+// zset: len, (member(string), score(8B小端序double))*
+// hash: len, (field(string), value(string))*
+// listpack编码的hash|zset: listpack(string)
+// list: 节点数量, (QUICKLIST_NODE_CONTAINER_PACKED, listpack(string))*
+// checksum: 之前所有数据的crc64(8B小端序)，未启用校验和时为0。编码时增量计算，不需要缓存整个文件
 
-    buf.put_u8(EOF); // 结束标志
-    let checksum = if CONFIG.rdb.enable_checksum {
-        crc::Crc::<u64>::new(&crc::CRC_64_REDIS).checksum(&buf)
-    } else {
-        0
-    };
-    buf.put_u64(checksum); // checksum 8 bytes 使用crc64
+// 同一时刻只能有一个线程写入临时文件
+static SAVE_LOCK: Mutex<()> = Mutex::new(());
+
+static CRC64: crc::Crc<u64> = crc::Crc::<u64>::new(&crc::CRC_64_REDIS);
+
+// 编码的数据积累到该大小后才写入writer
+pub(super) const RDB_WRITE_CHUNK: usize = 64 * 1024;
+// 异步写入时，编码线程与写入任务之间最多缓存的数据块数量
+const RDB_PENDING_CHUNKS: usize = 16;
+
+/// 将快照保存为RDB文件，返回已保存的文件。dirty为生成快照时键空间的修改次数，保存成功后
+/// rdb_changes_since_last_save从该值开始计算
+pub fn rdb_save(snapshot: Snapshot, dirty: u64) -> anyhow::Result<File> {
+    rdb_save_to(snapshot, dirty, &CONFIG.rdb_path())
+}
+
+pub(super) fn rdb_save_to(snapshot: Snapshot, dirty: u64, path: &Path) -> anyhow::Result<File> {
+    let file = rdb_write_file(path, |file| rdb_encode(&snapshot, file).map(drop))
+        .with_context(|| format!("Failed to write RDB file {}", path.display()))?;
 
-    // 保存到文件
-    let mut file = std::fs::File::create("dump.rdb")?;
-    file.write_all(&buf)?;
+    save_succeeded(dirty);
+    Ok(file)
+}
+
+/// 将快照编码为RDB格式，流式地写入writer并返回writer。编码的数据每积累RDB_WRITE_CHUNK字节就写入writer，
+/// 同时增量地计算校验和，因此内存占用只与最大的单个键值对有关，与数据集的大小无关。
+/// 遍历快照时会阻塞当前线程
+pub fn rdb_encode<W: Write>(snapshot: &Snapshot, writer: W) -> io::Result<W> {
+    encode(snapshot, writer, false)
+}
+
+/// 与rdb_encode相同，但aof-base辅助字段为1，代表这是重写后的AOF文件开头的RDB数据
+pub fn rdb_encode_aof_preamble<W: Write>(snapshot: &Snapshot, writer: W) -> io::Result<W> {
+    encode(snapshot, writer, true)
+}
 
+fn encode<W: Write>(snapshot: &Snapshot, writer: W, aof_base: bool) -> io::Result<W> {
+    let mut encoder = RdbEncoder::new(writer);
+    let buf = &mut encoder.buf;
+    buf.extend_from_slice(format!("REDIS{:04}", RDB_VERSION).as_bytes());
+    encode_aux(buf, "redis-ver", REDIS_VERSION.into());
+    encode_aux(buf, "redis-bits", "64".into());
+    encode_aux_int(buf, "ctime", unix_secs() as i64);
+    encode_aux_int(buf, "used-mem", snapshot.used_memory() as i64);
+    encode_aux_int(buf, "aof-base", aof_base as i64);
+    encode_functions(buf, &SCRIPTING.function_codes()); // 函数库
+
+    // 空的数据库不会被保存
+    for id in (0..snapshot.databases()).filter(|&id| snapshot.db_len(id) > 0) {
+        let buf = &mut encoder.buf;
+        buf.put_u8(SELECTDB); // 选择数据库
+        encode_length(buf, id as u64, None); // 数据库编号
+        buf.put_u8(RESIZEDB); // 键的数量以及设置了过期时间的键的数量，用于载入时预先分配空间
+        encode_length(buf, snapshot.db_len(id) as u64, None);
+        encode_length(buf, snapshot.db_expires_len(id) as u64, None);
+
+        snapshot.try_for_each(id, |k, obj| {
+            encode_kv(&mut encoder.buf, k.clone(), obj);
+            encoder.write_if_full()
+        })?;
+    }
+
+    encoder.finish()
+}
+
+/// 在阻塞线程中编码快照，并异步地写入writer，用于socket等异步的目标。writer较慢时，
+/// 编码线程在缓存了RDB_PENDING_CHUNKS个数据块之后等待
+pub async fn rdb_encode_async<W: AsyncWrite + Unpin>(
+    snapshot: Snapshot,
+    writer: &mut W,
+) -> anyhow::Result<()> {
+    let (tx, mut rx) = mpsc::channel(RDB_PENDING_CHUNKS);
+    let encoder =
+        tokio::task::spawn_blocking(move || rdb_encode(&snapshot, ChunkSender(tx)).map(drop));
+    // 写入失败时接收端被丢弃，编码线程随之返回错误并结束
+    while let Some(chunk) = rx.recv().await {
+        writer.write_all(&chunk).await?;
+    }
+    encoder.await??;
+    writer.flush().await?;
     Ok(())
 }
 
-pub(super) fn encode_string_kv(buf: &mut Vec<u8>, key: Bytes, obj: &Object<db::String>) {
-    let expire_at = obj.expire_at;
-    if let Some(expire_at) = expire_at {
-        if let Ok(expire) = expire_at.duration_since(SystemTime::now()) {
-            buf.put_u8(EXPIRETIME_MS);
-            buf.put_u64(expire.as_millis() as u64);
-        } else {
-            // 过期则忽略
-            return;
+// 编码时使用的缓冲区，以及写入writer的数据的校验和
+struct RdbEncoder<W: Write> {
+    writer: W,
+    buf: Vec<u8>,
+    digest: Option<crc::Digest<'static, u64>>, // 未启用校验和时为None
+}
+
+impl<W: Write> RdbEncoder<W> {
+    fn new(writer: W) -> Self {
+        RdbEncoder {
+            writer,
+            buf: Vec::with_capacity(RDB_WRITE_CHUNK * 2),
+            digest: CONFIG.rdb.enable_checksum.then(|| CRC64.digest()),
+        }
+    }
+
+    fn write_if_full(&mut self) -> io::Result<()> {
+        if self.buf.len() >= RDB_WRITE_CHUNK {
+            self.write_buf()?;
+        }
+        Ok(())
+    }
+
+    fn write_buf(&mut self) -> io::Result<()> {
+        if let Some(digest) = &mut self.digest {
+            digest.update(&self.buf);
         }
+        self.writer.write_all(&self.buf)?;
+        self.buf.clear();
+        // 编码较大的键值对之后释放多余的空间
+        if self.buf.capacity() > RDB_WRITE_CHUNK * 4 {
+            self.buf.shrink_to(RDB_WRITE_CHUNK * 2);
+        }
+        Ok(())
+    }
+
+    // 写入结束标志以及之前所有数据的校验和(8B小端序)，未启用校验和时写入0
+    fn finish(mut self) -> io::Result<W> {
+        self.buf.put_u8(EOF);
+        self.write_buf()?;
+        let checksum = self.digest.map_or(0, |digest| digest.finalize());
+        self.writer.write_all(&checksum.to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+// 将写入的数据作为一个数据块发送给异步的写入任务
+struct ChunkSender(mpsc::Sender<Vec<u8>>);
+
+impl Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
-    buf.put_u8(RUREDIS_RDB_TYPE_STRING);
+}
+
+/// 原子地写入RDB文件：先由write写入同一目录下的临时文件temp-<pid>.rdb并fsync，再重命名为RDB文件，
+/// 最后fsync目录使重命名持久化。写入过程中崩溃不会破坏原来的RDB文件。返回可读写的RDB文件，
+/// 即使之后RDB文件被另一次保存替换，返回的文件的内容也不会改变
+fn rdb_write_file(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<File> {
+    let _guard = SAVE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let temp = dir.join(format!("temp-{}.rdb", std::process::id()));
+
+    let res = (|| {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp)?;
+        write(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)?;
+        File::open(dir)?.sync_all()?;
+        Ok(file)
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    res
+}
+
+pub(super) fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// FUNCTION DUMP的结果：function* RDB版本号(2B小端序) checksum(8B小端序)
+pub fn encode_functions_payload(codes: &[Bytes]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_functions(&mut buf, codes);
+    buf.put_u16_le(RDB_VERSION);
+    buf.put_u64_le(CRC64.checksum(&buf));
+    buf
+}
+
+pub(super) fn encode_aux(buf: &mut Vec<u8>, key: &str, value: Bytes) {
+    buf.put_u8(AUX);
+    encode_raw(buf, Bytes::copy_from_slice(key.as_bytes()));
+    encode_raw(buf, value);
+}
+
+// 整数值的辅助字段尽量使用整数编码，与Redis一致
+fn encode_aux_int(buf: &mut Vec<u8>, key: &str, value: i64) {
+    buf.put_u8(AUX);
+    encode_raw(buf, Bytes::copy_from_slice(key.as_bytes()));
+    match i32::try_from(value) {
+        Ok(i) => encode_int(buf, i),
+        Err(_) => encode_raw(buf, value.to_string().into()),
+    }
+}
+
+pub(super) fn encode_functions(buf: &mut Vec<u8>, codes: &[Bytes]) {
+    for code in codes {
+        buf.put_u8(FUNCTION2);
+        encode_raw(buf, code.clone());
+    }
+}
+
+pub(super) fn encode_kv(buf: &mut Vec<u8>, key: Bytes, obj: &RedisObject) {
+    // 已过期的键同样会被保存，载入时再忽略
+    if let Some(expire_at) = obj.expire_at {
+        let ms = expire_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        buf.put_u8(EXPIRETIME_MS);
+        buf.put_u64_le(ms);
+    }
+    buf.put_u8(match &obj.value {
+        ObjValue::Int(_) | ObjValue::Raw(_) => RUREDIS_RDB_TYPE_STRING,
+        ObjValue::List(_) => RUREDIS_RDB_TYPE_LIST_QUICKLIST_2,
+        ObjValue::Set(Set::IntSet(_)) => RUREDIS_RDB_TYPE_INTSET,
+        ObjValue::Set(_) => RUREDIS_RDB_TYPE_SET,
+        ObjValue::ZSet(ZSet::Listpack(_)) => RUREDIS_RDB_TYPE_ZSET_LISTPACK,
+        ObjValue::ZSet(_) => RUREDIS_RDB_TYPE_ZSET_2,
+        ObjValue::Hash(Hash::Listpack(_)) => RUREDIS_RDB_TYPE_HASH_LISTPACK,
+        ObjValue::Hash(_) => RUREDIS_RDB_TYPE_HASH,
+    });
     encode_key(buf, key);
     match &obj.value {
-        ObjValue::Int(i) => encode_int(buf, *i as i32),
+        // 超出i32范围的整数以字符串形式保存
+        ObjValue::Int(i) => match i32::try_from(*i) {
+            Ok(i) => encode_int(buf, i),
+            Err(_) => encode_raw(buf, i.to_string().into()),
+        },
         ObjValue::Raw(s) => encode_raw(buf, s.clone()),
-        _ => unreachable!(
-            "Cann't get stringobj value type because stringobj was encoded in wrong type!!!"
-        ),
+        // listpack编码的列表作为只有一个节点的quicklist保存
+        ObjValue::List(List::Listpack(lp)) => {
+            encode_length(buf, 1, None);
+            encode_quicklist_node(buf, lp);
+        }
+        ObjValue::List(List::Quicklist(ql)) => {
+            encode_length(buf, ql.node_count() as u64, None);
+            ql.listpacks().for_each(|lp| encode_quicklist_node(buf, &lp));
+        }
+        ObjValue::ZSet(ZSet::Listpack(lp)) | ObjValue::Hash(Hash::Listpack(lp)) => {
+            encode_raw(buf, Bytes::copy_from_slice(lp.as_bytes()));
+        }
+        ObjValue::Set(Set::IntSet(ints)) => encode_raw(buf, encode_intset(ints)),
+        ObjValue::Set(set) => {
+            encode_length(buf, set.len() as u64, None);
+            set.iter().for_each(|m| encode_raw(buf, m));
+        }
+        ObjValue::ZSet(zset) => {
+            encode_length(buf, zset.len() as u64, None);
+            zset.iter().for_each(|(m, score)| {
+                encode_raw(buf, m);
+                buf.put_f64_le(score);
+            });
+        }
+        ObjValue::Hash(hash) => {
+            encode_length(buf, hash.len() as u64, None);
+            hash.iter().for_each(|(f, v)| {
+                encode_raw(buf, f);
+                encode_raw(buf, v);
+            });
+        }
     }
 }
 
+fn encode_quicklist_node(buf: &mut Vec<u8>, lp: &Listpack) {
+    encode_length(buf, QUICKLIST_NODE_CONTAINER_PACKED as u64, None);
+    encode_raw(buf, Bytes::copy_from_slice(lp.as_bytes()));
+}
+
+// intset: 每个整数的字节数(4B小端序), 整数数量(4B小端序), 有序的整数(小端序)*
+fn encode_intset(ints: &[i64]) -> Bytes {
+    // 整数有序，只需要检查最小值和最大值
+    let (min, max) = (ints.first().map_or(0, |&i| i), ints.last().map_or(0, |&i| i));
+    let width = if i16::try_from(min).is_ok() && i16::try_from(max).is_ok() {
+        2
+    } else if i32::try_from(min).is_ok() && i32::try_from(max).is_ok() {
+        4
+    } else {
+        8
+    };
+    let mut buf = Vec::with_capacity(8 + ints.len() * width);
+    buf.put_u32_le(width as u32);
+    buf.put_u32_le(ints.len() as u32);
+    for &i in ints {
+        buf.put_int_le(i, width);
+    }
+    buf.into()
+}
+
 pub(super) fn encode_raw(buf: &mut Vec<u8>, value: Bytes) {
-    encode_length(buf, value.len() as u32, None);
+    // 较长的字符串尝试使用LZF压缩
+    if CONFIG.rdb.rdbcompression && value.len() > LZF_MIN_LEN && encode_lzf(buf, &value) {
+        return;
+    }
+    encode_length(buf, value.len() as u64, None);
     buf.extend(value);
 }
 
+// 与Redis一致，压缩后至少节省4个字节才保存压缩后的数据，返回是否进行了压缩
+fn encode_lzf(buf: &mut Vec<u8>, value: &[u8]) -> bool {
+    let compressed = lzf_compress(value);
+    if compressed.len() + 4 > value.len() {
+        return false;
+    }
+    encode_length(buf, 0, Some(RUREDIS_RDB_SPECTIAL_FORMAT_LZF));
+    encode_length(buf, compressed.len() as u64, None);
+    encode_length(buf, value.len() as u64, None);
+    buf.extend(compressed);
+    true
+}
+
 pub(super) fn encode_int(buf: &mut Vec<u8>, value: i32) {
-    // PERF: 还能优化RUREDIS_RDB_ENC_INT8..的占用空间
     if value >= i8::MIN as i32 && value <= i8::MAX as i32 {
         encode_length(buf, 0, Some(RUREDIS_RDB_SPECTIAL_FORMAT_INT8));
         buf.put_i8(value as i8);
     } else if value >= i16::MIN as i32 && value <= i16::MAX as i32 {
         encode_length(buf, 0, Some(RUREDIS_RDB_SPECTIAL_FORMAT_INT16));
-        buf.put_i16(value as i16);
+        buf.put_i16_le(value as i16);
     } else {
         encode_length(buf, 0, Some(RUREDIS_RDB_SPECTIAL_FORMAT_INT32));
-        buf.put_i32(value);
+        buf.put_i32_le(value);
     }
 }
 
 pub(super) fn encode_key(buf: &mut Vec<u8>, key: Bytes) {
-    encode_length(buf, key.len() as u32, None);
+    encode_length(buf, key.len() as u64, None);
     buf.extend(key);
 }
 
-pub(super) fn encode_length(buf: &mut Vec<u8>, len: u32, special_format: Option<u8>) {
+pub(super) fn encode_length(buf: &mut Vec<u8>, len: u64, special_format: Option<u8>) {
     if let Some(special_format) = special_format {
         // 11000000
         buf.put_u8(0xc0 | special_format);
@@ -100,9 +391,13 @@ pub(super) fn encode_length(buf: &mut Vec<u8>, len: u32, special_format: Option<
         // 01xxxxxx(高6位) xxxxxxxx(低8位)
         buf.put_u8((len >> 8 | 0x40) as u8);
         buf.put_u8(len as u8);
+    } else if len <= u32::MAX as u64 {
+        // 10000000 xxxxxxxx xxxxxxxx xxxxxxxx xxxxxxxx(32位大端序)
+        buf.put_u8(RUREDIS_RDB_32BITLEN);
+        buf.put_u32(len as u32);
     } else {
-        // 10xxxxxx(丢弃) xxxxxxxx xxxxxxxx xxxxxxxx xxxxxxxx
-        buf.put_u8(0x80);
-        buf.put_u32(len);
+        // 10000001 xxxxxxxx*8(64位大端序)
+        buf.put_u8(RUREDIS_RDB_64BITLEN);
+        buf.put_u64(len);
     }
 }
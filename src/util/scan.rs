@@ -0,0 +1,108 @@
+//! SCAN系列命令的游标。元素按照其哈希值的顺序被扫描，游标即下一个待扫描的位置。
+//! 扫描顺序只取决于元素本身，与哈希表的容量无关，因此扫描期间哈希表扩容或缩容
+//! 都不会使一直存在的元素被遗漏
+
+use bytes::Bytes;
+use std::collections::BTreeSet;
+
+// 元素在扫描顺序中的位置为其CRC64的高47位加1，不会为0，因此游标0只代表扫描的开始或结束。
+// 键空间的游标在低48位保存分片中的位置，高16位保存分片编号
+static SCAN_CRC: crc::Crc<u64> = crc::Crc::<u64>::new(&crc::CRC_64_REDIS);
+const POSITION_BITS: u32 = 48;
+
+/// 键空间游标能够表示的最大分片数量
+pub const MAX_SCAN_SHARDS: usize = 1 << (64 - POSITION_BITS);
+
+pub fn scan_position(item: &[u8]) -> u64 {
+    (SCAN_CRC.checksum(item) >> (64 - POSITION_BITS + 1)) + 1
+}
+
+/// 由分片编号与分片中的位置组成键空间的游标
+pub fn shard_cursor(shard: usize, pos: u64) -> u64 {
+    ((shard as u64) << POSITION_BITS) | pos
+}
+
+/// 将键空间的游标拆分为分片编号与分片中的位置
+pub fn split_shard_cursor(cursor: u64) -> (usize, u64) {
+    (
+        (cursor >> POSITION_BITS) as usize,
+        cursor & ((1 << POSITION_BITS) - 1),
+    )
+}
+
+/// 按扫描顺序排列的元素，每次扫描只需要访问返回的元素
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanIndex {
+    items: BTreeSet<(u64, Bytes)>,
+}
+
+impl ScanIndex {
+    /// 加入一个不在索引中的元素
+    pub fn insert(&mut self, item: Bytes) {
+        self.items.insert((scan_position(&item), item));
+    }
+
+    pub fn remove(&mut self, item: &[u8]) {
+        let pos = scan_position(item);
+        let found = self
+            .items
+            .range((pos, Bytes::new())..)
+            .take_while(|(p, _)| *p == pos)
+            .find(|(_, i)| i == item)
+            .cloned();
+        if let Some(found) = found {
+            self.items.remove(&found);
+        }
+    }
+
+    /// 从游标开始，按扫描顺序返回count个元素以及下一次扫描的位置，None代表扫描结束。
+    /// 位置相同的元素总是在同一次扫描中返回，因此返回的元素可能多于count个
+    pub fn scan(&self, cursor: u64, count: usize) -> (Option<u64>, Vec<Bytes>) {
+        let mut batch: Vec<Bytes> = Vec::with_capacity(count);
+        let mut last = 0;
+        for (pos, item) in self.items.range((cursor, Bytes::new())..) {
+            if batch.len() >= count && *pos != last {
+                return (Some(*pos), batch);
+            }
+            batch.push(item.clone());
+            last = *pos;
+        }
+        (None, batch)
+    }
+}
+
+#[test]
+fn test_scan_during_resize() {
+    use std::collections::HashSet;
+
+    let mut index = ScanIndex::default();
+    for i in 0..100 {
+        index.insert(format!("key{i}").into());
+    }
+    let mut seen = HashSet::new();
+    let mut cursor = 0;
+    let mut round = 0;
+    loop {
+        let (next, keys) = index.scan(cursor, 7);
+        assert!(keys.len() <= 8);
+        seen.extend(keys);
+        // 扫描过程中插入和删除其它键
+        if round < 4 {
+            for i in 0..200 {
+                index.insert(format!("new{round}-{i}").into());
+            }
+            for i in 0..100 {
+                index.remove(format!("new{round}-{i}").as_bytes());
+            }
+        }
+        round += 1;
+        match next {
+            Some(next) => cursor = next,
+            None => break,
+        }
+    }
+
+    // 扫描期间一直存在的键都被返回了
+    assert!((0..100).all(|i| seen.contains(format!("key{i}").as_bytes())));
+    assert_eq!(index.items.len(), 500);
+}
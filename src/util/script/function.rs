@@ -0,0 +1,385 @@
+//! Redis 7的函数库。每个库的代码以`#!lua name=<库名>`开头，载入时通过
+//! `redis.register_function`注册若干个具名函数，之后可以通过FCALL调用
+
+use super::{create_array, lua_error_cause, new_lua, ScriptOutput, Scripting};
+use crate::{db::DbInner, frame::Frame, util::glob_match};
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use mlua::{Function, Lua, Table, Value, Variadic};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    sync::{atomic::AtomicBool, Arc},
+};
+
+// 函数可以声明的标志
+const FUNCTION_FLAGS: &[&str] = &[
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+pub(super) struct FunctionState {
+    lua: Lua,
+    // 库名 -> 库。有序，使FUNCTION LIST和FUNCTION DUMP的结果稳定
+    libraries: BTreeMap<String, Library>,
+    // 函数名 -> 函数。函数名在所有库中唯一
+    functions: HashMap<String, FunctionEntry>,
+    killed: Arc<AtomicBool>,
+}
+
+struct Library {
+    code: Bytes,
+    functions: Vec<String>,
+}
+
+struct FunctionEntry {
+    library: String,
+    callback: Function,
+    flags: Vec<String>,
+}
+
+/// FUNCTION RESTORE处理库名冲突的策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestorePolicy {
+    // 库名冲突时报错
+    Append,
+    // 替换同名的库
+    Replace,
+    // 先删除所有的库
+    Flush,
+}
+
+impl FunctionState {
+    pub(super) fn new(killed: Arc<AtomicBool>) -> Self {
+        Self {
+            lua: new_lua(killed.clone()).expect("Failed to create lua state"),
+            libraries: BTreeMap::new(),
+            functions: HashMap::new(),
+            killed,
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.lua = new_lua(self.killed.clone())?;
+        self.libraries.clear();
+        self.functions.clear();
+        Ok(())
+    }
+
+    // 执行库的代码，注册其中的函数，返回库名
+    fn load(&mut self, code: &Bytes, replace: bool) -> Result<String> {
+        let (name, body) = parse_metadata(code)?;
+        if self.libraries.contains_key(&name) && !replace {
+            bail!("ERR Library '{}' already exists", name);
+        }
+
+        let registered = RefCell::new(Vec::<(String, Function, Vec<String>)>::new());
+        self.lua
+            .scope(|scope| {
+                let redis: Table = self.lua.globals().get("redis")?;
+                let register = scope.create_function(|_, argv: Variadic<Value>| {
+                    let (fname, callback, flags) = parse_register_args(argv)?;
+                    let mut registered = registered.borrow_mut();
+                    if registered.iter().any(|(n, _, _)| *n == fname) {
+                        return Err(mlua::Error::RuntimeError(
+                            "Function already exists in the library".to_string(),
+                        ));
+                    }
+                    registered.push((fname, callback, flags));
+                    Ok(())
+                })?;
+                redis.set("register_function", register)?;
+                self.lua.load(body).set_name("=user_function").exec()
+            })
+            .map_err(|e| anyhow!("ERR Error registering functions: {}", lua_error_cause(&e)))?;
+
+        let registered = registered.into_inner();
+        if registered.is_empty() {
+            bail!("ERR No functions registered");
+        }
+        for (fname, _, _) in &registered {
+            if let Some(entry) = self.functions.get(fname) {
+                if entry.library != name {
+                    bail!("ERR Function {} already exists", fname);
+                }
+            }
+        }
+
+        self.delete(&name);
+        let mut functions = Vec::with_capacity(registered.len());
+        for (fname, callback, flags) in registered {
+            functions.push(fname.clone());
+            self.functions.insert(
+                fname,
+                FunctionEntry {
+                    library: name.clone(),
+                    callback,
+                    flags,
+                },
+            );
+        }
+        self.libraries.insert(
+            name.clone(),
+            Library {
+                code: code.clone(),
+                functions,
+            },
+        );
+        Ok(name)
+    }
+
+    fn delete(&mut self, library: &str) -> bool {
+        match self.libraries.remove(library) {
+            Some(lib) => {
+                for fname in lib.functions {
+                    self.functions.remove(&fname);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Scripting {
+    /// 载入函数库，返回库名
+    pub fn function_load(&self, code: &Bytes, replace: bool) -> Result<String> {
+        let mut state = self.functions.lock().expect("Failed to lock lua state");
+        state.load(code, replace)
+    }
+
+    pub fn function_delete(&self, library: &str) -> Result<()> {
+        let mut state = self.functions.lock().expect("Failed to lock lua state");
+        if !state.delete(library) {
+            bail!("ERR Library not found");
+        }
+        Ok(())
+    }
+
+    pub fn function_flush(&self) -> Result<()> {
+        let mut state = self.functions.lock().expect("Failed to lock lua state");
+        state.flush()
+    }
+
+    /// 返回所有函数库的代码，用于持久化和FUNCTION DUMP
+    pub fn function_codes(&self) -> Vec<Bytes> {
+        let state = self.functions.lock().expect("Failed to lock lua state");
+        state
+            .libraries
+            .values()
+            .map(|lib| lib.code.clone())
+            .collect()
+    }
+
+    /// 载入多个函数库。任意一个库载入失败时，恢复原有的函数库
+    pub fn function_restore(&self, codes: Vec<Bytes>, policy: RestorePolicy) -> Result<()> {
+        let mut state = self.functions.lock().expect("Failed to lock lua state");
+        let old: Vec<Bytes> = state
+            .libraries
+            .values()
+            .map(|lib| lib.code.clone())
+            .collect();
+        if policy == RestorePolicy::Flush {
+            state.flush()?;
+        }
+
+        let res = codes.iter().try_for_each(|code| {
+            state
+                .load(code, policy != RestorePolicy::Append)
+                .map(|_| ())
+        });
+        if res.is_err() {
+            state.flush()?;
+            for code in &old {
+                state.load(code, true)?;
+            }
+        }
+        res
+    }
+
+    /// FUNCTION LIST的结果，可以通过glob模式过滤库名
+    pub fn function_list(&self, pattern: Option<&Bytes>, with_code: bool) -> Frame {
+        let state = self.functions.lock().expect("Failed to lock lua state");
+        let libraries = state
+            .libraries
+            .iter()
+            .filter(|(name, _)| pattern.is_none_or(|p| glob_match(p, name.as_bytes(), false)))
+            .map(|(name, lib)| {
+                let functions = lib
+                    .functions
+                    .iter()
+                    .map(|fname| {
+                        let entry = &state.functions[fname];
+                        Frame::Array(vec![
+                            Frame::Bulk("name".into()),
+                            Frame::Bulk(fname.clone().into()),
+                            Frame::Bulk("description".into()),
+                            Frame::Null,
+                            Frame::Bulk("flags".into()),
+                            Frame::Array(
+                                entry
+                                    .flags
+                                    .iter()
+                                    .map(|f| Frame::Bulk(f.clone().into()))
+                                    .collect(),
+                            ),
+                        ])
+                    })
+                    .collect();
+                let mut frames = vec![
+                    Frame::Bulk("library_name".into()),
+                    Frame::Bulk(name.clone().into()),
+                    Frame::Bulk("engine".into()),
+                    Frame::Bulk("LUA".into()),
+                    Frame::Bulk("functions".into()),
+                    Frame::Array(functions),
+                ];
+                if with_code {
+                    frames.push(Frame::Bulk("library_code".into()));
+                    frames.push(Frame::Bulk(lib.code.clone()));
+                }
+                Frame::Array(frames)
+            })
+            .collect();
+        Frame::Array(libraries)
+    }
+
+    /// 调用函数。函数以(KEYS, ARGV)作为参数
+    pub fn fcall(
+        &self,
+        db: &mut DbInner,
+        name: &str,
+        keys: &[Bytes],
+        args: &[Bytes],
+        read_only: bool,
+    ) -> Result<ScriptOutput> {
+        let state = self.functions.lock().expect("Failed to lock lua state");
+        let Some(entry) = state.functions.get(name) else {
+            bail!("ERR Function not found");
+        };
+        let no_writes = entry.flags.iter().any(|f| f == "no-writes");
+        if read_only && !no_writes {
+            bail!("ERR Can not execute a script with write flag using *_ro command.");
+        }
+
+        let lua = &state.lua;
+        let params = (create_array(lua, keys)?, create_array(lua, args)?);
+        self.call(
+            lua,
+            db,
+            entry.callback.clone(),
+            params,
+            read_only || no_writes,
+        )
+    }
+}
+
+// 解析库代码的第一行`#!lua name=<库名>`，返回库名和去掉该行之后的代码
+fn parse_metadata(code: &Bytes) -> Result<(String, Vec<u8>)> {
+    let first_line_end = code.iter().position(|&b| b == b'\n').unwrap_or(code.len());
+    let first_line = std::str::from_utf8(&code[..first_line_end])
+        .map_err(|_| anyhow!("ERR Missing library metadata"))?;
+    let Some(metadata) = first_line.strip_prefix("#!") else {
+        bail!("ERR Missing library metadata");
+    };
+
+    let mut parts = metadata.split_whitespace();
+    match parts.next() {
+        Some("lua") => {}
+        Some(engine) => bail!("ERR Engine '{}' not found", engine),
+        None => bail!("ERR Missing library metadata"),
+    }
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(n) => name = Some(n.to_string()),
+            None => bail!("ERR Invalid metadata value given: {}", part),
+        }
+    }
+    let Some(name) = name else {
+        bail!("ERR Library name was not given");
+    };
+    if !is_valid_name(&name) {
+        bail!("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long");
+    }
+
+    // 保留换行符，使错误信息中的行号与原代码一致
+    Ok((name, code[first_line_end..].to_vec()))
+}
+
+// redis.register_function(name, callback)或
+// redis.register_function{function_name=name, callback=callback, flags={...}}
+fn parse_register_args(argv: Variadic<Value>) -> mlua::Result<(String, Function, Vec<String>)> {
+    let err = |msg: &str| mlua::Error::RuntimeError(msg.to_string());
+    let (name, callback, flags) = match argv.as_slice() {
+        [Value::String(name), Value::Function(callback)] => {
+            (name.to_str()?.to_string(), callback.clone(), vec![])
+        }
+        [Value::Table(t)] => {
+            let name: mlua::LuaString = t.get("function_name").map_err(|_| {
+                err("function_name argument given to redis.register_function must be a string")
+            })?;
+            let callback: Function = t.get("callback").map_err(|_| {
+                err("callback argument given to redis.register_function must be a function")
+            })?;
+            let flags: Option<Vec<String>> = t
+                .get("flags")
+                .map_err(|_| err("flags argument to redis.register_function must be a table representing function flags"))?;
+            (
+                name.to_str()?.to_string(),
+                callback,
+                flags.unwrap_or_default(),
+            )
+        }
+        _ => return Err(err("wrong number of arguments to redis.register_function")),
+    };
+
+    if !is_valid_name(&name) {
+        return Err(err("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long"));
+    }
+    if let Some(flag) = flags.iter().find(|f| !FUNCTION_FLAGS.contains(&f.as_str())) {
+        return Err(mlua::Error::RuntimeError(format!(
+            "unknown flag given: {}",
+            flag
+        )));
+    }
+    Ok((name, callback, flags))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[test]
+fn test_function_load() {
+    let scripting = Scripting::new();
+    let code = Bytes::from(
+        "#!lua name=mylib\nredis.register_function('f1', function(keys, args) return args[1] end)\n\
+         redis.register_function{function_name='f2', callback=function() return 1 end, flags={'no-writes'}}",
+    );
+    assert_eq!(scripting.function_load(&code, false).unwrap(), "mylib");
+    assert!(scripting.function_load(&code, false).is_err());
+    assert!(scripting.function_load(&code, true).is_ok());
+
+    // 函数名不能与其它库的函数重复
+    let other = Bytes::from("#!lua name=other\nredis.register_function('f1', function() end)");
+    assert!(scripting.function_load(&other, false).is_err());
+    assert!(scripting
+        .function_load(&Bytes::from("return 1"), false)
+        .is_err());
+
+    assert_eq!(scripting.function_codes(), vec![code.clone()]);
+    scripting
+        .function_restore(vec![other.clone()], RestorePolicy::Append)
+        .unwrap_err();
+    assert_eq!(scripting.function_codes(), vec![code.clone()]);
+    scripting
+        .function_restore(vec![other.clone()], RestorePolicy::Flush)
+        .unwrap();
+    assert_eq!(scripting.function_codes(), vec![other]);
+    scripting.function_delete("other").unwrap();
+    assert!(scripting.function_delete("other").is_err());
+}
@@ -0,0 +1,458 @@
+//! Lua脚本引擎。所有脚本在同一个Lua 5.1虚拟机中执行，脚本被编译为名为`f_<sha1>`的全局函数。
+//! 脚本通过`redis.call`/`redis.pcall`执行命令，命令与客户端发送的命令走同一条解析路径
+
+mod function;
+
+pub use function::*;
+
+use crate::{conf::CONFIG, db::DbInner, frame::Frame};
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use mlua::{
+    Function, HookTriggers, IntoLuaMulti, Lua, LuaOptions, StdLib, Table, Value, Variadic, VmState,
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// 不能在脚本中执行的命令
+const NOSCRIPT_CMDS: &[&[u8]] = &[
+    b"eval",
+    b"evalsha",
+    b"eval_ro",
+    b"evalsha_ro",
+    b"script",
+    b"fcall",
+    b"fcall_ro",
+    b"function",
+    b"multi",
+    b"exec",
+    b"discard",
+    b"watch",
+    b"unwatch",
+];
+
+pub struct Scripting {
+    state: Mutex<LuaState>,
+    // 函数库使用独立的Lua虚拟机，SCRIPT FLUSH不会影响函数库
+    functions: Mutex<FunctionState>,
+    // 正在执行的脚本的开始时间(毫秒时间戳)，0代表没有脚本在执行
+    running_since: AtomicU64,
+    // 正在执行的脚本是否已经执行过写命令。执行过写命令的脚本不能被SCRIPT KILL
+    wrote: AtomicBool,
+    // SCRIPT KILL会设置该标志，脚本会在下一次检查时中止
+    killed: Arc<AtomicBool>,
+}
+
+struct LuaState {
+    lua: Lua,
+    // sha1 -> 脚本
+    scripts: HashMap<String, Bytes>,
+}
+
+/// 脚本的执行结果以及脚本执行过的写命令。脚本的写命令会代替脚本本身被传播给从节点和AOF
+pub struct ScriptOutput {
+    pub reply: Frame,
+    pub effects: Vec<Frame>,
+}
+
+impl Scripting {
+    pub fn new() -> Self {
+        let killed = Arc::new(AtomicBool::new(false));
+        Self {
+            state: Mutex::new(LuaState {
+                lua: new_lua(killed.clone()).expect("Failed to create lua state"),
+                scripts: HashMap::new(),
+            }),
+            functions: Mutex::new(FunctionState::new(killed.clone())),
+            running_since: AtomicU64::new(0),
+            wrote: AtomicBool::new(false),
+            killed,
+        }
+    }
+
+    /// 编译并缓存脚本，返回脚本的sha1
+    pub fn load(&self, body: &Bytes) -> Result<String> {
+        let mut state = self.state.lock().expect("Failed to lock lua state");
+        let sha = sha1hex(body);
+        state.compile(&sha, body)?;
+        Ok(sha)
+    }
+
+    pub fn exists(&self, sha: &str) -> bool {
+        let state = self.state.lock().expect("Failed to lock lua state");
+        state.scripts.contains_key(&sha.to_ascii_lowercase())
+    }
+
+    /// 清空脚本缓存，并重建Lua虚拟机
+    pub fn flush(&self) -> Result<()> {
+        let mut state = self.state.lock().expect("Failed to lock lua state");
+        state.lua = new_lua(self.killed.clone())?;
+        state.scripts.clear();
+        Ok(())
+    }
+
+    /// 中止正在执行的脚本。执行过写命令的脚本不能被中止，否则会破坏脚本的原子性
+    pub fn kill(&self) -> Result<()> {
+        if self.running_since.load(Ordering::SeqCst) == 0 {
+            bail!("NOTBUSY No scripts in execution right now.");
+        }
+        if self.wrote.load(Ordering::SeqCst) {
+            bail!("UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.");
+        }
+        self.killed.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// 脚本执行时间超过busy_reply_threshold_ms后，服务器只接受SCRIPT KILL等少数命令，
+    /// 其它命令都会返回BUSY错误
+    pub fn check_busy(&self, frame: &Frame) -> Result<()> {
+        let since = self.running_since.load(Ordering::SeqCst);
+        if since == 0 || now_ms().saturating_sub(since) < CONFIG.server.busy_reply_threshold_ms {
+            return Ok(());
+        }
+
+        if let Frame::Array(args) = frame {
+            let arg = |i: usize| match args.get(i) {
+                Some(Frame::Bulk(b)) => b.to_ascii_lowercase(),
+                _ => vec![],
+            };
+            if matches!(
+                (arg(0).as_slice(), arg(1).as_slice()),
+                (b"script", b"kill") | (b"function", b"kill") | (b"shutdown", b"nosave")
+            ) {
+                return Ok(());
+            }
+        }
+        bail!("BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.")
+    }
+
+    /// 执行脚本。body为None时，脚本必须已经被缓存
+    pub fn eval(
+        &self,
+        db: &mut DbInner,
+        sha: &str,
+        body: Option<&Bytes>,
+        keys: &[Bytes],
+        args: &[Bytes],
+        read_only: bool,
+    ) -> Result<ScriptOutput> {
+        let mut state = self.state.lock().expect("Failed to lock lua state");
+        let sha = sha.to_ascii_lowercase();
+        if !state.scripts.contains_key(&sha) {
+            match body {
+                Some(body) => state.compile(&sha, body)?,
+                None => bail!("NOSCRIPT No matching script. Please use EVAL."),
+            }
+        }
+
+        let lua = &state.lua;
+        let globals = lua.globals();
+        globals.set("KEYS", create_array(lua, keys)?)?;
+        globals.set("ARGV", create_array(lua, args)?)?;
+        let f: Function = globals.get(format!("f_{}", sha))?;
+        self.call(lua, db, f, (), read_only)
+    }
+
+    /// 在调用者持有的写锁下调用Lua函数，调用期间`redis.call`/`redis.pcall`可以访问数据库
+    fn call(
+        &self,
+        lua: &Lua,
+        db: &mut DbInner,
+        f: Function,
+        params: impl IntoLuaMulti,
+        read_only: bool,
+    ) -> Result<ScriptOutput> {
+        self.killed.store(false, Ordering::SeqCst);
+        self.wrote.store(false, Ordering::SeqCst);
+        self.running_since.store(now_ms(), Ordering::SeqCst);
+
+        let db = RefCell::new(db);
+        let effects = RefCell::new(Vec::new());
+        let res = lua.scope(|scope| {
+            let redis: Table = lua.globals().get("redis")?;
+            let call_fn = |raise: bool| {
+                let (db, effects) = (&db, &effects);
+                scope.create_function_mut(move |lua, argv: Variadic<Value>| {
+                    let reply = self.redis_call(
+                        &mut db.borrow_mut(),
+                        &mut effects.borrow_mut(),
+                        argv,
+                        read_only,
+                    );
+                    match reply {
+                        Frame::Error(e) if raise => Err(mlua::Error::RuntimeError(e)),
+                        reply => frame_to_lua(lua, reply),
+                    }
+                })
+            };
+            redis.set("call", call_fn(true)?)?;
+            redis.set("pcall", call_fn(false)?)?;
+            f.call::<Value>(params)
+        });
+
+        self.running_since.store(0, Ordering::SeqCst);
+        let reply = match res {
+            Ok(value) => lua_to_frame(value),
+            Err(e) => Frame::Error(lua_error_message(&e)),
+        };
+        Ok(ScriptOutput {
+            reply,
+            effects: effects.into_inner(),
+        })
+    }
+
+    // 执行脚本中的一条命令，返回命令的结果
+    fn redis_call(
+        &self,
+        db: &mut DbInner,
+        effects: &mut Vec<Frame>,
+        argv: Variadic<Value>,
+        read_only: bool,
+    ) -> Frame {
+        let mut bulks = Vec::with_capacity(argv.len());
+        for arg in argv.iter() {
+            match arg {
+                Value::String(s) => bulks.push(Frame::Bulk(Bytes::copy_from_slice(&s.as_bytes()))),
+                Value::Integer(i) => bulks.push(Frame::Bulk(i.to_string().into())),
+                Value::Number(n) => bulks.push(Frame::Bulk(n.to_string().into())),
+                _ => {
+                    return Frame::Error(
+                        "ERR Lua redis lib command arguments must be strings or integers"
+                            .to_string(),
+                    )
+                }
+            }
+        }
+        if bulks.is_empty() {
+            return Frame::Error(
+                "ERR Please specify at least one argument for this redis lib call".to_string(),
+            );
+        }
+
+        if let Some(Frame::Bulk(name)) = bulks.first() {
+            let name = name.to_ascii_lowercase();
+            if NOSCRIPT_CMDS.contains(&name.as_slice()) {
+                return Frame::Error(
+                    "ERR This Redis command is not allowed from script".to_string(),
+                );
+            }
+        }
+
+        let frame = Frame::Array(bulks);
+        let cmd = match frame.clone().parse_cmd() {
+            Ok(cmd) => cmd,
+            Err(e) => return Frame::Error(e.to_string()),
+        };
+        if cmd.is_write() {
+            if read_only {
+                return Frame::Error(
+                    "ERR Write commands are not allowed from read-only scripts.".to_string(),
+                );
+            }
+            self.wrote.store(true, Ordering::SeqCst);
+        }
+        let reply = cmd
+            .execute_locked(db)
+            .unwrap_or_else(|e| Frame::Error(e.to_string()));
+        if cmd.is_write() {
+            effects.extend(cmd.propagation(frame));
+        }
+        reply
+    }
+}
+
+impl Default for Scripting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LuaState {
+    // 将脚本编译为全局函数f_<sha1>
+    fn compile(&mut self, sha: &str, body: &Bytes) -> Result<()> {
+        let mut code = format!("function f_{}() ", sha).into_bytes();
+        code.extend_from_slice(body);
+        code.extend_from_slice(b"\nend");
+        self.lua
+            .load(code)
+            .set_name("=user_script")
+            .exec()
+            .map_err(|e| anyhow!("ERR Error compiling script: {}", lua_error_cause(&e)))?;
+        self.scripts.insert(sha.to_string(), body.clone());
+        Ok(())
+    }
+}
+
+// 创建Lua虚拟机，只加载base, table, string, math库，并注册redis库
+fn new_lua(killed: Arc<AtomicBool>) -> Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )?;
+
+    let redis = lua.create_table()?;
+    redis.set(
+        "status_reply",
+        lua.create_function(|lua, s: mlua::LuaString| {
+            let t = lua.create_table()?;
+            t.set("ok", s)?;
+            Ok(t)
+        })?,
+    )?;
+    redis.set(
+        "error_reply",
+        lua.create_function(|lua, s: mlua::LuaString| {
+            let t = lua.create_table()?;
+            t.set("err", s)?;
+            Ok(t)
+        })?,
+    )?;
+    redis.set(
+        "sha1hex",
+        lua.create_function(|_, s: mlua::LuaString| Ok(sha1hex(&s.as_bytes())))?,
+    )?;
+    lua.globals().set("redis", redis)?;
+
+    // 每执行一定数量的指令检查一次脚本是否被SCRIPT KILL
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(1000),
+        move |_, _| {
+            if killed.load(Ordering::SeqCst) {
+                return Err(mlua::Error::RuntimeError(
+                    "ERR Script killed by user with SCRIPT KILL...".to_string(),
+                ));
+            }
+            Ok(VmState::Continue)
+        },
+    )?;
+    Ok(lua)
+}
+
+fn create_array(lua: &Lua, items: &[Bytes]) -> mlua::Result<Table> {
+    lua.create_sequence_from(
+        items
+            .iter()
+            .map(|item| lua.create_string(item))
+            .collect::<mlua::Result<Vec<_>>>()?,
+    )
+}
+
+pub fn sha1hex(body: &[u8]) -> String {
+    sha1_smol::Sha1::from(body).digest().to_string()
+}
+
+/// 将命令的回复转换为Lua值：
+/// integer -> number, bulk -> string, nil -> false, array -> table,
+/// status -> {ok=status}, error -> {err=error}
+fn frame_to_lua(lua: &Lua, frame: Frame) -> mlua::Result<Value> {
+    Ok(match frame {
+        Frame::Integer(i) => Value::Integer(i),
+        Frame::Bulk(b) => Value::String(lua.create_string(&b)?),
+        Frame::Null => Value::Boolean(false),
+        Frame::Array(frames) => {
+            let t = lua.create_table_with_capacity(frames.len(), 0)?;
+            for frame in frames {
+                t.raw_push(frame_to_lua(lua, frame)?)?;
+            }
+            Value::Table(t)
+        }
+        Frame::Simple(s) => {
+            let t = lua.create_table()?;
+            t.set("ok", s)?;
+            Value::Table(t)
+        }
+        Frame::Error(e) => {
+            let t = lua.create_table()?;
+            t.set("err", e)?;
+            Value::Table(t)
+        }
+    })
+}
+
+/// 将Lua值转换为回复：
+/// number -> integer(截断小数部分), string -> bulk, table(数组) -> array(遇到nil截止),
+/// {ok=...} -> status, {err=...} -> error, true -> 1, false和nil -> nil
+fn lua_to_frame(value: Value) -> Frame {
+    match value {
+        Value::Integer(i) => Frame::Integer(i),
+        Value::Number(n) => Frame::Integer(n as i64),
+        Value::String(s) => Frame::Bulk(Bytes::copy_from_slice(&s.as_bytes())),
+        Value::Boolean(true) => Frame::Integer(1),
+        Value::Table(t) => {
+            if let Ok(Value::String(e)) = t.raw_get::<Value>("err") {
+                return Frame::Error(e.to_string_lossy());
+            }
+            if let Ok(Value::String(s)) = t.raw_get::<Value>("ok") {
+                return Frame::Simple(s.to_string_lossy());
+            }
+            let mut frames = Vec::new();
+            for i in 1.. {
+                match t.raw_get::<Value>(i) {
+                    Ok(Value::Nil) | Err(_) => break,
+                    Ok(value) => frames.push(lua_to_frame(value)),
+                }
+            }
+            Frame::Array(frames)
+        }
+        _ => Frame::Null,
+    }
+}
+
+// 提取Lua错误中最内层的错误信息，并去掉调用栈
+fn lua_error_cause(e: &mlua::Error) -> String {
+    let msg = match e {
+        mlua::Error::CallbackError { cause, .. } => return lua_error_cause(cause),
+        mlua::Error::RuntimeError(msg) | mlua::Error::SyntaxError { message: msg, .. } => {
+            msg.clone()
+        }
+        e => e.to_string(),
+    };
+    msg.lines().next().unwrap_or_default().to_string()
+}
+
+// 脚本的错误回复。没有错误码的错误会加上ERR前缀
+fn lua_error_message(e: &mlua::Error) -> String {
+    let msg = lua_error_cause(e);
+    let code = msg.split(' ').next().unwrap_or_default();
+    if !code.is_empty() && code.chars().all(|c| c.is_ascii_uppercase()) {
+        msg
+    } else {
+        format!("ERR {}", msg)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[test]
+fn test_lua_resp_conversion() {
+    let lua = Lua::new();
+    let frame = Frame::Array(vec![
+        Frame::Integer(-1),
+        Frame::Bulk("hello".into()),
+        Frame::Simple("OK".to_string()),
+        Frame::Error("ERR oops".to_string()),
+    ]);
+    let value = frame_to_lua(&lua, frame.clone()).unwrap();
+    assert_eq!(lua_to_frame(value), frame);
+
+    // nil会被转换为false，false再被转换为nil。数组在第一个nil处截止
+    let value = frame_to_lua(&lua, Frame::Null).unwrap();
+    assert_eq!(value, Value::Boolean(false));
+    assert_eq!(lua_to_frame(value), Frame::Null);
+    let value: Value = lua.load("return {1, 2.5, nil, 3}").eval().unwrap();
+    assert_eq!(
+        lua_to_frame(value),
+        Frame::Array(vec![Frame::Integer(1), Frame::Integer(2)])
+    );
+}